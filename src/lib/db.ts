@@ -872,7 +872,7 @@ export async function deleteTask(id: number): Promise<void> {
   saveDatabase();
 
   // Sync to CRDT (soft delete)
-  deleteTaskFromCRDT(id);
+  deleteTaskFromCRDT(String(id));
 }
 
 export async function getTasks(filter?: FilterOptions): Promise<Task[]> {
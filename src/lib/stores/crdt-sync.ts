@@ -52,9 +52,8 @@ async function doInit(): Promise<boolean> {
     wasmModule = wasm;
 
     // Generate or load node ID
-    const timestamp = Date.now() & 0xffffffff;
     nodeId =
-      localStorage.getItem(NODE_ID_KEY) || wasm.generate_node_id(timestamp);
+      localStorage.getItem(NODE_ID_KEY) || wasm.generate_node_id_v2();
     localStorage.setItem(NODE_ID_KEY, nodeId);
 
     // Create CRDT document
@@ -67,7 +66,15 @@ async function doInit(): Promise<boolean> {
         crdtDoc.import(savedDoc);
         console.log("✅ CRDT document loaded:", crdtDoc.stats());
       } catch (e) {
-        console.warn("⚠️ Failed to load existing CRDT doc, starting fresh");
+        // Saved before string task ids: migrate it, then store it in the
+        // new format. Anything else is kept as is rather than overwritten.
+        try {
+          crdtDoc.import_legacy(savedDoc);
+          saveCRDTDocument();
+          console.log("✅ CRDT document migrated:", crdtDoc.stats());
+        } catch (legacyError) {
+          console.warn("⚠️ Failed to load existing CRDT doc, starting fresh");
+        }
       }
     }
 
@@ -92,21 +99,23 @@ export function syncTaskToCRDT(task: Task): void {
 
   try {
     // Sync all task fields
-    crdtDoc.upsert_field(task.id, "title", task.title);
-    crdtDoc.upsert_field(task.id, "project", task.project || "");
-    crdtDoc.upsert_field(task.id, "category", task.category);
-    crdtDoc.upsert_field(task.id, "status", task.status);
-    crdtDoc.upsert_field(task.id, "notes", task.notes || "");
-    crdtDoc.upsert_field(task.id, "date", task.date);
-    crdtDoc.upsert_field(task.id, "assignee", task.assignee?.name || "");
-    crdtDoc.upsert_field(task.id, "sprint_id", String(task.sprint_id || ""));
+    // Tasks migrated by import_legacy live under "<node_id>:<id>"
+    const taskId = crdtDoc.resolve_task_id(String(task.id));
+    crdtDoc.upsert_field(taskId, "title", task.title);
+    crdtDoc.upsert_field(taskId, "project", task.project || "");
+    crdtDoc.upsert_field(taskId, "category", task.category);
+    crdtDoc.upsert_field(taskId, "status", task.status);
+    crdtDoc.upsert_field(taskId, "notes", task.notes || "");
+    crdtDoc.upsert_field(taskId, "date", task.date);
+    crdtDoc.upsert_field(taskId, "assignee", task.assignee?.name || "");
+    crdtDoc.upsert_field(taskId, "sprint_id", String(task.sprint_id || ""));
     crdtDoc.upsert_field(
-      task.id,
+      taskId,
       "is_archived",
       String(task.is_archived ? 1 : 0),
     );
     crdtDoc.upsert_field(
-      task.id,
+      taskId,
       "checklist",
       task.checklist ? JSON.stringify(task.checklist) : "",
     );
@@ -121,11 +130,11 @@ export function syncTaskToCRDT(task: Task): void {
 /**
  * Delete task from CRDT
  */
-export function deleteTaskFromCRDT(taskId: number): void {
+export function deleteTaskFromCRDT(taskId: string): void {
   if (!crdtDoc) return;
 
   try {
-    crdtDoc.delete_task(taskId);
+    crdtDoc.delete_task(crdtDoc.resolve_task_id(taskId));
     saveCRDTDocument();
     updatePendingCount();
   } catch (error) {
@@ -216,6 +225,14 @@ export function mergeCRDTDocument(remoteDoc: string): {
   }
 }
 
+/**
+ * SQLite id of a CRDT task id: ids are strings in the CRDT, and tasks
+ * migrated by `import_legacy` are named "<node_id>:<id>"
+ */
+function toTaskId(id: string): number {
+  return Number(id.slice(id.lastIndexOf(":") + 1));
+}
+
 /**
  * Get all tasks from CRDT
  */
@@ -225,7 +242,7 @@ export function getCRDTTasks(): Task[] {
   try {
     const tasks = crdtDoc.get_tasks();
     return tasks.map((t: any) => ({
-      id: t.id,
+      id: toTaskId(t.id),
       title: t.fields.title?.value || "",
       project: t.fields.project?.value || "",
       category: t.fields.category?.value || "งานหลัก",
@@ -43,8 +43,7 @@ export async function initCrdt() {
         initSignaling();
         
         // Generate or load node ID
-        const timestamp = Date.now() & 0xFFFFFFFF;
-        nodeId = localStorage.getItem('sync-node-id') || wasm.generate_node_id(timestamp);
+        nodeId = localStorage.getItem('sync-node-id') || wasm.generate_node_id_v2();
         localStorage.setItem('sync-node-id', nodeId);
         
         crdtDoc = new wasm.CrdtDocument(nodeId);
@@ -52,7 +51,13 @@ export async function initCrdt() {
         // Load existing document if any
         const savedDoc = localStorage.getItem('crdt-document');
         if (savedDoc) {
-            crdtDoc.import(savedDoc);
+            try {
+                crdtDoc.import(savedDoc);
+            } catch (e) {
+                // Saved before string task ids
+                crdtDoc.import_legacy(savedDoc);
+                saveDocument();
+            }
         }
         
         // Subscribe to incoming sync data
@@ -348,13 +353,15 @@ export async function performSync(): Promise<boolean> {
 export function syncTask(task: Task) {
     if (!crdtDoc) return;
     
-    crdtDoc.upsert_field(task.id!, 'title', task.title);
-    crdtDoc.upsert_field(task.id!, 'project', task.project || '');
-    crdtDoc.upsert_field(task.id!, 'category', task.category);
-    crdtDoc.upsert_field(task.id!, 'status', task.status);
-    crdtDoc.upsert_field(task.id!, 'notes', task.notes || '');
-    crdtDoc.upsert_field(task.id!, 'date', task.date);
-    crdtDoc.upsert_field(task.id!, 'assignee', task.assignee?.name || '');
+    // Tasks migrated by import_legacy live under "<node_id>:<id>"
+    const taskId = crdtDoc.resolve_task_id(String(task.id!));
+    crdtDoc.upsert_field(taskId, 'title', task.title);
+    crdtDoc.upsert_field(taskId, 'project', task.project || '');
+    crdtDoc.upsert_field(taskId, 'category', task.category);
+    crdtDoc.upsert_field(taskId, 'status', task.status);
+    crdtDoc.upsert_field(taskId, 'notes', task.notes || '');
+    crdtDoc.upsert_field(taskId, 'date', task.date);
+    crdtDoc.upsert_field(taskId, 'assignee', task.assignee?.name || '');
     
     saveDocument();
     incrementPendingChanges();
@@ -364,7 +371,7 @@ export function syncTask(task: Task) {
 export function syncDeleteTask(taskId: number) {
     if (!crdtDoc) return;
     
-    crdtDoc.delete_task(taskId);
+    crdtDoc.delete_task(crdtDoc.resolve_task_id(String(taskId)));
     saveDocument();
     incrementPendingChanges();
 }
@@ -386,7 +393,8 @@ export function getCrdtTasks(): Task[] {
     
     const tasks = crdtDoc.get_tasks();
     return tasks.map((t: any) => ({
-        id: t.id,
+        // Migrated legacy ids are "<node_id>:<id>"
+        id: Number(t.id.slice(t.id.lastIndexOf(':') + 1)),
         title: t.fields.title?.value || '',
         project: t.fields.project?.value || '',
         category: t.fields.category?.value || 'งานหลัก',
@@ -1,26 +1,224 @@
 use axum::{
-    extract::{ws::{Message, WebSocket, WebSocketUpgrade}, Path, State, Json},
+    extract::{ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade}, Path, Query, State, Json},
+    http::{header, HeaderMap, StatusCode},
     response::IntoResponse,
     routing::{get, post},
     Router,
 };
 use rand::Rng;
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use serde::{Deserialize, Serialize};
-use std::{sync::Arc, time::Duration as StdDuration};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path as FsPath, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration as StdDuration,
+};
 use tokio::sync::broadcast;
 use tracing::{info, warn};
 use uuid::Uuid;
+use wasm_crdt::CrdtDocument;
 use dotenv::dotenv; // Import dotenv
 use tower_governor::{key_extractor::KeyExtractor, errors::GovernorError};
 
 
 type SharedState = Arc<AppState>;
 
+/// `websocket_url` when neither `PUBLIC_WS_URL` nor a `Host` header is available
+const DEFAULT_WS_URL: &str = "ws://localhost:3001/ws";
+
+/// Default for `MAX_MESSAGE_BYTES`
+const DEFAULT_MAX_MESSAGE_BYTES: usize = 1024 * 1024;
+
+/// Default for `HISTORY_SIZE`
+const DEFAULT_HISTORY_SIZE: usize = 50;
+
+/// Default for `ROOM_CHANNEL_CAPACITY`
+const DEFAULT_ROOM_CHANNEL_CAPACITY: usize = 256;
+
+/// Default for `SYNC_REQUEST_TIMEOUT_MS`
+const DEFAULT_SYNC_REQUEST_TIMEOUT_MS: u64 = 3000;
+
+/// Default for `RATE_LIMIT_PER_SECOND`
+const DEFAULT_RATE_LIMIT_PER_SECOND: u32 = 20;
+
+/// Messages a connection may have dropped by the rate limit, without
+/// slowing down in between, before it is disconnected
+const MAX_RATE_LIMIT_VIOLATIONS: u32 = 50;
+
+/// What `join` does when the room already has a peer with that id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePeerPolicy {
+    /// Refuse the join with an error
+    #[default]
+    Reject,
+    /// Join as `<peer_id>-2`, `-3`, ...; `connected` carries the new id
+    Rename,
+}
+
+impl DuplicatePeerPolicy {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "reject" => Some(Self::Reject),
+            "rename" => Some(Self::Rename),
+            _ => None,
+        }
+    }
+}
+
+/// CRDT node id the server merges synced documents as
+const SERVER_NODE_ID: &str = "sync-server";
+
 pub struct AppState {
     pub rooms: DashMap<String, Room>,
     pub room_idle_timeout_seconds: u64,
+    /// Connections silent for this long are dropped (0 = never)
+    pub peer_timeout_seconds: u64,
     pub system_tx: broadcast::Sender<SystemEvent>,
+    /// Directory rooms are saved to (None = in-memory only)
+    pub persist_dir: Option<PathBuf>,
+    /// Rooms changed since they were last saved
+    pub dirty_rooms: DashSet<String>,
+    /// Bearer token for admin endpoints (None = admin endpoints disabled)
+    pub admin_token: Option<String>,
+    /// `websocket_url` returned by `POST /api/rooms` (None = derive from the request)
+    pub public_ws_url: Option<String>,
+    /// Largest incoming message (and stored document) accepted, in bytes (0 = no limit)
+    pub max_message_bytes: usize,
+    /// Messages per second each connection may send, with bursts up to the same number (0 = no limit)
+    pub rate_limit_per_second: u32,
+    /// Broadcasts kept per room for replay to peers that join later (0 = none)
+    pub history_size: usize,
+    /// Room events buffered per room; a peer that falls further behind is
+    /// resynced with the stored document
+    pub room_channel_capacity: usize,
+    /// How long a `request_sync` with `fresh` waits for the host to sync
+    /// before the stored document is sent instead (0 = never ask the host)
+    pub sync_request_timeout_ms: u64,
+    /// HS256 secret user tokens are signed with (None = no authentication)
+    pub jwt_secret: Option<String>,
+    pub duplicate_peer_policy: DuplicatePeerPolicy,
+    pub metrics: Metrics,
+}
+
+/// Counters exposed on `/metrics`
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub messages_received_total: AtomicU64,
+    pub rooms_created_total: AtomicU64,
+    pub rooms_cleaned_total: AtomicU64,
+}
+
+impl AppState {
+    pub fn new(
+        room_idle_timeout_seconds: u64,
+        peer_timeout_seconds: u64,
+        system_tx: broadcast::Sender<SystemEvent>,
+    ) -> Self {
+        Self {
+            rooms: DashMap::new(),
+            room_idle_timeout_seconds,
+            peer_timeout_seconds,
+            system_tx,
+            persist_dir: None,
+            dirty_rooms: DashSet::new(),
+            admin_token: None,
+            public_ws_url: None,
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+            rate_limit_per_second: DEFAULT_RATE_LIMIT_PER_SECOND,
+            history_size: DEFAULT_HISTORY_SIZE,
+            room_channel_capacity: DEFAULT_ROOM_CHANNEL_CAPACITY,
+            sync_request_timeout_ms: DEFAULT_SYNC_REQUEST_TIMEOUT_MS,
+            jwt_secret: None,
+            duplicate_peer_policy: DuplicatePeerPolicy::default(),
+            metrics: Metrics::default(),
+        }
+    }
+
+    pub fn with_admin_token(mut self, admin_token: Option<String>) -> Self {
+        self.admin_token = admin_token;
+        self
+    }
+
+    pub fn with_persist_dir(mut self, persist_dir: Option<PathBuf>) -> Self {
+        self.persist_dir = persist_dir;
+        self
+    }
+
+    pub fn with_public_ws_url(mut self, public_ws_url: Option<String>) -> Self {
+        self.public_ws_url = public_ws_url;
+        self
+    }
+
+    pub fn with_max_message_bytes(mut self, max_message_bytes: usize) -> Self {
+        self.max_message_bytes = max_message_bytes;
+        self
+    }
+
+    pub fn with_rate_limit(mut self, rate_limit_per_second: u32) -> Self {
+        self.rate_limit_per_second = rate_limit_per_second;
+        self
+    }
+
+    pub fn with_history_size(mut self, history_size: usize) -> Self {
+        self.history_size = history_size;
+        self
+    }
+
+    pub fn with_room_channel_capacity(mut self, room_channel_capacity: usize) -> Self {
+        self.room_channel_capacity = room_channel_capacity;
+        self
+    }
+
+    pub fn with_sync_request_timeout_ms(mut self, sync_request_timeout_ms: u64) -> Self {
+        self.sync_request_timeout_ms = sync_request_timeout_ms;
+        self
+    }
+
+    pub fn with_jwt_secret(mut self, jwt_secret: Option<String>) -> Self {
+        self.jwt_secret = jwt_secret;
+        self
+    }
+
+    pub fn with_duplicate_peer_policy(mut self, duplicate_peer_policy: DuplicatePeerPolicy) -> Self {
+        self.duplicate_peer_policy = duplicate_peer_policy;
+        self
+    }
+
+    /// User id from a token signed with `jwt_secret`. Without a secret
+    /// tokens are ignored and every request is anonymous (`Ok(None)`).
+    fn authenticate(&self, token: Option<&str>) -> Result<Option<String>, String> {
+        let Some(secret) = &self.jwt_secret else {
+            return Ok(None);
+        };
+        let token = token.ok_or("Missing token")?;
+        let data = jsonwebtoken::decode::<Claims>(
+            token,
+            &jsonwebtoken::DecodingKey::from_secret(secret.as_bytes()),
+            &jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256),
+        )
+        .map_err(|e| format!("Invalid token: {}", e))?;
+        Ok(Some(data.claims.sub))
+    }
+
+    /// Reject payloads over `max_message_bytes` before doing anything with them
+    fn check_message_size(&self, len: usize) -> Result<(), String> {
+        if self.max_message_bytes > 0 && len > self.max_message_bytes {
+            return Err(format!("Message too large: {} bytes (max {})", len, self.max_message_bytes));
+        }
+        Ok(())
+    }
+
+    /// Queue a room to be saved (or its file removed) on the next flush
+    fn mark_dirty(&self, room_code: &str) {
+        if self.persist_dir.is_some() {
+            self.dirty_rooms.insert(room_code.to_string());
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -33,6 +231,69 @@ pub struct Room {
     pub document_state: Option<String>,
     pub last_sync: chrono::DateTime<chrono::Utc>,
     pub empty_since: Option<chrono::DateTime<chrono::Utc>>,
+    pub password: Option<PasswordHash>,
+    /// Overrides `ROOM_IDLE_TIMEOUT_SECONDS` for this room (0 = never expire)
+    pub idle_timeout_seconds: Option<u64>,
+    /// User who created the room, when `JWT_SECRET` is set
+    pub owner: Option<String>,
+    /// Broadcasts `(from, data)` since the last document sync, oldest
+    /// first, replayed to joining peers. Not persisted.
+    pub history: VecDeque<(String, String)>,
+    /// Peers waiting for the host to answer a fresh `request_sync`, with
+    /// when they asked. Not persisted.
+    pub sync_requests: HashMap<String, tokio::time::Instant>,
+}
+
+/// Room as saved under `PERSIST_DIR`: everything except live connections
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedRoom {
+    id: String,
+    host_id: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+    document_state: Option<String>,
+    last_sync: chrono::DateTime<chrono::Utc>,
+    password: Option<PasswordHash>,
+    #[serde(default)]
+    idle_timeout_seconds: Option<u64>,
+    #[serde(default)]
+    owner: Option<String>,
+}
+
+/// Claims read from user tokens; `exp` is required
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    /// User id
+    sub: String,
+    exp: u64,
+}
+
+/// Salted SHA-256 hash of a room password
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasswordHash {
+    salt: [u8; 16],
+    hash: [u8; 32],
+}
+
+impl PasswordHash {
+    pub fn new(password: &str) -> Self {
+        let salt: [u8; 16] = rand::thread_rng().gen();
+        let hash = Self::digest(&salt, password);
+        Self { salt, hash }
+    }
+
+    pub fn verify(&self, password: &str) -> bool {
+        let hash = Self::digest(&self.salt, password);
+        // Compare every byte so timing doesn't reveal the matching prefix
+        hash.iter().zip(&self.hash).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+    }
+
+    fn digest(salt: &[u8], password: &str) -> [u8; 32] {
+        Sha256::new()
+            .chain_update(salt)
+            .chain_update(password.as_bytes())
+            .finalize()
+            .into()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,6 +302,12 @@ pub struct PeerInfo {
     pub joined_at: chrono::DateTime<chrono::Utc>,
     pub is_host: bool,
     pub metadata: Option<serde_json::Value>,
+    /// Authenticated user, when `JWT_SECRET` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    /// Read-only peer: receives room events but may not broadcast or sync
+    #[serde(default)]
+    pub observer: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -51,12 +318,20 @@ pub enum RoomEvent {
     DataSync { from: String, data: String },
     DocumentUpdate { from: String, document: String },
     HostChanged { new_host_id: String },
+    RoomClosed,
+    /// The host removed this peer from the room
+    PeerKicked { peer_id: String },
+    PeerMetadataUpdated { peer_id: String, metadata: Option<serde_json::Value> },
+    /// `peer_id` wants the current document from the host
+    DocumentRequested { host_id: String, peer_id: String },
+    /// The host did not answer `peer_id`'s request in time
+    SyncRequestExpired { peer_id: String },
 }
 
 #[derive(Debug, Clone)]
 pub enum SystemEvent {
     RoomCreated { room_id: String },
-    RoomClosed { room_id: String },
+    RoomClosed { room_id: String, room_code: String },
     Shutdown,
 }
 
@@ -68,12 +343,43 @@ pub enum ClientMessage {
         peer_id: String,
         is_host: bool,
         metadata: Option<serde_json::Value>,
+        #[serde(default)]
+        password: Option<String>,
+        /// User token, required when `JWT_SECRET` is set
+        #[serde(default)]
+        token: Option<String>,
+        /// Join read-only, see `PeerInfo::observer`
+        #[serde(default)]
+        observer: bool,
     },
     Leave,
     Broadcast { data: String },
+    /// Replace this peer's `metadata` (presence: name, cursor, ...)
+    UpdateMetadata { metadata: Option<serde_json::Value> },
     SyncDocument { document: String },
-    RequestSync,
-    Ping,
+    /// Get the room's document. With `fresh`, the host is asked to sync
+    /// first, since the stored document may lack its unsynced changes.
+    RequestSync {
+        #[serde(default)]
+        fresh: bool,
+    },
+    /// Remove a peer from the room (host only)
+    Kick { peer_id: String },
+    /// `client_time` (any clock, e.g. `Date.now()`) is echoed in the pong
+    Ping {
+        #[serde(default)]
+        client_time: Option<i64>,
+    },
+}
+
+/// A client message plus its optional sequence number. Messages with a
+/// `seq` get an `ack` carrying it once the server has processed them.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientFrame {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    #[serde(flatten)]
+    pub message: ClientMessage,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -82,6 +388,7 @@ pub enum ServerMessage {
     Connected { peer_id: String, room_code: String },
     PeerJoined { peer: PeerInfo },
     PeerLeft { peer_id: String },
+    PeerMetadataUpdated { peer_id: String, metadata: Option<serde_json::Value> },
     Data { from: String, data: String },
     DocumentSync { document: String },
     Error { message: String },
@@ -90,7 +397,20 @@ pub enum ServerMessage {
         host_id: String,
         peers: Vec<PeerInfo>,
     },
-    Pong,
+    HostChanged { new_host_id: String },
+    /// Sent to the host: `peer_id` asked for a fresh document, answer
+    /// with `sync_document`
+    DocumentRequested { peer_id: String },
+    RoomClosed { room_code: String },
+    Kicked { room_code: String },
+    /// The message sent with this `seq` was processed
+    Ack { seq: u64 },
+    /// Reply to `ping`: its `client_time`, plus the server's clock in Unix
+    /// milliseconds for estimating round trip and clock offset
+    Pong {
+        client_time: Option<i64>,
+        server_time: i64,
+    },
 }
 
 #[derive(Clone, Copy)]
@@ -100,7 +420,8 @@ impl KeyExtractor for IpHeaderKeyExtractor {
     type Key = String;
 
     fn extract<B>(&self, req: &axum::http::Request<B>) -> Result<Self::Key, GovernorError> {
-        req.headers()
+        let key = req
+            .headers()
             .get("x-forwarded-for")
             .and_then(|h| h.to_str().ok())
             .and_then(|s| s.split(',').next())
@@ -110,9 +431,8 @@ impl KeyExtractor for IpHeaderKeyExtractor {
                     .get("x-real-ip")
                     .and_then(|h| h.to_str().ok())
                     .map(|s| s.to_string())
-            })
-            .ok_or(GovernorError::UnableToExtractKey)
-            .or_else(|_| Ok("unknown".to_string()))
+            });
+        Ok(key.unwrap_or_else(|| "unknown".to_string()))
     }
 }
 
@@ -122,6 +442,16 @@ impl KeyExtractor for IpHeaderKeyExtractor {
 pub struct CreateRoomRequest {
     pub desired_room_code: Option<String>,
     pub desired_host_id: Option<String>,
+    /// Optional password required to join the room
+    pub password: Option<String>,
+    /// How long the room is kept once empty, instead of the server's
+    /// `ROOM_IDLE_TIMEOUT_SECONDS` (0 = never expire)
+    pub idle_timeout_seconds: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct HostQuery {
+    pub host_id: Option<String>,
 }
 
 #[tokio::main]
@@ -145,17 +475,144 @@ async fn main() {
         );
     }
 
+    // Clients ping every 30s, so allow a few missed pings
+    let peer_timeout_seconds = std::env::var("PEER_TIMEOUT_SECONDS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(90);
+
+    if peer_timeout_seconds == 0 {
+        info!("💓 Peer timeout: disabled");
+    } else {
+        info!("💓 Peer timeout configured: {}s (default is 90s)", peer_timeout_seconds);
+    }
+
+    let persist_dir = std::env::var("PERSIST_DIR")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from);
+
+    if let Some(dir) = &persist_dir {
+        std::fs::create_dir_all(dir).expect("failed to create PERSIST_DIR");
+        info!("💾 Persisting rooms to {}", dir.display());
+    } else {
+        info!("💾 Room persistence: disabled (set PERSIST_DIR to enable)");
+    }
+
+    let admin_token = std::env::var("ADMIN_TOKEN").ok().filter(|token| !token.is_empty());
+    if admin_token.is_some() {
+        info!("🔑 Admin endpoints enabled");
+    }
+
+    let jwt_secret = std::env::var("JWT_SECRET").ok().filter(|secret| !secret.is_empty());
+    if jwt_secret.is_some() {
+        info!("🔑 User authentication enabled: rooms require a JWT");
+    }
+
+    let max_message_bytes = std::env::var("MAX_MESSAGE_BYTES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_MESSAGE_BYTES);
+
+    if max_message_bytes == 0 {
+        info!("📦 Message size limit: disabled");
+    } else {
+        info!("📦 Message size limit: {} bytes", max_message_bytes);
+    }
+
+    let rate_limit_per_second = std::env::var("RATE_LIMIT_PER_SECOND")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_RATE_LIMIT_PER_SECOND);
+
+    if rate_limit_per_second == 0 {
+        info!("🚦 Per-connection rate limit: disabled");
+    } else {
+        info!("🚦 Per-connection rate limit: {} messages/s", rate_limit_per_second);
+    }
+
+    let duplicate_peer_policy = match std::env::var("DUPLICATE_PEER_ID") {
+        Ok(value) => DuplicatePeerPolicy::parse(&value).unwrap_or_else(|| {
+            warn!("👥 Unknown DUPLICATE_PEER_ID {:?}, using reject", value);
+            DuplicatePeerPolicy::Reject
+        }),
+        Err(_) => DuplicatePeerPolicy::default(),
+    };
+    info!("👥 Duplicate peer ids: {:?}", duplicate_peer_policy);
+
+    let history_size = std::env::var("HISTORY_SIZE")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_HISTORY_SIZE);
+    info!("🔁 Broadcast history per room: {}", history_size);
+
+    let room_channel_capacity = std::env::var("ROOM_CHANNEL_CAPACITY")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|&capacity: &usize| capacity > 0)
+        .unwrap_or(DEFAULT_ROOM_CHANNEL_CAPACITY);
+    info!("📨 Room event buffer: {} events", room_channel_capacity);
+
+    let sync_request_timeout_ms = std::env::var("SYNC_REQUEST_TIMEOUT_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_SYNC_REQUEST_TIMEOUT_MS);
+    if sync_request_timeout_ms == 0 {
+        info!("📄 Fresh sync requests: disabled, stored document only");
+    } else {
+        info!("📄 Fresh sync requests wait {}ms for the host", sync_request_timeout_ms);
+    }
+
+    let public_ws_url = std::env::var("PUBLIC_WS_URL").ok().filter(|url| !url.is_empty());
+    if let Some(url) = &public_ws_url {
+        info!("🌐 Public WebSocket URL: {}", url);
+    }
+
     let (system_tx, _) = broadcast::channel(100);
-    let state = Arc::new(AppState {
-        rooms: DashMap::new(),
-        room_idle_timeout_seconds,
-        system_tx: system_tx.clone(),
-    });
+    let state = Arc::new(
+        AppState::new(room_idle_timeout_seconds, peer_timeout_seconds, system_tx.clone())
+            .with_persist_dir(persist_dir)
+            .with_admin_token(admin_token)
+            .with_jwt_secret(jwt_secret)
+            .with_duplicate_peer_policy(duplicate_peer_policy)
+            .with_public_ws_url(public_ws_url)
+            .with_max_message_bytes(max_message_bytes)
+            .with_rate_limit(rate_limit_per_second)
+            .with_history_size(history_size)
+            .with_room_channel_capacity(room_channel_capacity)
+            .with_sync_request_timeout_ms(sync_request_timeout_ms),
+    );
     
-    if room_idle_timeout_seconds > 0 {
-        spawn_room_cleanup_task(state.clone());
+    if state.persist_dir.is_some() {
+        let restored = load_persisted_rooms(&state);
+        info!("💾 Restored {} rooms from disk", restored);
+        spawn_persist_task(state.clone());
     }
 
+    // Runs even when the default is 0: rooms may set their own timeout
+    spawn_room_cleanup_task(state.clone());
+
+    let app = build_router(state.clone());
+
+    let port = std::env::var("PORT")
+        .ok()
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(3001);
+
+    let addr = format!("0.0.0.0:{}", port);
+    info!("📡 Server listening on http://{}", addr);
+    info!("🔗 WebSocket endpoint: ws://{}/ws", addr);
+
+    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal(system_tx))
+        .await
+        .unwrap();
+
+    persist_dirty_rooms(&state).await;
+}
+
+fn build_router(state: SharedState) -> Router {
     let governor_conf = Arc::new(
         tower_governor::governor::GovernorConfigBuilder::default()
             .key_extractor(IpHeaderKeyExtractor)
@@ -165,16 +622,19 @@ async fn main() {
             .unwrap(),
     );
 
-    let app = Router::new()
+    Router::new()
         .route("/", get(root_handler))
         .route("/health", get(health_check))
+        .route("/metrics", get(metrics_handler))
         .route(
             "/api/rooms", 
-            post(create_room).layer(tower_governor::GovernorLayer {
-                config: governor_conf,
-            }),
+            post(create_room)
+                .layer(tower_governor::GovernorLayer {
+                    config: governor_conf,
+                })
+                .get(list_rooms),
         )
-        .route("/api/rooms/:room_code", get(get_room_info))
+        .route("/api/rooms/:room_code", get(get_room_info).delete(delete_room))
         .route("/ws", get(ws_handler))
         .layer(
             tower_http::cors::CorsLayer::new()
@@ -182,22 +642,7 @@ async fn main() {
                 .allow_methods(tower_http::cors::Any)
                 .allow_headers(tower_http::cors::Any),
         )
-        .with_state(state);
-
-    let port = std::env::var("PORT")
-        .ok()
-        .and_then(|p| p.parse().ok())
-        .unwrap_or(3001);
-
-    let addr = format!("0.0.0.0:{}", port);
-    info!("📡 Server listening on http://{}", addr);
-    info!("🔗 WebSocket endpoint: ws://{}/ws", addr);
-
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal(system_tx))
-        .await
-        .unwrap();
+        .with_state(state)
 }
 
 async fn shutdown_signal(tx: broadcast::Sender<SystemEvent>) {
@@ -235,6 +680,8 @@ async fn root_handler() -> impl IntoResponse {
         "websocket": "/ws",
         "api": {
             "create_room": "POST /api/rooms",
+            "list_rooms": "GET /api/rooms (admin)",
+            "metrics": "GET /metrics",
             "room_info": "GET /api/rooms/:room_code"
         }
     }))
@@ -248,34 +695,95 @@ async fn health_check(State(state): State<SharedState>) -> impl IntoResponse {
     }))
 }
 
+async fn metrics_handler(State(state): State<SharedState>) -> impl IntoResponse {
+    let peers_total: usize = state.rooms.iter().map(|room| room.peers.len()).sum();
+    let metrics = &state.metrics;
+    let values = [
+        ("rooms_total", "gauge", "Rooms currently held by the server", state.rooms.len() as u64),
+        ("peers_total", "gauge", "Peers connected across all rooms", peers_total as u64),
+        (
+            "messages_received_total",
+            "counter",
+            "WebSocket messages received from clients",
+            metrics.messages_received_total.load(Ordering::Relaxed),
+        ),
+        (
+            "rooms_created_total",
+            "counter",
+            "Rooms created since startup",
+            metrics.rooms_created_total.load(Ordering::Relaxed),
+        ),
+        (
+            "rooms_cleaned_total",
+            "counter",
+            "Empty rooms removed after the idle timeout",
+            metrics.rooms_cleaned_total.load(Ordering::Relaxed),
+        ),
+    ];
+
+    let mut body = String::new();
+    for (name, kind, help, value) in values {
+        body.push_str(&format!("# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"));
+    }
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
 async fn create_room(
     State(state): State<SharedState>,
+    headers: HeaderMap,
     payload: Option<Json<CreateRoomRequest>>,
 ) -> impl IntoResponse {
-    let (requested_code, requested_host_id) = if let Some(Json(req)) = payload {
-        (req.desired_room_code, req.desired_host_id)
+    let owner = match state.authenticate(bearer_token(&headers)) {
+        Ok(owner) => owner,
+        Err(e) => {
+            warn!("🔒 Rejected room creation: {}", e);
+            return (
+                StatusCode::UNAUTHORIZED,
+                axum::Json(serde_json::json!({
+                    "success": false,
+                    "error": e
+                })),
+            );
+        }
+    };
+
+    let (requested_code, requested_host_id, password, idle_timeout_seconds) = if let Some(Json(req)) = payload {
+        (req.desired_room_code, req.desired_host_id, req.password, req.idle_timeout_seconds)
     } else {
-        (None, None)
+        (None, None, None, None)
     };
+    let password = password.filter(|p| !p.is_empty());
 
     let room_code = requested_code.unwrap_or_else(generate_room_code);
 
     // If room already exists, return it (idempotent/recovery)
     if let Some(room) = state.rooms.get(&room_code) {
-        return axum::Json(serde_json::json!({
-            "success": true,
-            "room_code": room_code,
-            "room_id": room.id,
-            "host_id": room.host_id,
-            "websocket_url": format!("ws://localhost:3001/ws"),
-            "restored": true
-        }));
+        if !room_password_matches(&room, password.as_deref()) {
+            return (
+                StatusCode::OK,
+                axum::Json(serde_json::json!({
+                    "success": false,
+                    "error": "Invalid room password"
+                })),
+            );
+        }
+        return (
+            StatusCode::OK,
+            axum::Json(serde_json::json!({
+                "success": true,
+                "room_code": room_code,
+                "room_id": room.id,
+                "host_id": room.host_id,
+                "websocket_url": websocket_url(&state, &headers),
+                "restored": true
+            })),
+        );
     }
 
     let room_id = Uuid::new_v4().to_string();
     let host_id = requested_host_id.unwrap_or_else(|| format!("host_{}", generate_random_id()));
 
-    let (tx, _) = broadcast::channel(256);
+    let (tx, _) = broadcast::channel(state.room_channel_capacity);
 
     let room = Room {
         id: room_id.clone(),
@@ -286,19 +794,112 @@ async fn create_room(
         document_state: None,
         last_sync: chrono::Utc::now(),
         empty_since: Some(chrono::Utc::now()), 
+        password: password.as_deref().map(PasswordHash::new),
+        idle_timeout_seconds,
+        owner,
+        history: VecDeque::new(),
+        sync_requests: HashMap::new(),
     };
 
     state.rooms.insert(room_code.clone(), room);
+    state.mark_dirty(&room_code);
+    state.metrics.rooms_created_total.fetch_add(1, Ordering::Relaxed);
 
     info!("🆕 Room created: {} (host: {})", room_code, host_id);
 
-    axum::Json(serde_json::json!({
-        "success": true,
-        "room_code": room_code,
-        "room_id": room_id,
-        "host_id": host_id,
-        "websocket_url": format!("ws://localhost:3001/ws"),
-    }))
+    (
+        StatusCode::OK,
+        axum::Json(serde_json::json!({
+            "success": true,
+            "room_code": room_code,
+            "room_id": room_id,
+            "host_id": host_id,
+            "websocket_url": websocket_url(&state, &headers),
+        })),
+    )
+}
+
+/// WebSocket URL handed to clients: `PUBLIC_WS_URL` if set, else built from
+/// the request's `Host` (`wss` behind a proxy reporting `X-Forwarded-Proto: https`)
+fn websocket_url(state: &AppState, headers: &HeaderMap) -> String {
+    if let Some(url) = &state.public_ws_url {
+        return url.clone();
+    }
+    let Some(host) = headers.get(header::HOST).and_then(|value| value.to_str().ok()) else {
+        return DEFAULT_WS_URL.to_string();
+    };
+    let secure = headers
+        .get("x-forwarded-proto")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|proto| proto.eq_ignore_ascii_case("https"));
+    format!("{}://{}/ws", if secure { "wss" } else { "ws" }, host)
+}
+
+/// Token from `Authorization: Bearer <token>`
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Whether the request carries `Authorization: Bearer <ADMIN_TOKEN>`
+fn is_admin(state: &AppState, headers: &HeaderMap) -> bool {
+    let Some(expected) = &state.admin_token else {
+        return false;
+    };
+    let Some(token) = bearer_token(headers) else {
+        return false;
+    };
+    // Compare every byte so timing doesn't reveal the matching prefix
+    token.len() == expected.len()
+        && token.bytes().zip(expected.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+async fn list_rooms(State(state): State<SharedState>, headers: HeaderMap) -> impl IntoResponse {
+    if state.admin_token.is_none() {
+        return (
+            StatusCode::NOT_FOUND,
+            axum::Json(serde_json::json!({
+                "success": false,
+                "error": "Room listing is disabled"
+            })),
+        );
+    }
+    if !is_admin(&state, &headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            axum::Json(serde_json::json!({
+                "success": false,
+                "error": "Unauthorized"
+            })),
+        );
+    }
+
+    let mut rooms: Vec<serde_json::Value> = state
+        .rooms
+        .iter()
+        .map(|entry| {
+            let room = entry.value();
+            serde_json::json!({
+                "room_code": entry.key(),
+                "host_id": room.host_id,
+                "owner": room.owner,
+                "peer_count": room.peers.len(),
+                "created_at": room.created_at,
+                "has_document": room.document_state.is_some(),
+            })
+        })
+        .collect();
+    rooms.sort_by(|a, b| a["room_code"].as_str().cmp(&b["room_code"].as_str()));
+
+    (
+        StatusCode::OK,
+        axum::Json(serde_json::json!({
+            "success": true,
+            "rooms": rooms
+        })),
+    )
 }
 
 async fn get_room_info(
@@ -320,6 +921,7 @@ async fn get_room_info(
                 "peers": peers,
                 "created_at": room.created_at,
                 "peer_count": peers.len(),
+                "password_protected": room.password.is_some(),
             }))
         }
         None => axum::Json(serde_json::json!({
@@ -329,6 +931,66 @@ async fn get_room_info(
     }
 }
 
+/// Close a room for everyone. Only the host may do this; the host id is
+/// taken from the `X-Host-Id` header or the `host_id` query parameter.
+/// The host id is public (`get_room_info` shows it), so a password
+/// protected room also needs `X-Room-Password`, and a room created with a
+/// JWT needs the owner's token.
+async fn delete_room(
+    Path(room_code): Path<String>,
+    State(state): State<SharedState>,
+    Query(query): Query<HostQuery>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let host_id = headers
+        .get("x-host-id")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .or(query.host_id);
+
+    let room_id = match state.rooms.get(&room_code) {
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                axum::Json(serde_json::json!({
+                    "success": false,
+                    "error": "Room not found"
+                })),
+            );
+        }
+        Some(room) => {
+            let password = headers.get("x-room-password").and_then(|value| value.to_str().ok());
+            if let Err((status, error)) = may_close_room(&state, &room, host_id.as_deref(), password, bearer_token(&headers)) {
+                warn!("🔒 Rejected closing room {}: {}", room_code, error);
+                return (
+                    status,
+                    axum::Json(serde_json::json!({
+                        "success": false,
+                        "error": error
+                    })),
+                );
+            }
+            room.id.clone()
+        }
+    };
+
+    if let Some((_, room)) = state.rooms.remove(&room_code) {
+        // Peers get this before their receiver sees the channel close
+        let _ = room.tx.send(RoomEvent::RoomClosed);
+        state.mark_dirty(&room_code);
+        let _ = state.system_tx.send(SystemEvent::RoomClosed { room_id, room_code: room_code.clone() });
+        info!("🚪 Room closed by host: {}", room_code);
+    }
+
+    (
+        StatusCode::OK,
+        axum::Json(serde_json::json!({
+            "success": true,
+            "room_code": room_code
+        })),
+    )
+}
+
 async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<SharedState>,
@@ -343,69 +1005,114 @@ async fn handle_socket(mut socket: WebSocket, state: SharedState) {
     
     let mut system_rx = state.system_tx.subscribe();
 
+    // Any message counts as a heartbeat; a half-open socket never errors,
+    // so a silent peer is dropped once it exceeds the peer timeout
+    let peer_timeout = StdDuration::from_secs(state.peer_timeout_seconds);
+    let mut last_seen = tokio::time::Instant::now();
+    // Replies and room events use the encoding of the client's last message
+    let mut encoding = Encoding::Text;
+    let mut rate_limiter = RateLimiter::new(state.rate_limit_per_second);
+
     info!("🔌 New WebSocket connection");
 
     loop {
         tokio::select! {
+            _ = tokio::time::sleep_until(last_seen + peer_timeout), if !peer_timeout.is_zero() => {
+                warn!("💀 No message from peer {:?} in {}s, evicting", current_peer_id, peer_timeout.as_secs());
+                let _ = socket.send(CloseReason::PeerTimeout.frame()).await;
+                break;
+            }
+
             msg = socket.recv() => {
+                if let Some(Ok(_)) = msg {
+                    last_seen = tokio::time::Instant::now();
+                }
                 match msg {
                     Some(Ok(msg)) => {
-                        match msg {
+                        let parsed = match msg {
                             Message::Text(text) => {
+                                encoding = Encoding::Text;
                                 if text.len() < 200 {
                                     info!("📨 Received: {}", text);
                                 } else {
                                     info!("📨 Received (len={}): {}...", text.len(), &text[0..50]);
                                 }
-                                
-                                match serde_json::from_str::<ClientMessage>(&text) {
-                                    Ok(client_msg) => {
-                                        match handle_client_message(
-                                            &mut socket,
-                                            &state,
-                                            &client_msg,
-                                            &mut current_room,
-                                            &mut current_peer_id,
-                                            &mut room_rx,
-                                        )
-                                        .await
-                                        {
-                                            Ok(should_close) => {
-                                                if should_close {
-                                                    break;
-                                                }
-                                            }
-                                            Err(e) => {
-                                                warn!("Error handling message: {}", e);
-                                                let error_msg = ServerMessage::Error {
-                                                    message: e.to_string(),
-                                                };
-                                                let _ = socket
-                                                    .send(Message::Text(
-                                                        serde_json::to_string(&error_msg).unwrap(),
-                                                    ))
-                                                    .await;
-                                            }
+                                state.check_message_size(text.len()).and_then(|()| {
+                                    serde_json::from_str::<ClientFrame>(&text)
+                                        .map_err(|e| format!("Invalid message format: {}", e))
+                                })
+                            }
+                            Message::Binary(data) => {
+                                encoding = Encoding::Binary;
+                                info!("📨 Received binary frame (len={})", data.len());
+                                state
+                                    .check_message_size(data.len())
+                                    .and_then(|()| decode_frame(&data).map_err(|e| format!("Invalid frame: {}", e)))
+                                    .and_then(|json| {
+                                        state.check_message_size(json.len())?;
+                                        serde_json::from_slice::<ClientFrame>(&json)
+                                            .map_err(|e| format!("Invalid message format: {}", e))
+                                    })
+                            }
+                            Message::Close(_) => {
+                                info!("🔌 Client closed connection");
+                                break;
+                            }
+                            _ => continue,
+                        };
+                        state.metrics.messages_received_total.fetch_add(1, Ordering::Relaxed);
+
+                        if let Err(violations) = rate_limiter.acquire() {
+                            if violations == 1 {
+                                warn!("🚦 Peer {:?} is over the rate limit, dropping messages", current_peer_id);
+                                let error_msg = ServerMessage::Error {
+                                    message: format!("Rate limit exceeded: max {} messages per second", state.rate_limit_per_second),
+                                };
+                                let _ = send_message(&mut socket, &error_msg, encoding).await;
+                            }
+                            if violations >= MAX_RATE_LIMIT_VIOLATIONS {
+                                warn!("🚦 Peer {:?} kept flooding, disconnecting", current_peer_id);
+                                let _ = socket.send(CloseReason::RateLimited.frame()).await;
+                                break;
+                            }
+                            continue;
+                        }
+
+                        match parsed {
+                            Ok(frame) => {
+                                match handle_client_message(
+                                    &mut socket,
+                                    &state,
+                                    &frame.message,
+                                    encoding,
+                                    &mut current_room,
+                                    &mut current_peer_id,
+                                    &mut room_rx,
+                                )
+                                .await
+                                {
+                                    Ok(should_close) => {
+                                        if let Some(seq) = frame.seq {
+                                            let _ = send_message(&mut socket, &ServerMessage::Ack { seq }, encoding).await;
+                                        }
+                                        if should_close {
+                                            break;
                                         }
                                     }
                                     Err(e) => {
-                                        warn!("❌ Invalid message format: {}", e);
+                                        warn!("Error handling message: {}", e);
                                         let error_msg = ServerMessage::Error {
-                                            message: format!("Invalid message format: {}", e),
+                                            message: e.to_string(),
                                         };
-                                        let _ = socket
-                                            .send(Message::Text(
-                                                serde_json::to_string(&error_msg).unwrap(),
-                                            ))
-                                            .await;
+                                        let _ = send_message(&mut socket, &error_msg, encoding).await;
                                     }
                                 }
                             }
-                            Message::Close(_) => {
-                                info!("🔌 Client closed connection");
-                                break;
+                            Err(e) => {
+                                warn!("❌ Rejected message: {}", e);
+                                let error_msg = ServerMessage::Error { message: e };
+                                let _ = send_message(&mut socket, &error_msg, encoding).await;
                             }
-                            _ => {}
                         }
                     }
                     Some(Err(e)) => {
@@ -426,9 +1133,46 @@ async fn handle_socket(mut socket: WebSocket, state: SharedState) {
                     futures::future::pending().await
                 }
             } => {
-                if let Ok(event) = event {
-                    if let Err(e) = forward_room_event(&mut socket, event, current_peer_id.as_ref()).await {
-                        warn!("Failed to forward room event: {}", e);
+                match event {
+                    Ok(RoomEvent::RoomClosed) => {
+                        let room_code = current_room.take().unwrap_or_default();
+                        info!("🚪 Room {} closed, disconnecting peer: {:?}", room_code, current_peer_id);
+                        let _ = send_message(&mut socket, &ServerMessage::RoomClosed { room_code }, encoding).await;
+                        let _ = socket.send(CloseReason::RoomClosed.frame()).await;
+                        break;
+                    }
+                    Ok(RoomEvent::PeerKicked { peer_id }) if Some(&peer_id) == current_peer_id.as_ref() => {
+                        // The host already removed us from the room
+                        let room_code = current_room.take().unwrap_or_default();
+                        info!("🥾 Peer {} kicked from room {}, disconnecting", peer_id, room_code);
+                        let _ = send_message(&mut socket, &ServerMessage::Kicked { room_code }, encoding).await;
+                        let _ = socket.send(CloseReason::Kicked.frame()).await;
+                        break;
+                    }
+                    Ok(RoomEvent::SyncRequestExpired { peer_id }) if Some(&peer_id) == current_peer_id.as_ref() => {
+                        let stored = current_room.as_ref().and_then(|code| state.rooms.get(code)).map(|room| stored_document(&room));
+                        if let Some(sync) = stored {
+                            info!("📄 Host did not answer {} in time, sent the stored document", peer_id);
+                            let _ = send_message(&mut socket, &sync, encoding).await;
+                        }
+                    }
+                    Ok(event) => {
+                        if let Err(e) = forward_room_event(&mut socket, event, current_peer_id.as_ref(), encoding).await {
+                            warn!("Failed to forward room event: {}", e);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Closed) => room_rx = None,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        // The missed events are gone; the stored document
+                        // and broadcast history cover them
+                        warn!("🐢 Peer {:?} missed {} room events, resyncing", current_peer_id, missed);
+                        let resync = current_room.as_ref().and_then(|code| state.rooms.get(code)).map(|room| resync_messages(&room));
+                        for msg in resync.unwrap_or_default() {
+                            if let Err(e) = send_message(&mut socket, &msg, encoding).await {
+                                warn!("Failed to resync peer: {}", e);
+                                break;
+                            }
+                        }
                     }
                 }
             }
@@ -437,7 +1181,15 @@ async fn handle_socket(mut socket: WebSocket, state: SharedState) {
                 match sys_msg {
                     Ok(SystemEvent::Shutdown) => {
                         info!("🛑 Server shutting down, closing connection for peer: {:?}", current_peer_id);
-                        let _ = socket.send(Message::Close(None)).await;
+                        let _ = socket.send(CloseReason::Shutdown.frame()).await;
+                        break;
+                    }
+                    Ok(SystemEvent::RoomClosed { room_code, .. }) if current_room.as_ref() == Some(&room_code) => {
+                        // Covers peers whose room receiver missed the close
+                        current_room = None;
+                        info!("🚪 Room {} closed, disconnecting peer: {:?}", room_code, current_peer_id);
+                        let _ = send_message(&mut socket, &ServerMessage::RoomClosed { room_code }, encoding).await;
+                        let _ = socket.send(CloseReason::RoomClosed.frame()).await;
                         break;
                     }
                     _ => {}
@@ -451,11 +1203,56 @@ async fn handle_socket(mut socket: WebSocket, state: SharedState) {
     }
 }
 
-async fn forward_room_event(
-    socket: &mut WebSocket,
-    event: RoomEvent,
-    current_peer_id: Option<&String>,
-) -> Result<(), String> {
+/// Token bucket for one connection: holds up to `rate` tokens, refilled at
+/// `rate` per second, one taken per message
+struct RateLimiter {
+    rate: f64,
+    tokens: f64,
+    last_refill: tokio::time::Instant,
+    /// Messages dropped since the bucket was last full
+    violations: u32,
+}
+
+impl RateLimiter {
+    fn new(per_second: u32) -> Self {
+        Self {
+            rate: per_second as f64,
+            tokens: per_second as f64,
+            last_refill: tokio::time::Instant::now(),
+            violations: 0,
+        }
+    }
+
+    /// Take a token for one message, or return how many messages have been
+    /// dropped in a row (counting this one). A rate of 0 never limits.
+    fn acquire(&mut self) -> Result<(), u32> {
+        if self.rate == 0.0 {
+            return Ok(());
+        }
+        let now = tokio::time::Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        if self.tokens >= self.rate {
+            // Slowed down long enough to refill completely
+            self.violations = 0;
+        }
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            self.violations += 1;
+            Err(self.violations)
+        }
+    }
+}
+
+async fn forward_room_event(
+    socket: &mut WebSocket,
+    event: RoomEvent,
+    current_peer_id: Option<&String>,
+    encoding: Encoding,
+) -> Result<(), String> {
     let server_msg = match event {
         RoomEvent::PeerJoined { peer } => {
             Some(ServerMessage::PeerJoined { peer })
@@ -479,23 +1276,138 @@ async fn forward_room_event(
             }
         }
         RoomEvent::HostChanged { new_host_id } => {
-            info!("👑 Host changed to: {}", new_host_id);
-            None 
+            Some(ServerMessage::HostChanged { new_host_id })
         }
+        RoomEvent::PeerMetadataUpdated { peer_id, metadata } => {
+            if Some(&peer_id) == current_peer_id {
+                None
+            } else {
+                Some(ServerMessage::PeerMetadataUpdated { peer_id, metadata })
+            }
+        }
+        RoomEvent::DocumentRequested { host_id, peer_id } => {
+            if Some(&host_id) == current_peer_id {
+                Some(ServerMessage::DocumentRequested { peer_id })
+            } else {
+                None
+            }
+        }
+        // Handled in `handle_socket`, which also disconnects the peer;
+        // the rest of the room gets `PeerLeft`
+        RoomEvent::RoomClosed | RoomEvent::PeerKicked { .. } => None,
+        // Handled in `handle_socket` by the peer that asked
+        RoomEvent::SyncRequestExpired { .. } => None,
     };
 
     if let Some(msg) = server_msg {
-        let json = serde_json::to_string(&msg).map_err(|e| e.to_string())?;
-        socket.send(Message::Text(json)).await.map_err(|e| e.to_string())?;
+        send_message(socket, &msg, encoding).await?;
     }
 
     Ok(())
 }
 
+/// Why the server closed a connection, sent as the close frame's code
+/// and reason so clients can tell a shutdown from a kick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CloseReason {
+    Shutdown,
+    RateLimited,
+    RoomClosed,
+    Kicked,
+    PeerTimeout,
+}
+
+impl CloseReason {
+    fn code(self) -> u16 {
+        match self {
+            CloseReason::Shutdown => 1001,
+            CloseReason::RateLimited => 1008,
+            CloseReason::RoomClosed => 4000,
+            CloseReason::Kicked => 4001,
+            CloseReason::PeerTimeout => 4002,
+        }
+    }
+
+    fn reason(self) -> &'static str {
+        match self {
+            CloseReason::Shutdown => "Server shutting down",
+            CloseReason::RateLimited => "Rate limit exceeded",
+            CloseReason::RoomClosed => "Room closed",
+            CloseReason::Kicked => "Kicked by host",
+            CloseReason::PeerTimeout => "Peer timed out",
+        }
+    }
+
+    fn frame(self) -> Message {
+        Message::Close(Some(CloseFrame { code: self.code(), reason: self.reason().into() }))
+    }
+}
+
+/// How a client talks to the server: JSON text frames, or binary frames
+/// holding LZ4-compressed JSON
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Text,
+    Binary,
+}
+
+/// Binary frames use the `wasm-compress` format: `KP`, format version 1,
+/// a CRC32 of the JSON, then the size-prepended LZ4 block
+const FRAME_MAGIC: [u8; 3] = [b'K', b'P', 1];
+
+/// Largest JSON a binary frame may expand to, so a tiny frame can't make
+/// the server allocate gigabytes
+const MAX_FRAME_DECOMPRESSED_SIZE: usize = 16 * 1024 * 1024;
+
+fn encode_frame(json: &[u8]) -> Vec<u8> {
+    let block = lz4_flex::compress_prepend_size(json);
+    let mut frame = Vec::with_capacity(FRAME_MAGIC.len() + 4 + block.len());
+    frame.extend_from_slice(&FRAME_MAGIC);
+    frame.extend_from_slice(&crc32fast::hash(json).to_le_bytes());
+    frame.extend_from_slice(&block);
+    frame
+}
+
+/// Decompress a binary frame. Frames without the header are plain
+/// size-prepended LZ4, as written by older `wasm-compress` versions.
+fn decode_frame(frame: &[u8]) -> Result<Vec<u8>, String> {
+    let (checksum, block) = match frame.strip_prefix(&FRAME_MAGIC[..]) {
+        Some(rest) => {
+            let (checksum, block) = rest.split_first_chunk::<4>().ok_or("Truncated frame header")?;
+            (Some(u32::from_le_bytes(*checksum)), block)
+        }
+        None => (None, frame),
+    };
+
+    let size = block
+        .first_chunk::<4>()
+        .map(|size| u32::from_le_bytes(*size) as usize)
+        .ok_or("Truncated frame")?;
+    if size > MAX_FRAME_DECOMPRESSED_SIZE {
+        return Err(format!("Frame too large: {} bytes", size));
+    }
+
+    let json = lz4_flex::decompress_size_prepended(block).map_err(|e| format!("Decompression error: {}", e))?;
+    if checksum.is_some_and(|checksum| checksum != crc32fast::hash(&json)) {
+        return Err("Checksum mismatch".to_string());
+    }
+    Ok(json)
+}
+
+async fn send_message(socket: &mut WebSocket, msg: &ServerMessage, encoding: Encoding) -> Result<(), String> {
+    let json = serde_json::to_string(msg).map_err(|e| e.to_string())?;
+    let frame = match encoding {
+        Encoding::Text => Message::Text(json),
+        Encoding::Binary => Message::Binary(encode_frame(json.as_bytes())),
+    };
+    socket.send(frame).await.map_err(|e| e.to_string())
+}
+
 async fn handle_client_message(
     socket: &mut WebSocket,
     state: &SharedState,
     msg: &ClientMessage,
+    encoding: Encoding,
     current_room: &mut Option<String>,
     current_peer_id: &mut Option<String>,
     room_rx: &mut Option<broadcast::Receiver<RoomEvent>>,
@@ -506,8 +1418,42 @@ async fn handle_client_message(
             peer_id,
             is_host,
             metadata,
+            password,
+            token,
+            observer,
         } => {
+            let user_id = state.authenticate(token.as_deref()).map_err(|e| {
+                warn!("🔒 Rejected join to room {} by {}: {}", room_code, peer_id, e);
+                e
+            })?;
+
             if let Some(mut room) = state.rooms.get_mut(room_code) {
+                if !room_password_matches(&room, password.as_deref()) {
+                    warn!("🔒 Rejected join to room {} by {}: wrong password", room_code, peer_id);
+                    return Err("Invalid room password".to_string());
+                }
+
+                // Re-sending join on the same connection isn't a collision
+                let rejoin = current_room.as_ref() == Some(room_code) && current_peer_id.as_ref() == Some(peer_id);
+                let peer_id = &if room.peers.contains_key(peer_id) && !rejoin {
+                    match state.duplicate_peer_policy {
+                        DuplicatePeerPolicy::Reject => {
+                            warn!("👥 Rejected join to room {}: peer id {} is taken", room_code, peer_id);
+                            return Err(format!("Peer id already in room: {}", peer_id));
+                        }
+                        DuplicatePeerPolicy::Rename => {
+                            let renamed = (2..)
+                                .map(|n| format!("{}-{}", peer_id, n))
+                                .find(|candidate| !room.peers.contains_key(candidate))
+                                .unwrap_or_default();
+                            info!("👥 Peer id {} is taken in room {}, joining as {}", peer_id, room_code, renamed);
+                            renamed
+                        }
+                    }
+                } else {
+                    peer_id.clone()
+                };
+
                 if room.empty_since.is_some() {
                     room.empty_since = None;
                     info!("🔄 Room revived: {}", room_code);
@@ -520,6 +1466,8 @@ async fn handle_client_message(
                     joined_at: chrono::Utc::now(),
                     is_host: *is_host,
                     metadata: metadata.clone(),
+                    user_id,
+                    observer: *observer,
                 };
 
                 room.peers.insert(peer_id.clone(), peer_info.clone());
@@ -538,42 +1486,42 @@ async fn handle_client_message(
                     host_id: room.host_id.clone(),
                     peers,
                 };
-                socket
-                    .send(Message::Text(
-                        serde_json::to_string(&response).unwrap(),
-                    ))
-                    .await
-                    .map_err(|e| e.to_string())?;
+                send_message(socket, &response, encoding).await?;
 
                 let connected = ServerMessage::Connected {
                     peer_id: peer_id.clone(),
                     room_code: room_code.clone(),
                 };
-                socket
-                    .send(Message::Text(
-                        serde_json::to_string(&connected).unwrap(),
-                    ))
-                    .await
-                    .map_err(|e| e.to_string())?;
+                send_message(socket, &connected, encoding).await?;
 
                 *current_room = Some(room_code.clone());
                 *current_peer_id = Some(peer_id.clone());
 
                 info!(
-                    "👤 Peer joined: {} in room {} (host: {})",
+                    "👤 Peer joined: {} in room {} (host: {}, observer: {})",
                     current_peer_id.as_ref().unwrap(),
                     current_room.as_ref().unwrap(),
-                    is_host
+                    is_host,
+                    observer
                 );
 
                 if let Some(doc) = &room.document_state {
                     let sync = ServerMessage::DocumentSync {
                         document: doc.clone(),
                     };
-                    socket
-                        .send(Message::Text(serde_json::to_string(&sync).unwrap()))
-                        .await
-                        .map_err(|e| e.to_string())?;
+                    send_message(socket, &sync, encoding).await?;
+                }
+
+                // Deltas broadcast since the document was last synced
+                for (from, data) in &room.history {
+                    let replay = ServerMessage::Data {
+                        from: from.clone(),
+                        data: data.clone(),
+                    };
+                    send_message(socket, &replay, encoding).await?;
+                }
+                if !room.history.is_empty() {
+                    info!("🔁 Replayed {} broadcasts to {} in room {}", room.history.len(), peer_id, room_code);
                 }
 
                 Ok(false)
@@ -593,7 +1541,16 @@ async fn handle_client_message(
 
         ClientMessage::Broadcast { data } => {
             if let (Some(room_code), Some(peer_id)) = (current_room.as_ref(), current_peer_id.as_ref()) {
-                if let Some(room) = state.rooms.get(room_code) {
+                if let Some(mut room) = state.rooms.get_mut(room_code) {
+                    if is_observer(&room, peer_id) {
+                        return Err("Observers cannot broadcast".to_string());
+                    }
+                    if state.history_size > 0 {
+                        room.history.push_back((peer_id.clone(), data.clone()));
+                        while room.history.len() > state.history_size {
+                            room.history.pop_front();
+                        }
+                    }
                     let event = RoomEvent::DataSync {
                         from: peer_id.clone(),
                         data: data.clone(),
@@ -604,15 +1561,42 @@ async fn handle_client_message(
             Ok(false)
         }
 
+        ClientMessage::UpdateMetadata { metadata } => {
+            if let (Some(room_code), Some(peer_id)) = (current_room.as_ref(), current_peer_id.as_ref()) {
+                if let Some(room) = state.rooms.get(room_code) {
+                    if let Some(mut peer) = room.peers.get_mut(peer_id) {
+                        peer.metadata = metadata.clone();
+                    }
+                    let event = RoomEvent::PeerMetadataUpdated {
+                        peer_id: peer_id.clone(),
+                        metadata: metadata.clone(),
+                    };
+                    let _ = room.tx.send(event);
+                }
+            }
+            Ok(false)
+        }
+
         ClientMessage::SyncDocument { document } => {
             if let (Some(room_code), Some(peer_id)) = (current_room.as_ref(), current_peer_id.as_ref()) {
+                state.check_message_size(document.len())?;
                 if let Some(mut room) = state.rooms.get_mut(room_code) {
-                    room.document_state = Some(document.clone());
+                    if is_observer(&room, peer_id) {
+                        return Err("Observers cannot sync the document".to_string());
+                    }
+                    let merged = merge_document(room.document_state.as_deref(), document);
+                    state.check_message_size(merged.len())?;
+                    room.document_state = Some(merged.clone());
+                    // The syncing peer had applied every broadcast so far
+                    room.history.clear();
+                    // Everyone else gets the update, answering their requests
+                    room.sync_requests.retain(|requester, _| requester == peer_id);
                     room.last_sync = chrono::Utc::now();
+                    state.mark_dirty(room_code);
 
                     let event = RoomEvent::DocumentUpdate {
                         from: peer_id.clone(),
-                        document: document.clone(),
+                        document: merged,
                     };
                     let _ = room.tx.send(event);
 
@@ -622,44 +1606,156 @@ async fn handle_client_message(
             Ok(false)
         }
 
-        ClientMessage::RequestSync => {
-            if let Some(room_code) = current_room.as_ref() {
-                if let Some(room) = state.rooms.get(room_code) {
-                    if let Some(doc) = &room.document_state {
-                        let sync = ServerMessage::DocumentSync {
-                            document: doc.clone(),
-                        };
-                        socket
-                            .send(Message::Text(serde_json::to_string(&sync).unwrap()))
-                            .await
-                            .map_err(|e| e.to_string())?;
-                        info!("📄 Sent document to peer upon request in room {}", room_code);
-                    } else {
-                        let sync = ServerMessage::DocumentSync {
-                            document: String::new(),
+        ClientMessage::RequestSync { fresh } => {
+            if let (Some(room_code), Some(peer_id)) = (current_room.as_ref(), current_peer_id.as_ref()) {
+                let sync = {
+                    let Some(mut room) = state.rooms.get_mut(room_code) else {
+                        return Ok(false);
+                    };
+                    let host_online = room.host_id != *peer_id && room.peers.contains_key(&room.host_id);
+                    if *fresh && host_online && state.sync_request_timeout_ms > 0 {
+                        // The host's next sync_document reaches us as document_sync
+                        let requested_at = tokio::time::Instant::now();
+                        room.sync_requests.insert(peer_id.clone(), requested_at);
+                        let event = RoomEvent::DocumentRequested {
+                            host_id: room.host_id.clone(),
+                            peer_id: peer_id.clone(),
                         };
-                        socket
-                            .send(Message::Text(serde_json::to_string(&sync).unwrap()))
-                            .await
-                            .map_err(|e| e.to_string())?;
-                        info!("📄 Sent empty document (no data yet) in room {}", room_code);
+                        let _ = room.tx.send(event);
+                        spawn_sync_request_timeout(state.clone(), room_code.clone(), peer_id.clone(), requested_at);
+                        info!("📄 Asked the host of room {} for a fresh document for {}", room_code, peer_id);
+                        return Ok(false);
                     }
+                    stored_document(&room)
+                };
+                send_message(socket, &sync, encoding).await?;
+                info!("📄 Sent document to peer upon request in room {}", room_code);
+            }
+            Ok(false)
+        }
+
+        ClientMessage::Kick { peer_id } => {
+            let (Some(room_code), Some(sender)) = (current_room.as_ref(), current_peer_id.as_ref()) else {
+                return Err("Not in a room".to_string());
+            };
+            {
+                let Some(room) = state.rooms.get(room_code) else {
+                    return Err("Room not found".to_string());
+                };
+                if room.host_id != *sender {
+                    return Err("Only the host can kick peers".to_string());
                 }
+                if peer_id == sender {
+                    return Err("The host cannot kick itself".to_string());
+                }
+                if !room.peers.contains_key(peer_id) {
+                    return Err("Peer not found".to_string());
+                }
+                let _ = room.tx.send(RoomEvent::PeerKicked { peer_id: peer_id.clone() });
             }
+            info!("🥾 Host {} kicked {} from room {}", sender, peer_id, room_code);
+            leave_room(state, room_code, peer_id).await;
             Ok(false)
         }
 
-        ClientMessage::Ping => {
-            let pong = ServerMessage::Pong;
-            socket
-                .send(Message::Text(serde_json::to_string(&pong).unwrap()))
-                .await
-                .map_err(|e| e.to_string())?;
+        ClientMessage::Ping { client_time } => {
+            let pong = ServerMessage::Pong { client_time: *client_time, server_time: chrono::Utc::now().timestamp_millis() };
+            send_message(socket, &pong, encoding).await?;
             Ok(false)
         }
     }
 }
 
+/// The room's stored document as `document_sync`, empty if nothing was
+/// synced yet
+fn stored_document(room: &Room) -> ServerMessage {
+    ServerMessage::DocumentSync {
+        document: room.document_state.clone().unwrap_or_default(),
+    }
+}
+
+/// Everything a peer needs to catch up on the room: the stored document,
+/// then the broadcasts made since it was synced
+fn resync_messages(room: &Room) -> Vec<ServerMessage> {
+    let replay = room.history.iter().map(|(from, data)| ServerMessage::Data {
+        from: from.clone(),
+        data: data.clone(),
+    });
+    std::iter::once(stored_document(room)).chain(replay).collect()
+}
+
+/// Fall back to the stored document if the host has not synced within
+/// `sync_request_timeout_ms` of `requested_at`
+fn spawn_sync_request_timeout(state: SharedState, room_code: String, peer_id: String, requested_at: tokio::time::Instant) {
+    let timeout = StdDuration::from_millis(state.sync_request_timeout_ms);
+    tokio::spawn(async move {
+        tokio::time::sleep_until(requested_at + timeout).await;
+        let Some(mut room) = state.rooms.get_mut(&room_code) else {
+            return;
+        };
+        // Answered, or superseded by a newer request
+        if room.sync_requests.get(&peer_id) != Some(&requested_at) {
+            return;
+        }
+        room.sync_requests.remove(&peer_id);
+        let _ = room.tx.send(RoomEvent::SyncRequestExpired { peer_id });
+    });
+}
+
+/// Merge a synced document into the room's stored one, so peers that
+/// edited offline and sync separately all keep their changes. Documents
+/// that aren't wasm-crdt exports (e.g. the CSV export older clients send)
+/// replace the stored one unchanged; a stored document that no longer
+/// parses is dropped.
+fn merge_document(stored: Option<&str>, incoming: &str) -> String {
+    let mut document = CrdtDocument::new(SERVER_NODE_ID.to_string());
+    if let Err(e) = document.merge_state(incoming) {
+        warn!("Storing a document that can't be merged as is: {}", e);
+        return incoming.to_string();
+    }
+    if let Some(stored) = stored {
+        if let Err(e) = document.merge_state(stored) {
+            warn!("Dropping stored document that failed to merge: {}", e);
+        }
+    }
+    document.export()
+}
+
+fn is_observer(room: &Room, peer_id: &str) -> bool {
+    room.peers.get(peer_id).is_some_and(|peer| peer.observer)
+}
+
+/// Whether a `delete_room` caller holds every credential the room has
+fn may_close_room(
+    state: &AppState,
+    room: &Room,
+    host_id: Option<&str>,
+    password: Option<&str>,
+    token: Option<&str>,
+) -> Result<(), (StatusCode, String)> {
+    if host_id != Some(room.host_id.as_str()) {
+        return Err((StatusCode::FORBIDDEN, "Only the host can close the room".to_string()));
+    }
+    if !room_password_matches(room, password) {
+        return Err((StatusCode::FORBIDDEN, "Invalid room password".to_string()));
+    }
+    if let (Some(owner), Some(_)) = (&room.owner, &state.jwt_secret) {
+        let user = state.authenticate(token).map_err(|e| (StatusCode::UNAUTHORIZED, e))?;
+        if user.as_deref() != Some(owner.as_str()) {
+            return Err((StatusCode::FORBIDDEN, "Only the room owner can close the room".to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Rooms without a password accept anyone
+fn room_password_matches(room: &Room, password: Option<&str>) -> bool {
+    match &room.password {
+        Some(hash) => password.is_some_and(|p| hash.verify(p)),
+        None => true,
+    }
+}
+
 async fn leave_room(state: &SharedState, room_code: &str, peer_id: &str) {
     if let Some(mut room) = state.rooms.get_mut(room_code) {
         room.peers.remove(peer_id);
@@ -671,14 +1767,35 @@ async fn leave_room(state: &SharedState, room_code: &str, peer_id: &str) {
 
         info!("👤 Peer left: {} from room {}", peer_id, room_code);
 
+        if room.host_id == peer_id {
+            // Promote the longest-connected remaining peer. Observers can't
+            // sync the document, so they are never promoted
+            let next_host = room
+                .peers
+                .iter()
+                .filter(|entry| !entry.observer)
+                .min_by(|a, b| (a.joined_at, &a.id).cmp(&(b.joined_at, &b.id)))
+                .map(|entry| entry.key().clone());
+            if let Some(new_host_id) = next_host {
+                if let Some(mut peer) = room.peers.get_mut(&new_host_id) {
+                    peer.is_host = true;
+                }
+                room.host_id = new_host_id.clone();
+                state.mark_dirty(room_code);
+                info!("👑 Host of room {} changed to: {}", room_code, new_host_id);
+                let _ = room.tx.send(RoomEvent::HostChanged { new_host_id });
+            }
+        }
+
         if room.peers.is_empty() {
             room.empty_since = Some(chrono::Utc::now());
-            if state.room_idle_timeout_seconds == 0 {
+            let timeout_seconds = room.idle_timeout_seconds.unwrap_or(state.room_idle_timeout_seconds);
+            if timeout_seconds == 0 {
                 info!("🕒 Room {} is empty; keeping indefinitely", room_code);
             } else {
                 info!(
                     "🕒 Room {} is empty; keeping for {}s before cleanup",
-                    room_code, state.room_idle_timeout_seconds
+                    room_code, timeout_seconds
                 );
             }
         }
@@ -691,32 +1808,173 @@ fn spawn_room_cleanup_task(state: SharedState) {
 
         loop {
             interval.tick().await;
+            remove_idle_rooms(&state);
+        }
+    });
+}
 
-            let now = chrono::Utc::now();
-            let timeout_seconds = state.room_idle_timeout_seconds as i64;
+/// Remove rooms that have been empty for longer than their idle timeout
+/// (the room's own, else `room_idle_timeout_seconds`; 0 keeps them)
+fn remove_idle_rooms(state: &AppState) {
+    let now = chrono::Utc::now();
+
+    let stale_rooms: Vec<String> = state
+        .rooms
+        .iter()
+        .filter_map(|entry| {
+            let room = entry.value();
+            let empty_since = room.empty_since.as_ref()?;
+            let timeout_seconds = room.idle_timeout_seconds.unwrap_or(state.room_idle_timeout_seconds);
+            if timeout_seconds == 0 {
+                return None;
+            }
+            let idle_seconds = now.signed_duration_since(*empty_since).num_seconds();
+            if idle_seconds >= timeout_seconds as i64 {
+                Some(entry.key().clone())
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    for room_code in stale_rooms {
+        if let Some((_, room)) = state.rooms.remove(&room_code) {
+            // A peer may have subscribed between the idle check and removal
+            let _ = room.tx.send(RoomEvent::RoomClosed);
+            state.mark_dirty(&room_code);
+            let _ = state.system_tx.send(SystemEvent::RoomClosed { room_id: room.id.clone(), room_code: room_code.clone() });
+            state.metrics.rooms_cleaned_total.fetch_add(1, Ordering::Relaxed);
+            info!("🗑️ Room removed after idle timeout: {}", room_code);
+        }
+    }
+}
 
-            let stale_rooms: Vec<String> = state
-                .rooms
-                .iter()
-                .filter_map(|entry| {
-                    let room = entry.value();
-                    let empty_since = room.empty_since.as_ref()?;
-                    let idle_seconds = now.signed_duration_since(empty_since.clone()).num_seconds();
-                    if idle_seconds >= timeout_seconds {
-                        Some(entry.key().clone())
-                    } else {
-                        None
-                    }
-                })
-                .collect();
+/// How often changed rooms are written to `PERSIST_DIR`; document syncs
+/// in between are coalesced into one write
+const PERSIST_INTERVAL_SECONDS: u64 = 5;
+
+fn spawn_persist_task(state: SharedState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(StdDuration::from_secs(PERSIST_INTERVAL_SECONDS));
+
+        loop {
+            interval.tick().await;
+            persist_dirty_rooms(&state).await;
+        }
+    });
+}
 
-            for room_code in stale_rooms {
-                if state.rooms.remove(&room_code).is_some() {
-                    info!("🗑️ Room removed after idle timeout: {}", room_code);
+/// File a room is saved in. Room codes can be chosen by clients, so codes
+/// that aren't plain file names are never persisted.
+fn room_file(dir: &FsPath, room_code: &str) -> Option<PathBuf> {
+    let safe = !room_code.is_empty()
+        && room_code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    safe.then(|| dir.join(format!("{}.json", room_code)))
+}
+
+/// Write every dirty room to disk, removing the files of deleted rooms
+async fn persist_dirty_rooms(state: &AppState) {
+    let Some(dir) = &state.persist_dir else {
+        return;
+    };
+
+    let room_codes: Vec<String> = state.dirty_rooms.iter().map(|code| code.clone()).collect();
+    for room_code in room_codes {
+        state.dirty_rooms.remove(&room_code);
+        let Some(path) = room_file(dir, &room_code) else {
+            warn!("💾 Not persisting room with unsafe code: {:?}", room_code);
+            continue;
+        };
+
+        let saved = state.rooms.get(&room_code).map(|room| {
+            serde_json::to_vec(&PersistedRoom {
+                id: room.id.clone(),
+                host_id: room.host_id.clone(),
+                created_at: room.created_at,
+                document_state: room.document_state.clone(),
+                last_sync: room.last_sync,
+                password: room.password.clone(),
+                idle_timeout_seconds: room.idle_timeout_seconds,
+                owner: room.owner.clone(),
+            })
+        });
+
+        let result = match saved {
+            Some(Ok(json)) => {
+                // Write then rename, so a crash never leaves a torn file
+                let tmp = path.with_extension("json.tmp");
+                match tokio::fs::write(&tmp, json).await {
+                    Ok(()) => tokio::fs::rename(&tmp, &path).await,
+                    Err(e) => Err(e),
                 }
             }
+            Some(Err(e)) => Err(e.into()),
+            None => match tokio::fs::remove_file(&path).await {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                other => other,
+            },
+        };
+        if let Err(e) = result {
+            warn!("💾 Failed to persist room {}: {}", room_code, e);
         }
-    });
+    }
+}
+
+/// Load rooms saved by `persist_dirty_rooms`. Restored rooms start empty,
+/// so they are cleaned up after the idle timeout unless someone rejoins.
+fn load_persisted_rooms(state: &AppState) -> usize {
+    let Some(dir) = &state.persist_dir else {
+        return 0;
+    };
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("💾 Failed to read {}: {}", dir.display(), e);
+            return 0;
+        }
+    };
+
+    let mut restored = 0;
+    for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+            continue;
+        }
+        let Some(room_code) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let saved: PersistedRoom = match std::fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()))
+        {
+            Ok(saved) => saved,
+            Err(e) => {
+                warn!("💾 Skipping unreadable room file {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        let (tx, _) = broadcast::channel(state.room_channel_capacity);
+        state.rooms.insert(
+            room_code.to_string(),
+            Room {
+                id: saved.id,
+                host_id: saved.host_id,
+                created_at: saved.created_at,
+                tx,
+                peers: DashMap::new(),
+                document_state: saved.document_state,
+                last_sync: saved.last_sync,
+                empty_since: Some(chrono::Utc::now()),
+                password: saved.password,
+                idle_timeout_seconds: saved.idle_timeout_seconds,
+                owner: saved.owner,
+                history: VecDeque::new(),
+                sync_requests: HashMap::new(),
+            },
+        );
+        restored += 1;
+    }
+    restored
 }
 
 fn generate_room_code() -> String {
@@ -733,3 +1991,1043 @@ fn generate_room_code() -> String {
 fn generate_random_id() -> String {
     uuid::Uuid::new_v4().to_string()[..8].to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{SinkExt, StreamExt};
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::{tungstenite, MaybeTlsStream, WebSocketStream};
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn spawn_server(peer_timeout_seconds: u64) -> (SharedState, std::net::SocketAddr) {
+        let (system_tx, _) = broadcast::channel(100);
+        let state = Arc::new(AppState::new(0, peer_timeout_seconds, system_tx));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = build_router(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (state, addr)
+    }
+
+    async fn response_json(response: impl IntoResponse) -> serde_json::Value {
+        let bytes = axum::body::to_bytes(response.into_response().into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    async fn create(state: &SharedState, request: serde_json::Value) -> serde_json::Value {
+        let request = serde_json::from_value(request).unwrap();
+        response_json(create_room(State(state.clone()), HeaderMap::new(), Some(Json(request))).await).await
+    }
+
+    async fn connect(addr: std::net::SocketAddr) -> Client {
+        let (client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+        client
+    }
+
+    async fn send(client: &mut Client, message: serde_json::Value) {
+        client.send(tungstenite::Message::Text(message.to_string())).await.unwrap();
+    }
+
+    /// Next text message from the server
+    async fn recv(client: &mut Client) -> serde_json::Value {
+        loop {
+            let message = tokio::time::timeout(StdDuration::from_secs(5), client.next())
+                .await
+                .expect("timed out waiting for server message")
+                .unwrap()
+                .unwrap();
+            if let tungstenite::Message::Text(text) = message {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_password_protected_room() {
+        let (state, addr) = spawn_server(0).await;
+        let created = create(&state, serde_json::json!({ "desired_room_code": "SECRET", "password": "hunter2" })).await;
+        assert_eq!(created["success"], true);
+        let restored = create(&state, serde_json::json!({ "desired_room_code": "SECRET" })).await;
+        assert_eq!(restored["success"], false);
+
+        let mut client = connect(addr).await;
+        send(&mut client, serde_json::json!({
+            "action": "join", "room_code": "SECRET", "peer_id": "p1", "is_host": false, "metadata": null
+        }))
+        .await;
+        let reply = recv(&mut client).await;
+        assert_eq!(reply["type"], "error");
+        assert_eq!(reply["message"], "Invalid room password");
+
+        send(&mut client, serde_json::json!({
+            "action": "join", "room_code": "SECRET", "peer_id": "p1", "is_host": false, "metadata": null,
+            "password": "wrong"
+        }))
+        .await;
+        assert_eq!(recv(&mut client).await["type"], "error");
+        assert!(state.rooms.get("SECRET").unwrap().peers.is_empty());
+
+        send(&mut client, serde_json::json!({
+            "action": "join", "room_code": "SECRET", "peer_id": "p1", "is_host": false, "metadata": null,
+            "password": "hunter2"
+        }))
+        .await;
+        assert_eq!(recv(&mut client).await["type"], "room_info");
+        assert_eq!(recv(&mut client).await["type"], "connected");
+    }
+
+    #[tokio::test]
+    async fn test_rooms_without_password_stay_open() {
+        let (state, addr) = spawn_server(0).await;
+        create(&state, serde_json::json!({ "desired_room_code": "OPEN01" })).await;
+
+        let mut client = connect(addr).await;
+        send(&mut client, serde_json::json!({
+            "action": "join", "room_code": "OPEN01", "peer_id": "p1", "is_host": true, "metadata": null
+        }))
+        .await;
+        assert_eq!(recv(&mut client).await["type"], "room_info");
+    }
+
+    /// Code of the close frame that ends the connection
+    async fn recv_close(client: &mut Client) -> Option<u16> {
+        loop {
+            let message = tokio::time::timeout(StdDuration::from_secs(5), client.next())
+                .await
+                .expect("timed out waiting for close frame");
+            match message {
+                Some(Ok(tungstenite::Message::Close(frame))) => return frame.map(|frame| frame.code.into()),
+                None | Some(Err(_)) => return None,
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+
+    /// Next message of the given type, skipping any others
+    async fn recv_type(client: &mut Client, message_type: &str) -> serde_json::Value {
+        loop {
+            let message = recv(client).await;
+            if message["type"] == message_type {
+                return message;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_host_leaving_promotes_next_peer() {
+        let (state, addr) = spawn_server(0).await;
+        create(&state, serde_json::json!({ "desired_room_code": "HOST01", "desired_host_id": "host" })).await;
+
+        let mut host = connect(addr).await;
+        send(&mut host, serde_json::json!({
+            "action": "join", "room_code": "HOST01", "peer_id": "host", "is_host": true, "metadata": null
+        }))
+        .await;
+        recv_type(&mut host, "connected").await;
+        let mut guest = connect(addr).await;
+        send(&mut guest, serde_json::json!({
+            "action": "join", "room_code": "HOST01", "peer_id": "guest", "is_host": false, "metadata": null
+        }))
+        .await;
+        recv_type(&mut guest, "connected").await;
+
+        send(&mut host, serde_json::json!({ "action": "leave" })).await;
+        let changed = recv_type(&mut guest, "host_changed").await;
+        assert_eq!(changed["new_host_id"], "guest");
+
+        let room = state.rooms.get("HOST01").unwrap();
+        assert_eq!(room.host_id, "guest");
+        assert!(room.peers.get("guest").unwrap().is_host);
+    }
+
+    #[tokio::test]
+    async fn test_observer_is_not_promoted_to_host() {
+        let (state, addr) = spawn_server(0).await;
+        create(&state, serde_json::json!({ "desired_room_code": "HOST02", "desired_host_id": "host" })).await;
+
+        let mut clients = Vec::new();
+        for (peer_id, is_host, observer) in [("host", true, false), ("dashboard", false, true), ("guest", false, false)] {
+            let mut client = connect(addr).await;
+            send(&mut client, serde_json::json!({
+                "action": "join", "room_code": "HOST02", "peer_id": peer_id, "is_host": is_host, "metadata": null,
+                "observer": observer
+            }))
+            .await;
+            recv_type(&mut client, "connected").await;
+            clients.push(client);
+        }
+        let (mut host, mut dashboard) = (clients.remove(0), clients.remove(0));
+
+        // The dashboard has been connected longest, but is read-only
+        send(&mut host, serde_json::json!({ "action": "leave" })).await;
+        assert_eq!(recv_type(&mut dashboard, "host_changed").await["new_host_id"], "guest");
+        assert!(!state.rooms.get("HOST02").unwrap().peers.get("dashboard").unwrap().is_host);
+
+        // With only observers left the room keeps no host
+        send(&mut clients[0], serde_json::json!({ "action": "leave" })).await;
+        assert_eq!(recv_type(&mut dashboard, "peer_left").await["peer_id"], "guest");
+        let room = state.rooms.get("HOST02").unwrap();
+        assert_eq!(room.host_id, "guest");
+        assert!(!room.peers.get("dashboard").unwrap().is_host);
+    }
+
+    #[tokio::test]
+    async fn test_silent_peer_is_evicted() {
+        let (state, addr) = spawn_server(1).await;
+        create(&state, serde_json::json!({ "desired_room_code": "IDLE01" })).await;
+
+        let mut client = connect(addr).await;
+        send(&mut client, serde_json::json!({
+            "action": "join", "room_code": "IDLE01", "peer_id": "quiet", "is_host": false, "metadata": null
+        }))
+        .await;
+        recv_type(&mut client, "connected").await;
+        assert!(state.rooms.get("IDLE01").unwrap().peers.contains_key("quiet"));
+
+        // Say nothing past the timeout
+        let deadline = tokio::time::Instant::now() + StdDuration::from_secs(5);
+        while state.rooms.get("IDLE01").unwrap().peers.contains_key("quiet") {
+            assert!(tokio::time::Instant::now() < deadline, "silent peer was not evicted");
+            tokio::time::sleep(StdDuration::from_millis(100)).await;
+        }
+        assert!(state.rooms.get("IDLE01").unwrap().empty_since.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_persisted_rooms_survive_restart() {
+        let dir = std::env::temp_dir().join(format!("sync-server-test-{}", generate_random_id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (system_tx, _) = broadcast::channel(100);
+        let state = Arc::new(AppState::new(0, 0, system_tx).with_persist_dir(Some(dir.clone())));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = build_router(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        create(&state, serde_json::json!({ "desired_room_code": "SAVE01", "password": "pw" })).await;
+
+        let mut client = connect(addr).await;
+        send(&mut client, serde_json::json!({
+            "action": "join", "room_code": "SAVE01", "peer_id": "p1", "is_host": true, "metadata": null,
+            "password": "pw"
+        }))
+        .await;
+        recv_type(&mut client, "connected").await;
+        let document = CrdtDocument::new("p1".to_string()).export();
+        send(&mut client, serde_json::json!({ "action": "sync_document", "document": document })).await;
+        // Ping is answered after the sync is handled
+        send(&mut client, serde_json::json!({ "action": "ping" })).await;
+        recv_type(&mut client, "pong").await;
+        persist_dirty_rooms(&state).await;
+        assert!(state.dirty_rooms.is_empty());
+
+        // A fresh server reading the same directory
+        let (system_tx, _) = broadcast::channel(100);
+        let restarted = AppState::new(0, 0, system_tx).with_persist_dir(Some(dir.clone()));
+        assert_eq!(load_persisted_rooms(&restarted), 1);
+        let room = restarted.rooms.get("SAVE01").unwrap();
+        assert!(room.document_state.is_some());
+        assert_eq!(room.document_state, state.rooms.get("SAVE01").unwrap().document_state);
+        assert_eq!(room.host_id, state.rooms.get("SAVE01").unwrap().host_id);
+        assert!(room_password_matches(&room, Some("pw")));
+        assert!(room.peers.is_empty());
+        drop(room);
+
+        // Removed rooms lose their file on the next flush
+        state.rooms.remove("SAVE01");
+        state.mark_dirty("SAVE01");
+        persist_dirty_rooms(&state).await;
+        assert!(!dir.join("SAVE01.json").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_list_rooms_requires_admin_token() {
+        let (system_tx, _) = broadcast::channel(100);
+        let state = Arc::new(AppState::new(0, 0, system_tx).with_admin_token(Some("s3cret".to_string())));
+        create(&state, serde_json::json!({ "desired_room_code": "ROOM01", "desired_host_id": "h1" })).await;
+        create(&state, serde_json::json!({ "desired_room_code": "ROOM02", "desired_host_id": "h2" })).await;
+        state.rooms.get_mut("ROOM02").unwrap().document_state = Some("{}".to_string());
+
+        let list = |token: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(token) = token {
+                headers.insert(header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+            }
+            list_rooms(State(state.clone()), headers)
+        };
+        assert_eq!(list(None).await.into_response().status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(list(Some("wrong")).await.into_response().status(), StatusCode::UNAUTHORIZED);
+
+        let listing = response_json(list(Some("s3cret")).await).await;
+        let rooms = listing["rooms"].as_array().unwrap();
+        assert_eq!(rooms.len(), 2);
+        assert_eq!(rooms[0]["room_code"], "ROOM01");
+        assert_eq!(rooms[0]["host_id"], "h1");
+        assert_eq!(rooms[0]["has_document"], false);
+        assert_eq!(rooms[1]["room_code"], "ROOM02");
+        assert_eq!(rooms[1]["peer_count"], 0);
+        assert_eq!(rooms[1]["has_document"], true);
+
+        // Without a configured token the listing isn't available at all
+        let (system_tx, _) = broadcast::channel(100);
+        let open = Arc::new(AppState::new(0, 0, system_tx));
+        let response = list_rooms(State(open), HeaderMap::new()).await.into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_count_created_rooms() {
+        let (state, addr) = spawn_server(0).await;
+        create(&state, serde_json::json!({ "desired_room_code": "METRIC" })).await;
+        // Restoring an existing room doesn't create another
+        create(&state, serde_json::json!({ "desired_room_code": "METRIC" })).await;
+
+        let mut client = connect(addr).await;
+        send(&mut client, serde_json::json!({
+            "action": "join", "room_code": "METRIC", "peer_id": "p1", "is_host": true, "metadata": null
+        }))
+        .await;
+        recv_type(&mut client, "connected").await;
+
+        let response = metrics_handler(State(state.clone())).await.into_response();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        let lines: Vec<&str> = body.lines().filter(|line| !line.starts_with('#')).collect();
+        assert!(lines.contains(&"rooms_created_total 1"), "{}", body);
+        assert!(lines.contains(&"rooms_total 1"));
+        assert!(lines.contains(&"peers_total 1"));
+        assert!(lines.contains(&"messages_received_total 1"));
+        assert!(lines.contains(&"rooms_cleaned_total 0"));
+    }
+
+    /// Next binary frame from the server, decoded
+    async fn recv_binary(client: &mut Client) -> serde_json::Value {
+        loop {
+            let message = tokio::time::timeout(StdDuration::from_secs(5), client.next())
+                .await
+                .expect("timed out waiting for server message")
+                .unwrap()
+                .unwrap();
+            match message {
+                tungstenite::Message::Binary(data) => return serde_json::from_slice(&decode_frame(&data).unwrap()).unwrap(),
+                tungstenite::Message::Text(text) => panic!("expected a binary frame, got {}", text),
+                _ => {}
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_binary_frames_are_routed_like_text() {
+        let (state, addr) = spawn_server(0).await;
+        create(&state, serde_json::json!({ "desired_room_code": "BIN001" })).await;
+
+        let mut binary = connect(addr).await;
+        let join = serde_json::json!({
+            "action": "join", "room_code": "BIN001", "peer_id": "compact", "is_host": true, "metadata": null
+        });
+        binary.send(tungstenite::Message::Binary(encode_frame(join.to_string().as_bytes()))).await.unwrap();
+        assert_eq!(recv_binary(&mut binary).await["type"], "room_info");
+        assert_eq!(recv_binary(&mut binary).await["type"], "connected");
+
+        let mut text = connect(addr).await;
+        send(&mut text, serde_json::json!({
+            "action": "join", "room_code": "BIN001", "peer_id": "plain", "is_host": false, "metadata": null
+        }))
+        .await;
+        recv_type(&mut text, "connected").await;
+
+        let broadcast = serde_json::json!({ "action": "broadcast", "data": "hello" });
+        binary.send(tungstenite::Message::Binary(encode_frame(broadcast.to_string().as_bytes()))).await.unwrap();
+        let data = recv_type(&mut text, "data").await;
+        assert_eq!(data["from"], "compact");
+        assert_eq!(data["data"], "hello");
+
+        // Room events reach the binary client compressed
+        send(&mut text, serde_json::json!({ "action": "broadcast", "data": "hi back" })).await;
+        loop {
+            let message = recv_binary(&mut binary).await;
+            if message["type"] == "data" {
+                assert_eq!(message["data"], "hi back");
+                break;
+            }
+        }
+
+        // Corrupt frames are rejected without dropping the connection
+        let mut corrupt = encode_frame(broadcast.to_string().as_bytes());
+        corrupt[4] ^= 0xff;
+        binary.send(tungstenite::Message::Binary(corrupt)).await.unwrap();
+        assert_eq!(recv_binary(&mut binary).await["type"], "error");
+    }
+
+    #[tokio::test]
+    async fn test_oversized_message_is_rejected() {
+        let (state, addr) = spawn_server(0).await;
+        create(&state, serde_json::json!({ "desired_room_code": "BIG001" })).await;
+        let mut client = connect(addr).await;
+        send(&mut client, serde_json::json!({
+            "action": "join", "room_code": "BIG001", "peer_id": "host", "is_host": true, "metadata": null
+        }))
+        .await;
+        recv_type(&mut client, "connected").await;
+
+        let document = "x".repeat(DEFAULT_MAX_MESSAGE_BYTES);
+        send(&mut client, serde_json::json!({ "action": "sync_document", "document": document })).await;
+        let error = recv_type(&mut client, "error").await;
+        assert!(error["message"].as_str().unwrap().starts_with("Message too large"));
+        assert!(state.rooms.get("BIG001").unwrap().document_state.is_none());
+
+        // The connection survives the rejected message
+        send(&mut client, serde_json::json!({ "action": "ping" })).await;
+        recv_type(&mut client, "pong").await;
+    }
+
+    #[tokio::test]
+    async fn test_create_room_websocket_url() {
+        let (system_tx, _) = broadcast::channel(100);
+        let state = Arc::new(AppState::new(0, 0, system_tx.clone()));
+        let request = || Some(Json(serde_json::from_value(serde_json::json!({ "desired_room_code": "URL001" })).unwrap()));
+
+        let created = response_json(create_room(State(state.clone()), HeaderMap::new(), request()).await).await;
+        assert_eq!(created["websocket_url"], DEFAULT_WS_URL);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, "sync.example.com".parse().unwrap());
+        headers.insert("x-forwarded-proto", "https".parse().unwrap());
+        let restored = response_json(create_room(State(state.clone()), headers, request()).await).await;
+        assert_eq!(restored["restored"], true);
+        assert_eq!(restored["websocket_url"], "wss://sync.example.com/ws");
+
+        let state = Arc::new(
+            AppState::new(0, 0, system_tx).with_public_ws_url(Some("wss://tracker.example.org/sync/ws".to_string())),
+        );
+        let created = response_json(create_room(State(state), HeaderMap::new(), request()).await).await;
+        assert_eq!(created["websocket_url"], "wss://tracker.example.org/sync/ws");
+    }
+
+    #[tokio::test]
+    async fn test_host_closes_room() {
+        let (state, addr) = spawn_server(0).await;
+        let created = create(&state, serde_json::json!({ "desired_room_code": "END001", "desired_host_id": "host_1" })).await;
+        assert_eq!(created["host_id"], "host_1");
+        let mut peer = connect(addr).await;
+        send(&mut peer, serde_json::json!({
+            "action": "join", "room_code": "END001", "peer_id": "guest", "is_host": false, "metadata": null
+        }))
+        .await;
+        recv_type(&mut peer, "connected").await;
+
+        let delete = |host_id: Option<&str>| {
+            let state = state.clone();
+            let query = HostQuery { host_id: host_id.map(str::to_string) };
+            async move { delete_room(Path("END001".to_string()), State(state), Query(query), HeaderMap::new()).await.into_response() }
+        };
+        assert_eq!(delete(None).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(delete(Some("guest")).await.status(), StatusCode::FORBIDDEN);
+
+        let mut system_rx = state.system_tx.subscribe();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-host-id", "host_1".parse().unwrap());
+        let response = delete_room(Path("END001".to_string()), State(state.clone()), Query(HostQuery { host_id: None }), headers).await;
+        assert_eq!(response.into_response().status(), StatusCode::OK);
+
+        let closed = recv_type(&mut peer, "room_closed").await;
+        assert_eq!(closed["room_code"], "END001");
+        assert!(matches!(system_rx.try_recv(), Ok(SystemEvent::RoomClosed { room_id, .. }) if room_id == created["room_id"]));
+
+        let info = response_json(get_room_info(Path("END001".to_string()), State(state.clone())).await).await;
+        assert_eq!(info["success"], false);
+        assert_eq!(info["error"], "Room not found");
+        assert_eq!(delete(Some("host_1")).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_synced_documents_are_merged() {
+        let (state, addr) = spawn_server(0).await;
+        create(&state, serde_json::json!({ "desired_room_code": "MERGE1" })).await;
+
+        // Two peers edited offline and sync their own version
+        let mut phone_doc = CrdtDocument::new("phone".to_string());
+        phone_doc.upsert_value("task-a", "title".to_string(), "From phone");
+        let mut laptop_doc = CrdtDocument::new("laptop".to_string());
+        laptop_doc.upsert_value("task-b", "title".to_string(), "From laptop");
+        for (peer_id, document) in [("phone", phone_doc.export()), ("laptop", laptop_doc.export())] {
+            let mut client = connect(addr).await;
+            send(&mut client, serde_json::json!({
+                "action": "join", "room_code": "MERGE1", "peer_id": peer_id, "is_host": false, "metadata": null
+            }))
+            .await;
+            recv_type(&mut client, "connected").await;
+            send(&mut client, serde_json::json!({ "action": "sync_document", "document": document })).await;
+            send(&mut client, serde_json::json!({ "action": "ping" })).await;
+            recv_type(&mut client, "pong").await;
+        }
+
+        // A late joiner gets both
+        let mut late = connect(addr).await;
+        send(&mut late, serde_json::json!({
+            "action": "join", "room_code": "MERGE1", "peer_id": "tablet", "is_host": false, "metadata": null
+        }))
+        .await;
+        let sync = recv_type(&mut late, "document_sync").await;
+        let document: serde_json::Value = serde_json::from_str(sync["document"].as_str().unwrap()).unwrap();
+        assert_eq!(document["tasks"]["task-a"]["fields"]["title"]["value"], "From phone");
+        assert_eq!(document["tasks"]["task-b"]["fields"]["title"]["value"], "From laptop");
+
+        // A CSV export from an older client is kept as sent
+        let csv = "id,title\n1,From CSV";
+        send(&mut late, serde_json::json!({ "action": "sync_document", "document": csv })).await;
+        send(&mut late, serde_json::json!({ "action": "ping" })).await;
+        recv_type(&mut late, "pong").await;
+        let mut csv_peer = connect(addr).await;
+        send(&mut csv_peer, serde_json::json!({
+            "action": "join", "room_code": "MERGE1", "peer_id": "desktop", "is_host": false, "metadata": null
+        }))
+        .await;
+        assert_eq!(recv_type(&mut csv_peer, "document_sync").await["document"], csv);
+    }
+
+    #[tokio::test]
+    async fn test_merged_document_respects_message_limit() {
+        // Each sync fits on its own, the two merged don't
+        let mut phone_doc = CrdtDocument::new("phone".to_string());
+        phone_doc.upsert_value("task-a", "notes".to_string(), "p".repeat(2000));
+        let mut laptop_doc = CrdtDocument::new("laptop".to_string());
+        laptop_doc.upsert_value("task-b", "notes".to_string(), "l".repeat(2000));
+        let (phone, laptop) = (phone_doc.export(), laptop_doc.export());
+        let limit = 3000;
+        assert!(merge_document(Some(&phone), &laptop).len() > limit);
+
+        let (system_tx, _) = broadcast::channel(100);
+        let state = Arc::new(AppState::new(0, 0, system_tx).with_max_message_bytes(limit));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = build_router(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        create(&state, serde_json::json!({ "desired_room_code": "LIMIT1" })).await;
+
+        let mut client = connect(addr).await;
+        send(&mut client, serde_json::json!({
+            "action": "join", "room_code": "LIMIT1", "peer_id": "phone", "is_host": false, "metadata": null
+        }))
+        .await;
+        recv_type(&mut client, "connected").await;
+        send(&mut client, serde_json::json!({ "action": "sync_document", "document": phone })).await;
+        send(&mut client, serde_json::json!({ "action": "sync_document", "document": laptop })).await;
+        let error = recv_type(&mut client, "error").await;
+        assert!(error["message"].as_str().unwrap().starts_with("Message too large"), "{}", error);
+        let stored = state.rooms.get("LIMIT1").unwrap().document_state.clone().unwrap();
+        assert_eq!(stored, merge_document(None, &phone));
+    }
+
+    #[tokio::test]
+    async fn test_flooding_peer_is_rate_limited() {
+        let (system_tx, _) = broadcast::channel(100);
+        let state = Arc::new(AppState::new(0, 0, system_tx).with_rate_limit(5));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = build_router(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        create(&state, serde_json::json!({ "desired_room_code": "FLOOD1" })).await;
+
+        let mut clients = Vec::new();
+        for peer_id in ["flooder", "listener"] {
+            let mut client = connect(addr).await;
+            send(&mut client, serde_json::json!({
+                "action": "join", "room_code": "FLOOD1", "peer_id": peer_id, "is_host": false, "metadata": null
+            }))
+            .await;
+            recv_type(&mut client, "connected").await;
+            clients.push(client);
+        }
+        let (mut flooder, mut listener) = (clients.remove(0), clients.remove(0));
+
+        // The join used one of the 5 tokens, so 4 of the burst get through
+        for n in 0..10 {
+            send(&mut flooder, serde_json::json!({ "action": "broadcast", "data": n.to_string() })).await;
+        }
+        let error = recv_type(&mut flooder, "error").await;
+        assert!(error["message"].as_str().unwrap().starts_with("Rate limit exceeded"), "{}", error);
+        for n in 0..4 {
+            assert_eq!(recv_type(&mut listener, "data").await["data"], n.to_string());
+        }
+        let extra = tokio::time::timeout(StdDuration::from_millis(300), recv_type(&mut listener, "data")).await;
+        assert!(extra.is_err(), "message over the rate limit was broadcast: {:?}", extra);
+
+        // Keep flooding until the server gives up on the connection
+        for n in 10..100 {
+            let message = serde_json::json!({ "action": "broadcast", "data": n.to_string() });
+            if flooder.send(tungstenite::Message::Text(message.to_string())).await.is_err() {
+                break;
+            }
+        }
+        let closed = tokio::time::timeout(StdDuration::from_secs(5), async {
+            loop {
+                match flooder.next().await {
+                    Some(Ok(tungstenite::Message::Close(_))) | None | Some(Err(_)) => return,
+                    Some(Ok(_)) => {}
+                }
+            }
+        })
+        .await;
+        assert!(closed.is_ok(), "flooding peer was not disconnected");
+    }
+
+    #[tokio::test]
+    async fn test_late_joiner_gets_recent_broadcasts() {
+        let (state, addr) = spawn_server(0).await;
+        create(&state, serde_json::json!({ "desired_room_code": "LATE01" })).await;
+
+        let mut early = connect(addr).await;
+        send(&mut early, serde_json::json!({
+            "action": "join", "room_code": "LATE01", "peer_id": "early", "is_host": false, "metadata": null
+        }))
+        .await;
+        recv_type(&mut early, "connected").await;
+        for delta in ["delta-1", "delta-2", "delta-3"] {
+            send(&mut early, serde_json::json!({ "action": "broadcast", "data": delta })).await;
+        }
+        send(&mut early, serde_json::json!({ "action": "ping" })).await;
+        recv_type(&mut early, "pong").await;
+
+        let mut late = connect(addr).await;
+        send(&mut late, serde_json::json!({
+            "action": "join", "room_code": "LATE01", "peer_id": "late", "is_host": false, "metadata": null
+        }))
+        .await;
+        recv_type(&mut late, "connected").await;
+        for delta in ["delta-1", "delta-2", "delta-3"] {
+            let data = recv_type(&mut late, "data").await;
+            assert_eq!(data["from"], "early");
+            assert_eq!(data["data"], delta);
+        }
+
+        // A document sync covers everything broadcast before it
+        let document = CrdtDocument::new("early".to_string()).export();
+        send(&mut early, serde_json::json!({ "action": "sync_document", "document": document })).await;
+        send(&mut early, serde_json::json!({ "action": "ping" })).await;
+        recv_type(&mut early, "pong").await;
+        assert!(state.rooms.get("LATE01").unwrap().history.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_room_idle_timeout_overrides_default() {
+        let (system_tx, _) = broadcast::channel(100);
+        let state = Arc::new(AppState::new(3600, 0, system_tx));
+        create(&state, serde_json::json!({ "desired_room_code": "QUICK1", "idle_timeout_seconds": 2 })).await;
+        create(&state, serde_json::json!({ "desired_room_code": "TEAM01" })).await;
+        create(&state, serde_json::json!({ "desired_room_code": "KEEP01", "idle_timeout_seconds": 0 })).await;
+
+        remove_idle_rooms(&state);
+        assert!(state.rooms.contains_key("QUICK1"));
+
+        tokio::time::sleep(StdDuration::from_millis(2100)).await;
+        remove_idle_rooms(&state);
+        assert!(!state.rooms.contains_key("QUICK1"));
+        assert!(state.rooms.contains_key("TEAM01"));
+        assert!(state.rooms.contains_key("KEEP01"));
+        assert_eq!(state.metrics.rooms_cleaned_total.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_jwt_required_when_configured() {
+        let (system_tx, _) = broadcast::channel(100);
+        let state = Arc::new(AppState::new(0, 0, system_tx).with_jwt_secret(Some("jwt-s3cret".to_string())));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = build_router(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let token = |sub: &str, expires_in: i64| {
+            let claims = Claims { sub: sub.to_string(), exp: (chrono::Utc::now().timestamp() + expires_in) as u64 };
+            jsonwebtoken::encode(
+                &jsonwebtoken::Header::default(),
+                &claims,
+                &jsonwebtoken::EncodingKey::from_secret(b"jwt-s3cret"),
+            )
+            .unwrap()
+        };
+        let create_with = |room_code: &str, token: Option<String>| {
+            let mut headers = HeaderMap::new();
+            if let Some(token) = token {
+                headers.insert(header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+            }
+            let request = serde_json::from_value(serde_json::json!({ "desired_room_code": room_code })).unwrap();
+            create_room(State(state.clone()), headers, Some(Json(request)))
+        };
+
+        let missing = create_with("AUTH01", None).await.into_response();
+        assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+        let expired = create_with("AUTH01", Some(token("alice", -3600))).await.into_response();
+        assert_eq!(expired.status(), StatusCode::UNAUTHORIZED);
+        let forged = create_with("AUTH01", Some(token("alice", 3600) + "x")).await.into_response();
+        assert_eq!(forged.status(), StatusCode::UNAUTHORIZED);
+        assert!(state.rooms.is_empty());
+
+        let created = response_json(create_with("AUTH01", Some(token("alice", 3600))).await).await;
+        assert_eq!(created["success"], true);
+        assert_eq!(state.rooms.get("AUTH01").unwrap().owner.as_deref(), Some("alice"));
+
+        let mut client = connect(addr).await;
+        for token in [None, Some(token("bob", -3600))] {
+            send(&mut client, serde_json::json!({
+                "action": "join", "room_code": "AUTH01", "peer_id": "p1", "is_host": false, "metadata": null,
+                "token": token
+            }))
+            .await;
+            let reply = recv(&mut client).await;
+            assert_eq!(reply["type"], "error");
+            assert!(reply["message"].as_str().unwrap().contains("token"));
+        }
+        assert!(state.rooms.get("AUTH01").unwrap().peers.is_empty());
+
+        send(&mut client, serde_json::json!({
+            "action": "join", "room_code": "AUTH01", "peer_id": "p1", "is_host": false, "metadata": null,
+            "token": token("bob", 3600)
+        }))
+        .await;
+        let info = recv_type(&mut client, "room_info").await;
+        assert_eq!(info["peers"][0]["user_id"], "bob");
+        assert_eq!(state.rooms.get("AUTH01").unwrap().peers.get("p1").unwrap().user_id.as_deref(), Some("bob"));
+    }
+
+    #[tokio::test]
+    async fn test_room_info_is_not_enough_to_close_a_room() {
+        let (system_tx, _) = broadcast::channel(100);
+        let state = Arc::new(AppState::new(0, 0, system_tx).with_jwt_secret(Some("jwt-s3cret".to_string())));
+        let token = |sub: &str| {
+            let claims = Claims { sub: sub.to_string(), exp: (chrono::Utc::now().timestamp() + 3600) as u64 };
+            jsonwebtoken::encode(
+                &jsonwebtoken::Header::default(),
+                &claims,
+                &jsonwebtoken::EncodingKey::from_secret(b"jwt-s3cret"),
+            )
+            .unwrap()
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, format!("Bearer {}", token("alice")).parse().unwrap());
+        let request = serde_json::from_value(serde_json::json!({ "desired_room_code": "OWNED1", "password": "hunter2" })).unwrap();
+        create_room(State(state.clone()), headers, Some(Json(request))).await;
+
+        // Anyone can read the host id
+        let info = response_json(get_room_info(Path("OWNED1".to_string()), State(state.clone())).await).await;
+        let host_id = info["host_id"].as_str().unwrap().to_string();
+        let delete = |password: Option<&str>, user: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-host-id", host_id.parse().unwrap());
+            if let Some(password) = password {
+                headers.insert("x-room-password", password.parse().unwrap());
+            }
+            if let Some(user) = user {
+                headers.insert(header::AUTHORIZATION, format!("Bearer {}", token(user)).parse().unwrap());
+            }
+            let state = state.clone();
+            async move {
+                delete_room(Path("OWNED1".to_string()), State(state), Query(HostQuery { host_id: None }), headers).await.into_response()
+            }
+        };
+        assert_eq!(delete(None, None).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(delete(Some("wrong"), Some("alice")).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(delete(Some("hunter2"), None).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(delete(Some("hunter2"), Some("mallory")).await.status(), StatusCode::FORBIDDEN);
+        assert!(state.rooms.contains_key("OWNED1"));
+
+        assert_eq!(delete(Some("hunter2"), Some("alice")).await.status(), StatusCode::OK);
+        assert!(!state.rooms.contains_key("OWNED1"));
+    }
+
+    #[tokio::test]
+    async fn test_host_kicks_peer() {
+        let (state, addr) = spawn_server(0).await;
+        create(&state, serde_json::json!({ "desired_room_code": "KICK01", "desired_host_id": "host_1" })).await;
+
+        let mut host = connect(addr).await;
+        send(&mut host, serde_json::json!({
+            "action": "join", "room_code": "KICK01", "peer_id": "host_1", "is_host": true, "metadata": null
+        }))
+        .await;
+        recv_type(&mut host, "connected").await;
+        let mut guest = connect(addr).await;
+        send(&mut guest, serde_json::json!({
+            "action": "join", "room_code": "KICK01", "peer_id": "guest", "is_host": false, "metadata": null
+        }))
+        .await;
+        recv_type(&mut guest, "connected").await;
+
+        send(&mut guest, serde_json::json!({ "action": "kick", "peer_id": "host_1" })).await;
+        let reply = recv_type(&mut guest, "error").await;
+        assert_eq!(reply["message"], "Only the host can kick peers");
+        send(&mut host, serde_json::json!({ "action": "kick", "peer_id": "nobody" })).await;
+        assert_eq!(recv_type(&mut host, "error").await["message"], "Peer not found");
+
+        send(&mut host, serde_json::json!({ "action": "kick", "peer_id": "guest" })).await;
+        assert_eq!(recv_type(&mut guest, "kicked").await["room_code"], "KICK01");
+        assert_eq!(recv_close(&mut guest).await, Some(4001));
+        assert_eq!(recv_type(&mut host, "peer_left").await["peer_id"], "guest");
+        let room = state.rooms.get("KICK01").unwrap();
+        assert!(!room.peers.contains_key("guest"));
+        assert!(room.peers.contains_key("host_1"));
+    }
+
+    #[tokio::test]
+    async fn test_metadata_updates_reach_other_peers() {
+        let (state, addr) = spawn_server(0).await;
+        create(&state, serde_json::json!({ "desired_room_code": "META01" })).await;
+
+        let mut alice = connect(addr).await;
+        send(&mut alice, serde_json::json!({
+            "action": "join", "room_code": "META01", "peer_id": "alice", "is_host": false, "metadata": { "name": "Alice" }
+        }))
+        .await;
+        recv_type(&mut alice, "connected").await;
+        let mut bob = connect(addr).await;
+        send(&mut bob, serde_json::json!({
+            "action": "join", "room_code": "META01", "peer_id": "bob", "is_host": false, "metadata": null
+        }))
+        .await;
+        recv_type(&mut bob, "connected").await;
+
+        let metadata = serde_json::json!({ "name": "Alice B.", "cursor": { "task_id": "t1" } });
+        send(&mut alice, serde_json::json!({ "action": "update_metadata", "metadata": metadata })).await;
+        let update = recv_type(&mut bob, "peer_metadata_updated").await;
+        assert_eq!(update["peer_id"], "alice");
+        assert_eq!(update["metadata"], metadata);
+
+        // Kept for the room_info sent to peers that join later
+        let room = state.rooms.get("META01").unwrap();
+        assert_eq!(room.peers.get("alice").unwrap().metadata, Some(metadata));
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_peer_id_is_not_overwritten() {
+        let join = serde_json::json!({
+            "action": "join", "room_code": "TWIN01", "peer_id": "tab", "is_host": false, "metadata": { "tab": 1 }
+        });
+        let (state, addr) = spawn_server(0).await;
+        create(&state, serde_json::json!({ "desired_room_code": "TWIN01" })).await;
+        let mut first = connect(addr).await;
+        send(&mut first, join.clone()).await;
+        recv_type(&mut first, "connected").await;
+
+        let mut second = connect(addr).await;
+        send(&mut second, join.clone()).await;
+        assert_eq!(recv(&mut second).await["message"], "Peer id already in room: tab");
+        assert_eq!(state.rooms.get("TWIN01").unwrap().peers.len(), 1);
+        // The same connection may join again
+        send(&mut first, join.clone()).await;
+        recv_type(&mut first, "connected").await;
+
+        let (system_tx, _) = broadcast::channel(100);
+        let state = Arc::new(AppState::new(0, 0, system_tx).with_duplicate_peer_policy(DuplicatePeerPolicy::Rename));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = build_router(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        create(&state, serde_json::json!({ "desired_room_code": "TWIN01" })).await;
+        let mut clients = Vec::new();
+        for expected in ["tab", "tab-2", "tab-3"] {
+            let mut client = connect(addr).await;
+            send(&mut client, join.clone()).await;
+            assert_eq!(recv_type(&mut client, "connected").await["peer_id"], expected);
+            clients.push(client);
+        }
+        let room = state.rooms.get("TWIN01").unwrap();
+        assert_eq!(room.peers.len(), 3);
+        assert_eq!(room.peers.get("tab").unwrap().metadata, Some(serde_json::json!({ "tab": 1 })));
+    }
+
+    #[tokio::test]
+    async fn test_idle_cleanup_notifies_connected_peers() {
+        let (state, addr) = spawn_server(0).await;
+        let mut system_rx = state.system_tx.subscribe();
+        let created = create(&state, serde_json::json!({ "desired_room_code": "IDLE01", "idle_timeout_seconds": 1 })).await;
+
+        let mut peer = connect(addr).await;
+        send(&mut peer, serde_json::json!({
+            "action": "join", "room_code": "IDLE01", "peer_id": "late", "is_host": false, "metadata": null
+        }))
+        .await;
+        recv_type(&mut peer, "connected").await;
+
+        // Simulate a join racing the idle check
+        state.rooms.get_mut("IDLE01").unwrap().empty_since = Some(chrono::Utc::now() - chrono::Duration::seconds(5));
+        remove_idle_rooms(&state);
+
+        assert!(!state.rooms.contains_key("IDLE01"));
+        let event = loop {
+            match system_rx.try_recv() {
+                Ok(SystemEvent::RoomCreated { .. }) => continue,
+                other => break other,
+            }
+        };
+        assert!(matches!(event, Ok(SystemEvent::RoomClosed { room_id, room_code })
+            if room_id == created["room_id"] && room_code == "IDLE01"));
+        assert_eq!(recv_type(&mut peer, "room_closed").await["room_code"], "IDLE01");
+    }
+
+    #[tokio::test]
+    async fn test_messages_with_seq_are_acknowledged() {
+        let (state, addr) = spawn_server(0).await;
+        create(&state, serde_json::json!({ "desired_room_code": "ACK001" })).await;
+
+        let mut client = connect(addr).await;
+        send(&mut client, serde_json::json!({
+            "action": "join", "room_code": "ACK001", "peer_id": "peer", "is_host": true, "metadata": null, "seq": 1
+        }))
+        .await;
+        recv_type(&mut client, "connected").await;
+        assert_eq!(recv_type(&mut client, "ack").await["seq"], 1);
+
+        let document = CrdtDocument::new("node-a".to_string()).export();
+        send(&mut client, serde_json::json!({ "action": "sync_document", "document": document, "seq": 2 })).await;
+        assert_eq!(recv_type(&mut client, "ack").await["seq"], 2);
+        assert!(state.rooms.get("ACK001").unwrap().document_state.is_some());
+
+        // No seq, no ack: the pong is the next thing to arrive
+        send(&mut client, serde_json::json!({ "action": "ping" })).await;
+        assert_eq!(recv(&mut client).await["type"], "pong");
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_sends_going_away_close_frame() {
+        let (state, addr) = spawn_server(0).await;
+        let mut client = connect(addr).await;
+        send(&mut client, serde_json::json!({ "action": "ping" })).await;
+        recv_type(&mut client, "pong").await;
+
+        state.system_tx.send(SystemEvent::Shutdown).unwrap();
+        assert_eq!(recv_close(&mut client).await, Some(1001));
+    }
+
+    #[tokio::test]
+    async fn test_observer_cannot_broadcast() {
+        let (state, addr) = spawn_server(0).await;
+        create(&state, serde_json::json!({ "desired_room_code": "VIEW01" })).await;
+
+        let mut editor = connect(addr).await;
+        send(&mut editor, serde_json::json!({
+            "action": "join", "room_code": "VIEW01", "peer_id": "editor", "is_host": true, "metadata": null
+        }))
+        .await;
+        recv_type(&mut editor, "connected").await;
+        let mut screen = connect(addr).await;
+        send(&mut screen, serde_json::json!({
+            "action": "join", "room_code": "VIEW01", "peer_id": "screen", "is_host": false, "metadata": null,
+            "observer": true
+        }))
+        .await;
+        recv_type(&mut screen, "connected").await;
+        let joined = loop {
+            let joined = recv_type(&mut editor, "peer_joined").await;
+            if joined["peer"]["id"] == "screen" {
+                break joined;
+            }
+        };
+        assert_eq!(joined["peer"]["observer"], true);
+
+        send(&mut screen, serde_json::json!({ "action": "broadcast", "data": "from-screen" })).await;
+        assert_eq!(recv_type(&mut screen, "error").await["message"], "Observers cannot broadcast");
+        let document = CrdtDocument::new("screen".to_string()).export();
+        send(&mut screen, serde_json::json!({ "action": "sync_document", "document": document })).await;
+        assert_eq!(recv_type(&mut screen, "error").await["message"], "Observers cannot sync the document");
+        assert!(state.rooms.get("VIEW01").unwrap().document_state.is_none());
+
+        // The editor's broadcast is the first data it sees relayed
+        send(&mut editor, serde_json::json!({ "action": "broadcast", "data": "from-editor" })).await;
+        let data = recv_type(&mut screen, "data").await;
+        assert_eq!(data["from"], "editor");
+        assert_eq!(data["data"], "from-editor");
+        assert!(state.rooms.get("VIEW01").unwrap().history.iter().all(|(from, _)| from == "editor"));
+    }
+
+    #[tokio::test]
+    async fn test_fresh_request_sync_prompts_the_host() {
+        let (system_tx, _) = broadcast::channel(100);
+        let state = Arc::new(AppState::new(0, 0, system_tx).with_sync_request_timeout_ms(200));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = build_router(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        create(&state, serde_json::json!({ "desired_room_code": "FRESH1", "desired_host_id": "host" })).await;
+
+        let mut clients = Vec::new();
+        for peer_id in ["host", "joiner"] {
+            let mut client = connect(addr).await;
+            send(&mut client, serde_json::json!({
+                "action": "join", "room_code": "FRESH1", "peer_id": peer_id, "is_host": peer_id == "host", "metadata": null
+            }))
+            .await;
+            recv_type(&mut client, "connected").await;
+            clients.push(client);
+        }
+        let [host, joiner] = &mut clients[..] else { unreachable!() };
+
+        send(joiner, serde_json::json!({ "action": "request_sync", "fresh": true })).await;
+        assert_eq!(recv_type(host, "document_requested").await["peer_id"], "joiner");
+        let mut doc = CrdtDocument::new("host".to_string());
+        doc.upsert_value("task-1", "title".to_string(), "Unsynced on host");
+        send(host, serde_json::json!({ "action": "sync_document", "document": doc.export() })).await;
+        let synced = recv_type(joiner, "document_sync").await;
+        assert!(synced["document"].as_str().unwrap().contains("Unsynced on host"));
+        assert!(state.rooms.get("FRESH1").unwrap().sync_requests.is_empty());
+
+        // A host that doesn't answer: the stored document after the timeout
+        send(joiner, serde_json::json!({ "action": "request_sync", "fresh": true })).await;
+        recv_type(host, "document_requested").await;
+        let fallback = recv_type(joiner, "document_sync").await;
+        assert_eq!(fallback["document"], synced["document"]);
+        assert!(state.rooms.get("FRESH1").unwrap().sync_requests.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_lagging_peer_is_resynced() {
+        let (system_tx, _) = broadcast::channel(100);
+        let state = Arc::new(AppState::new(0, 0, system_tx).with_room_channel_capacity(2));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = build_router(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        create(&state, serde_json::json!({ "desired_room_code": "SLOW01" })).await;
+
+        let mut peer = connect(addr).await;
+        send(&mut peer, serde_json::json!({
+            "action": "join", "room_code": "SLOW01", "peer_id": "slow", "is_host": false, "metadata": null
+        }))
+        .await;
+        recv_type(&mut peer, "connected").await;
+        recv_type(&mut peer, "peer_joined").await;
+
+        // Overflow the buffer before the connection gets to run
+        let document = CrdtDocument::new("writer".to_string()).export();
+        {
+            let mut room = state.rooms.get_mut("SLOW01").unwrap();
+            room.document_state = Some(document.clone());
+            room.history.push_back(("writer".to_string(), "delta-9".to_string()));
+            for i in 0..10 {
+                let _ = room.tx.send(RoomEvent::DataSync { from: "writer".to_string(), data: format!("delta-{}", i) });
+            }
+        }
+
+        let resync = recv_type(&mut peer, "document_sync").await;
+        assert_eq!(resync["document"], document);
+        assert_eq!(recv_type(&mut peer, "data").await["data"], "delta-9");
+    }
+
+    #[tokio::test]
+    async fn test_pong_echoes_client_time() {
+        let (_state, addr) = spawn_server(0).await;
+        let mut client = connect(addr).await;
+        let before = chrono::Utc::now().timestamp_millis();
+        send(&mut client, serde_json::json!({ "action": "ping", "client_time": 1234 })).await;
+        let pong = recv_type(&mut client, "pong").await;
+        assert_eq!(pong["client_time"], 1234);
+        let server_time = pong["server_time"].as_i64().unwrap();
+        assert!(server_time >= before && server_time <= chrono::Utc::now().timestamp_millis());
+
+        // Older clients send a bare ping
+        send(&mut client, serde_json::json!({ "action": "ping" })).await;
+        let pong = recv_type(&mut client, "pong").await;
+        assert!(pong["client_time"].is_null() && pong["server_time"].is_i64());
+    }
+}
@@ -1,12 +1,18 @@
 use wasm_bindgen::prelude::*;
+use base64::{engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}, Engine as _};
+use lz4_flex::block::{compress_prepend_size_with_dict, decompress_size_prepended_with_dict};
 use lz4_flex::{compress_prepend_size, decompress_size_prepended};
 
+#[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(js_namespace = console)]
     fn log(s: &str);
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+fn log(_s: &str) {}
+
 macro_rules! console_log {
     ($($t:tt)*) => (log(&format_args!($($t)*).to_string()))
 }
@@ -18,16 +24,107 @@ pub fn compress(data: &str) -> Result<String, JsValue> {
     #[cfg(feature = "console_error_panic_hook")]
     console_error_panic_hook::set_once();
     
-    let bytes = data.as_bytes();
-    let compressed = compress_prepend_size(bytes);
+    let compressed = compress_bytes(data.as_bytes());
     
     // Convert to base64 for safe storage
-    let base64 = base64_encode(&compressed);
+    Ok(base64_encode(&compressed))
+}
+
+/// Version of the compressed format, stored after `FRAME_MAGIC`
+const FORMAT_VERSION: u8 = 1;
+
+/// Compressed payloads start with `KP`, the format version and a CRC32
+/// of the original bytes, followed by the size-prepended LZ4 block.
+/// Payloads without the header are legacy (plain size-prepended LZ4).
+const FRAME_MAGIC: [u8; 3] = [b'K', b'P', FORMAT_VERSION];
+
+/// Compress binary data using LZ4, without base64 or UTF-8 handling.
+/// Returns the raw compressed bytes (a `Uint8Array` in JS).
+#[wasm_bindgen]
+pub fn compress_bytes(data: &[u8]) -> Vec<u8> {
+    frame(data, &compress_prepend_size(data))
+}
+
+/// Compress data using the slower high-compression LZ4 match finder,
+/// for documents stored long-term. `level` 1-12 sets how hard it searches
+/// for matches (higher is smaller but slower); 0 uses the default
+/// compressor. Output is read by the regular `decompress`.
+#[wasm_bindgen]
+pub fn compress_hc(data: &str, level: u32) -> Result<String, JsValue> {
+    #[cfg(feature = "console_error_panic_hook")]
+    console_error_panic_hook::set_once();
+    
+    let bytes = data.as_bytes();
+    if level == 0 {
+        return compress(data);
+    }
     
-    let ratio = (compressed.len() as f32 / bytes.len() as f32) * 100.0;
-    console_log!("Compressed: {} bytes -> {} bytes ({:.1}%)", bytes.len(), compressed.len(), ratio);
+    let mut block = (bytes.len() as u32).to_le_bytes().to_vec();
+    block.extend(hc::compress_block(bytes, level));
+    Ok(base64_encode(&frame(bytes, &block)))
+}
+
+/// Wrap a size-prepended LZ4 block in the current payload format
+fn frame(data: &[u8], block: &[u8]) -> Vec<u8> {
+    let mut compressed = Vec::with_capacity(FRAME_MAGIC.len() + 4 + block.len());
+    compressed.extend_from_slice(&FRAME_MAGIC);
+    compressed.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+    compressed.extend_from_slice(block);
+    
+    let ratio = (compressed.len() as f32 / data.len() as f32) * 100.0;
+    console_log!("Compressed: {} bytes -> {} bytes ({:.1}%)", data.len(), compressed.len(), ratio);
+    
+    compressed
+}
+
+/// Decompress bytes produced by `compress_bytes`
+#[wasm_bindgen]
+pub fn decompress_bytes(data: &[u8]) -> Result<Vec<u8>, JsValue> {
+    #[cfg(feature = "console_error_panic_hook")]
+    console_error_panic_hook::set_once();
+    
+    let result = decompress_raw(data).map_err(|e| JsValue::from_str(&e))?;
+    
+    console_log!("Decompressed: {} bytes", result.len());
+    
+    Ok(result)
+}
+
+fn decompress_raw(data: &[u8]) -> Result<Vec<u8>, String> {
+    let framed = match data {
+        [b'K', b'P', FORMAT_VERSION, frame @ ..] => decompress_frame(frame),
+        [b'K', b'P', version, ..] => Err(format!("Unsupported compression format version {}", version)),
+        _ => return decompress_legacy(data),
+    };
+    // A legacy payload can start with the magic bytes by chance
+    framed.or_else(|err| decompress_legacy(data).map_err(|_| err))
+}
+
+/// Decompress a current-format payload (after the magic) and verify its
+/// checksum
+fn decompress_frame(frame: &[u8]) -> Result<Vec<u8>, String> {
+    let (checksum, block) = frame
+        .split_first_chunk::<4>()
+        .ok_or("Decompression error: truncated header")?;
+    let decompressed = decompress_legacy(block)?;
     
-    Ok(base64)
+    if crc32fast::hash(&decompressed) != u32::from_le_bytes(*checksum) {
+        return Err("Checksum mismatch: compressed data is corrupted".to_string());
+    }
+    Ok(decompressed)
+}
+
+/// Decompress a size-prepended LZ4 block. The declared size is checked
+/// first: lz4_flex allocates it up front, and a corrupt header (or a
+/// current-format payload read as legacy) can claim gigabytes.
+fn decompress_legacy(data: &[u8]) -> Result<Vec<u8>, String> {
+    let (size, block) = data.split_first_chunk::<4>().ok_or("Decompression error: truncated header")?;
+    let size = u32::from_le_bytes(*size) as usize;
+    if size > block.len().saturating_mul(MAX_LZ4_RATIO) {
+        return Err(format!("Decompression error: declared size {} is more than {} compressed bytes can hold", size, block.len()));
+    }
+    decompress_size_prepended(data)
+        .map_err(|e| format!("Decompression error: {:?}", e))
 }
 
 /// Decompress data using LZ4
@@ -37,41 +134,291 @@ pub fn decompress(data: &str) -> Result<String, JsValue> {
     #[cfg(feature = "console_error_panic_hook")]
     console_error_panic_hook::set_once();
     
+    let result = decompress_str(data).map_err(|e| JsValue::from_str(&e))?;
+    
+    console_log!("Decompressed: {} bytes", result.len());
+    
+    Ok(result)
+}
+
+fn decompress_str(data: &str) -> Result<String, String> {
     // Decode base64
     let compressed = base64_decode(data)
-        .map_err(|e| JsValue::from_str(&format!("Base64 decode error: {}", e)))?;
+        .map_err(|e| format!("Base64 decode error: {}", e))?;
     
     // Decompress
-    let decompressed = decompress_size_prepended(&compressed)
-        .map_err(|e| JsValue::from_str(&format!("Decompression error: {:?}", e)))?;
+    let decompressed = decompress_raw(&compressed)?;
     
-    let result = String::from_utf8(decompressed)
-        .map_err(|e| JsValue::from_str(&format!("UTF-8 decode error: {}", e)))?;
+    String::from_utf8(decompressed)
+        .map_err(|e| format!("UTF-8 decode error: {}", e))
+}
+
+/// Compress data using LZ4
+/// Returns URL-safe base64 (`-` and `_`, no padding) for use in links
+#[wasm_bindgen]
+pub fn compress_urlsafe(data: &str) -> Result<String, JsValue> {
+    #[cfg(feature = "console_error_panic_hook")]
+    console_error_panic_hook::set_once();
     
-    console_log!("Decompressed: {} bytes", result.len());
+    let compressed = compress_bytes(data.as_bytes());
+    Ok(URL_SAFE_NO_PAD.encode(compressed))
+}
+
+/// Decompress data produced by `compress_urlsafe`
+#[wasm_bindgen]
+pub fn decompress_urlsafe(data: &str) -> Result<String, JsValue> {
+    #[cfg(feature = "console_error_panic_hook")]
+    console_error_panic_hook::set_once();
     
-    Ok(result)
+    decompress_urlsafe_str(data).map_err(|e| JsValue::from_str(&e))
 }
 
+fn decompress_urlsafe_str(data: &str) -> Result<String, String> {
+    let compressed = URL_SAFE_NO_PAD
+        .decode(data.trim())
+        .map_err(|e| format!("Base64 decode error: {}", e))?;
+    
+    let decompressed = decompress_raw(&compressed)?;
+    
+    String::from_utf8(decompressed)
+        .map_err(|e| format!("UTF-8 decode error: {}", e))
+}
+
+/// Chunked payloads start with `KC` and the format version, then the
+/// chunk count (u32 LE), each chunk's size (u32 LE) and the chunks, each
+/// in the `compress_bytes` format
+const CHUNKED_MAGIC: [u8; 3] = [b'K', b'C', FORMAT_VERSION];
+
+/// Compress data in independent chunks of `chunk_size` bytes, so only
+/// one chunk is being compressed at a time. Returns base64; read it
+/// back with `decompress_chunked`.
+#[wasm_bindgen]
+pub fn compress_chunked(data: &str, chunk_size: usize) -> Result<String, JsValue> {
+    #[cfg(feature = "console_error_panic_hook")]
+    console_error_panic_hook::set_once();
+    
+    let chunked = compress_chunked_bytes(data.as_bytes(), chunk_size).map_err(|e| JsValue::from_str(&e))?;
+    Ok(base64_encode(&chunked))
+}
+
+/// Decompress data produced by `compress_chunked`
+#[wasm_bindgen]
+pub fn decompress_chunked(data: &str) -> Result<String, JsValue> {
+    #[cfg(feature = "console_error_panic_hook")]
+    console_error_panic_hook::set_once();
+    
+    decompress_chunked_str(data).map_err(|e| JsValue::from_str(&e))
+}
+
+fn compress_chunked_bytes(data: &[u8], chunk_size: usize) -> Result<Vec<u8>, String> {
+    if chunk_size == 0 {
+        return Err("Chunk size must be greater than 0".to_string());
+    }
+    
+    let chunks: Vec<Vec<u8>> = data.chunks(chunk_size).map(compress_bytes).collect();
+    let header_len = CHUNKED_MAGIC.len() + 4 * (chunks.len() + 1);
+    let mut out = Vec::with_capacity(header_len + chunks.iter().map(Vec::len).sum::<usize>());
+    out.extend_from_slice(&CHUNKED_MAGIC);
+    out.extend_from_slice(&(chunks.len() as u32).to_le_bytes());
+    for chunk in &chunks {
+        out.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+    }
+    for chunk in &chunks {
+        out.extend_from_slice(chunk);
+    }
+    Ok(out)
+}
+
+fn decompress_chunked_str(data: &str) -> Result<String, String> {
+    let chunked = base64_decode(data)
+        .map_err(|e| format!("Base64 decode error: {}", e))?;
+    
+    let decompressed = decompress_chunked_bytes(&chunked)?;
+    
+    String::from_utf8(decompressed)
+        .map_err(|e| format!("UTF-8 decode error: {}", e))
+}
+
+/// Decompress a chunked payload. Its header is untrusted, so every
+/// offset is checked: a crafted count or size must not overflow (usize
+/// is 32 bits on wasm32).
+fn decompress_chunked_bytes(data: &[u8]) -> Result<Vec<u8>, String> {
+    let truncated = || "Chunked payload is truncated".to_string();
+    let corrupt = || "Corrupt chunked payload".to_string();
+    let read_u32 = |pos: usize| -> Result<usize, String> {
+        let bytes = data.get(pos..pos.checked_add(4).ok_or_else(corrupt)?).ok_or_else(truncated)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    };
+    
+    if !data.starts_with(&CHUNKED_MAGIC) {
+        return Err("Not a chunked payload".to_string());
+    }
+    let count = read_u32(CHUNKED_MAGIC.len())?;
+    let sizes_at = CHUNKED_MAGIC.len() + 4;
+    // Each chunk needs at least its 4-byte size in the header
+    if count > (data.len() - sizes_at) / 4 {
+        return Err(corrupt());
+    }
+    let mut pos = sizes_at + 4 * count;
+    
+    let mut out = Vec::new();
+    for i in 0..count {
+        let size = read_u32(sizes_at + 4 * i)?;
+        let end = pos.checked_add(size).ok_or_else(corrupt)?;
+        let chunk = data.get(pos..end).ok_or_else(truncated)?;
+        out.extend(decompress_raw(chunk).map_err(|e| format!("Chunk {}: {}", i, e))?);
+        pos += size;
+    }
+    if pos != data.len() {
+        return Err("Chunked payload has trailing data".to_string());
+    }
+    Ok(out)
+}
+
+/// Delta patches start with `KD` and the format version, then a mode byte
+const DELTA_MAGIC: [u8; 3] = [b'K', b'D', FORMAT_VERSION];
+/// Mode: the new version in the `compress_bytes` format
+const DELTA_FULL: u8 = 0;
+/// Mode: common prefix and suffix lengths (u32 LE), CRC32s of the old and
+/// new versions, then the changed middle LZ4-compressed against the old
+/// middle as dictionary
+const DELTA_PATCH: u8 = 1;
+
+/// Compress `new` as a patch against `old`. Falls back to compressing
+/// `new` in full when that is smaller. Returns base64; apply it with
+/// `apply_delta` and the same `old`.
+#[wasm_bindgen]
+pub fn compress_delta(old: &str, new: &str) -> Result<String, JsValue> {
+    #[cfg(feature = "console_error_panic_hook")]
+    console_error_panic_hook::set_once();
+    
+    Ok(base64_encode(&delta_bytes(old.as_bytes(), new.as_bytes())))
+}
+
+/// Rebuild the new version from `old` and a patch from `compress_delta`
+#[wasm_bindgen]
+pub fn apply_delta(old: &str, patch: &str) -> Result<String, JsValue> {
+    #[cfg(feature = "console_error_panic_hook")]
+    console_error_panic_hook::set_once();
+    
+    apply_delta_str(old, patch).map_err(|e| JsValue::from_str(&e))
+}
+
+fn delta_bytes(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let max_suffix = old.len().min(new.len()) - prefix;
+    let suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take(max_suffix)
+        .take_while(|(a, b)| a == b)
+        .count();
+    
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+    
+    let mut patch = DELTA_MAGIC.to_vec();
+    patch.push(DELTA_PATCH);
+    patch.extend_from_slice(&(prefix as u32).to_le_bytes());
+    patch.extend_from_slice(&(suffix as u32).to_le_bytes());
+    patch.extend_from_slice(&crc32fast::hash(old).to_le_bytes());
+    patch.extend_from_slice(&crc32fast::hash(new).to_le_bytes());
+    patch.extend(compress_prepend_size_with_dict(new_middle, old_middle));
+    
+    let full = compress_bytes(new);
+    if full.len() + DELTA_MAGIC.len() + 1 < patch.len() {
+        patch.truncate(DELTA_MAGIC.len());
+        patch.push(DELTA_FULL);
+        patch.extend(full);
+    }
+    patch
+}
+
+fn apply_delta_str(old: &str, patch: &str) -> Result<String, String> {
+    let patch = base64_decode(patch)
+        .map_err(|e| format!("Base64 decode error: {}", e))?;
+    
+    let new = apply_delta_bytes(old.as_bytes(), &patch)?;
+    
+    String::from_utf8(new)
+        .map_err(|e| format!("UTF-8 decode error: {}", e))
+}
+
+fn apply_delta_bytes(old: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let body = patch.strip_prefix(&DELTA_MAGIC[..]).ok_or("Not a delta patch")?;
+    let (mode, body) = body.split_first().ok_or("Delta patch is truncated")?;
+    match *mode {
+        DELTA_FULL => decompress_raw(body),
+        DELTA_PATCH => {
+            let (header, block) = body.split_first_chunk::<16>().ok_or("Delta patch is truncated")?;
+            let field = |i: usize| u32::from_le_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]]);
+            let (prefix, suffix) = (field(0) as usize, field(4) as usize);
+            if crc32fast::hash(old) != field(8) || prefix.checked_add(suffix).is_none_or(|n| n > old.len()) {
+                return Err("Delta base mismatch: patch was made against a different version".to_string());
+            }
+            
+            let old_middle = &old[prefix..old.len() - suffix];
+            let middle = decompress_size_prepended_with_dict(block, old_middle)
+                .map_err(|e| format!("Decompression error: {:?}", e))?;
+            
+            let mut new = Vec::with_capacity(prefix + middle.len() + suffix);
+            new.extend_from_slice(&old[..prefix]);
+            new.extend(middle);
+            new.extend_from_slice(&old[old.len() - suffix..]);
+            if crc32fast::hash(&new) != field(12) {
+                return Err("Checksum mismatch: delta patch is corrupted".to_string());
+            }
+            Ok(new)
+        }
+        mode => Err(format!("Unknown delta mode {}", mode)),
+    }
+}
+
+/// Base64 characters inspected by `is_compressed`
+const SNIFF_CHARS: usize = 24;
+
+/// LZ4 can't expand data by more than this factor
+const MAX_LZ4_RATIO: usize = 255;
+
 /// Check if data is compressed (by checking prefix)
 #[wasm_bindgen]
 pub fn is_compressed(data: &str) -> bool {
-    // Check if it starts with our magic bytes or looks like base64 of compressed data
     if data.len() < 10 {
         return false;
     }
     
-    // Try to decode first few bytes and check for LZ4 magic
-    if let Ok(decoded) = base64_decode(&data[..20]) {
-        // LZ4 frame format starts with specific magic number
-        // But for our simple prepend_size format, we just check if it can be decompressed
-        decompress_size_prepended(&decoded).is_ok()
-    } else {
-        false
-    }
+    // Decode a bounded prefix, taking whole base64 quads only. Working
+    // on bytes keeps raw user text (multibyte UTF-8) from panicking;
+    // it simply fails to decode.
+    let prefix: Vec<u8> = data
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace())
+        .take(SNIFF_CHARS)
+        .collect();
+    let usable = prefix.len() / 4 * 4;
+    let Ok(decoded) = base64_decode_bytes(&prefix[..usable]) else {
+        return false;
+    };
+    
+    // The LZ4 block starts with the uncompressed size (u32 LE), after the
+    // frame header unless this is a legacy payload. The size can't exceed
+    // what the rest of the input could expand to.
+    let size_bytes = match decoded.strip_prefix(&FRAME_MAGIC[..]) {
+        Some(frame) => frame.get(4..8),
+        None => decoded.get(..4),
+    };
+    let Some(&[a, b, c, d]) = size_bytes else {
+        return false;
+    };
+    let size = u32::from_le_bytes([a, b, c, d]) as usize;
+    let max_size = (data.len() / 4 * 3).saturating_mul(MAX_LZ4_RATIO);
+    size <= max_size
 }
 
-/// Get compression ratio
+/// Get compression ratio: space saved in percent of the original size.
+/// Negative when the compressed form is larger, as it is for small or
+/// random input.
 #[wasm_bindgen]
 pub fn get_compression_ratio(original: &str, compressed: &str) -> f32 {
     let original_len = original.len();
@@ -81,69 +428,179 @@ pub fn get_compression_ratio(original: &str, compressed: &str) -> f32 {
         return 0.0;
     }
     
-    ((original_len - compressed_len) as f32 / original_len as f32) * 100.0
+    ((1.0 - compressed_len as f64 / original_len as f64) * 100.0) as f32
 }
 
-/// Simple base64 encode
-fn base64_encode(data: &[u8]) -> String {
-    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
-    let mut result = String::new();
-    
-    for chunk in data.chunks(3) {
-        let b = match chunk.len() {
-            1 => [chunk[0], 0, 0],
-            2 => [chunk[0], chunk[1], 0],
-            _ => [chunk[0], chunk[1], chunk[2]],
-        };
-        
-        result.push(ALPHABET[(b[0] >> 2) as usize] as char);
-        result.push(ALPHABET[(((b[0] & 0x03) << 4) | (b[1] >> 4)) as usize] as char);
-        
-        if chunk.len() > 1 {
-            result.push(ALPHABET[(((b[1] & 0x0f) << 2) | (b[2] >> 6)) as usize] as char);
-        } else {
-            result.push('=');
-        }
-        
-        if chunk.len() > 2 {
-            result.push(ALPHABET[(b[2] & 0x3f) as usize] as char);
-        } else {
-            result.push('=');
-        }
+/// Get the space saved by compression, in percent of the original size.
+/// `compressed_base64` is decoded first so base64 overhead isn't counted
+/// against the compressor. Clamped to 0..=100: 0 means compression
+/// didn't help (or the input isn't valid base64), 100 is never reached
+/// for non-empty input.
+#[wasm_bindgen]
+pub fn get_real_compression_ratio(original: &str, compressed_base64: &str) -> f32 {
+    let original_len = original.len();
+    if original_len == 0 {
+        return 0.0;
     }
+    let Ok(compressed) = base64_decode(compressed_base64) else {
+        return 0.0;
+    };
     
-    result
+    let saved = 1.0 - compressed.len() as f32 / original_len as f32;
+    (saved * 100.0).clamp(0.0, 100.0)
 }
 
-/// Simple base64 decode
-fn base64_decode(data: &str) -> Result<Vec<u8>, &'static str> {
-    const ALPHABET: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+/// Standard (padded) base64 encode
+fn base64_encode(data: &[u8]) -> String {
+    STANDARD.encode(data)
+}
+
+/// Standard base64 decode. ASCII whitespace (e.g. line breaks added by
+/// storage layers) is ignored; bad characters, lengths or padding are
+/// rejected.
+fn base64_decode(data: &str) -> Result<Vec<u8>, base64::DecodeError> {
+    let cleaned: Vec<u8> = data.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    base64_decode_bytes(&cleaned)
+}
+
+fn base64_decode_bytes(data: &[u8]) -> Result<Vec<u8>, base64::DecodeError> {
+    STANDARD.decode(data)
+}
+
+/// High-compression LZ4 block encoder. `lz4_flex` only ships the fast
+/// single-probe encoder, so this searches a hash chain of earlier
+/// positions for the longest match, with one step of lazy matching.
+/// Output is a standard LZ4 block.
+mod hc {
+    const MIN_MATCH: usize = 4;
+    /// The last 5 bytes of a block are always literals
+    const LAST_LITERALS: usize = 5;
+    /// The last match must start at least 12 bytes before the end
+    const MF_LIMIT: usize = 12;
+    const MAX_DISTANCE: usize = 65_535;
+    const HASH_LOG: u32 = 16;
+    const WINDOW_MASK: usize = 0xFFFF;
+    const NONE: usize = usize::MAX;
+    const MAX_LEVEL: u32 = 12;
     
-    let mut result = Vec::new();
-    let chars: Vec<char> = data.chars().collect();
+    struct MatchFinder<'a> {
+        input: &'a [u8],
+        head: Vec<usize>,
+        chain: Vec<usize>,
+        next_insert: usize,
+        max_attempts: usize,
+    }
     
-    for chunk in chars.chunks(4) {
-        if chunk.len() < 2 {
-            return Err("Invalid base64");
+    impl<'a> MatchFinder<'a> {
+        fn new(input: &'a [u8], level: u32) -> Self {
+            Self {
+                input,
+                head: vec![NONE; 1 << HASH_LOG],
+                chain: vec![NONE; WINDOW_MASK + 1],
+                next_insert: 0,
+                max_attempts: 1 << (level.clamp(1, MAX_LEVEL) - 1),
+            }
         }
         
-        let c1 = ALPHABET.find(chunk[0]).ok_or("Invalid char")? as u8;
-        let c2 = ALPHABET.find(chunk[1]).ok_or("Invalid char")? as u8;
+        fn hash(&self, pos: usize) -> usize {
+            let bytes = [self.input[pos], self.input[pos + 1], self.input[pos + 2], self.input[pos + 3]];
+            (u32::from_le_bytes(bytes).wrapping_mul(2_654_435_761) >> (32 - HASH_LOG)) as usize
+        }
         
-        result.push((c1 << 2) | (c2 >> 4));
+        /// Longest earlier match for `pos` as `(length, offset)`; matches
+        /// never extend past `limit`
+        fn find(&mut self, pos: usize, limit: usize) -> Option<(usize, usize)> {
+            while self.next_insert < pos {
+                let h = self.hash(self.next_insert);
+                self.chain[self.next_insert & WINDOW_MASK] = self.head[h];
+                self.head[h] = self.next_insert;
+                self.next_insert += 1;
+            }
+            
+            let mut best: Option<(usize, usize)> = None;
+            let mut candidate = self.head[self.hash(pos)];
+            for _ in 0..self.max_attempts {
+                if candidate == NONE || pos - candidate > MAX_DISTANCE {
+                    break;
+                }
+                let len = self.input[candidate..limit]
+                    .iter()
+                    .zip(&self.input[pos..limit])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len >= MIN_MATCH && best.is_none_or(|(best_len, _)| len > best_len) {
+                    best = Some((len, pos - candidate));
+                }
+                let next = self.chain[candidate & WINDOW_MASK];
+                if next >= candidate {
+                    break;
+                }
+                candidate = next;
+            }
+            best
+        }
+    }
+    
+    pub fn compress_block(input: &[u8], level: u32) -> Vec<u8> {
+        let mut out = Vec::with_capacity(input.len() / 2 + 16);
+        let mut anchor = 0;
         
-        if chunk.len() > 2 && chunk[2] != '=' {
-            let c3 = ALPHABET.find(chunk[2]).ok_or("Invalid char")? as u8;
-            result.push(((c2 & 0x0f) << 4) | (c3 >> 2));
+        if input.len() > MF_LIMIT {
+            let last_match_start = input.len() - MF_LIMIT;
+            let match_limit = input.len() - LAST_LITERALS;
+            let mut finder = MatchFinder::new(input, level);
+            let mut pos = 0;
             
-            if chunk.len() > 3 && chunk[3] != '=' {
-                let c4 = ALPHABET.find(chunk[3]).ok_or("Invalid char")? as u8;
-                result.push(((c3 & 0x03) << 6) | c4);
+            while pos <= last_match_start {
+                let Some((mut len, mut offset)) = finder.find(pos, match_limit) else {
+                    pos += 1;
+                    continue;
+                };
+                // Lazy matching: prefer a longer match starting one byte later
+                if pos < last_match_start {
+                    if let Some((next_len, next_offset)) = finder.find(pos + 1, match_limit) {
+                        if next_len > len {
+                            pos += 1;
+                            len = next_len;
+                            offset = next_offset;
+                        }
+                    }
+                }
+                
+                write_sequence(&mut out, &input[anchor..pos], Some((offset, len)));
+                pos += len;
+                anchor = pos;
             }
         }
+        
+        write_sequence(&mut out, &input[anchor..], None);
+        out
     }
     
-    Ok(result)
+    fn write_sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+        let match_code = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+        let token = ((literals.len().min(15) as u8) << 4) | match_code.min(15) as u8;
+        out.push(token);
+        if literals.len() >= 15 {
+            write_length(out, literals.len() - 15);
+        }
+        out.extend_from_slice(literals);
+        
+        if let Some((offset, _)) = matched {
+            out.extend_from_slice(&(offset as u16).to_le_bytes());
+            if match_code >= 15 {
+                write_length(out, match_code - 15);
+            }
+        }
+    }
+    
+    fn write_length(out: &mut Vec<u8>, mut len: usize) {
+        while len >= 255 {
+            out.push(255);
+            len -= 255;
+        }
+        out.push(len as u8);
+    }
 }
 
 #[cfg(test)]
@@ -165,4 +622,234 @@ mod tests {
         let decoded = base64_decode(&encoded).unwrap();
         assert_eq!(data.to_vec(), decoded);
     }
+
+    #[test]
+    fn test_decompress_ignores_whitespace() {
+        let original = "Task list export ".repeat(20);
+        let compressed = compress(&original).unwrap();
+        let wrapped: Vec<String> = compressed
+            .as_bytes()
+            .chunks(16)
+            .map(|line| String::from_utf8(line.to_vec()).unwrap())
+            .collect();
+        let wrapped = format!("  {}\r\n", wrapped.join("\r\n"));
+        assert_eq!(decompress_str(&wrapped).unwrap(), original);
+    }
+    
+    #[test]
+    fn test_malformed_base64_is_rejected() {
+        let compressed = compress("Hello, World! Hello, World!").unwrap();
+        assert!(decompress_str(&compressed[..compressed.len() - 1]).is_err());
+        assert!(base64_decode("SGVsbG8").is_err());
+        assert!(base64_decode("SGVsbG8=A").is_err());
+        assert!(base64_decode("SGV*bG8=").is_err());
+    }
+
+    #[test]
+    fn test_is_compressed() {
+        assert!(!is_compressed("สวัสดีชาวโลก"));
+        assert!(!is_compressed("ab สวัสดีชาวโลก"));
+        assert!(!is_compressed("plain task notes"));
+        assert!(is_compressed(&compress("Hello, World! Hello, World!").unwrap()));
+        assert!(is_compressed(&compress(&"สวัสดี ".repeat(100)).unwrap()));
+    }
+
+    #[test]
+    fn test_bytes_roundtrip_with_invalid_utf8() {
+        let original: Vec<u8> = [0xff, 0xfe, 0x00, 0xc3, 0x28, 0x80]
+            .iter()
+            .copied()
+            .cycle()
+            .take(600)
+            .collect();
+        assert!(String::from_utf8(original.clone()).is_err());
+        
+        let compressed = compress_bytes(&original);
+        assert!(compressed.len() < original.len());
+        assert_eq!(decompress_raw(&compressed).unwrap(), original);
+        assert!(decompress_raw(&compressed[..compressed.len() - 2]).is_err());
+    }
+    
+    #[test]
+    fn test_bytes_roundtrip_random_buffers() {
+        // xorshift, so failures reproduce
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        };
+        for len in [0, 1, 15, 255, 4096, 70_000] {
+            let original: Vec<u8> = (0..len).map(|_| next()).collect();
+            assert_eq!(decompress_raw(&compress_bytes(&original)).unwrap(), original, "length {}", len);
+        }
+        assert_eq!(decompress_str(&compress("").unwrap()).unwrap(), "");
+    }
+
+    #[test]
+    fn test_urlsafe_roundtrip() {
+        // Bytes that encode to `+` and `/` in the standard alphabet
+        let original = "~~~??>>> สวัสดี ~~~??>>> 🌍";
+        assert!(compress(original).unwrap().contains(['+', '/']));
+        
+        let encoded = compress_urlsafe(original).unwrap();
+        assert!(!encoded.contains(['+', '/', '=']));
+        assert_eq!(decompress_urlsafe_str(&encoded).unwrap(), original);
+    }
+
+    #[test]
+    fn test_corrupted_payload_fails_checksum() {
+        let original = "Quarterly planning: migrate sync server, fix offline queue";
+        let mut compressed = compress_bytes(original.as_bytes());
+        let last = compressed.len() - 3;
+        compressed[last] ^= 0x01;
+        
+        let err = decompress_raw(&compressed).unwrap_err();
+        assert!(err.contains("Checksum mismatch"), "{}", err);
+        
+        // Read as legacy, "KP\x01" and the first checksum byte claim ~4 GB
+        compressed[3] = 0xff;
+        let err = decompress_raw(&compressed).unwrap_err();
+        assert!(err.contains("Checksum mismatch"), "{}", err);
+        assert!(decompress_legacy(&compressed).unwrap_err().contains("declared size"));
+    }
+    
+    #[test]
+    fn test_legacy_payload_still_decompresses() {
+        let original = "Hello, World! สวัสดีชาวโลก";
+        let legacy = base64_encode(&compress_prepend_size(original.as_bytes()));
+        assert!(is_compressed(&legacy));
+        assert_eq!(decompress_str(&legacy).unwrap(), original);
+        
+        let mut future = compress_bytes(original.as_bytes());
+        future[2] = FORMAT_VERSION + 1;
+        assert!(decompress_raw(&future).unwrap_err().contains("version"));
+    }
+
+    #[test]
+    fn test_compression_ratio_of_random_input() {
+        // xorshift over printable ASCII: LZ4 finds nothing to match
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let original: String = (0..2000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (b'!' + (state % 94) as u8) as char
+            })
+            .collect();
+        let compressed = compress(&original).unwrap();
+        assert!(compressed.len() > original.len());
+        assert!(get_compression_ratio(&original, &compressed) < 0.0);
+        assert_eq!(get_compression_ratio("aaaa", "aa"), 50.0);
+    }
+
+    #[test]
+    fn test_real_compression_ratio() {
+        let original = "status: todo; ".repeat(500);
+        let compressed = compress(&original).unwrap();
+        let ratio = get_real_compression_ratio(&original, &compressed);
+        assert!(ratio > 90.0 && ratio < 100.0, "{}", ratio);
+        assert!(ratio > get_compression_ratio(&original, &compressed));
+        
+        let short = "hi there";
+        assert_eq!(get_real_compression_ratio(short, &compress(short).unwrap()), 0.0);
+        assert_eq!(get_real_compression_ratio(short, "not base64!"), 0.0);
+    }
+
+    #[test]
+    fn test_compress_hc_is_no_larger() {
+        let original = "task: Review PR #42, status: in review, assignee: somchai; ".repeat(200);
+        let fast = compress(&original).unwrap();
+        for level in [1, 4, 9, 12] {
+            let hc = compress_hc(&original, level).unwrap();
+            assert!(hc.len() <= fast.len(), "level {}: {} > {}", level, hc.len(), fast.len());
+            assert_eq!(decompress_str(&hc).unwrap(), original);
+        }
+    }
+    
+    #[test]
+    fn test_compress_hc_roundtrips_edge_cases() {
+        let pseudo_random: String = (0u32..5000)
+            .map(|i| char::from(b'a' + (i.wrapping_mul(2_654_435_761) >> 27) as u8 % 26))
+            .collect();
+        let inputs = [
+            String::new(),
+            "short".to_string(),
+            "a".repeat(100_000),
+            "สวัสดีชาวโลก ".repeat(50),
+            pseudo_random,
+        ];
+        for input in &inputs {
+            let hc = compress_hc(input, 9).unwrap();
+            assert_eq!(&decompress_str(&hc).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn test_chunked_roundtrip() {
+        let original: String = (0..60_000)
+            .map(|i| format!("{{\"id\":{},\"title\":\"งาน {}\"}},", i, i % 97))
+            .collect();
+        assert!(original.len() > 2_000_000);
+        
+        let chunked = compress_chunked_bytes(original.as_bytes(), 256 * 1024).unwrap();
+        let count = u32::from_le_bytes([chunked[3], chunked[4], chunked[5], chunked[6]]);
+        assert!(count > 1);
+        assert!(chunked.len() < original.len());
+        
+        let encoded = compress_chunked(&original, 256 * 1024).unwrap();
+        assert_eq!(decompress_chunked_str(&encoded).unwrap(), original);
+        assert!(decompress_chunked_bytes(&chunked[..chunked.len() - 1]).is_err());
+        assert!(compress_chunked_bytes(b"data", 0).is_err());
+    }
+
+    #[test]
+    fn test_chunked_header_cannot_overflow() {
+        let mut crafted = CHUNKED_MAGIC.to_vec();
+        crafted.extend_from_slice(&u32::MAX.to_le_bytes());
+        crafted.extend_from_slice(&[0; 8]);
+        assert_eq!(decompress_chunked_bytes(&crafted).unwrap_err(), "Corrupt chunked payload");
+        
+        // One chunk claiming u32::MAX bytes
+        let mut crafted = CHUNKED_MAGIC.to_vec();
+        crafted.extend_from_slice(&1u32.to_le_bytes());
+        crafted.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(decompress_chunked_bytes(&crafted).is_err());
+        assert!(decompress_chunked_bytes(&CHUNKED_MAGIC).is_err());
+    }
+
+    #[test]
+    fn test_delta_is_smaller_than_full() {
+        let old: String = (0..500)
+            .map(|i| format!("{{\"id\":{},\"title\":\"Task {}\",\"status\":\"todo\"}},", i, i * 7919 % 1000))
+            .collect();
+        let task = old.find("\"id\":250,").unwrap();
+        let status = task + old[task..].find("todo").unwrap();
+        let new = format!("{}done{}", &old[..status], &old[status + 4..]);
+        
+        let delta = compress_delta(&old, &new).unwrap();
+        let full = compress(&new).unwrap();
+        assert!(delta.len() * 10 < full.len(), "delta {} vs full {}", delta.len(), full.len());
+        assert_eq!(apply_delta_str(&old, &delta).unwrap(), new);
+        assert!(apply_delta_str(&new, &delta).unwrap_err().contains("base mismatch"));
+        
+        // Prefix and suffix lengths that overflow when added
+        let mut patch = base64_decode(&delta).unwrap();
+        let header = DELTA_MAGIC.len() + 1;
+        patch[header..header + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        patch[header + 4..header + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(apply_delta_bytes(old.as_bytes(), &patch).unwrap_err().contains("base mismatch"));
+    }
+    
+    #[test]
+    fn test_delta_falls_back_to_full() {
+        let old = "completely different";
+        let new = "ข้อมูลใหม่ทั้งหมด ".repeat(30);
+        let patch = delta_bytes(old.as_bytes(), new.as_bytes());
+        assert_eq!(patch[DELTA_MAGIC.len()], DELTA_FULL);
+        assert_eq!(apply_delta_bytes(old.as_bytes(), &patch).unwrap(), new.as_bytes());
+        assert_eq!(apply_delta_str("", &compress_delta("", "").unwrap()).unwrap(), "");
+    }
 }
@@ -1,22 +1,49 @@
 use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 
+#[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(js_namespace = console)]
     fn log(s: &str);
 }
 
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_name = queueMicrotask)]
+    fn queue_microtask(callback: &js_sys::Function);
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = crypto, js_name = getRandomValues)]
+    fn get_random_values(buf: &mut [u8]);
+}
+
+// Native builds (tests, server-side tooling) have no JS console to log to
+#[cfg(not(target_arch = "wasm32"))]
+fn log(_s: &str) {}
+
 macro_rules! console_log {
     ($($t:tt)*) => (log(&format_args!($($t)*).to_string()))
 }
 
-/// Lamport Timestamp for ordering
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+/// Lamport Timestamp for ordering, optionally led by wall-clock time
+/// (hybrid logical clock, see `CrdtDocument::tick`)
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct LamportTimestamp {
     pub counter: u64,
     pub node_id: String,
+    /// Milliseconds since the epoch, 0 for replicas that never `tick`
+    /// and for timestamps written before wall time was recorded
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub wall_ms: u64,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
 }
 
 impl LamportTimestamp {
@@ -24,315 +51,6107 @@ impl LamportTimestamp {
         Self {
             counter,
             node_id: node_id.to_string(),
+            wall_ms: 0,
         }
     }
     
     pub fn increment(&mut self) {
         self.counter += 1;
     }
+    
+    /// Operation id derived from this timestamp (`node_id:counter`)
+    pub fn op_id(&self) -> String {
+        format!("{}:{}", self.node_id, self.counter)
+    }
+}
+
+impl Ord for LamportTimestamp {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.wall_ms, self.counter, &self.node_id).cmp(&(other.wall_ms, other.counter, &other.node_id))
+    }
+}
+
+impl PartialOrd for LamportTimestamp {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Whether a write at `a` beats one at `b`. Timestamps are totally
+/// ordered: by wall time, then by counter, then by node id for writes
+/// from different nodes with the same counter. Equal timestamps are the
+/// same write, so neither wins. Every last-write-wins decision goes
+/// through this, so replicas pick the same winner whatever order writes
+/// arrive in.
+fn wins(a: &LamportTimestamp, b: &LamportTimestamp) -> bool {
+    a > b
 }
 
 /// CRDT Operation for tasks
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Operation {
     Insert {
-        task_id: u32,
+        #[serde(default)]
+        op_id: String,
+        task_id: String,
         field: String,
-        value: String,
+        value: serde_json::Value,
         timestamp: LamportTimestamp,
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        clock: VectorClock,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        author: Option<String>,
     },
     Update {
-        task_id: u32,
+        #[serde(default)]
+        op_id: String,
+        task_id: String,
         field: String,
-        value: String,
+        value: serde_json::Value,
+        timestamp: LamportTimestamp,
+        /// Writer's vector clock when the value was written (empty if unknown)
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        clock: VectorClock,
+        /// Name set with `set_author` on the writing document
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        author: Option<String>,
+    },
+    /// Several fields of one task written together under one timestamp
+    UpdateFields {
+        #[serde(default)]
+        op_id: String,
+        task_id: String,
+        fields: BTreeMap<String, serde_json::Value>,
         timestamp: LamportTimestamp,
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        clock: VectorClock,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        author: Option<String>,
     },
     Delete {
-        task_id: u32,
+        #[serde(default)]
+        op_id: String,
+        task_id: String,
+        timestamp: LamportTimestamp,
+    },
+    /// Undo of a delete
+    Restore {
+        #[serde(default)]
+        op_id: String,
+        task_id: String,
+        timestamp: LamportTimestamp,
+    },
+    /// Hide a task from the board without deleting it
+    Archive {
+        #[serde(default)]
+        op_id: String,
+        task_id: String,
+        timestamp: LamportTimestamp,
+    },
+    /// Undo of an archive
+    Unarchive {
+        #[serde(default)]
+        op_id: String,
+        task_id: String,
+        timestamp: LamportTimestamp,
+    },
+    /// Permanent removal, leaving only a delete marker
+    Purge {
+        #[serde(default)]
+        op_id: String,
+        task_id: String,
+        timestamp: LamportTimestamp,
+    },
+    Increment {
+        #[serde(default)]
+        op_id: String,
+        task_id: String,
+        field: String,
+        delta: i64,
+        /// The node's running total on `delta`'s side of the counter after
+        /// this increment. Applied as a max, like a merge, so an increment
+        /// that also arrives in a merged state isn't counted twice. Ops
+        /// from before it carry none and are added.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        total: Option<u64>,
+        timestamp: LamportTimestamp,
+    },
+    /// Add to a task's tag set; the op id identifies this add
+    AddTag {
+        #[serde(default)]
+        op_id: String,
+        task_id: String,
+        tag: String,
+        timestamp: LamportTimestamp,
+    },
+    /// Remove a tag: only the adds listed in `removed` (those the remover
+    /// had seen) are cancelled, so a concurrent add survives
+    RemoveTag {
+        #[serde(default)]
+        op_id: String,
+        task_id: String,
+        tag: String,
+        removed: Vec<String>,
         timestamp: LamportTimestamp,
     },
 }
 
-/// CRDT Document for a Task
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct CrdtTask {
-    pub id: u32,
-    pub fields: HashMap<String, CrdtValue>,
-    pub deleted: bool,
-    pub created_at: LamportTimestamp,
-    pub updated_at: LamportTimestamp,
+impl Operation {
+    /// Update op re-emitting a field value taken over during a merge. The
+    /// fields of one `upsert_fields` share a timestamp, so the id adds the
+    /// field: `node_id:counter:field`.
+    fn merged_update(task_id: &str, field: &str, value: &CrdtValue) -> Self {
+        Operation::Update {
+            op_id: format!("{}:{}", value.timestamp.op_id(), field),
+            task_id: task_id.to_string(),
+            field: field.to_string(),
+            value: value.value.clone(),
+            timestamp: value.timestamp.clone(),
+            clock: VectorClock::new(),
+            author: value.author.clone(),
+        }
+    }
+    
+    pub fn timestamp(&self) -> &LamportTimestamp {
+        match self {
+            Operation::Insert { timestamp, .. }
+            | Operation::Update { timestamp, .. }
+            | Operation::UpdateFields { timestamp, .. }
+            | Operation::Delete { timestamp, .. }
+            | Operation::Restore { timestamp, .. }
+            | Operation::Archive { timestamp, .. }
+            | Operation::Unarchive { timestamp, .. }
+            | Operation::Purge { timestamp, .. }
+            | Operation::Increment { timestamp, .. }
+            | Operation::AddTag { timestamp, .. }
+            | Operation::RemoveTag { timestamp, .. } => timestamp,
+        }
+    }
+    
+    pub fn task_id(&self) -> &str {
+        match self {
+            Operation::Insert { task_id, .. }
+            | Operation::Update { task_id, .. }
+            | Operation::UpdateFields { task_id, .. }
+            | Operation::Delete { task_id, .. }
+            | Operation::Restore { task_id, .. }
+            | Operation::Archive { task_id, .. }
+            | Operation::Unarchive { task_id, .. }
+            | Operation::Purge { task_id, .. }
+            | Operation::Increment { task_id, .. }
+            | Operation::AddTag { task_id, .. }
+            | Operation::RemoveTag { task_id, .. } => task_id,
+        }
+    }
+    
+    /// Unique id of this operation. Ops serialized before ids existed
+    /// fall back to the id derived from their timestamp.
+    pub fn op_id(&self) -> String {
+        let op_id = match self {
+            Operation::Insert { op_id, .. }
+            | Operation::Update { op_id, .. }
+            | Operation::UpdateFields { op_id, .. }
+            | Operation::Delete { op_id, .. }
+            | Operation::Restore { op_id, .. }
+            | Operation::Archive { op_id, .. }
+            | Operation::Unarchive { op_id, .. }
+            | Operation::Purge { op_id, .. }
+            | Operation::Increment { op_id, .. }
+            | Operation::AddTag { op_id, .. }
+            | Operation::RemoveTag { op_id, .. } => op_id,
+        };
+        if op_id.is_empty() {
+            self.timestamp().op_id()
+        } else {
+            op_id.clone()
+        }
+    }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct CrdtValue {
-    pub value: String,
-    pub timestamp: LamportTimestamp,
+/// Highest counter seen from each node
+pub type VectorClock = HashMap<String, u64>;
+
+/// Whether `clock` covers the write made at `timestamp`
+fn has_seen(clock: &VectorClock, timestamp: &LamportTimestamp) -> bool {
+    clock.get(&timestamp.node_id).is_some_and(|&counter| counter >= timestamp.counter)
 }
 
-/// CRDT Document Store
-#[wasm_bindgen]
-pub struct CrdtDocument {
-    node_id: String,
-    counter: u64,
-    tasks: HashMap<u32, CrdtTask>,
-    operations: Vec<Operation>,
+/// Whether two writes happened without either writer knowing the other.
+/// An empty remote clock means its knowledge is unknown (older peers),
+/// which is never reported as concurrent.
+fn is_concurrent(
+    local: &LamportTimestamp,
+    local_clock: &VectorClock,
+    remote: &LamportTimestamp,
+    remote_clock: &VectorClock,
+) -> bool {
+    !remote_clock.is_empty() && !has_seen(local_clock, remote) && !has_seen(remote_clock, local)
 }
 
-#[wasm_bindgen]
-impl CrdtDocument {
-    #[wasm_bindgen(constructor)]
-    pub fn new(node_id: String) -> Self {
-        #[cfg(feature = "console_error_panic_hook")]
-        console_error_panic_hook::set_once();
-        
-        console_log!("CRDT Document created for node: {}", node_id);
-        
-        Self {
-            node_id,
-            counter: 0,
-            tasks: HashMap::new(),
-            operations: Vec::new(),
+/// Out-of-order counters remembered per node before the oldest gap is
+/// given up on
+const MAX_SEEN_OVERFLOW: usize = 256;
+
+/// Ids of applied operations, bounded per node: everything up to
+/// `contiguous` plus the counters seen above it. Lamport counters skip
+/// values, so when the overflow is full the oldest gap is assumed seen.
+#[derive(Clone, Debug, Default)]
+struct SeenNode {
+    contiguous: u64,
+    overflow: BTreeSet<u64>,
+}
+
+impl SeenNode {
+    fn contains(&self, counter: u64) -> bool {
+        counter <= self.contiguous || self.overflow.contains(&counter)
+    }
+    
+    fn insert(&mut self, counter: u64) -> bool {
+        if self.contains(counter) {
+            return false;
         }
+        self.overflow.insert(counter);
+        if self.overflow.len() > MAX_SEEN_OVERFLOW {
+            self.contiguous = self.overflow.pop_first().unwrap_or(self.contiguous);
+        }
+        self.compact();
+        true
     }
     
-    fn new_timestamp(&mut self) -> LamportTimestamp {
-        self.counter += 1;
-        LamportTimestamp::new(self.counter, &self.node_id)
+    /// Mark every counter up to `counter` as seen
+    fn advance_to(&mut self, counter: u64) {
+        self.contiguous = self.contiguous.max(counter);
+        self.compact();
     }
     
-    /// Insert or update a task field
-    pub fn upsert_field(&mut self, task_id: u32, field: String, value: String) {
-        let timestamp = self.new_timestamp();
-        
-        let task = self.tasks.entry(task_id).or_insert_with(|| CrdtTask {
-            id: task_id,
+    fn compact(&mut self) {
+        self.overflow.retain(|&c| c > self.contiguous);
+        while self.overflow.first() == Some(&(self.contiguous + 1)) {
+            self.overflow.pop_first();
+            self.contiguous += 1;
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct SeenOps {
+    nodes: HashMap<String, SeenNode>,
+    /// Ids not in `node:counter` form
+    other: HashSet<String>,
+}
+
+impl SeenOps {
+    fn parse(op_id: &str) -> Option<(&str, u64)> {
+        let (node_id, counter) = op_id.rsplit_once(':')?;
+        Some((node_id, counter.parse().ok()?))
+    }
+    
+    /// `node:counter` of a re-emitted merge op (`node:counter:field`)
+    fn source(op_id: &str) -> Option<&str> {
+        op_id.match_indices(':').map(|(i, _)| &op_id[..i]).find(|prefix| Self::parse(prefix).is_some())
+    }
+    
+    /// Whether an op was applied. A re-emitted field write counts as seen
+    /// once the op it came from is.
+    fn contains(&self, op_id: &str) -> bool {
+        match Self::parse(op_id) {
+            Some((node_id, counter)) => self.nodes.get(node_id).is_some_and(|node| node.contains(counter)),
+            None => self.other.contains(op_id) || Self::source(op_id).is_some_and(|source| self.contains(source)),
+        }
+    }
+    
+    /// Record an op id; false if it was already seen
+    fn insert(&mut self, op_id: &str) -> bool {
+        match Self::parse(op_id) {
+            Some((node_id, counter)) => self.nodes.entry(node_id.to_string()).or_default().insert(counter),
+            None => !self.contains(op_id) && self.other.insert(op_id.to_string()),
+        }
+    }
+}
+
+/// Highest Lamport counter in a document state
+fn max_counter(tasks: &HashMap<String, CrdtTask>, clock: &VectorClock) -> u64 {
+    tasks
+        .values()
+        .flat_map(|task| {
+            let field_counters = task.fields.values().map(|value| value.timestamp.counter);
+            [task.created_at.counter, task.updated_at.counter].into_iter().chain(field_counters)
+        })
+        .chain(clock.values().copied())
+        .max()
+        .unwrap_or(0)
+}
+
+/// Latest wall time in a document state
+fn max_wall_ms(tasks: &HashMap<String, CrdtTask>) -> u64 {
+    tasks
+        .values()
+        .flat_map(|task| {
+            let field_walls = task.fields.values().map(|value| value.timestamp.wall_ms);
+            [task.created_at.wall_ms, task.updated_at.wall_ms].into_iter().chain(field_walls)
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// CRDT Document for a Task
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CrdtTask {
+    pub id: String,
+    pub fields: HashMap<String, CrdtValue>,
+    pub deleted: bool,
+    pub created_at: LamportTimestamp,
+    pub updated_at: LamportTimestamp,
+    /// Last delete or restore. `deleted` is its own last-write-wins value,
+    /// so field writes never decide it. Exports from before this field
+    /// use `updated_at` for deleted tasks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<LamportTimestamp>,
+    /// Hidden from the board but kept, unlike `deleted`. Its own
+    /// last-write-wins value, set at `archived_at`.
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archived_at: Option<LamportTimestamp>,
+    /// PN-Counter fields, kept apart from the LWW `fields`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub counters: HashMap<String, PnCounter>,
+    /// Observed-remove set of tags
+    #[serde(default, skip_serializing_if = "OrSet::is_empty")]
+    pub tags: OrSet,
+}
+
+impl CrdtTask {
+    fn new(id: &str, timestamp: &LamportTimestamp) -> Self {
+        Self {
+            id: id.to_string(),
             fields: HashMap::new(),
             deleted: false,
             created_at: timestamp.clone(),
             updated_at: timestamp.clone(),
-        });
-        
-        // CRDT: Keep the value with higher timestamp (last-write-wins)
-        let should_update = match task.fields.get(&field) {
-            Some(existing) => timestamp > existing.timestamp,
-            None => true,
-        };
-        
-        if should_update {
-            task.fields.insert(field.clone(), CrdtValue {
-                value: value.clone(),
-                timestamp: timestamp.clone(),
-            });
-            task.updated_at = timestamp.clone();
-            
-            let op = if task.fields.len() == 1 && field == "title" {
-                Operation::Insert { task_id, field: field.clone(), value: value.clone(), timestamp: timestamp.clone() }
-            } else {
-                Operation::Update { task_id, field: field.clone(), value: value.clone(), timestamp: timestamp.clone() }
-            };
-            
-            self.operations.push(op);
-            console_log!("Upserted field {} for task {}", field, task_id);
+            deleted_at: None,
+            archived: false,
+            archived_at: None,
+            counters: HashMap::new(),
+            tags: OrSet::default(),
         }
     }
     
-    /// Delete a task (soft delete)
-    pub fn delete_task(&mut self, task_id: u32) {
-        let timestamp = self.new_timestamp();
-        
-        if let Some(task) = self.tasks.get_mut(&task_id) {
-            task.deleted = true;
-            task.updated_at = timestamp.clone();
-            
-            self.operations.push(Operation::Delete { task_id, timestamp });
-            console_log!("Deleted task {}", task_id);
-        }
+    /// Timestamp `deleted` was last set at, if ever
+    fn deletion_timestamp(&self) -> Option<&LamportTimestamp> {
+        self.deleted_at.as_ref().or(self.deleted.then_some(&self.updated_at))
     }
     
-    /// Get all non-deleted tasks
-    pub fn get_tasks(&self) -> JsValue {
-        let tasks: Vec<&CrdtTask> = self.tasks
-            .values()
-            .filter(|t| !t.deleted)
-            .collect();
-        
-        serde_wasm_bindgen::to_value(&tasks).unwrap_or(JsValue::NULL)
+    /// Whether a delete or restore at `timestamp` beats the current one
+    fn deletion_wins(&self, timestamp: &LamportTimestamp) -> bool {
+        self.deletion_timestamp().is_none_or(|current| wins(timestamp, current))
     }
     
-    /// Get task by ID
-    pub fn get_task(&self, task_id: u32) -> JsValue {
-        match self.tasks.get(&task_id) {
-            Some(task) if !task.deleted => {
-                serde_wasm_bindgen::to_value(task).unwrap_or(JsValue::NULL)
-            }
-            _ => JsValue::NULL,
-        }
+    /// Whether an archive or unarchive at `timestamp` beats the current one
+    fn archive_wins(&self, timestamp: &LamportTimestamp) -> bool {
+        self.archived_at.as_ref().is_none_or(|current| wins(timestamp, current))
     }
     
-    /// Merge another document into this one
-    pub fn merge(&mut self, other_json: &str) -> Result<(), JsValue> {
-        let other: HashMap<u32, CrdtTask> = serde_json::from_str(other_json)
-            .map_err(|e| JsValue::from_str(&format!("Parse error: {}", e)))?;
-        
-        for (task_id, other_task) in other {
-            match self.tasks.get_mut(&task_id) {
-                Some(local_task) => {
-                    // Merge fields using LWW (Last-Write-Wins)
-                    for (field, other_value) in &other_task.fields {
-                        match local_task.fields.get(field) {
-                            Some(local_value) => {
-                                if other_value.timestamp > local_value.timestamp {
-                                    local_task.fields.insert(field.clone(), other_value.clone());
-                                }
-                            }
-                            None => {
-                                local_task.fields.insert(field.clone(), other_value.clone());
-                            }
-                        }
-                    }
-                    
-                    // Handle deletion
-                    if other_task.deleted && other_task.updated_at > local_task.updated_at {
-                        local_task.deleted = true;
-                    }
-                    
-                    // Update timestamps
-                    if other_task.updated_at > local_task.updated_at {
-                        local_task.updated_at = other_task.updated_at.clone();
-                    }
-                }
-                None => {
-                    // Task doesn't exist locally, add it
-                    if !other_task.deleted {
-                        self.tasks.insert(task_id, other_task);
-                    }
-                }
-            }
-        }
-        
-        console_log!("Merged document. Total tasks: {}", self.tasks.len());
-        Ok(())
+    /// Whether merging `other` into this task would change nothing: it is
+    /// not newer, and holds the same field writes, counters and tags. A
+    /// task that isn't newer can still carry a concurrent write to another
+    /// field (`updated_at` is only the newest write), hence the field check.
+    fn covers(&self, other: &CrdtTask) -> bool {
+        !wins(&other.updated_at, &self.updated_at)
+            && other.deletion_timestamp().is_none_or(|timestamp| !self.deletion_wins(timestamp))
+            && other.archived_at.as_ref().is_none_or(|timestamp| !self.archive_wins(timestamp))
+            && self.counters == other.counters
+            && self.tags == other.tags
+            && other.fields.iter().all(|(field, value)| {
+                self.fields.get(field).is_some_and(|local| {
+                    local.timestamp == value.timestamp && local.value == value.value && local.conflicting == value.conflicting
+                })
+            })
     }
     
-    /// Get document state as JSON for syncing
-    pub fn export(&self) -> String {
-        serde_json::to_string(&self.tasks).unwrap_or_default()
+    /// Whether the task shows on the board: neither deleted nor archived
+    fn on_board(&self) -> bool {
+        !self.deleted && !self.archived
     }
     
-    /// Import document state from JSON
-    pub fn import(&mut self, json: &str) -> Result<(), JsValue> {
-        self.tasks = serde_json::from_str(json)
-            .map_err(|e| JsValue::from_str(&format!("Import error: {}", e)))?;
-        console_log!("Imported document with {} tasks", self.tasks.len());
-        Ok(())
+    fn touch(&mut self, timestamp: &LamportTimestamp) {
+        if wins(timestamp, &self.updated_at) {
+            self.updated_at = timestamp.clone();
+        }
     }
     
-    /// Get operations since last sync
-    pub fn get_operations(&self) -> JsValue {
-        serde_wasm_bindgen::to_value(&self.operations).unwrap_or(JsValue::NULL)
+    /// Whether two versions of a task look the same to the app: deletion,
+    /// archival, field values (timestamps aside), counters and tags
+    fn same_content(&self, other: &CrdtTask) -> bool {
+        self.deleted == other.deleted
+            && self.archived == other.archived
+            && self.counters == other.counters
+            && self.tags.values() == other.tags.values()
+            && self.fields.len() == other.fields.len()
+            && self.fields.iter().all(|(field, value)| {
+                other.fields.get(field).is_some_and(|o| o.value == value.value && o.conflicting == value.conflicting)
+            })
     }
     
-    /// Apply remote operations
-    pub fn apply_operations(&mut self, ops_json: &str) -> Result<(), JsValue> {
-        let ops: Vec<Operation> = serde_json::from_str(ops_json)
-            .map_err(|e| JsValue::from_str(&format!("Parse error: {}", e)))?;
-        
-        for op in ops {
-            match op {
-                Operation::Insert { task_id, field, value, timestamp } |
-                Operation::Update { task_id, field, value, timestamp } => {
-                    self.apply_field_update(task_id, field, value, timestamp);
-                }
-                Operation::Delete { task_id, timestamp } => {
-                    self.apply_deletion(task_id, timestamp);
-                }
-            }
+    /// Value of a field, counter or `tags`, as reported to `set_on_change`
+    fn field_value(&self, field: &str) -> Option<serde_json::Value> {
+        if let Some(value) = self.fields.get(field) {
+            return Some(value.value.clone());
         }
-        
-        Ok(())
+        if let Some(counter) = self.counters.get(field) {
+            return Some(counter.value().into());
+        }
+        (field == "tags" && !self.tags.is_empty()).then(|| self.tags.values().into())
+    }
+}
+
+/// Positive-negative counter: each node only ever grows its own positive
+/// and negative totals, so merging takes the per-node maximum of each and
+/// concurrent increments from different nodes all survive.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PnCounter {
+    pub positive: HashMap<String, u64>,
+    pub negative: HashMap<String, u64>,
+}
+
+impl PnCounter {
+    pub fn value(&self) -> i64 {
+        let positive: u64 = self.positive.values().sum();
+        let negative: u64 = self.negative.values().sum();
+        positive as i64 - negative as i64
     }
     
-    fn apply_field_update(&mut self, task_id: u32, field: String, value: String, timestamp: LamportTimestamp) {
-        let task = self.tasks.entry(task_id).or_insert_with(|| CrdtTask {
-            id: task_id,
-            fields: HashMap::new(),
-            deleted: false,
-            created_at: timestamp.clone(),
-            updated_at: timestamp.clone(),
-        });
-        
-        match task.fields.get(&field) {
-            Some(existing) if existing.timestamp > timestamp => {
-                // Local value is newer, keep it
-            }
-            _ => {
-                task.fields.insert(field, CrdtValue { value, timestamp });
-            }
+    /// Add `delta` to the node's side; returns the new total of that side
+    fn add(&mut self, node_id: &str, delta: i64) -> u64 {
+        let side = if delta >= 0 { &mut self.positive } else { &mut self.negative };
+        let total = side.entry(node_id.to_string()).or_insert(0);
+        *total += delta.unsigned_abs();
+        *total
+    }
+    
+    /// Raise the node's total on `delta`'s side to `total`; returns whether
+    /// it grew
+    fn raise(&mut self, node_id: &str, delta: i64, total: u64) -> bool {
+        let side = if delta >= 0 { &mut self.positive } else { &mut self.negative };
+        let entry = side.entry(node_id.to_string()).or_insert(0);
+        if total > *entry {
+            *entry = total;
+            return true;
         }
+        false
     }
     
-    fn apply_deletion(&mut self, task_id: u32, timestamp: LamportTimestamp) {
-        if let Some(task) = self.tasks.get_mut(&task_id) {
-            if timestamp > task.updated_at {
-                task.deleted = true;
-                task.updated_at = timestamp;
+    /// Take the per-node maxima; returns whether anything changed
+    fn merge(&mut self, other: &PnCounter) -> bool {
+        let mut changed = false;
+        for (local, remote) in [(&mut self.positive, &other.positive), (&mut self.negative, &other.negative)] {
+            for (node_id, &total) in remote {
+                let entry = local.entry(node_id.clone()).or_insert(0);
+                if total > *entry {
+                    *entry = total;
+                    changed = true;
+                }
             }
         }
+        changed
+    }
+}
+
+/// Observed-remove set. Every add is tagged with a unique id and a remove
+/// only cancels the add ids it has seen, so when an add and a remove of
+/// the same element race, the add wins.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OrSet {
+    /// Element -> ids of the adds that inserted it
+    pub adds: HashMap<String, BTreeSet<String>>,
+    /// Add ids cancelled by a remove
+    pub removed: BTreeSet<String>,
+}
+
+impl OrSet {
+    pub fn is_empty(&self) -> bool {
+        self.adds.is_empty() && self.removed.is_empty()
     }
     
-    /// Clear all operations (after successful sync)
-    pub fn clear_operations(&mut self) {
-        self.operations.clear();
+    pub fn contains(&self, element: &str) -> bool {
+        self.adds
+            .get(element)
+            .is_some_and(|ids| ids.iter().any(|id| !self.removed.contains(id)))
     }
     
-    /// Get document stats
-    pub fn stats(&self) -> JsValue {
-        let active_tasks = self.tasks.values().filter(|t| !t.deleted).count();
-        let deleted_tasks = self.tasks.values().filter(|t| t.deleted).count();
-        let pending_ops = self.operations.len();
-        
-        let stats = serde_json::json!({
-            "node_id": self.node_id,
-            "active_tasks": active_tasks,
-            "deleted_tasks": deleted_tasks,
-            "pending_operations": pending_ops,
-            "counter": self.counter,
-        });
-        
-        serde_wasm_bindgen::to_value(&stats).unwrap_or(JsValue::NULL)
+    /// Present elements, sorted
+    pub fn values(&self) -> Vec<&str> {
+        let mut values: Vec<&str> = self.adds
+            .keys()
+            .filter(|element| self.contains(element))
+            .map(String::as_str)
+            .collect();
+        values.sort_unstable();
+        values
     }
     
-    /// Generate sync code (short hash of node_id)
-    pub fn get_sync_code(&self) -> String {
-        use std::collections::hash_map::DefaultHasher;
-        use std::hash::{Hash, Hasher};
-        
-        let mut hasher = DefaultHasher::new();
-        self.node_id.hash(&mut hasher);
-        let hash = hasher.finish();
-        
-        // Convert to 6-digit alphanumeric
-        const CHARS: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
-        let mut result = String::new();
-        let mut num = hash;
-        
-        for _ in 0..6 {
-            result.push(CHARS[(num % CHARS.len() as u64) as usize] as char);
-            num /= CHARS.len() as u64;
+    fn add(&mut self, element: &str, add_id: String) {
+        self.adds.entry(element.to_string()).or_default().insert(add_id);
+    }
+    
+    /// Live add ids of `element`, i.e. what a remove right now observes
+    fn observed(&self, element: &str) -> Vec<String> {
+        self.adds
+            .get(element)
+            .map(|ids| ids.iter().filter(|id| !self.removed.contains(*id)).cloned().collect())
+            .unwrap_or_default()
+    }
+    
+    fn remove(&mut self, add_ids: &[String]) {
+        self.removed.extend(add_ids.iter().cloned());
+    }
+    
+    /// Union both sides; returns whether anything changed
+    fn merge(&mut self, other: &OrSet) -> bool {
+        let mut changed = false;
+        for (element, ids) in &other.adds {
+            let local = self.adds.entry(element.clone()).or_default();
+            for id in ids {
+                changed |= local.insert(id.clone());
+            }
         }
-        
-        result
+        for id in &other.removed {
+            changed |= self.removed.insert(id.clone());
+        }
+        changed
     }
 }
 
-/// Utility: Generate unique node ID
-/// Takes timestamp from JS (Date.now()) to avoid SystemTime issues in WASM
-#[wasm_bindgen]
-pub fn generate_node_id(timestamp_ms: u32) -> String {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-    
-    let mut hasher = DefaultHasher::new();
-    timestamp_ms.hash(&mut hasher);
-    format!("node_{:x}", hasher.finish())
+/// Task as returned to JS, with counter fields resolved to their values
+#[derive(Serialize)]
+struct TaskView<'a> {
+    #[serde(flatten)]
+    task: &'a CrdtTask,
+    counter_values: HashMap<&'a str, i64>,
+    tag_values: Vec<&'a str>,
+    /// Author of the newest field value
+    last_modified_by: Option<&'a str>,
+    /// Unexpired `upsert_ephemeral_field` values (`get_task` only)
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    ephemeral: BTreeMap<&'a str, &'a serde_json::Value>,
+}
+
+impl<'a> TaskView<'a> {
+    fn new(task: &'a CrdtTask) -> Self {
+        let counter_values = task
+            .counters
+            .iter()
+            .map(|(field, counter)| (field.as_str(), counter.value()))
+            .collect();
+        let last_modified_by = task
+            .fields
+            .values()
+            .reduce(|newest, value| if wins(&value.timestamp, &newest.timestamp) { value } else { newest })
+            .and_then(|newest| newest.author.as_deref());
+        TaskView { task, counter_values, tag_values: task.tags.values(), last_modified_by, ephemeral: BTreeMap::new() }
+    }
+}
+
+/// Local-only field value set by `upsert_ephemeral_field`
+#[derive(Clone, Debug)]
+struct EphemeralValue {
+    value: serde_json::Value,
+    /// `prune_ephemeral` tick at which the value is dropped
+    expires_at: u64,
+}
+
+/// Query for `get_tasks_filtered`: every other key is a field that must
+/// equal the given value, e.g. `{status: "doing", limit: 50}`
+#[derive(Debug, Default, Deserialize)]
+struct TaskFilter {
+    /// Only tasks updated after this Lamport counter
+    updated_after: Option<u64>,
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+    #[serde(flatten)]
+    fields: serde_json::Map<String, serde_json::Value>,
+}
+
+impl TaskFilter {
+    fn matches(&self, task: &CrdtTask) -> bool {
+        self.updated_after.is_none_or(|counter| task.updated_at.counter > counter)
+            && self.fields.iter().all(|(field, value)| {
+                task.fields.get(field).is_some_and(|current| current.value == *value)
+            })
+    }
+}
+
+/// LWW field value. Values are any JSON type; exports from before typed
+/// values hold plain strings, which load as JSON strings.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct CrdtValue {
+    pub value: serde_json::Value,
+    pub timestamp: LamportTimestamp,
+    /// Concurrent writes a `manual` field keeps until `resolve_conflict`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conflicting: Vec<CrdtValue>,
+    /// `set_author` name of whoever wrote this value
+    #[serde(rename = "last_modified_by", default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+}
+
+impl CrdtValue {
+    fn new(value: serde_json::Value, timestamp: LamportTimestamp) -> Self {
+        Self { value, timestamp, conflicting: Vec::new(), author: None }
+    }
+    
+    fn by(mut self, author: Option<String>) -> Self {
+        self.author = author;
+        self
+    }
+}
+
+/// How a field combines concurrent writes, set with `set_field_strategy`.
+/// Strategies are not synced: every replica must set the same ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum FieldStrategy {
+    /// Last write wins
+    #[default]
+    Lww,
+    /// Larger number wins
+    MaxNumeric,
+    /// Lines (strings) or elements (arrays) of both writes, deduplicated
+    ConcatUnique,
+    /// Newest write wins, the others wait in `conflicting` for the UI
+    Manual,
+}
+
+impl FieldStrategy {
+    const NAMES: [&'static str; 4] = ["lww", "max_numeric", "concat_unique", "manual"];
+    
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "lww" => Ok(FieldStrategy::Lww),
+            "max_numeric" => Ok(FieldStrategy::MaxNumeric),
+            "concat_unique" => Ok(FieldStrategy::ConcatUnique),
+            "manual" => Ok(FieldStrategy::Manual),
+            _ => Err(format!("Unknown merge strategy '{}'; valid strategies: {}", name, Self::NAMES.join(", "))),
+        }
+    }
+    
+    /// Combine two concurrent writes. The result only depends on the two
+    /// values, not on which one is local, so replicas agree in any merge
+    /// order. It carries the newer timestamp.
+    fn join(self, a: &CrdtValue, b: &CrdtValue) -> CrdtValue {
+        let key = |v: &CrdtValue| (v.timestamp.clone(), v.value.to_string());
+        let (older, newer) = if key(a) < key(b) { (a, b) } else { (b, a) };
+        let value = match self {
+            FieldStrategy::Lww => newer.value.clone(),
+            FieldStrategy::MaxNumeric => match (older.value.as_f64(), newer.value.as_f64()) {
+                (Some(older_number), Some(newer_number)) if older_number > newer_number => older.value.clone(),
+                _ => newer.value.clone(),
+            },
+            FieldStrategy::ConcatUnique => concat_unique(&older.value, &newer.value).unwrap_or_else(|| newer.value.clone()),
+            FieldStrategy::Manual => {
+                let mut writes: Vec<&CrdtValue> = [a, b]
+                    .into_iter()
+                    .flat_map(|v| std::iter::once(v).chain(&v.conflicting))
+                    .collect();
+                writes.sort_by_key(|write| std::cmp::Reverse(key(write)));
+                let mut kept: Vec<CrdtValue> = Vec::new();
+                for write in writes {
+                    if !kept.iter().any(|k| k.value == write.value) {
+                        kept.push(CrdtValue::new(write.value.clone(), write.timestamp.clone()).by(write.author.clone()));
+                    }
+                }
+                let mut newest = kept.remove(0);
+                newest.conflicting = kept;
+                return newest;
+            }
+        };
+        CrdtValue::new(value, newer.timestamp.clone()).by(newer.author.clone())
+    }
+}
+
+/// `concat_unique` join: the lines (strings) or elements (arrays) of
+/// `older`, then those of `newer` not already present. None for other types.
+fn concat_unique(older: &serde_json::Value, newer: &serde_json::Value) -> Option<serde_json::Value> {
+    match (older, newer) {
+        (serde_json::Value::String(older), serde_json::Value::String(newer)) => {
+            let mut lines: Vec<&str> = older.lines().collect();
+            for line in newer.lines() {
+                if !lines.contains(&line) {
+                    lines.push(line);
+                }
+            }
+            Some(lines.join("\n").into())
+        }
+        (serde_json::Value::Array(older), serde_json::Value::Array(newer)) => {
+            let mut items = older.clone();
+            for item in newer {
+                if !items.contains(item) {
+                    items.push(item.clone());
+                }
+            }
+            Some(items.into())
+        }
+        _ => None,
+    }
+}
+
+/// What `local` becomes after receiving `remote`, or None to keep it.
+/// LWW compares timestamps. Other strategies take the write that has
+/// seen the other one and join writes made without seeing each other;
+/// peers that send no clock fall back to LWW.
+fn resolve_write(
+    strategy: FieldStrategy,
+    local: &CrdtValue,
+    local_clock: &VectorClock,
+    remote: &CrdtValue,
+    remote_clock: &VectorClock,
+) -> Option<CrdtValue> {
+    let same = local.value == remote.value && local.conflicting == remote.conflicting;
+    if strategy == FieldStrategy::Lww || remote_clock.is_empty() || same {
+        return wins(&remote.timestamp, &local.timestamp).then(|| remote.clone());
+    }
+    match (has_seen(remote_clock, &local.timestamp), has_seen(local_clock, &remote.timestamp)) {
+        (true, false) => Some(remote.clone()),
+        (false, true) => None,
+        _ => Some(strategy.join(local, remote)).filter(|joined| joined != local),
+    }
+}
+
+/// What a merge does with an incoming task that shares an id with a
+/// different local task, see `is_id_collision`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum CollisionPolicy {
+    /// Merge the two as one task
+    #[default]
+    Merge,
+    /// Keep the incoming task under `<id>@<its creator node>`
+    Fork,
+    /// Fail the merge
+    Error,
+}
+
+impl CollisionPolicy {
+    const NAMES: [&'static str; 3] = ["merge", "fork", "error"];
+    
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "merge" => Ok(CollisionPolicy::Merge),
+            "fork" => Ok(CollisionPolicy::Fork),
+            "error" => Ok(CollisionPolicy::Error),
+            _ => Err(format!("Unknown collision policy '{}'; valid policies: {}", name, Self::NAMES.join(", "))),
+        }
+    }
+}
+
+/// Whether two tasks with the same id were most likely created apart:
+/// by different nodes, with titles sharing no in-word bigram. Tasks without
+/// a text title are never considered colliding.
+fn is_id_collision(local: &CrdtTask, incoming: &CrdtTask) -> bool {
+    if local.created_at.node_id == incoming.created_at.node_id {
+        return false;
+    }
+    let title = |task: &CrdtTask| task.fields.get("title").and_then(|title| title.value.as_str()).map(str::to_lowercase);
+    let (Some(local_title), Some(incoming_title)) = (title(local), title(incoming)) else {
+        return false;
+    };
+    let bigrams = |text: &str| -> HashSet<(char, char)> {
+        let chars: Vec<char> = text.chars().collect();
+        chars
+            .windows(2)
+            .filter(|pair| !pair.iter().any(|c| c.is_whitespace()))
+            .map(|pair| (pair[0], pair[1]))
+            .collect()
+    };
+    bigrams(&local_title).is_disjoint(&bigrams(&incoming_title)) && local_title != incoming_title
+}
+
+/// Incoming task moved to another id by the `fork` collision policy
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct TaskRemap {
+    pub from: String,
+    pub to: String,
+}
+
+/// Kind of value a schema field holds. `null` fits every kind.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum ValueKind {
+    String,
+    Number,
+    Boolean,
+    Array,
+    Object,
+    Any,
+}
+
+impl ValueKind {
+    fn matches(self, value: &serde_json::Value) -> bool {
+        value.is_null() || match self {
+            ValueKind::String => value.is_string(),
+            ValueKind::Number => value.is_number(),
+            ValueKind::Boolean => value.is_boolean(),
+            ValueKind::Array => value.is_array(),
+            ValueKind::Object => value.is_object(),
+            ValueKind::Any => true,
+        }
+    }
+    
+    fn name(self) -> &'static str {
+        match self {
+            ValueKind::String => "a string",
+            ValueKind::Number => "a number",
+            ValueKind::Boolean => "a boolean",
+            ValueKind::Array => "an array",
+            ValueKind::Object => "an object",
+            ValueKind::Any => "any value",
+        }
+    }
+}
+
+/// Fields outside the schema are kept under this prefix
+const EXTRAS_PREFIX: &str = "extras.";
+
+/// Allowed task fields, set with `set_schema`. Like field strategies the
+/// schema is not synced; each replica checks what it writes and receives.
+#[derive(Clone, Debug, Deserialize)]
+struct Schema {
+    /// Field name -> kind of value
+    fields: HashMap<String, ValueKind>,
+    /// Reject invalid local writes; otherwise they go to `extras.<field>`
+    #[serde(default = "strict_by_default")]
+    strict: bool,
+}
+
+fn strict_by_default() -> bool {
+    true
+}
+
+impl Schema {
+    /// Why `value` may not be stored in `field`, if it may not. Extras
+    /// and the ordering fields `move_task` writes are always allowed.
+    fn violation(&self, field: &str, value: &serde_json::Value) -> Option<String> {
+        if field.starts_with(EXTRAS_PREFIX) || field == ORDER_FIELD || field == COLUMN_POSITION_FIELD {
+            return None;
+        }
+        match self.fields.get(field) {
+            None => Some(format!("unknown field '{}'", field)),
+            Some(kind) if !kind.matches(value) => Some(format!("field '{}' must be {}", field, kind.name())),
+            Some(_) => None,
+        }
+    }
+    
+    /// Move fields that break the schema to `extras.<field>`, keeping the
+    /// newer value if that name is taken. Returns how many moved.
+    fn quarantine(&self, task: &mut CrdtTask) -> usize {
+        let invalid: Vec<String> = task
+            .fields
+            .iter()
+            .filter(|(field, value)| self.violation(field, &value.value).is_some())
+            .map(|(field, _)| field.clone())
+            .collect();
+        for field in &invalid {
+            let Some(value) = task.fields.remove(field) else { continue };
+            let extra = format!("{}{}", EXTRAS_PREFIX, field);
+            if task.fields.get(&extra).is_none_or(|existing| wins(&value.timestamp, &existing.timestamp)) {
+                task.fields.insert(extra, value);
+            }
+        }
+        invalid.len()
+    }
+}
+
+/// A stored field that breaks the schema, reported by `validate`
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct SchemaViolation {
+    pub task_id: String,
+    pub field: String,
+    pub message: String,
+}
+
+/// Concurrent write to an `lww` field. The discarded value is
+/// kept here so the UI can tell the user.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct Conflict {
+    pub task_id: String,
+    pub field: String,
+    pub kept: serde_json::Value,
+    pub discarded: serde_json::Value,
+}
+
+/// Export format version. Version 2 switched task ids from numbers to
+/// strings; unversioned exports load through `import_legacy`.
+const STATE_VERSION: u64 = 2;
+
+/// Exported document state
+#[derive(Serialize)]
+struct DocumentState<'a> {
+    version: u64,
+    node_id: &'a str,
+    tasks: &'a HashMap<String, CrdtTask>,
+    clock: &'a VectorClock,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    purged: &'a HashMap<String, LamportTimestamp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    history: Option<&'a HistoryEntries>,
+}
+
+#[derive(Deserialize)]
+struct ImportedState {
+    #[serde(default)]
+    node_id: Option<String>,
+    tasks: HashMap<String, CrdtTask>,
+    #[serde(default)]
+    clock: VectorClock,
+    #[serde(default)]
+    purged: HashMap<String, LamportTimestamp>,
+    #[serde(default)]
+    history: HistoryEntries,
+}
+
+impl ImportedState {
+    fn parse(json: &str) -> Result<Self, serde_json::Error> {
+        Self::from_value(serde_json::from_str(json)?)
+    }
+    
+    fn from_value(value: serde_json::Value) -> Result<Self, serde_json::Error> {
+        use serde::de::Error;
+        match value.get("version").and_then(|v| v.as_u64()) {
+            Some(STATE_VERSION) => serde_json::from_value(value),
+            Some(version) => Err(Error::custom(format!("unsupported export version {}", version))),
+            None => Err(Error::custom("export has numeric task ids, load it with import_legacy")),
+        }
+    }
+    
+    /// Parse an unversioned export (a bare task map, or `{node_id, tasks,
+    /// clock}`) and move its numeric ids to strings with `legacy_task_id`.
+    /// `purged` markers are dropped: they record who deleted a task, not
+    /// who created it, so their ids can't be mapped.
+    fn parse_legacy(json: &str) -> Result<Self, serde_json::Error> {
+        use serde::de::Error;
+        let mut value: serde_json::Value = serde_json::from_str(json)?;
+        // Task map keys are ids, so a `tasks` key means the wrapped format
+        if value.get("tasks").is_none() {
+            value = serde_json::json!({ "tasks": value });
+        }
+        let tasks: Vec<serde_json::Value> = serde_json::from_value::<HashMap<String, _>>(value["tasks"].take())?
+            .into_values()
+            .collect();
+        let mut migrated = serde_json::Map::new();
+        for mut task in tasks {
+            let id = task["id"].as_u64().ok_or_else(|| Error::custom("legacy task without a numeric id"))?;
+            let creator = task["created_at"]["node_id"].as_str()
+                .ok_or_else(|| Error::custom(format!("legacy task {} without created_at", id)))?;
+            let id = legacy_task_id(creator, id);
+            task["id"] = id.clone().into();
+            migrated.insert(id, task);
+        }
+        value["tasks"] = migrated.into();
+        value["version"] = STATE_VERSION.into();
+        if let Some(state) = value.as_object_mut() {
+            state.remove("purged");
+        }
+        serde_json::from_value(value)
+    }
+}
+
+/// Bounds on what a single merge, import or batch of operations may
+/// bring in, so a malformed or hostile payload is rejected as a whole
+/// instead of wedging the document. 0 disables a limit.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct MergeLimits {
+    pub max_tasks: usize,
+    pub max_fields_per_task: usize,
+    /// Bytes of one field value (as JSON, or the text of a string) or tag
+    pub max_value_bytes: usize,
+    /// How far an incoming timestamp may move the Lamport counter ahead.
+    /// A huge counter would make every later local edit lose to it.
+    pub max_counter_jump: u64,
+    /// How far an incoming wall time may be ahead of this replica's latest
+    /// `tick`. A clock far in the future would win every concurrent write
+    /// until real time caught up. Not checked before the first `tick`.
+    pub max_wall_skew_ms: u64,
+}
+
+impl Default for MergeLimits {
+    fn default() -> Self {
+        Self {
+            max_tasks: 100_000,
+            max_fields_per_task: 1_000,
+            max_value_bytes: 1024 * 1024,
+            max_counter_jump: 1 << 32,
+            max_wall_skew_ms: 24 * 60 * 60 * 1000,
+        }
+    }
+}
+
+/// Payload rejected by `MergeLimits`: which limit, its maximum and what
+/// arrived
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct LimitExceeded {
+    pub limit: &'static str,
+    pub max: u64,
+    pub actual: u64,
+    /// Task that broke the limit, for per-task limits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task_id: Option<String>,
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Limit exceeded: {} is {}, got {}", self.limit, self.max, self.actual)?;
+        if let Some(task_id) = &self.task_id {
+            write!(f, " (task {})", task_id)?;
+        }
+        Ok(())
+    }
+}
+
+impl MergeLimits {
+    fn check(&self, limit: &'static str, max: u64, actual: u64, task_id: Option<&str>) -> Result<(), LimitExceeded> {
+        if max > 0 && actual > max {
+            return Err(LimitExceeded { limit, max, actual, task_id: task_id.map(str::to_string) });
+        }
+        Ok(())
+    }
+    
+    fn check_value(&self, task_id: &str, value: &serde_json::Value) -> Result<(), LimitExceeded> {
+        if self.max_value_bytes == 0 {
+            return Ok(());
+        }
+        let len = match value {
+            serde_json::Value::String(text) => text.len(),
+            other => other.to_string().len(),
+        };
+        self.check("max_value_bytes", self.max_value_bytes as u64, len as u64, Some(task_id))
+    }
+    
+    fn check_counter(&self, counter: u64, incoming: u64) -> Result<(), LimitExceeded> {
+        self.check("max_counter_jump", self.max_counter_jump, incoming.saturating_sub(counter), None)
+    }
+    
+    /// `now_ms` is the latest `tick`, 0 if the replica never ticked
+    fn check_wall(&self, now_ms: u64, incoming: u64) -> Result<(), LimitExceeded> {
+        if now_ms == 0 {
+            return Ok(());
+        }
+        self.check("max_wall_skew_ms", self.max_wall_skew_ms, incoming.saturating_sub(now_ms), None)
+    }
+    
+    /// Check an incoming state against a document at `counter`, last
+    /// ticked at `now_ms`
+    fn check_state(&self, state: &ImportedState, counter: u64, now_ms: u64) -> Result<(), LimitExceeded> {
+        self.check("max_tasks", self.max_tasks as u64, state.tasks.len() as u64, None)?;
+        for (task_id, task) in &state.tasks {
+            let fields = task.fields.len() + task.counters.len();
+            self.check("max_fields_per_task", self.max_fields_per_task as u64, fields as u64, Some(task_id))?;
+            for value in task.fields.values() {
+                self.check_value(task_id, &value.value)?;
+            }
+            for tag in task.tags.values() {
+                self.check_value(task_id, &serde_json::Value::from(tag))?;
+            }
+        }
+        let purged_max = state.purged.values().map(|ts| ts.counter).max().unwrap_or(0);
+        self.check_counter(counter, max_counter(&state.tasks, &state.clock).max(purged_max))?;
+        let purged_wall = state.purged.values().map(|ts| ts.wall_ms).max().unwrap_or(0);
+        self.check_wall(now_ms, max_wall_ms(&state.tasks).max(purged_wall))
+    }
+    
+    /// Check a batch of operations against a document at `counter`, last
+    /// ticked at `now_ms`
+    fn check_operations(&self, ops: &[Operation], counter: u64, now_ms: u64) -> Result<(), LimitExceeded> {
+        let mut fields: HashMap<&str, HashSet<&str>> = HashMap::new();
+        for op in ops {
+            let task_id = op.task_id();
+            let task_fields = fields.entry(task_id).or_default();
+            match op {
+                Operation::Insert { field, value, .. } | Operation::Update { field, value, .. } => {
+                    self.check_value(task_id, value)?;
+                    task_fields.insert(field);
+                }
+                Operation::UpdateFields { fields: written, .. } => {
+                    for (field, value) in written {
+                        self.check_value(task_id, value)?;
+                        task_fields.insert(field);
+                    }
+                }
+                Operation::Increment { field, .. } => {
+                    task_fields.insert(field);
+                }
+                Operation::AddTag { tag, .. } | Operation::RemoveTag { tag, .. } => {
+                    self.check_value(task_id, &serde_json::Value::from(tag.as_str()))?;
+                }
+                _ => {}
+            }
+            let count = task_fields.len() as u64;
+            self.check("max_fields_per_task", self.max_fields_per_task as u64, count, Some(task_id))?;
+            self.check_counter(counter, op.timestamp().counter)?;
+            self.check_wall(now_ms, op.timestamp().wall_ms)?;
+        }
+        self.check("max_tasks", self.max_tasks as u64, fields.len() as u64, None)
+    }
+}
+
+/// String id for numeric task `id` from a legacy export. Devices numbered
+/// tasks on their own, so two of them can both have a "task 1"; prefixing
+/// the creating node keeps those apart, while the same synced task maps to
+/// the same id on every device.
+fn legacy_task_id(creator: &str, id: u64) -> String {
+    format!("{}:{}", creator, id)
+}
+
+/// Result of `compact_tombstones`: tombstones removed, and tombstones old
+/// enough to remove but still waiting for a peer to acknowledge them
+#[derive(Clone, Debug, Default, Serialize, PartialEq, Eq)]
+pub struct CompactionReport {
+    pub purged: usize,
+    pub pending: usize,
+}
+
+/// Encoded size of one task in bytes of JSON
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct TaskSize {
+    pub task_id: String,
+    pub bytes: usize,
+}
+
+/// Result of `stats`. Sizes are JSON bytes; every task is encoded once
+/// and the totals are summed from those lengths.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct DocumentStats {
+    pub node_id: String,
+    pub active_tasks: usize,
+    pub deleted_tasks: usize,
+    pub purged_tasks: usize,
+    /// Deleted tasks plus purge markers
+    pub tombstones: usize,
+    pub pending_operations: usize,
+    pub counter: u64,
+    pub task_bytes: usize,
+    pub operation_bytes: usize,
+    /// Roughly the size of `export`
+    pub total_bytes: usize,
+    /// Biggest tasks first, ties by task id
+    pub largest_tasks: Vec<TaskSize>,
+}
+
+/// How many tasks `stats` lists in `largest_tasks`
+const STATS_LARGEST_TASKS: usize = 10;
+
+/// Where `checkpoint` last truncated the operation log
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Newest operation folded into the snapshot
+    pub timestamp: LamportTimestamp,
+    /// Highest counter folded in from each node
+    pub clock: VectorClock,
+}
+
+/// Answer to `get_delta_since`
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Delta<'a> {
+    /// Every operation after the requested point
+    Operations { operations: Vec<&'a Operation> },
+    /// Some operations after the requested point were truncated: merge a
+    /// full `export` instead
+    SnapshotRequired { checkpoint: &'a Checkpoint },
+}
+
+/// One write to a field, as returned by `get_field_history`
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct HistoryEntry {
+    pub value: serde_json::Value,
+    pub timestamp: LamportTimestamp,
+    /// Lost a last-write-wins race to a concurrent write
+    #[serde(default)]
+    pub superseded: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+}
+
+impl HistoryEntry {
+    fn of(write: &CrdtValue) -> Self {
+        Self { value: write.value.clone(), timestamp: write.timestamp.clone(), superseded: false, author: write.author.clone() }
+    }
+}
+
+/// Default number of writes kept per field by `get_field_history`
+const DEFAULT_HISTORY_LIMIT: usize = 20;
+
+/// Past writes of each field: task id -> field -> writes, oldest first
+type HistoryEntries = HashMap<String, HashMap<String, Vec<HistoryEntry>>>;
+
+/// Field history derived from local edits, operations and merges. Not
+/// exported unless `set_include_history` is on.
+#[derive(Debug)]
+struct FieldHistory {
+    limit: usize,
+    entries: HistoryEntries,
+}
+
+impl FieldHistory {
+    /// Add a write in timestamp order, which is causal order. A write seen
+    /// before (by timestamp) is only updated with the `superseded` flag.
+    fn record(&mut self, task_id: &str, field: &str, entry: HistoryEntry) {
+        if self.limit == 0 {
+            return;
+        }
+        let writes = self.entries.entry(task_id.to_string()).or_default().entry(field.to_string()).or_default();
+        match writes.binary_search_by(|probe| probe.timestamp.cmp(&entry.timestamp)) {
+            Ok(i) => writes[i].superseded |= entry.superseded,
+            Err(i) => writes.insert(i, entry),
+        }
+        if writes.len() > self.limit {
+            writes.drain(..writes.len() - self.limit);
+        }
+    }
+    
+    /// Flag the write made at `timestamp` as having lost a race
+    fn supersede(&mut self, task_id: &str, field: &str, timestamp: &LamportTimestamp) {
+        let writes = self.entries.get_mut(task_id).and_then(|fields| fields.get_mut(field));
+        if let Some(entry) = writes.into_iter().flatten().find(|entry| entry.timestamp == *timestamp) {
+            entry.superseded = true;
+        }
+    }
+    
+    fn merge(&mut self, other: HistoryEntries) {
+        for (task_id, fields) in other {
+            for (field, writes) in fields {
+                for entry in writes {
+                    self.record(&task_id, &field, entry);
+                }
+            }
+        }
+    }
+    
+    fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        for writes in self.entries.values_mut().flat_map(|fields| fields.values_mut()) {
+            if writes.len() > limit {
+                writes.drain(..writes.len() - limit);
+            }
+        }
+        self.entries.retain(|_, fields| {
+            fields.retain(|_, writes| !writes.is_empty());
+            !fields.is_empty()
+        });
+    }
+}
+
+/// Default number of local edits `undo` can step back through
+const DEFAULT_MAX_UNDO: usize = 100;
+
+/// A local edit as recorded on the undo/redo stacks. A step only applies
+/// while its target still holds what it wrote, so remote edits made since
+/// are never reverted.
+#[derive(Clone, Debug)]
+enum UndoStep {
+    /// Field written locally; `previous` is the value it replaced
+    Field {
+        task_id: String,
+        field: String,
+        previous: Option<serde_json::Value>,
+        value: serde_json::Value,
+    },
+    /// Task created (or restored) locally
+    Created { task_id: String },
+    /// Task deleted locally
+    Deleted { task_id: String },
+    /// Task archived (or unarchived) locally
+    Archived { task_id: String, archived: bool },
+}
+
+/// Summary of the local changes made by a merge. `conflicts` counts
+/// fields where the incoming value lost to a newer, different local value.
+#[derive(Clone, Debug, Default, Serialize, PartialEq, Eq)]
+pub struct MergeReport {
+    pub added: usize,
+    pub updated: usize,
+    pub deleted: usize,
+    pub conflicts: usize,
+    /// Incoming tasks forked to a new id by the `fork` collision policy
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub remapped: Vec<TaskRemap>,
+    /// Which tasks changed, returned by `merge_js`
+    #[serde(skip)]
+    pub changes: MergeChanges,
+}
+
+/// Task ids touched by a merge, so the UI can re-render only those
+#[derive(Clone, Debug, Default, Serialize, PartialEq, Eq)]
+pub struct MergeChanges {
+    pub created: Vec<String>,
+    pub updated: Vec<TaskChange>,
+    pub deleted: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct TaskChange {
+    pub task_id: String,
+    /// Changed fields, counters and `tags`, sorted
+    pub fields: Vec<String>,
+}
+
+/// Tasks changed by `apply_operations`. `skipped` counts operations that
+/// changed nothing: already applied, outdated by a newer write, or aimed
+/// at a purged task.
+#[derive(Clone, Debug, Default, Serialize, PartialEq, Eq)]
+pub struct ApplyReport {
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub deleted: Vec<String>,
+    pub applied: usize,
+    pub skipped: usize,
+}
+
+/// Effect of `import` or `import_replace` on the local tasks
+#[derive(Clone, Debug, Default, Serialize, PartialEq, Eq)]
+pub struct ImportSummary {
+    /// Tasks that were not here before
+    pub added: usize,
+    /// Local tasks that came out unchanged
+    pub preserved: usize,
+    /// Local tasks that were changed or dropped
+    pub overwritten: usize,
+}
+
+impl ImportSummary {
+    fn between(before: &HashMap<String, CrdtTask>, after: &HashMap<String, CrdtTask>) -> Self {
+        let preserved = before
+            .iter()
+            .filter(|(task_id, task)| after.get(*task_id).is_some_and(|imported| imported.same_content(task)))
+            .count();
+        ImportSummary {
+            added: after.keys().filter(|task_id| !before.contains_key(*task_id)).count(),
+            preserved,
+            overwritten: before.len() - preserved,
+        }
+    }
+}
+
+/// What `merge` would do with a peer export, returned by `diff`
+#[derive(Clone, Debug, Default, Serialize, PartialEq)]
+pub struct MergePreview {
+    pub created: Vec<String>,
+    /// Tasks with fields that would change or whose incoming value loses
+    pub updated: Vec<TaskDiff>,
+    pub deleted: Vec<String>,
+    /// Deleted tasks the peer brought back
+    pub restored: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct TaskDiff {
+    pub task_id: String,
+    /// Sorted by field
+    pub fields: Vec<FieldDiff>,
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct FieldDiff {
+    pub field: String,
+    pub local: Option<serde_json::Value>,
+    pub incoming: Option<serde_json::Value>,
+    /// Value after the merge
+    pub result: Option<serde_json::Value>,
+    pub winner: DiffWinner,
+}
+
+/// Which side a field ends up with. `local` fields do not change.
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffWinner {
+    Local,
+    Incoming,
+    /// Joined by the field's merge strategy, or a counter or tag set
+    /// combining both sides
+    Merged,
+}
+
+/// Where a change reported to the `set_on_change` callback came from
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeOrigin {
+    /// An edit made on this document
+    Local,
+    /// `merge` or `apply_operations`
+    Remote,
+}
+
+/// One change passed to the `set_on_change` callback. `field` is a field,
+/// counter, `tags` or `deleted`; `value` is its new resolved value.
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct Change {
+    pub task_id: String,
+    pub field: String,
+    pub value: serde_json::Value,
+    pub origin: ChangeOrigin,
+}
+
+/// Changes waiting to be passed to the `set_on_change` callback; nothing
+/// is collected while no callback is set
+#[derive(Debug, Default)]
+struct ChangeLog(Option<Vec<Change>>);
+
+impl ChangeLog {
+    fn push(&mut self, task_id: &str, field: &str, origin: ChangeOrigin, value: impl FnOnce() -> serde_json::Value) {
+        if let Some(changes) = &mut self.0 {
+            changes.push(Change { task_id: task_id.to_string(), field: field.to_string(), value: value(), origin });
+        }
+    }
+    
+    fn take(&mut self) -> Vec<Change> {
+        self.0.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+}
+
+/// CRDT Document Store
+#[wasm_bindgen]
+pub struct CrdtDocument {
+    node_id: String,
+    counter: u64,
+    /// Hybrid clock wall time: the latest `tick` or wall time seen in a
+    /// remote write, whichever is later
+    wall_ms: u64,
+    /// Latest `tick` alone, what `MergeLimits::max_wall_skew_ms` is
+    /// measured from
+    ticked_ms: u64,
+    tasks: HashMap<String, CrdtTask>,
+    operations: Vec<Operation>,
+    seen_ops: SeenOps,
+    track_merge_ops: bool,
+    max_operations: usize,
+    undo_stack: VecDeque<UndoStep>,
+    redo_stack: Vec<UndoStep>,
+    max_undo: usize,
+    clock: VectorClock,
+    conflicts: Vec<Conflict>,
+    /// Vector clock of each peer as last reported by the peer itself
+    peer_clocks: HashMap<String, VectorClock>,
+    /// Delete markers left by `compact_tombstones` and `purge_task`: task id -> timestamp
+    /// of the delete. They act like the removed tombstones, so merges and
+    /// ops from peers that missed the delete cannot resurrect the task.
+    purged: HashMap<String, LamportTimestamp>,
+    on_change: Option<js_sys::Function>,
+    changes: ChangeLog,
+    /// Fields that don't use last-write-wins
+    field_strategies: HashMap<String, FieldStrategy>,
+    checkpoint: Option<Checkpoint>,
+    /// Sync target (peer, room, ...) -> highest counter it acknowledged
+    sync_cursors: BTreeMap<String, u64>,
+    history: FieldHistory,
+    /// Add `history` to `export`
+    include_history: bool,
+    limits: MergeLimits,
+    /// Name attached to local writes, see `set_author`
+    author: Option<String>,
+    schema: Option<Schema>,
+    collision_policy: CollisionPolicy,
+    /// Presence-style fields: task id -> field -> value. Never exported,
+    /// merged or logged as operations.
+    ephemeral: HashMap<String, BTreeMap<String, EphemeralValue>>,
+    /// Latest tick passed to `prune_ephemeral`
+    ephemeral_tick: u64,
+}
+
+#[wasm_bindgen]
+impl CrdtDocument {
+    #[wasm_bindgen(constructor)]
+    pub fn new(node_id: String) -> Self {
+        #[cfg(feature = "console_error_panic_hook")]
+        console_error_panic_hook::set_once();
+        
+        console_log!("CRDT Document created for node: {}", node_id);
+        
+        Self {
+            node_id,
+            counter: 0,
+            wall_ms: 0,
+            ticked_ms: 0,
+            tasks: HashMap::new(),
+            operations: Vec::new(),
+            seen_ops: SeenOps::default(),
+            track_merge_ops: false,
+            max_operations: 0,
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            max_undo: DEFAULT_MAX_UNDO,
+            clock: VectorClock::new(),
+            conflicts: Vec::new(),
+            peer_clocks: HashMap::new(),
+            purged: HashMap::new(),
+            on_change: None,
+            changes: ChangeLog::default(),
+            field_strategies: HashMap::new(),
+            checkpoint: None,
+            sync_cursors: BTreeMap::new(),
+            history: FieldHistory { limit: DEFAULT_HISTORY_LIMIT, entries: HashMap::new() },
+            include_history: false,
+            limits: MergeLimits::default(),
+            author: None,
+            schema: None,
+            collision_policy: CollisionPolicy::default(),
+            ephemeral: HashMap::new(),
+            ephemeral_tick: 0,
+        }
+    }
+    
+    fn new_timestamp(&mut self) -> LamportTimestamp {
+        self.counter += 1;
+        self.clock.insert(self.node_id.clone(), self.counter);
+        LamportTimestamp { wall_ms: self.wall_ms, ..LamportTimestamp::new(self.counter, &self.node_id) }
+    }
+    
+    /// Record a remote write in the vector clock and advance the Lamport
+    /// counter and wall time past it, so later local writes win over it
+    fn observe(&mut self, timestamp: &LamportTimestamp) {
+        let seen = self.clock.entry(timestamp.node_id.clone()).or_insert(0);
+        *seen = (*seen).max(timestamp.counter);
+        self.counter = self.counter.max(timestamp.counter);
+        self.wall_ms = self.wall_ms.max(timestamp.wall_ms);
+    }
+    
+    /// Append a local op to the pending log, compacting once the log
+    /// exceeds `max_operations`
+    fn record_operation(&mut self, op: Operation) {
+        self.seen_ops.insert(&op.op_id());
+        self.operations.push(op);
+        if self.max_operations > 0 && self.operations.len() > self.max_operations {
+            self.compact_operations();
+        }
+    }
+    
+    /// Insert or update a task field. `value` may be a string, number,
+    /// boolean, array or object and keeps its type through sync.
+    pub fn upsert_field(&mut self, task_id: &str, field: String, value: JsValue) -> Result<(), JsValue> {
+        let value: serde_json::Value = serde_wasm_bindgen::from_value(value)
+            .map_err(|e| JsValue::from_str(&format!("Invalid field value: {}", e)))?;
+        let field = self.schema_field(field, &value).map_err(|e| JsValue::from_str(&e))?;
+        self.upsert_value(task_id, field, value);
+        self.notify_changes();
+        Ok(())
+    }
+    
+    /// Insert or update several fields of one task in a single call, e.g.
+    /// `{title: "Plan", status: "todo", priority: 2}`. The fields share one
+    /// timestamp and are synced as a single operation.
+    pub fn upsert_task(&mut self, task_id: &str, fields_js: JsValue) -> Result<(), JsValue> {
+        let fields: serde_json::Map<String, serde_json::Value> = serde_wasm_bindgen::from_value(fields_js)
+            .map_err(|e| JsValue::from_str(&format!("Invalid task fields: {}", e)))?;
+        let fields = self.schema_fields(fields).map_err(|e| JsValue::from_str(&e))?;
+        self.upsert_fields(task_id, fields);
+        self.notify_changes();
+        Ok(())
+    }
+    
+    /// Bulk `upsert_task` for imports: `{ [task_id]: {field: value, ...} }`.
+    /// Returns the number of tasks written.
+    pub fn upsert_tasks(&mut self, tasks_js: JsValue) -> Result<usize, JsValue> {
+        let tasks: serde_json::Value = serde_wasm_bindgen::from_value(tasks_js)
+            .map_err(|e| JsValue::from_str(&format!("Invalid tasks: {}", e)))?;
+        let tasks: BTreeMap<String, serde_json::Map<String, serde_json::Value>> = serde_json::from_value(tasks)
+            .map_err(|e| JsValue::from_str(&format!("Invalid tasks: {}", e)))?;
+        // Check every task before writing any
+        let tasks = tasks
+            .into_iter()
+            .map(|(task_id, fields)| Ok((task_id, self.schema_fields(fields)?)))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|e| JsValue::from_str(&e))?;
+        let count = tasks.len();
+        for (task_id, fields) in tasks {
+            self.upsert_fields(&task_id, fields);
+        }
+        self.notify_changes();
+        Ok(count)
+    }
+    
+    /// Delete a task (soft delete)
+    pub fn delete_task(&mut self, task_id: &str) {
+        if self.write_deleted(task_id, true) {
+            self.push_undo(UndoStep::Deleted { task_id: task_id.to_string() });
+        }
+        self.notify_changes();
+    }
+    
+    /// Bring a deleted task back from the trash. The restore carries a new
+    /// timestamp, so it wins over the delete on every peer.
+    pub fn restore_task(&mut self, task_id: &str) {
+        if self.write_deleted(task_id, false) {
+            self.push_undo(UndoStep::Created { task_id: task_id.to_string() });
+        }
+        self.notify_changes();
+    }
+    
+    /// Archive a task: it leaves `get_tasks` and the columns but stays in
+    /// the document, unlike a delete, so tombstone compaction never
+    /// removes it. Merges resolve it by timestamp like a delete.
+    pub fn archive_task(&mut self, task_id: &str) {
+        if self.write_archived(task_id, true) {
+            self.push_undo(UndoStep::Archived { task_id: task_id.to_string(), archived: true });
+        }
+        self.notify_changes();
+    }
+    
+    /// Put an archived task back on the board
+    pub fn unarchive_task(&mut self, task_id: &str) {
+        if self.write_archived(task_id, false) {
+            self.push_undo(UndoStep::Archived { task_id: task_id.to_string(), archived: false });
+        }
+        self.notify_changes();
+    }
+    
+    /// Archived tasks that aren't deleted, sorted by id
+    pub fn get_archived_tasks(&self) -> JsValue {
+        let tasks: Vec<TaskView> = self.archived_tasks().into_iter().map(TaskView::new).collect();
+        serde_wasm_bindgen::to_value(&tasks).unwrap_or(JsValue::NULL)
+    }
+    
+    /// Permanently remove a task. A delete marker is kept (and exported)
+    /// so peers that still have the task drop it instead of sending it back.
+    pub fn purge_task(&mut self, task_id: &str) {
+        self.purge(task_id);
+        self.notify_changes();
+    }
+    
+    /// Deleted tasks still in the document (the trash), sorted by id
+    pub fn get_deleted_tasks(&self) -> JsValue {
+        let mut tasks: Vec<&CrdtTask> = self.tasks.values().filter(|t| t.deleted).collect();
+        tasks.sort_by(|a, b| a.id.cmp(&b.id));
+        let tasks: Vec<TaskView> = tasks.into_iter().map(TaskView::new).collect();
+        serde_wasm_bindgen::to_value(&tasks).unwrap_or(JsValue::NULL)
+    }
+    
+    /// Revert the most recent local edit still in effect, writing the old
+    /// state with a new timestamp. Edits since overwritten by a remote
+    /// change are dropped instead. Returns whether anything changed.
+    pub fn undo(&mut self) -> bool {
+        while let Some(step) = self.undo_stack.pop_back() {
+            if let Some(inverse) = self.revert(step) {
+                self.redo_stack.push(inverse);
+                self.notify_changes();
+                return true;
+            }
+        }
+        false
+    }
+    
+    /// Re-apply the last undone edit, unless a remote change has
+    /// overwritten what `undo` wrote. Returns whether anything changed.
+    pub fn redo(&mut self) -> bool {
+        while let Some(step) = self.redo_stack.pop() {
+            if let Some(inverse) = self.revert(step) {
+                self.undo_stack.push_back(inverse);
+                self.notify_changes();
+                return true;
+            }
+        }
+        false
+    }
+    
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+    
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+    
+    /// Advance the hybrid clock to `now_ms` (pass `Date.now()`) before
+    /// making changes. Writes then compare by wall time first, so a fresh
+    /// edit beats a stale one from a device whose counter ran ahead.
+    /// Replicas that never tick write wall time 0, which loses to any
+    /// ticked write. The clock never moves backwards.
+    pub fn tick(&mut self, now_ms: f64) {
+        self.ticked_ms = self.ticked_ms.max(now_ms as u64);
+        self.wall_ms = self.wall_ms.max(self.ticked_ms);
+    }
+    
+    /// Name (e.g. user or display name) attached to this replica's
+    /// future writes. Each field reports the author of its current value
+    /// as `last_modified_by` in `get_task`/`get_tasks`, and each task the
+    /// author of its newest field. None stops attaching a name.
+    pub fn set_author(&mut self, name: Option<String>) {
+        self.author = name;
+    }
+    
+    /// Past writes of a field, oldest first: `[{value, timestamp:
+    /// {counter, node_id}, superseded, author}]`. `superseded` marks writes that
+    /// lost a last-write-wins race to a concurrent edit. Only writes this
+    /// replica has seen are listed.
+    pub fn get_field_history(&self, task_id: &str, field: &str) -> JsValue {
+        let writes = self.field_history(task_id, field);
+        serde_wasm_bindgen::to_value(writes).unwrap_or(JsValue::NULL)
+    }
+    
+    /// Limit how many writes are kept per field (default 20); the oldest
+    /// are forgotten first. 0 stops recording history.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history.set_limit(limit);
+    }
+    
+    /// Include field history in `export` (off by default), so it
+    /// survives `import` and is shared by `merge`
+    pub fn set_include_history(&mut self, enabled: bool) {
+        self.include_history = enabled;
+    }
+    
+    /// Limit how many local edits `undo` can step back through (default
+    /// 100); the oldest are forgotten first
+    pub fn set_max_undo(&mut self, depth: usize) {
+        self.max_undo = depth;
+        while self.undo_stack.len() > depth {
+            self.undo_stack.pop_front();
+        }
+    }
+    
+    /// Add `delta` (may be negative) to a PN-Counter field. Unlike
+    /// `upsert_field`, concurrent increments from different nodes add up
+    /// instead of overwriting each other.
+    pub fn increment_counter(&mut self, task_id: &str, field: String, delta: i32) {
+        let timestamp = self.new_timestamp();
+        let delta = delta as i64;
+        
+        let task = self.tasks.entry(task_id.to_string()).or_insert_with(|| CrdtTask::new(task_id, &timestamp));
+        let counter = task.counters.entry(field.clone()).or_default();
+        let total = Some(counter.add(&self.node_id, delta));
+        task.updated_at = timestamp.clone();
+        self.changes.push(task_id, &field, ChangeOrigin::Local, || counter.value().into());
+        
+        let op_id = timestamp.op_id();
+        self.record_operation(Operation::Increment { op_id, task_id: task_id.to_string(), field: field.clone(), delta, total, timestamp });
+        console_log!("Incremented counter {} for task {} by {}", field, task_id, delta);
+        self.notify_changes();
+    }
+    
+    /// Add a tag to a task. Concurrent adds of different tags all survive.
+    pub fn add_tag(&mut self, task_id: &str, tag: String) {
+        let timestamp = self.new_timestamp();
+        let op_id = timestamp.op_id();
+        
+        let task = self.tasks.entry(task_id.to_string()).or_insert_with(|| CrdtTask::new(task_id, &timestamp));
+        let added = !task.tags.contains(&tag);
+        task.tags.add(&tag, op_id.clone());
+        task.updated_at = timestamp.clone();
+        if added {
+            self.changes.push(task_id, "tags", ChangeOrigin::Local, || task.tags.values().into());
+        }
+        
+        console_log!("Added tag {} to task {}", tag, task_id);
+        self.record_operation(Operation::AddTag { op_id, task_id: task_id.to_string(), tag, timestamp });
+        self.notify_changes();
+    }
+    
+    /// Remove a tag from a task. Adds of the same tag this replica hasn't
+    /// seen yet are not affected.
+    pub fn remove_tag(&mut self, task_id: &str, tag: String) {
+        let removed = match self.tasks.get(task_id) {
+            Some(task) => task.tags.observed(&tag),
+            None => return,
+        };
+        if removed.is_empty() {
+            return;
+        }
+        
+        let timestamp = self.new_timestamp();
+        if let Some(task) = self.tasks.get_mut(task_id) {
+            task.tags.remove(&removed);
+            task.updated_at = timestamp.clone();
+            self.changes.push(task_id, "tags", ChangeOrigin::Local, || task.tags.values().into());
+        }
+        
+        let op_id = timestamp.op_id();
+        console_log!("Removed tag {} from task {}", tag, task_id);
+        self.record_operation(Operation::RemoveTag { op_id, task_id: task_id.to_string(), tag, removed, timestamp });
+        self.notify_changes();
+    }
+    
+    /// Tags of a task, sorted
+    pub fn get_tags(&self, task_id: &str) -> Vec<String> {
+        self.tasks
+            .get(task_id)
+            .map(|task| task.tags.values().into_iter().map(str::to_string).collect())
+            .unwrap_or_default()
+    }
+    
+    /// Same as `increment_counter`: makes `field` a counter field, e.g.
+    /// minutes spent, that sums increments from every device
+    pub fn increment_field(&mut self, task_id: &str, field: String, delta: i32) {
+        self.increment_counter(task_id, field, delta);
+    }
+    
+    /// Resolved value of a counter field (0 if unset), as a JS number
+    pub fn get_counter(&self, task_id: &str, field: &str) -> f64 {
+        self.tasks
+            .get(task_id)
+            .and_then(|task| task.counters.get(field))
+            .map_or(0, PnCounter::value) as f64
+    }
+    
+    /// Get all non-deleted tasks, sorted by their `order` key. Archived
+    /// tasks are left out unless `include_archived` is set.
+    pub fn get_tasks(&self, include_archived: Option<bool>) -> JsValue {
+        let tasks: Vec<TaskView> = self.ordered_tasks(include_archived.unwrap_or(false)).into_iter().map(TaskView::new).collect();
+        serde_wasm_bindgen::to_value(&tasks).unwrap_or(JsValue::NULL)
+    }
+    
+    /// `get_tasks`, but only the page of tasks matching `filter_js`:
+    /// `{updated_after, offset, limit, ...fields}`, where each other key is
+    /// a field that must equal the given value, e.g. `{status: "doing"}`.
+    /// `updated_after` is a counter from `last_seen`.
+    pub fn get_tasks_filtered(&self, filter_js: JsValue) -> Result<JsValue, JsValue> {
+        let filter: serde_json::Value = serde_wasm_bindgen::from_value(filter_js)
+            .map_err(|e| JsValue::from_str(&format!("Invalid filter: {}", e)))?;
+        let filter: TaskFilter = serde_json::from_value(filter)
+            .map_err(|e| JsValue::from_str(&format!("Invalid filter: {}", e)))?;
+        let tasks: Vec<TaskView> = self.filtered_tasks(&filter).into_iter().map(TaskView::new).collect();
+        Ok(serde_wasm_bindgen::to_value(&tasks).unwrap_or(JsValue::NULL))
+    }
+    
+    /// Ids of the tasks on the board, in `get_tasks` order
+    pub fn get_task_ids(&self) -> Vec<String> {
+        self.sorted_tasks().into_iter().map(|task| task.id.clone()).collect()
+    }
+    
+    /// Move a task between two neighbours by giving it a fractional `order`
+    /// key that sorts after `before_id` and before `after_id`. Either neighbour
+    /// may be omitted to move the task to the start or end of the list.
+    /// Returns the new order key.
+    pub fn move_task(&mut self, task_id: &str, before_id: Option<String>, after_id: Option<String>) -> Result<String, JsValue> {
+        let key = self.move_task_between(task_id, before_id.as_deref(), after_id.as_deref())
+            .map_err(|e| JsValue::from_str(&e))?;
+        self.notify_changes();
+        Ok(key)
+    }
+    
+    /// Move a task into a kanban column, right after `after_task_id` (or
+    /// to the top of the column). The column and position are written as
+    /// one LWW value, so concurrent moves of the same task resolve by
+    /// timestamp while moves of different tasks all apply. Returns the new
+    /// position key.
+    pub fn move_to_column(&mut self, task_id: &str, column: String, after_task_id: Option<String>) -> Result<String, JsValue> {
+        let key = self.move_into_column(task_id, &column, after_task_id.as_deref())
+            .map_err(|e| JsValue::from_str(&e))?;
+        self.notify_changes();
+        Ok(key)
+    }
+    
+    /// Ids of the tasks on the board in a column, top to bottom
+    pub fn get_column_order(&self, column: &str) -> Vec<String> {
+        self.column_entries(column).into_iter().map(|(_, id)| id.to_string()).collect()
+    }
+    
+    /// Get task by ID
+    pub fn get_task(&self, task_id: &str) -> JsValue {
+        match self.task_view(task_id) {
+            Some(view) => serde_wasm_bindgen::to_value(&view).unwrap_or(JsValue::NULL),
+            None => JsValue::NULL,
+        }
+    }
+    
+    /// Id a task is stored under: `task_id` itself, or the
+    /// `"<node_id>:<task_id>"` that `import_legacy` renamed it to (this
+    /// node's own first). Writing to the old numeric id after a migration
+    /// would start a second copy of the task.
+    pub fn resolve_task_id(&self, task_id: &str) -> String {
+        if self.tasks.contains_key(task_id) || task_id.contains(':') {
+            return task_id.to_string();
+        }
+        let own = format!("{}:{}", self.node_id, task_id);
+        if self.tasks.contains_key(&own) {
+            return own;
+        }
+        self.tasks
+            .keys()
+            .filter(|id| id.rsplit_once(':').is_some_and(|(_, legacy)| legacy == task_id))
+            .min()
+            .cloned()
+            .unwrap_or_else(|| task_id.to_string())
+    }
+    
+    /// Set a local-only field, e.g. "being edited by X", that expires
+    /// `ttl_ticks` after the latest `prune_ephemeral` tick. It shows up
+    /// under `ephemeral` in `get_task`, and is never exported, merged or
+    /// sent as an operation. Rewriting it extends the expiry.
+    pub fn upsert_ephemeral_field(&mut self, task_id: &str, field: String, value: JsValue, ttl_ticks: u64) -> Result<(), JsValue> {
+        let value: serde_json::Value = serde_wasm_bindgen::from_value(value)
+            .map_err(|e| JsValue::from_str(&format!("Invalid field value: {}", e)))?;
+        self.set_ephemeral(task_id, field, value, ttl_ticks);
+        Ok(())
+    }
+    
+    /// Advance the ephemeral clock to `current_tick` (e.g. seconds since
+    /// start, driven from JS) and drop every ephemeral field that has
+    /// expired. Returns how many were dropped.
+    pub fn prune_ephemeral(&mut self, current_tick: u64) -> usize {
+        self.ephemeral_tick = self.ephemeral_tick.max(current_tick);
+        let now = self.ephemeral_tick;
+        let mut pruned = 0;
+        self.ephemeral.retain(|_, fields| {
+            let before = fields.len();
+            fields.retain(|_, field| field.expires_at > now);
+            pruned += before - fields.len();
+            !fields.is_empty()
+        });
+        pruned
+    }
+    
+    /// Re-emit changes applied by `merge` as operations, so
+    /// `get_operations` also relays them to other peers. Off by default.
+    pub fn set_track_merge_ops(&mut self, enabled: bool) {
+        self.track_merge_ops = enabled;
+    }
+    
+    pub fn track_merge_ops(&self) -> bool {
+        self.track_merge_ops
+    }
+    
+    /// Call `callback` with `{task_id, field, value, origin}` for every
+    /// change to a field, counter, `tags` or `deleted` made by edits,
+    /// `merge` or `apply_operations`. Writes that lose to a newer value
+    /// are not reported. Calls are queued as a microtask, so the callback
+    /// may freely read or edit the document.
+    pub fn set_on_change(&mut self, callback: js_sys::Function) {
+        self.on_change = Some(callback);
+        self.changes = ChangeLog(Some(Vec::new()));
+    }
+    
+    pub fn clear_on_change(&mut self) {
+        self.on_change = None;
+        self.changes = ChangeLog::default();
+    }
+    
+    /// Restrict task fields to `{fields: {title: "string", priority:
+    /// "number", ...}, strict}`; kinds are string, number, boolean, array,
+    /// object or any. Strict (the default) makes `upsert_field`,
+    /// `upsert_task` and `upsert_tasks` reject unknown fields and values
+    /// of the wrong kind; with `strict: false` they are written to
+    /// `extras.<field>` instead. Invalid fields merged or received from
+    /// peers always go to `extras.<field>`. `null` removes the schema.
+    pub fn set_schema(&mut self, schema_js: JsValue) -> Result<(), JsValue> {
+        self.schema = serde_wasm_bindgen::from_value(schema_js)
+            .map_err(|e| JsValue::from_str(&format!("Invalid schema: {}", e)))?;
+        Ok(())
+    }
+    
+    /// Stored fields of live tasks that break the schema, as
+    /// `[{task_id, field, message}]` (e.g. from before it was set)
+    pub fn validate(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.schema_violations()).unwrap_or(JsValue::NULL)
+    }
+    
+    /// Choose how `field` combines concurrent writes: `lww` (default),
+    /// `max_numeric`, `concat_unique` or `manual`. Strategies are not
+    /// synced, so set the same ones on every device before merging.
+    pub fn set_field_strategy(&mut self, field: String, strategy: String) -> Result<(), JsValue> {
+        self.set_strategy(field, &strategy).map_err(|e| JsValue::from_str(&e))
+    }
+    
+    /// What `merge` does with an incoming task that has the same id as a
+    /// local one but was created on another node with an unrelated title
+    /// (no shared bigram), e.g. two devices both picking id "7" offline:
+    /// `merge` (default) blends them into one task, `fork` keeps the
+    /// incoming task as `<id>@<creating node>` and lists it under
+    /// `remapped` in the merge report, `error` fails the merge. Forks are
+    /// local: other peers get them with this document's next export.
+    pub fn set_collision_policy(&mut self, policy: &str) -> Result<(), JsValue> {
+        self.collision_policy = CollisionPolicy::parse(policy).map_err(|e| JsValue::from_str(&e))?;
+        Ok(())
+    }
+    
+    /// Settle a `manual` field whose concurrent values are listed in its
+    /// `conflicting` by writing the chosen `value`. Returns false, writing
+    /// nothing, if the field has no open conflict.
+    pub fn resolve_conflict(&mut self, task_id: &str, field: String, value: JsValue) -> Result<bool, JsValue> {
+        let value: serde_json::Value = serde_wasm_bindgen::from_value(value)
+            .map_err(|e| JsValue::from_str(&format!("Invalid field value: {}", e)))?;
+        let resolved = self.resolve_value(task_id, field, value);
+        self.notify_changes();
+        Ok(resolved)
+    }
+    
+    /// Set the limits merges, imports and `apply_operations` enforce:
+    /// `{max_tasks, max_fields_per_task, max_value_bytes, max_counter_jump,
+    /// max_wall_skew_ms}`.
+    /// Omitted keys keep their default, 0 disables a limit. A payload over
+    /// a limit is rejected whole with an error naming the limit.
+    pub fn set_merge_limits(&mut self, limits_js: JsValue) -> Result<(), JsValue> {
+        let limits: serde_json::Value = serde_wasm_bindgen::from_value(limits_js)
+            .map_err(|e| JsValue::from_str(&format!("Invalid limits: {}", e)))?;
+        self.limits = serde_json::from_value(limits)
+            .map_err(|e| JsValue::from_str(&format!("Invalid limits: {}", e)))?;
+        Ok(())
+    }
+    
+    /// Merge another document into this one
+    pub fn merge(&mut self, other_json: &str) -> Result<(), JsValue> {
+        self.merge_state(other_json).map_err(|e| JsValue::from_str(&e))?;
+        self.notify_changes();
+        Ok(())
+    }
+    
+    /// Merge another document and return `{added, updated, deleted, conflicts}`
+    /// counts describing what changed locally
+    pub fn merge_with_report(&mut self, other_json: &str) -> Result<JsValue, JsValue> {
+        let report = self.merge_state(other_json).map_err(|e| JsValue::from_str(&e))?;
+        self.notify_changes();
+        serde_wasm_bindgen::to_value(&report)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+    
+    /// Merge a peer export passed as a JS object (no `JSON.stringify`
+    /// needed) and return `{created, updated: [{task_id, fields}], deleted}`
+    pub fn merge_js(&mut self, other: JsValue) -> Result<JsValue, JsValue> {
+        let value: serde_json::Value = serde_wasm_bindgen::from_value(other)
+            .map_err(|e| JsValue::from_str(&format!("Parse error: {}", e)))?;
+        let report = self.merge_value(value).map_err(|e| JsValue::from_str(&e))?;
+        self.notify_changes();
+        serde_wasm_bindgen::to_value(&report.changes)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+    
+    /// Preview `merge(other_json)` without changing anything: tasks that
+    /// would be created, deleted or restored, and per field the local and
+    /// incoming values and which one would win
+    pub fn diff(&self, other_json: &str) -> Result<JsValue, JsValue> {
+        let preview = self.merge_preview(other_json).map_err(|e| JsValue::from_str(&e))?;
+        serde_wasm_bindgen::to_value(&preview)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+    
+    /// Get document state as JSON for syncing: `{node_id, tasks, clock}`,
+    /// plus `purged` delete markers once tombstones have been compacted
+    pub fn export(&self) -> String {
+        serde_json::to_string(&self.state()).unwrap_or_default()
+    }
+    
+    /// Import document state from JSON produced by `export`, e.g. a
+    /// backup. It is merged like a peer's export, so local edits newer
+    /// than the backup survive. Returns `{added, preserved, overwritten}`
+    /// counts of tasks.
+    pub fn import(&mut self, json: &str) -> Result<JsValue, JsValue> {
+        let summary = self.import_state(json).map_err(|e| JsValue::from_str(&e))?;
+        self.notify_changes();
+        serde_wasm_bindgen::to_value(&summary)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+    
+    /// Replace the document state with an `export`, discarding local
+    /// tasks and pending operations that it doesn't contain. Returns
+    /// `{added, preserved, overwritten}` like `import`.
+    pub fn import_replace(&mut self, json: &str) -> Result<JsValue, JsValue> {
+        let summary = self.replace_state(json).map_err(|e| JsValue::from_str(&e))?;
+        serde_wasm_bindgen::to_value(&summary)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+    
+    /// Import an export from before string task ids (no `version` field).
+    /// Numeric id `N` becomes `"<creating node_id>:N"`, so every device
+    /// migrates a synced task to the same id. Export again afterwards to
+    /// sync with migrated peers.
+    pub fn import_legacy(&mut self, json: &str) -> Result<(), JsValue> {
+        let state = ImportedState::parse_legacy(json)
+            .map_err(|e| JsValue::from_str(&format!("Import error: {}", e)))?;
+        self.load_checked(state).map_err(|e| JsValue::from_str(&format!("Import error: {}", e)))
+    }
+    
+    /// Export document state as LZ4-compressed, base64-encoded JSON in the
+    /// `wasm-compress` chunked format (read by its `decompress_chunked`).
+    /// Every chunk carries a CRC32, so corruption is caught on import.
+    #[cfg(feature = "compression")]
+    pub fn export_compressed(&self) -> String {
+        compression::compress(self.export().as_bytes())
+    }
+    
+    /// Import document state produced by `export_compressed`, or a plain
+    /// `export`. A corrupted payload fails with the byte offset of the
+    /// damaged chunk and leaves the document untouched.
+    #[cfg(feature = "compression")]
+    pub fn import_compressed(&mut self, data: &str) -> Result<(), JsValue> {
+        let json = if data.trim_start().starts_with('{') {
+            data.to_string()
+        } else {
+            compression::decompress(data).map_err(|e| JsValue::from_str(&e))?
+        };
+        self.import_state(&json).map_err(|e| JsValue::from_str(&e))?;
+        self.notify_changes();
+        Ok(())
+    }
+    
+    /// Export document state as MessagePack (a `Uint8Array` in JS): the
+    /// same content as `export`, smaller and faster to encode. `export`
+    /// stays the readable format for debugging.
+    #[cfg(feature = "binary")]
+    pub fn export_binary(&self) -> Vec<u8> {
+        binary::encode(&self.state())
+    }
+    
+    /// Import document state produced by `export_binary`, merging like
+    /// `import`
+    #[cfg(feature = "binary")]
+    pub fn import_binary(&mut self, bytes: &[u8]) -> Result<(), JsValue> {
+        let state = binary::decode(bytes).map_err(|e| JsValue::from_str(&format!("Import error: {}", e)))?;
+        self.merge_imported(state).map_err(|e| JsValue::from_str(&format!("Import error: {}", e)))?;
+        self.notify_changes();
+        Ok(())
+    }
+    
+    /// `merge` for a peer's `export_binary`
+    #[cfg(feature = "binary")]
+    pub fn merge_binary(&mut self, bytes: &[u8]) -> Result<(), JsValue> {
+        self.merge_binary_state(bytes).map_err(|e| JsValue::from_str(&e))?;
+        self.notify_changes();
+        Ok(())
+    }
+    
+    /// Get operations since last sync
+    pub fn get_operations(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.operations).unwrap_or(JsValue::NULL)
+    }
+    
+    /// Get pending operations with a timestamp after `(counter, node_id)`,
+    /// a point previously returned by `last_seen`. Without `node_id`, every
+    /// operation with a counter above `counter` is returned. Operations
+    /// removed by `clear_operations` or compaction are not returned.
+    pub fn get_operations_since(&self, counter: u64, node_id: Option<String>) -> JsValue {
+        let ops = self.operations_since(counter, node_id.as_deref());
+        serde_wasm_bindgen::to_value(&ops).unwrap_or(JsValue::NULL)
+    }
+    
+    /// Like `get_operations_since`, but tells a peer that is behind the
+    /// last checkpoint to sync a full snapshot: returns `{type:
+    /// "operations", operations}` or `{type: "snapshot_required", checkpoint}`
+    pub fn get_delta_since(&self, counter: u64, node_id: Option<String>) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.delta_since(counter, node_id.as_deref())).unwrap_or(JsValue::NULL)
+    }
+    
+    /// Pending operations `target` has not acknowledged yet with
+    /// `ack_operations`. Naming a new target starts it at the beginning
+    /// of the log, and from then on the log keeps what it still needs.
+    pub fn get_operations_for(&mut self, target: String) -> JsValue {
+        let ops = self.operations_for(target);
+        serde_wasm_bindgen::to_value(&ops).unwrap_or(JsValue::NULL)
+    }
+    
+    /// Record that `target` has every operation up to `up_to_counter`.
+    /// Operations every target has acknowledged are folded out of the log
+    /// like `checkpoint`; returns how many were.
+    pub fn ack_operations(&mut self, target: String, up_to_counter: u64) -> usize {
+        let cursor = self.sync_cursors.entry(target).or_insert(0);
+        *cursor = (*cursor).max(up_to_counter);
+        let acked = self.sync_cursors.values().copied().min().unwrap_or(0);
+        self.fold_operations_where(|op| op.timestamp().counter <= acked)
+    }
+    
+    /// Forget a sync target, so the log no longer waits for it
+    pub fn remove_sync_target(&mut self, target: &str) -> bool {
+        self.sync_cursors.remove(target).is_some()
+    }
+    
+    /// Fold the pending operations into the document snapshot: record how
+    /// far they reached per node and truncate the log. Returns the
+    /// checkpoint `{timestamp, clock}`, or null if nothing was ever folded.
+    pub fn checkpoint(&mut self) -> JsValue {
+        self.fold_operations();
+        serde_wasm_bindgen::to_value(&self.checkpoint).unwrap_or(JsValue::NULL)
+    }
+    
+    /// `{checkpoint, operations}`: the last checkpoint (null if none) and
+    /// the operations recorded after it, as JSON
+    pub fn export_since_checkpoint(&self) -> String {
+        let export = serde_json::json!({
+            "checkpoint": self.checkpoint,
+            "operations": self.operations,
+        });
+        export.to_string()
+    }
+    
+    /// Current Lamport point `{counter, node_id}`. Store it per peer after
+    /// sending operations and pass it to `get_operations_since` next time.
+    pub fn last_seen(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&LamportTimestamp::new(self.counter, &self.node_id)).unwrap_or(JsValue::NULL)
+    }
+    
+    /// Apply remote operations. Operations whose id was already seen are
+    /// skipped, so re-applying the same batch is a no-op. A batch over the
+    /// merge limits is rejected without applying any of it. Returns the
+    /// tasks that actually changed, `{created, updated, deleted, applied,
+    /// skipped}`, so only those need re-rendering.
+    pub fn apply_operations(&mut self, ops_json: &str) -> Result<JsValue, JsValue> {
+        let report = self.apply_ops_json(ops_json).map_err(|e| JsValue::from_str(&e))?;
+        self.notify_changes();
+        serde_wasm_bindgen::to_value(&report)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+    
+    fn apply_ops(&mut self, ops: Vec<Operation>) -> ApplyReport {
+        let mut report = ApplyReport::default();
+        // Each touched task as it was before the batch
+        let mut before_batch: BTreeMap<String, Option<CrdtTask>> = BTreeMap::new();
+        for op in ops {
+            let task_id = op.task_id().to_string();
+            let before = self.tasks.get(&task_id).cloned();
+            self.apply_op(op);
+            let changed = match (&before, self.tasks.get(&task_id)) {
+                (Some(before), Some(after)) => !before.same_content(after),
+                (None, None) => false,
+                _ => true,
+            };
+            if changed {
+                report.applied += 1;
+                before_batch.entry(task_id).or_insert(before);
+            } else {
+                report.skipped += 1;
+            }
+        }
+        
+        // A task changed by one op and changed back by another is unchanged
+        for (task_id, before) in before_batch {
+            match (before.filter(|task| !task.deleted), self.tasks.get(&task_id).filter(|task| !task.deleted)) {
+                (None, Some(_)) => report.created.push(task_id),
+                (Some(_), None) => report.deleted.push(task_id),
+                (Some(before), Some(after)) if !before.same_content(after) => report.updated.push(task_id),
+                _ => {}
+            }
+        }
+        report
+    }
+    
+    fn apply_op(&mut self, op: Operation) {
+        if !self.seen_ops.insert(&op.op_id()) {
+            return;
+        }
+        
+        let timestamp = op.timestamp().clone();
+        let task_id = op.task_id().to_string();
+        if self.is_purged(&task_id) {
+            // Write to a task whose delete was already compacted away
+            self.observe(&timestamp);
+            return;
+        }
+        match op {
+            Operation::Insert { task_id, field, value, timestamp, clock, author, .. } |
+            Operation::Update { task_id, field, value, timestamp, clock, author, .. } => {
+                self.record_peer_clock(&timestamp.node_id, &clock);
+                let field = self.received_field(field, &value);
+                self.apply_field_update(&task_id, field, CrdtValue::new(value, timestamp).by(author), &clock);
+            }
+            Operation::UpdateFields { task_id, fields, timestamp, clock, author, .. } => {
+                self.record_peer_clock(&timestamp.node_id, &clock);
+                for (field, value) in fields {
+                    let field = self.received_field(field, &value);
+                    let remote = CrdtValue::new(value, timestamp.clone()).by(author.clone());
+                    self.apply_field_update(&task_id, field, remote, &clock);
+                }
+            }
+            Operation::Delete { task_id, timestamp, .. } => {
+                self.apply_deletion(&task_id, timestamp, true);
+            }
+            Operation::Restore { task_id, timestamp, .. } => {
+                self.apply_deletion(&task_id, timestamp, false);
+            }
+            Operation::Archive { task_id, timestamp, .. } => {
+                self.apply_archive(&task_id, timestamp, true);
+            }
+            Operation::Unarchive { task_id, timestamp, .. } => {
+                self.apply_archive(&task_id, timestamp, false);
+            }
+            Operation::Purge { task_id, timestamp, .. } => {
+                if self.apply_purge(&task_id, timestamp) {
+                    self.changes.push(&task_id, "deleted", ChangeOrigin::Remote, || true.into());
+                }
+            }
+            Operation::Increment { task_id, field, delta, total, timestamp, .. } => {
+                self.apply_increment(&task_id, field, delta, total, timestamp);
+            }
+            Operation::AddTag { op_id, task_id, tag, timestamp } => {
+                // Ops from before op ids derive the add id from the timestamp
+                let add_id = if op_id.is_empty() { timestamp.op_id() } else { op_id };
+                let task = self.tasks.entry(task_id.clone()).or_insert_with(|| CrdtTask::new(&task_id, &timestamp));
+                let added = !task.tags.contains(&tag);
+                task.tags.add(&tag, add_id);
+                if added {
+                    self.changes.push(&task_id, "tags", ChangeOrigin::Remote, || task.tags.values().into());
+                }
+            }
+            Operation::RemoveTag { task_id, removed, timestamp, .. } => {
+                let task = self.tasks.entry(task_id.clone()).or_insert_with(|| CrdtTask::new(&task_id, &timestamp));
+                let before = task.tags.values().len();
+                task.tags.remove(&removed);
+                if task.tags.values().len() != before {
+                    self.changes.push(&task_id, "tags", ChangeOrigin::Remote, || task.tags.values().into());
+                }
+            }
+        }
+        if let Some(task) = self.tasks.get_mut(&task_id) {
+            task.touch(&timestamp);
+        }
+        self.observe(&timestamp);
+    }
+    
+    /// Name a field received from a peer is stored under: fields that
+    /// break the schema go to `extras.<field>`
+    fn received_field(&self, field: String, value: &serde_json::Value) -> String {
+        match &self.schema {
+            Some(schema) if schema.violation(&field, value).is_some() => format!("{}{}", EXTRAS_PREFIX, field),
+            _ => field,
+        }
+    }
+    
+    fn apply_field_update(&mut self, task_id: &str, field: String, remote: CrdtValue, clock: &VectorClock) {
+        let task = self.tasks.entry(task_id.to_string()).or_insert_with(|| CrdtTask::new(task_id, &remote.timestamp));
+        let strategy = self.field_strategies.get(&field).copied().unwrap_or_default();
+        self.history.record(task_id, &field, HistoryEntry::of(&remote));
+        
+        let resolved = match task.fields.get(&field) {
+            Some(existing) => {
+                if strategy == FieldStrategy::Lww
+                    && existing.value != remote.value
+                    && is_concurrent(&existing.timestamp, &self.clock, &remote.timestamp, clock)
+                {
+                    let (kept, discarded) = if wins(&existing.timestamp, &remote.timestamp) {
+                        (existing, &remote)
+                    } else {
+                        (&remote, existing)
+                    };
+                    self.history.supersede(task_id, &field, &discarded.timestamp);
+                    self.conflicts.push(Conflict {
+                        task_id: task_id.to_string(),
+                        field: field.clone(),
+                        kept: kept.value.clone(),
+                        discarded: discarded.value.clone(),
+                    });
+                }
+                resolve_write(strategy, existing, &self.clock, &remote, clock)
+            }
+            None => Some(remote),
+        };
+        
+        if let Some(resolved) = resolved {
+            if task.fields.get(&field).is_none_or(|existing| existing.value != resolved.value || existing.conflicting != resolved.conflicting) {
+                self.changes.push(task_id, &field, ChangeOrigin::Remote, || resolved.value.clone());
+            }
+            task.fields.insert(field, resolved);
+        }
+    }
+    
+    fn apply_increment(&mut self, task_id: &str, field: String, delta: i64, total: Option<u64>, timestamp: LamportTimestamp) {
+        let task = self.tasks.entry(task_id.to_string()).or_insert_with(|| CrdtTask::new(task_id, &timestamp));
+        let counter = task.counters.entry(field.clone()).or_default();
+        let changed = match total {
+            Some(total) => counter.raise(&timestamp.node_id, delta, total),
+            None => {
+                counter.add(&timestamp.node_id, delta);
+                true
+            }
+        };
+        if changed {
+            self.changes.push(task_id, &field, ChangeOrigin::Remote, || counter.value().into());
+        }
+    }
+    
+    /// Apply a remote delete or restore. A delete that arrives before the
+    /// task's writes leaves a tombstone for them to land on.
+    fn apply_deletion(&mut self, task_id: &str, timestamp: LamportTimestamp, deleted: bool) {
+        let task = self.tasks.entry(task_id.to_string()).or_insert_with(|| CrdtTask::new(task_id, &timestamp));
+        if task.deletion_wins(&timestamp) {
+            if task.deleted != deleted {
+                self.changes.push(task_id, "deleted", ChangeOrigin::Remote, || deleted.into());
+            }
+            task.deleted = deleted;
+            task.deleted_at = Some(timestamp);
+        }
+    }
+    
+    /// Apply a remote archive or unarchive, creating the task like
+    /// `apply_deletion` if its writes haven't arrived yet
+    fn apply_archive(&mut self, task_id: &str, timestamp: LamportTimestamp, archived: bool) {
+        let task = self.tasks.entry(task_id.to_string()).or_insert_with(|| CrdtTask::new(task_id, &timestamp));
+        if task.archive_wins(&timestamp) {
+            if task.archived != archived {
+                self.changes.push(task_id, "archived", ChangeOrigin::Remote, || archived.into());
+            }
+            task.archived = archived;
+            task.archived_at = Some(timestamp);
+        }
+    }
+    
+    /// Concurrent edits resolved since the last `clear_conflicts`, as
+    /// `[{task_id, field, kept, discarded}]`
+    pub fn get_conflicts(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.conflicts).unwrap_or(JsValue::NULL)
+    }
+    
+    /// Forget reported conflicts (after the UI has shown them)
+    pub fn clear_conflicts(&mut self) {
+        self.conflicts.clear();
+    }
+    
+    /// Remove deleted tasks whose delete is older than `min_age_counter`
+    /// and has been acknowledged by every known peer, i.e.
+    /// each peer's last export or op carried a clock covering the delete.
+    /// A small delete marker is kept per purged task so that a peer which
+    /// missed the delete cannot bring the task back when merging.
+    /// Returns `{purged, pending}`.
+    pub fn compact_tombstones(&mut self, min_age_counter: u64) -> JsValue {
+        let report = self.purge_tombstones(min_age_counter);
+        serde_wasm_bindgen::to_value(&report).unwrap_or(JsValue::NULL)
+    }
+    
+    /// Get the vector clock (node id -> highest counter seen)
+    pub fn get_clock(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.clock).unwrap_or(JsValue::NULL)
+    }
+    
+    /// Clear all operations (after successful sync). Like `checkpoint`,
+    /// so peers behind the cleared ops are asked for a snapshot sync.
+    pub fn clear_operations(&mut self) {
+        self.fold_operations();
+    }
+    
+    /// Drop Update ops superseded by a later write to the same task field,
+    /// and UpdateFields ops whose fields all were. Fields are
+    /// last-write-wins, so peers applying the compacted log end up in the
+    /// same state. Returns the number of ops removed.
+    pub fn compact_operations(&mut self) -> usize {
+        let mut latest: HashSet<(&str, &str)> = HashSet::new();
+        let mut keep = vec![true; self.operations.len()];
+        
+        for (i, op) in self.operations.iter().enumerate().rev() {
+            match op {
+                Operation::Update { task_id, field, .. } => {
+                    keep[i] = latest.insert((task_id.as_str(), field.as_str()));
+                }
+                Operation::UpdateFields { task_id, fields, .. } => {
+                    // Record every field, not just up to the first newer one
+                    keep[i] = fields.keys().fold(false, |newer, field| latest.insert((task_id.as_str(), field.as_str())) | newer);
+                }
+                _ => {}
+            }
+        }
+        
+        let before = self.operations.len();
+        let mut keep = keep.into_iter();
+        self.operations.retain(|_| keep.next().unwrap_or(true));
+        before - self.operations.len()
+    }
+    
+    /// Cap the pending operation log: once it grows past `max` ops it is
+    /// compacted automatically. The log can still exceed the cap when it
+    /// holds that many distinct writes. 0 (the default) disables the cap.
+    pub fn set_max_operations(&mut self, max: usize) {
+        self.max_operations = max;
+        if max > 0 && self.operations.len() > max {
+            self.compact_operations();
+        }
+    }
+    
+    /// Treat every operation with a counter below `gc_counter` as seen,
+    /// dropping their individual ids. Only call this once every peer has
+    /// synced past that counter; older ops re-sent afterwards are skipped.
+    pub fn prune_seen_operations(&mut self, gc_counter: u64) {
+        // Re-emitted writes below the cutoff become covered by their source
+        let seen_ops = &mut self.seen_ops;
+        seen_ops.other.retain(|op_id| match SeenOps::source(op_id).and_then(SeenOps::parse) {
+            Some((node_id, counter)) if counter < gc_counter => {
+                seen_ops.nodes.entry(node_id.to_string()).or_default();
+                false
+            }
+            _ => true,
+        });
+        for node in seen_ops.nodes.values_mut() {
+            node.advance_to(gc_counter.saturating_sub(1));
+        }
+    }
+    
+    /// Whether an operation id (`node_id:counter`) was already applied
+    pub fn has_seen(&self, op_id: &str) -> bool {
+        self.seen_ops.contains(op_id)
+    }
+    
+    /// Get document stats: counts, encoded sizes, and the largest tasks
+    pub fn stats(&self) -> JsValue {
+        let stats = self.document_stats(STATS_LARGEST_TASKS);
+        serde_wasm_bindgen::to_value(&stats).unwrap_or(JsValue::NULL)
+    }
+    
+    /// Encoded size of a task in bytes, deleted tasks included
+    pub fn task_size(&self, task_id: &str) -> Option<usize> {
+        self.tasks.get(task_id).map(encoded_len)
+    }
+    
+    /// Generate sync code (short, stable hash of node_id)
+    pub fn get_sync_code(&self) -> String {
+        let hash = fnv1a_64(&[SYNC_CODE_SALT, self.node_id.as_bytes()]);
+        encode_sync_code(hash)
+    }
+    
+    /// Sync code derived from the document content. Two peers with equal
+    /// task state get the same code, so they can cheaply compare whether
+    /// they are in sync.
+    pub fn get_content_sync_code(&self) -> String {
+        // serde_json maps are ordered by key, so this is canonical
+        // regardless of HashMap iteration order
+        let canonical = serde_json::to_value(&self.tasks)
+            .map(|value| value.to_string())
+            .unwrap_or_default();
+        let hash = fnv1a_64(&[SYNC_CODE_SALT, canonical.as_bytes()]);
+        encode_sync_code(hash)
+    }
+}
+
+impl CrdtDocument {
+    /// `upsert_field` with an already converted value
+    pub fn upsert_value(&mut self, task_id: &str, field: String, value: impl Into<serde_json::Value>) {
+        self.upsert_quietly(task_id, field.clone(), value.into());
+        console_log!("Upserted field {} for task {}", field, task_id);
+    }
+    
+    /// `upsert_task` with already converted values. Several fields share
+    /// one timestamp and are sent as a single `UpdateFields` op.
+    pub fn upsert_fields(&mut self, task_id: &str, fields: serde_json::Map<String, serde_json::Value>) {
+        let count = fields.len();
+        if count < 2 {
+            for (field, value) in fields {
+                self.upsert_quietly(task_id, field, value);
+            }
+        } else {
+            let created = !self.tasks.contains_key(task_id);
+            let timestamp = self.new_timestamp();
+            let mut written = BTreeMap::new();
+            for (field, value) in fields {
+                let Some(previous) = self.store_field(task_id, field.clone(), value.clone(), &timestamp) else {
+                    continue;
+                };
+                if !created {
+                    self.push_undo(UndoStep::Field { task_id: task_id.to_string(), field: field.clone(), previous, value: value.clone() });
+                }
+                written.insert(field, value);
+            }
+            if created {
+                self.push_undo(UndoStep::Created { task_id: task_id.to_string() });
+            }
+            if !written.is_empty() {
+                let (clock, author) = (self.clock.clone(), self.author.clone());
+                let op_id = timestamp.op_id();
+                self.record_operation(Operation::UpdateFields { op_id, task_id: task_id.to_string(), fields: written, timestamp, clock, author });
+            }
+        }
+        console_log!("Upserted {} fields for task {}", count, task_id);
+    }
+    
+    /// `upsert_ephemeral_field` with an already converted value
+    pub fn set_ephemeral(&mut self, task_id: &str, field: String, value: serde_json::Value, ttl_ticks: u64) {
+        let expires_at = self.ephemeral_tick.saturating_add(ttl_ticks);
+        self.ephemeral.entry(task_id.to_string()).or_default().insert(field, EphemeralValue { value, expires_at });
+    }
+    
+    /// `get_task` before conversion to JS
+    fn task_view(&self, task_id: &str) -> Option<TaskView<'_>> {
+        let task = self.tasks.get(task_id).filter(|task| !task.deleted)?;
+        let mut view = TaskView::new(task);
+        if let Some(fields) = self.ephemeral.get(task_id) {
+            view.ephemeral = fields.iter().map(|(field, value)| (field.as_str(), &value.value)).collect();
+        }
+        Some(view)
+    }
+    
+    /// Name a local write to `field` is stored under, or why the schema
+    /// rejects it
+    fn schema_field(&self, field: String, value: &serde_json::Value) -> Result<String, String> {
+        let Some(schema) = &self.schema else {
+            return Ok(field);
+        };
+        match schema.violation(&field, value) {
+            None => Ok(field),
+            Some(violation) if schema.strict => Err(format!("Schema error: {}", violation)),
+            Some(_) => Ok(format!("{}{}", EXTRAS_PREFIX, field)),
+        }
+    }
+    
+    /// `schema_field` for each field of a task
+    fn schema_fields(
+        &self,
+        fields: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<serde_json::Map<String, serde_json::Value>, String> {
+        fields
+            .into_iter()
+            .map(|(field, value)| Ok((self.schema_field(field, &value)?, value)))
+            .collect()
+    }
+    
+    fn schema_violations(&self) -> Vec<SchemaViolation> {
+        let Some(schema) = &self.schema else {
+            return Vec::new();
+        };
+        let mut violations: Vec<SchemaViolation> = self
+            .tasks
+            .values()
+            .filter(|task| !task.deleted)
+            .flat_map(|task| {
+                task.fields.iter().filter_map(|(field, value)| {
+                    let message = schema.violation(field, &value.value)?;
+                    Some(SchemaViolation { task_id: task.id.clone(), field: field.clone(), message })
+                })
+            })
+            .collect();
+        violations.sort_by(|a, b| (&a.task_id, &a.field).cmp(&(&b.task_id, &b.field)));
+        violations
+    }
+    
+    /// `set_field_strategy` with a plain error
+    fn set_strategy(&mut self, field: String, strategy: &str) -> Result<(), String> {
+        match FieldStrategy::parse(strategy)? {
+            FieldStrategy::Lww => self.field_strategies.remove(&field),
+            strategy => self.field_strategies.insert(field, strategy),
+        };
+        Ok(())
+    }
+    
+    /// `resolve_conflict` with an already converted value
+    pub fn resolve_value(&mut self, task_id: &str, field: String, value: serde_json::Value) -> bool {
+        let open = self
+            .tasks
+            .get(task_id)
+            .and_then(|task| task.fields.get(&field))
+            .is_some_and(|current| !current.conflicting.is_empty());
+        if open {
+            // A local write has seen every conflicting value, so it
+            // replaces them on every replica
+            self.upsert_value(task_id, field, value);
+        }
+        open
+    }
+    
+    /// `upsert_value` without the console log, so bulk writes log once
+    fn upsert_quietly(&mut self, task_id: &str, field: String, value: serde_json::Value) {
+        let created = !self.tasks.contains_key(task_id);
+        if let Some(previous) = self.write_field(task_id, field.clone(), value.clone()) {
+            if created {
+                self.push_undo(UndoStep::Created { task_id: task_id.to_string() });
+            } else {
+                self.push_undo(UndoStep::Field { task_id: task_id.to_string(), field, previous, value });
+            }
+        }
+    }
+    
+    /// Write a field as a local edit; returns `Some(value it replaced)`, or
+    /// None if the write lost to a newer value
+    fn write_field(&mut self, task_id: &str, field: String, value: serde_json::Value) -> Option<Option<serde_json::Value>> {
+        let timestamp = self.new_timestamp();
+        let previous = self.store_field(task_id, field.clone(), value.clone(), &timestamp)?;
+        
+        let op_id = timestamp.op_id();
+        let first_field = self.tasks[task_id].fields.len() == 1;
+        let (task_id, clock, author) = (task_id.to_string(), self.clock.clone(), self.author.clone());
+        let op = if first_field && field == "title" {
+            Operation::Insert { op_id, task_id, field: field.clone(), value, timestamp, clock, author }
+        } else {
+            Operation::Update { op_id, task_id, field: field.clone(), value, timestamp, clock, author }
+        };
+        
+        self.record_operation(op);
+        Some(previous)
+    }
+    
+    /// Store a local write made at `timestamp` without recording an op;
+    /// returns what `write_field` does
+    fn store_field(
+        &mut self,
+        task_id: &str,
+        field: String,
+        value: serde_json::Value,
+        timestamp: &LamportTimestamp,
+    ) -> Option<Option<serde_json::Value>> {
+        let task = self.tasks.entry(task_id.to_string()).or_insert_with(|| CrdtTask::new(task_id, timestamp));
+        
+        // CRDT: Keep the value with higher timestamp (last-write-wins)
+        let should_update = match task.fields.get(&field) {
+            Some(existing) => wins(timestamp, &existing.timestamp),
+            None => true,
+        };
+        if !should_update {
+            return None;
+        }
+        
+        let written = CrdtValue::new(value.clone(), timestamp.clone()).by(self.author.clone());
+        self.history.record(task_id, &field, HistoryEntry::of(&written));
+        let previous = task.fields.insert(field.clone(), written);
+        task.updated_at = timestamp.clone();
+        if previous.as_ref().is_none_or(|previous| previous.value != value || !previous.conflicting.is_empty()) {
+            self.changes.push(task_id, &field, ChangeOrigin::Local, || value.clone());
+        }
+        Some(previous.map(|previous| previous.value))
+    }
+    
+    /// Delete or restore a task as a local edit; false if the task doesn't
+    /// exist or is already in that state
+    fn write_deleted(&mut self, task_id: &str, deleted: bool) -> bool {
+        if self.tasks.get(task_id).is_none_or(|task| task.deleted == deleted) {
+            return false;
+        }
+        let timestamp = self.new_timestamp();
+        if let Some(task) = self.tasks.get_mut(task_id) {
+            task.deleted = deleted;
+            task.updated_at = timestamp.clone();
+            task.deleted_at = Some(timestamp.clone());
+        }
+        self.changes.push(task_id, "deleted", ChangeOrigin::Local, || deleted.into());
+        
+        let op_id = timestamp.op_id();
+        if deleted {
+            self.record_operation(Operation::Delete { op_id, task_id: task_id.to_string(), timestamp });
+            console_log!("Deleted task {}", task_id);
+        } else {
+            self.record_operation(Operation::Restore { op_id, task_id: task_id.to_string(), timestamp });
+            console_log!("Restored task {}", task_id);
+        }
+        true
+    }
+    
+    /// Archive or unarchive a task as a local edit; false if the task
+    /// doesn't exist, is deleted, or is already in that state
+    fn write_archived(&mut self, task_id: &str, archived: bool) -> bool {
+        if self.tasks.get(task_id).is_none_or(|task| task.deleted || task.archived == archived) {
+            return false;
+        }
+        let timestamp = self.new_timestamp();
+        if let Some(task) = self.tasks.get_mut(task_id) {
+            task.archived = archived;
+            task.updated_at = timestamp.clone();
+            task.archived_at = Some(timestamp.clone());
+        }
+        self.changes.push(task_id, "archived", ChangeOrigin::Local, || archived.into());
+        
+        let op_id = timestamp.op_id();
+        if archived {
+            self.record_operation(Operation::Archive { op_id, task_id: task_id.to_string(), timestamp });
+            console_log!("Archived task {}", task_id);
+        } else {
+            self.record_operation(Operation::Unarchive { op_id, task_id: task_id.to_string(), timestamp });
+            console_log!("Unarchived task {}", task_id);
+        }
+        true
+    }
+    
+    /// Pass collected changes to the `set_on_change` callback. The call is
+    /// queued as a microtask because the document is still borrowed by the
+    /// wasm-bindgen call that made the changes: a callback reading it
+    /// synchronously would throw "recursive use of an object".
+    fn notify_changes(&mut self) {
+        let changes = self.changes.take();
+        #[cfg(test)]
+        tests::NOTIFIED.with(|notified| notified.borrow_mut().push(changes.clone()));
+        let Some(callback) = self.on_change.clone() else {
+            return;
+        };
+        if changes.is_empty() {
+            return;
+        }
+        let deliver = Closure::once_into_js(move || {
+            for change in &changes {
+                if let Ok(change) = serde_wasm_bindgen::to_value(change) {
+                    let _ = callback.call1(&JsValue::NULL, &change);
+                }
+            }
+        });
+        queue_microtask(deliver.unchecked_ref());
+    }
+    
+    /// Record a local edit; a new edit makes the redo history meaningless
+    fn push_undo(&mut self, step: UndoStep) {
+        self.redo_stack.clear();
+        if self.max_undo == 0 {
+            return;
+        }
+        self.undo_stack.push_back(step);
+        if self.undo_stack.len() > self.max_undo {
+            self.undo_stack.pop_front();
+        }
+    }
+    
+    /// Write the inverse of `step` if its effect is still current, and
+    /// return the step that would revert that in turn
+    fn revert(&mut self, step: UndoStep) -> Option<UndoStep> {
+        match step {
+            UndoStep::Field { task_id, field, previous, value } => {
+                let current = self.tasks.get(&task_id)?.fields.get(&field)?;
+                if current.value != value {
+                    // Overwritten since, most likely by a remote edit
+                    return None;
+                }
+                let restored = previous.unwrap_or(serde_json::Value::Null);
+                self.write_field(&task_id, field.clone(), restored.clone())?;
+                Some(UndoStep::Field { task_id, field, previous: Some(value), value: restored })
+            }
+            UndoStep::Created { task_id } => {
+                self.write_deleted(&task_id, true).then_some(UndoStep::Deleted { task_id })
+            }
+            UndoStep::Deleted { task_id } => {
+                self.write_deleted(&task_id, false).then_some(UndoStep::Created { task_id })
+            }
+            UndoStep::Archived { task_id, archived } => {
+                let archived = !archived;
+                self.write_archived(&task_id, archived).then_some(UndoStep::Archived { task_id, archived })
+            }
+        }
+    }
+    
+    /// Pending ops written after `counter` (and, with a node id, after
+    /// that exact timestamp)
+    fn operations_since(&self, counter: u64, node_id: Option<&str>) -> Vec<&Operation> {
+        self.operations
+            .iter()
+            .filter(|op| {
+                let timestamp = op.timestamp();
+                match node_id {
+                    Some(node_id) => (timestamp.counter, timestamp.node_id.as_str()) > (counter, node_id),
+                    None => timestamp.counter > counter,
+                }
+            })
+            .collect()
+    }
+    
+    fn delta_since(&self, counter: u64, node_id: Option<&str>) -> Delta<'_> {
+        if let Some(checkpoint) = &self.checkpoint {
+            let folded = &checkpoint.timestamp;
+            let behind = match node_id {
+                Some(node_id) => (counter, node_id) < (folded.counter, folded.node_id.as_str()),
+                None => counter < folded.counter,
+            };
+            if behind {
+                return Delta::SnapshotRequired { checkpoint };
+            }
+        }
+        Delta::Operations { operations: self.operations_since(counter, node_id) }
+    }
+    
+    /// Truncate the operation log, moving the checkpoint past it
+    fn fold_operations(&mut self) {
+        self.fold_operations_where(|_| true);
+    }
+    
+    /// `fold_operations` for the ops matching `fold`; returns how many
+    fn fold_operations_where(&mut self, fold: impl Fn(&Operation) -> bool) -> usize {
+        let (folded, kept): (Vec<Operation>, Vec<Operation>) = std::mem::take(&mut self.operations).into_iter().partition(|op| fold(op));
+        self.operations = kept;
+        let Some(newest) = folded.iter().map(Operation::timestamp).max().cloned() else {
+            return 0;
+        };
+        let checkpoint = self.checkpoint.get_or_insert_with(|| Checkpoint {
+            timestamp: newest.clone(),
+            clock: VectorClock::new(),
+        });
+        checkpoint.timestamp = checkpoint.timestamp.clone().max(newest);
+        for op in &folded {
+            let timestamp = op.timestamp();
+            let counter = checkpoint.clock.entry(timestamp.node_id.clone()).or_insert(0);
+            *counter = (*counter).max(timestamp.counter);
+        }
+        console_log!("Checkpoint at {}", checkpoint.timestamp.op_id());
+        folded.len()
+    }
+    
+    /// `get_operations_for` before conversion to JS
+    fn operations_for(&mut self, target: String) -> Vec<&Operation> {
+        let cursor = *self.sync_cursors.entry(target).or_insert(0);
+        self.operations_since(cursor, None)
+    }
+    
+    /// Replace tasks, clock and delete markers with an imported state
+    fn load(&mut self, state: ImportedState) {
+        self.counter = self.counter.max(max_counter(&state.tasks, &state.clock));
+        self.wall_ms = self.wall_ms.max(max_wall_ms(&state.tasks));
+        self.tasks = state.tasks;
+        self.clock = state.clock;
+        self.purged = state.purged;
+        self.history.entries.clear();
+        self.history.merge(state.history);
+        console_log!("Imported document with {} tasks", self.tasks.len());
+    }
+    
+    /// `load` after checking the state against the merge limits
+    fn load_checked(&mut self, state: ImportedState) -> Result<(), LimitExceeded> {
+        self.limits.check_state(&state, self.counter, self.ticked_ms)?;
+        self.load(state);
+        Ok(())
+    }
+    
+    /// `import` with a plain error
+    pub fn import_state(&mut self, json: &str) -> Result<ImportSummary, String> {
+        let state = ImportedState::parse(json).map_err(|e| format!("Import error: {}", e))?;
+        let before = self.tasks.clone();
+        self.merge_imported(state).map_err(|e| format!("Import error: {}", e))?;
+        Ok(ImportSummary::between(&before, &self.tasks))
+    }
+    
+    /// `import_replace_state`, then notify the `set_on_change` callback
+    fn replace_state(&mut self, json: &str) -> Result<ImportSummary, String> {
+        let summary = self.import_replace_state(json)?;
+        self.notify_changes();
+        Ok(summary)
+    }
+    
+    /// `import_replace` with a plain error
+    pub fn import_replace_state(&mut self, json: &str) -> Result<ImportSummary, String> {
+        let state = ImportedState::parse(json).map_err(|e| format!("Import error: {}", e))?;
+        let summary = ImportSummary::between(&self.tasks, &state.tasks);
+        let before = self.tasks.clone();
+        self.load_checked(state).map_err(|e| format!("Import error: {}", e))?;
+        // They describe edits that were just discarded
+        self.operations.clear();
+        self.push_replaced(&before);
+        Ok(summary)
+    }
+    
+    /// Report what replacing the tasks in `before` with the current ones
+    /// changed, as remote changes: every differing field, counter or
+    /// `tags` of a live task, and `deleted` for tasks that went away
+    fn push_replaced(&mut self, before: &HashMap<String, CrdtTask>) {
+        let live = |tasks: &HashMap<String, CrdtTask>, task_id: &str| tasks.get(task_id).filter(|task| !task.deleted).cloned();
+        let mut task_ids: Vec<&String> = before.keys().chain(self.tasks.keys()).collect();
+        task_ids.sort_unstable();
+        task_ids.dedup();
+        for task_id in task_ids {
+            match (live(before, task_id), live(&self.tasks, task_id)) {
+                (Some(_), None) => self.changes.push(task_id, "deleted", ChangeOrigin::Remote, || true.into()),
+                (old, Some(new)) => {
+                    let mut names: Vec<&str> = new.fields.keys().chain(new.counters.keys()).map(String::as_str).collect();
+                    if let Some(old) = &old {
+                        names.extend(old.fields.keys().chain(old.counters.keys()).map(String::as_str));
+                    }
+                    names.push("tags");
+                    names.sort_unstable();
+                    names.dedup();
+                    for name in names {
+                        let value = new.field_value(name);
+                        if old.as_ref().and_then(|old| old.field_value(name)) != value {
+                            self.changes.push(task_id, name, ChangeOrigin::Remote, || value.unwrap_or_default());
+                        }
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+    }
+    
+    /// Apply a JSON array of operations, see `apply_operations`
+    pub fn apply_ops_json(&mut self, ops_json: &str) -> Result<ApplyReport, String> {
+        let ops: Vec<Operation> = serde_json::from_str(ops_json).map_err(|e| format!("Parse error: {}", e))?;
+        self.limits.check_operations(&ops, self.counter, self.ticked_ms).map_err(|e| e.to_string())?;
+        Ok(self.apply_ops(ops))
+    }
+    
+    /// Merge an exported document state, tasks and vector clock
+    pub fn merge_state(&mut self, other_json: &str) -> Result<MergeReport, String> {
+        let value = serde_json::from_str(other_json).map_err(|e| format!("Parse error: {}", e))?;
+        self.merge_value(value)
+    }
+    
+    /// `merge_state` for a binary export
+    #[cfg(feature = "binary")]
+    pub fn merge_binary_state(&mut self, bytes: &[u8]) -> Result<MergeReport, String> {
+        let state = binary::decode(bytes).map_err(|e| format!("Parse error: {}", e))?;
+        self.merge_imported(state)
+    }
+    
+    fn state(&self) -> DocumentState<'_> {
+        DocumentState {
+            version: STATE_VERSION,
+            node_id: &self.node_id,
+            tasks: &self.tasks,
+            clock: &self.clock,
+            purged: &self.purged,
+            history: self.include_history.then_some(&self.history.entries),
+        }
+    }
+    
+    fn merge_value(&mut self, other: serde_json::Value) -> Result<MergeReport, String> {
+        let state = ImportedState::from_value(other).map_err(|e| format!("Parse error: {}", e))?;
+        self.merge_imported(state)
+    }
+    
+    fn merge_imported(&mut self, state: ImportedState) -> Result<MergeReport, String> {
+        self.limits.check_state(&state, self.counter, self.ticked_ms).map_err(|e| e.to_string())?;
+        let ImportedState { node_id, tasks: mut other, clock: other_clock, purged, history } = state;
+        let remapped = self.fork_collisions(&mut other)?;
+        self.history.merge(history);
+        if let Some(schema) = &self.schema {
+            let quarantined: usize = other.values_mut().map(|task| schema.quarantine(task)).sum();
+            if quarantined > 0 {
+                console_log!("Moved {} fields outside the schema to extras", quarantined);
+            }
+        }
+        
+        // Lamport rule: move past every incoming timestamp
+        let purged_max = purged.values().map(|ts| ts.counter).max().unwrap_or(0);
+        self.counter = self.counter.max(max_counter(&other, &other_clock)).max(purged_max);
+        self.wall_ms = self.wall_ms.max(max_wall_ms(&other));
+        
+        if let Some(node_id) = &node_id {
+            self.record_peer_clock(node_id, &other_clock);
+        }
+        let mut report = self.merge_tasks(other, &other_clock);
+        report.remapped = remapped;
+        let removed = self.merge_purged(purged);
+        for task_id in &removed {
+            self.changes.push(task_id, "deleted", ChangeOrigin::Remote, || true.into());
+        }
+        report.deleted += removed.len();
+        report.changes.deleted.extend(removed);
+        report.changes.created.sort_unstable();
+        report.changes.updated.sort_unstable_by(|a, b| a.task_id.cmp(&b.task_id));
+        report.changes.deleted.sort_unstable();
+        for (node_id, &counter) in &other_clock {
+            let seen = self.clock.entry(node_id.clone()).or_insert(0);
+            *seen = (*seen).max(counter);
+        }
+        Ok(report)
+    }
+    
+    /// Runs the merge on a scratch copy of the merge inputs, so the preview
+    /// makes exactly the decisions `merge` would
+    fn merge_preview(&self, other_json: &str) -> Result<MergePreview, String> {
+        let value: serde_json::Value = serde_json::from_str(other_json).map_err(|e| format!("Parse error: {}", e))?;
+        let incoming = ImportedState::from_value(value.clone()).map_err(|e| format!("Parse error: {}", e))?.tasks;
+        let mut scratch = CrdtDocument::new(self.node_id.clone());
+        scratch.counter = self.counter;
+        scratch.ticked_ms = self.ticked_ms;
+        scratch.tasks = self.tasks.clone();
+        scratch.clock = self.clock.clone();
+        scratch.purged = self.purged.clone();
+        scratch.field_strategies = self.field_strategies.clone();
+        scratch.limits = self.limits.clone();
+        scratch.collision_policy = self.collision_policy;
+        let changes = scratch.merge_value(value)?.changes;
+        
+        let (restored, created) = changes.created.into_iter().partition(|task_id| self.tasks.contains_key(task_id));
+        let mut preview = MergePreview { created, restored, deleted: changes.deleted, updated: Vec::new() };
+        let mut changed: HashMap<String, Vec<String>> =
+            changes.updated.into_iter().map(|change| (change.task_id, change.fields)).collect();
+        
+        // Field changes of tasks that end up deleted or get restored are not
+        // reported, like in `merge_js`
+        let mut task_ids: Vec<&String> = incoming
+            .keys()
+            .filter(|task_id| self.tasks.get(*task_id).is_some_and(|task| !task.deleted))
+            .filter(|task_id| scratch.tasks.get(*task_id).is_some_and(|task| !task.deleted))
+            .collect();
+        task_ids.sort_unstable();
+        for task_id in task_ids {
+            let (local, other, merged) = (&self.tasks[task_id], &incoming[task_id], &scratch.tasks[task_id]);
+            let changed_fields = changed.remove(task_id).unwrap_or_default();
+            let mut candidates: Vec<&str> = other
+                .fields
+                .keys()
+                .chain(other.counters.keys())
+                .chain(&changed_fields)
+                .map(String::as_str)
+                .collect();
+            if !other.tags.is_empty() {
+                candidates.push("tags");
+            }
+            candidates.sort_unstable();
+            candidates.dedup();
+            
+            let mut fields = Vec::new();
+            for field in candidates {
+                let local_value = local.field_value(field);
+                let incoming_value = other.field_value(field);
+                let result = merged.field_value(field);
+                let winner = if !changed_fields.iter().any(|changed| changed == field) {
+                    if local_value == incoming_value {
+                        continue;
+                    }
+                    DiffWinner::Local
+                } else if result == incoming_value {
+                    DiffWinner::Incoming
+                } else {
+                    DiffWinner::Merged
+                };
+                fields.push(FieldDiff { field: field.to_string(), local: local_value, incoming: incoming_value, result, winner });
+            }
+            if !fields.is_empty() {
+                preview.updated.push(TaskDiff { task_id: task_id.clone(), fields });
+            }
+        }
+        Ok(preview)
+    }
+    
+    /// Apply the collision policy to incoming tasks: move colliding ones
+    /// to a fork id, or fail before anything is merged
+    fn fork_collisions(&self, other: &mut HashMap<String, CrdtTask>) -> Result<Vec<TaskRemap>, String> {
+        if self.collision_policy == CollisionPolicy::Merge {
+            return Ok(Vec::new());
+        }
+        let mut colliding: Vec<String> = other
+            .iter()
+            .filter(|(task_id, task)| self.tasks.get(*task_id).is_some_and(|local| is_id_collision(local, task)))
+            .map(|(task_id, _)| task_id.clone())
+            .collect();
+        colliding.sort_unstable();
+        if self.collision_policy == CollisionPolicy::Error {
+            if let Some(task_id) = colliding.first() {
+                return Err(format!(
+                    "Task id collision: '{}' was created separately on {} and {}",
+                    task_id, self.tasks[task_id].created_at.node_id, other[task_id].created_at.node_id
+                ));
+            }
+        }
+        
+        let mut remapped = Vec::new();
+        for task_id in colliding {
+            let Some(mut task) = other.remove(&task_id) else { continue };
+            // Reuse a fork made by an earlier merge of the same task
+            let base = format!("{}@{}", task_id, task.created_at.node_id);
+            let is_free = |id: &String| {
+                !other.contains_key(id)
+                    && !self.is_purged(id)
+                    && self.tasks.get(id).is_none_or(|existing| existing.created_at == task.created_at)
+            };
+            let mut fork_id = base.clone();
+            for n in 2.. {
+                if is_free(&fork_id) {
+                    break;
+                }
+                fork_id = format!("{}-{}", base, n);
+            }
+            task.id = fork_id.clone();
+            other.insert(fork_id.clone(), task);
+            remapped.push(TaskRemap { from: task_id, to: fork_id });
+        }
+        Ok(remapped)
+    }
+    
+    /// LWW merge of a remote task map into this document
+    fn merge_tasks(&mut self, other: HashMap<String, CrdtTask>, other_clock: &VectorClock) -> MergeReport {
+        let mut report = MergeReport::default();
+        let mut merged_ops = Vec::new();
+        
+        for (task_id, other_task) in other {
+            if self.is_purged(&task_id) {
+                continue;
+            }
+            match self.tasks.get_mut(&task_id) {
+                // Nothing new in it, so skip the field-level work
+                Some(local_task) if local_task.covers(&other_task) => {}
+                Some(local_task) => {
+                    let mut changed_fields = Vec::new();
+                    
+                    // Merge fields by their strategy, LWW (Last-Write-Wins) by default
+                    for (field, other_value) in &other_task.fields {
+                        let strategy = self.field_strategies.get(field).copied().unwrap_or_default();
+                        self.history.record(&task_id, field, HistoryEntry::of(other_value));
+                        let resolved = match local_task.fields.get(field) {
+                            Some(local_value) => {
+                                let newer = wins(&other_value.timestamp, &local_value.timestamp);
+                                if wins(&local_value.timestamp, &other_value.timestamp) && other_value.value != local_value.value {
+                                    report.conflicts += 1;
+                                }
+                                if strategy == FieldStrategy::Lww
+                                    && other_value.value != local_value.value
+                                    && is_concurrent(&local_value.timestamp, &self.clock, &other_value.timestamp, other_clock)
+                                {
+                                    let (kept, discarded) = if newer { (other_value, local_value) } else { (local_value, other_value) };
+                                    self.history.supersede(&task_id, field, &discarded.timestamp);
+                                    self.conflicts.push(Conflict {
+                                        task_id: task_id.clone(),
+                                        field: field.clone(),
+                                        kept: kept.value.clone(),
+                                        discarded: discarded.value.clone(),
+                                    });
+                                }
+                                resolve_write(strategy, local_value, &self.clock, other_value, other_clock)
+                            }
+                            None => Some(other_value.clone()),
+                        };
+                        if let Some(resolved) = resolved {
+                            if local_task.fields.get(field).is_none_or(|local_value| {
+                                local_value.value != resolved.value || local_value.conflicting != resolved.conflicting
+                            }) {
+                                self.changes.push(&task_id, field, ChangeOrigin::Remote, || resolved.value.clone());
+                            }
+                            merged_ops.push(Operation::merged_update(&task_id, field, &resolved));
+                            local_task.fields.insert(field.clone(), resolved);
+                            changed_fields.push(field.clone());
+                        }
+                    }
+                    
+                    // Counters merge per node, never by LWW
+                    for (field, other_counter) in &other_task.counters {
+                        let counter = local_task.counters.entry(field.clone()).or_default();
+                        if counter.merge(other_counter) {
+                            self.changes.push(&task_id, field, ChangeOrigin::Remote, || counter.value().into());
+                            changed_fields.push(field.clone());
+                        }
+                    }
+                    if local_task.tags.merge(&other_task.tags) {
+                        self.changes.push(&task_id, "tags", ChangeOrigin::Remote, || local_task.tags.values().into());
+                        changed_fields.push("tags".to_string());
+                    }
+                    if let Some(timestamp) = other_task.archived_at.as_ref().filter(|&timestamp| local_task.archive_wins(timestamp)) {
+                        let timestamp = timestamp.clone();
+                        local_task.archived_at = Some(timestamp.clone());
+                        if local_task.archived != other_task.archived {
+                            local_task.archived = other_task.archived;
+                            self.changes.push(&task_id, "archived", ChangeOrigin::Remote, || other_task.archived.into());
+                            changed_fields.push("archived".to_string());
+                            let task_id = task_id.clone();
+                            merged_ops.push(if other_task.archived {
+                                Operation::Archive { op_id: timestamp.op_id(), task_id, timestamp }
+                            } else {
+                                Operation::Unarchive { op_id: timestamp.op_id(), task_id, timestamp }
+                            });
+                        }
+                    }
+                    
+                    // Handle deletion: the later delete or restore wins
+                    local_task.touch(&other_task.updated_at);
+                    let other_deletion = other_task.deletion_timestamp().filter(|&timestamp| local_task.deletion_wins(timestamp)).cloned();
+                    let was_deleted = local_task.deleted;
+                    if let Some(timestamp) = other_deletion {
+                        local_task.deleted = other_task.deleted;
+                        local_task.deleted_at = Some(timestamp.clone());
+                        if other_task.deleted && !was_deleted {
+                            self.changes.push(&task_id, "deleted", ChangeOrigin::Remote, || true.into());
+                            report.deleted += 1;
+                            report.changes.deleted.push(task_id.clone());
+                            merged_ops.push(Operation::Delete { op_id: timestamp.op_id(), task_id, timestamp });
+                            continue;
+                        } else if !other_task.deleted && was_deleted {
+                            // Restored (e.g. by undo) after our delete
+                            self.changes.push(&task_id, "deleted", ChangeOrigin::Remote, || false.into());
+                            report.updated += 1;
+                            report.changes.created.push(task_id.clone());
+                            merged_ops.push(Operation::Restore { op_id: timestamp.op_id(), task_id, timestamp });
+                            continue;
+                        }
+                    }
+                    if !changed_fields.is_empty() && !local_task.deleted {
+                        report.updated += 1;
+                        changed_fields.sort_unstable();
+                        report.changes.updated.push(TaskChange { task_id, fields: changed_fields });
+                    }
+                }
+                None if other_task.deleted => {
+                    // Keep the tombstone, so late writes to the task stay deleted
+                    self.tasks.insert(task_id, other_task);
+                }
+                None => {
+                    // Task doesn't exist locally, add it
+                    for (field, value) in &other_task.fields {
+                        self.history.record(&task_id, field, HistoryEntry::of(value));
+                        self.changes.push(&task_id, field, ChangeOrigin::Remote, || value.value.clone());
+                        merged_ops.push(Operation::merged_update(&task_id, field, value));
+                    }
+                    for (field, counter) in &other_task.counters {
+                        self.changes.push(&task_id, field, ChangeOrigin::Remote, || counter.value().into());
+                    }
+                    if !other_task.tags.is_empty() {
+                        self.changes.push(&task_id, "tags", ChangeOrigin::Remote, || other_task.tags.values().into());
+                    }
+                    self.tasks.insert(task_id.clone(), other_task);
+                    report.added += 1;
+                    report.changes.created.push(task_id);
+                }
+            }
+        }
+        
+        if self.track_merge_ops {
+            for op in merged_ops {
+                self.record_operation(op);
+            }
+        }
+        
+        console_log!("Merged document. Total tasks: {}", self.tasks.len());
+        report
+    }
+    
+    /// Adopt remote delete markers. A marker removes the local task, even
+    /// one written after the delete. Returns the ids of live tasks removed.
+    fn merge_purged(&mut self, purged: HashMap<String, LamportTimestamp>) -> Vec<String> {
+        purged
+            .into_iter()
+            .filter(|(task_id, timestamp)| self.apply_purge(task_id, timestamp.clone()))
+            .map(|(task_id, _)| task_id)
+            .collect()
+    }
+    
+    /// `purge_task` without notifying
+    fn purge(&mut self, task_id: &str) {
+        if !self.tasks.contains_key(task_id) {
+            return;
+        }
+        let timestamp = self.new_timestamp();
+        if self.apply_purge(task_id, timestamp.clone()) {
+            self.changes.push(task_id, "deleted", ChangeOrigin::Local, || true.into());
+        }
+        
+        let op_id = timestamp.op_id();
+        self.record_operation(Operation::Purge { op_id, task_id: task_id.to_string(), timestamp });
+        console_log!("Purged task {}", task_id);
+    }
+    
+    /// Apply a delete marker; returns whether it removed a live task.
+    /// The marker wins even over writes made after it, the same way
+    /// `merge_tasks` and `apply_op` drop every write to a purged task, so
+    /// a purge concurrent with an edit ends the same on both sides.
+    fn apply_purge(&mut self, task_id: &str, timestamp: LamportTimestamp) -> bool {
+        let mut removed = false;
+        if let Some(task) = self.tasks.get(task_id) {
+            removed = !task.deleted;
+            self.tasks.remove(task_id);
+            self.history.entries.remove(task_id);
+        }
+        let marker = self.purged.entry(task_id.to_string()).or_insert_with(|| timestamp.clone());
+        if wins(&timestamp, marker) {
+            *marker = timestamp;
+        }
+        removed
+    }
+    
+    /// Whether a task's tombstone was compacted away. Like a tombstone,
+    /// the marker ignores any later writes to the task.
+    fn is_purged(&self, task_id: &str) -> bool {
+        self.purged.contains_key(task_id)
+    }
+    
+    fn record_peer_clock(&mut self, node_id: &str, clock: &VectorClock) {
+        if node_id == self.node_id || clock.is_empty() {
+            return;
+        }
+        let known = self.peer_clocks.entry(node_id.to_string()).or_default();
+        for (node, &counter) in clock {
+            let seen = known.entry(node.clone()).or_insert(0);
+            *seen = (*seen).max(counter);
+        }
+    }
+    
+    /// Whether every known peer (written to the vector clock or sent us its
+    /// state) has seen `timestamp`
+    fn acknowledged_by_all(&self, timestamp: &LamportTimestamp) -> bool {
+        self.clock
+            .keys()
+            .chain(self.peer_clocks.keys())
+            .filter(|peer| **peer != self.node_id && **peer != timestamp.node_id)
+            .all(|peer| {
+                self.peer_clocks.get(peer).is_some_and(|clock| has_seen(clock, timestamp))
+            })
+    }
+    
+    fn purge_tombstones(&mut self, min_age_counter: u64) -> CompactionReport {
+        let mut report = CompactionReport::default();
+        let expired: Vec<(String, bool)> = self.tasks
+            .values()
+            .filter(|task| task.deleted && task.updated_at.counter < min_age_counter)
+            .map(|task| (task.id.clone(), self.acknowledged_by_all(&task.updated_at)))
+            .collect();
+        
+        for (task_id, acknowledged) in expired {
+            if !acknowledged {
+                report.pending += 1;
+                continue;
+            }
+            if let Some(task) = self.tasks.remove(&task_id) {
+                self.history.entries.remove(&task_id);
+                self.purged.insert(task_id, task.updated_at);
+                report.purged += 1;
+            }
+        }
+        
+        console_log!("Compacted {} tombstones, {} pending", report.purged, report.pending);
+        report
+    }
+    
+    fn document_stats(&self, top: usize) -> DocumentStats {
+        let mut sizes: Vec<TaskSize> = self.tasks
+            .values()
+            .map(|task| TaskSize { task_id: task.id.clone(), bytes: encoded_len(task) })
+            .collect();
+        let task_bytes = sizes.iter().map(|size| size.bytes).sum();
+        let operation_bytes = encoded_len(&self.operations);
+        sizes.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.task_id.cmp(&b.task_id)));
+        sizes.truncate(top);
+        
+        let deleted_tasks = self.tasks.values().filter(|t| t.deleted).count();
+        DocumentStats {
+            node_id: self.node_id.clone(),
+            active_tasks: self.tasks.len() - deleted_tasks,
+            deleted_tasks,
+            purged_tasks: self.purged.len(),
+            tombstones: deleted_tasks + self.purged.len(),
+            pending_operations: self.operations.len(),
+            counter: self.counter,
+            task_bytes,
+            operation_bytes,
+            total_bytes: task_bytes + operation_bytes,
+            largest_tasks: sizes,
+        }
+    }
+    
+    fn field_history(&self, task_id: &str, field: &str) -> &[HistoryEntry] {
+        self.history
+            .entries
+            .get(task_id)
+            .and_then(|fields| fields.get(field))
+            .map_or(&[], Vec::as_slice)
+    }
+    
+    fn filtered_tasks(&self, filter: &TaskFilter) -> Vec<&CrdtTask> {
+        self.sorted_tasks()
+            .into_iter()
+            .filter(|task| filter.matches(task))
+            .skip(filter.offset)
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+    
+    /// Tasks on the board ordered by `order` key; tasks without a key come
+    /// last, and task id breaks ties so every replica sorts identically
+    fn sorted_tasks(&self) -> Vec<&CrdtTask> {
+        self.ordered_tasks(false)
+    }
+    
+    /// `sorted_tasks`, plus the archived tasks if `include_archived`
+    fn ordered_tasks(&self, include_archived: bool) -> Vec<&CrdtTask> {
+        let mut tasks: Vec<&CrdtTask> = self.tasks
+            .values()
+            .filter(|t| !t.deleted && (include_archived || !t.archived))
+            .collect();
+        
+        tasks.sort_by(|a, b| {
+            let a_order = a.fields.get(ORDER_FIELD).and_then(|v| v.value.as_str());
+            let b_order = b.fields.get(ORDER_FIELD).and_then(|v| v.value.as_str());
+            match (a_order, b_order) {
+                (Some(x), Some(y)) => x.cmp(y),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => std::cmp::Ordering::Equal,
+            }
+            .then(a.id.cmp(&b.id))
+        });
+        
+        tasks
+    }
+    
+    fn order_key(&self, task_id: Option<&str>) -> Result<Option<String>, String> {
+        match task_id {
+            None => Ok(None),
+            Some(id) => match self.tasks.get(id) {
+                Some(task) if !task.deleted => {
+                    Ok(task.fields.get(ORDER_FIELD).and_then(|v| v.value.as_str()).map(str::to_string))
+                }
+                _ => Err(format!("Task {} not found", id)),
+            },
+        }
+    }
+    
+    fn move_task_between(&mut self, task_id: &str, before_id: Option<&str>, after_id: Option<&str>) -> Result<String, String> {
+        if self.tasks.get(task_id).is_none_or(|t| t.deleted) {
+            return Err(format!("Task {} not found", task_id));
+        }
+        
+        let lower = self.order_key(before_id)?.unwrap_or_default();
+        let upper = self.order_key(after_id)?;
+        if let Some(upper) = &upper {
+            if lower.as_str() >= upper.as_str() {
+                return Err(format!("Task {:?} does not sort before task {:?}", before_id, after_id));
+            }
+        }
+        
+        let key = order_key_between(&lower, upper.as_deref(), &self.node_id);
+        self.upsert_value(task_id, ORDER_FIELD.to_string(), key.clone());
+        Ok(key)
+    }
+    
+    /// `(position key, task id)` of the tasks in a column, in board order.
+    /// Task id breaks ties between equal keys.
+    fn column_entries(&self, column: &str) -> Vec<(&str, &str)> {
+        let mut entries: Vec<(&str, &str)> = self.tasks
+            .values()
+            .filter(|task| task.on_board())
+            .filter_map(|task| {
+                let position = &task.fields.get(COLUMN_POSITION_FIELD)?.value;
+                if position.get("column")?.as_str()? != column {
+                    return None;
+                }
+                Some((position.get("key")?.as_str()?, task.id.as_str()))
+            })
+            .collect();
+        entries.sort_unstable();
+        entries
+    }
+    
+    /// Archived tasks that aren't deleted, sorted by id
+    fn archived_tasks(&self) -> Vec<&CrdtTask> {
+        let mut tasks: Vec<&CrdtTask> = self.tasks.values().filter(|task| task.archived && !task.deleted).collect();
+        tasks.sort_by(|a, b| a.id.cmp(&b.id));
+        tasks
+    }
+    
+    fn move_into_column(&mut self, task_id: &str, column: &str, after_task_id: Option<&str>) -> Result<String, String> {
+        if self.tasks.get(task_id).is_none_or(|t| t.deleted) {
+            return Err(format!("Task {} not found", task_id));
+        }
+        
+        let entries: Vec<(&str, &str)> = self.column_entries(column)
+            .into_iter()
+            .filter(|&(_, id)| id != task_id)
+            .collect();
+        let lower = match after_task_id {
+            None => "",
+            Some(after) => entries
+                .iter()
+                .find(|&&(_, id)| id == after)
+                .map(|&(key, _)| key)
+                .ok_or_else(|| format!("Task {} is not in column {}", after, column))?,
+        };
+        // Skip neighbours sharing the same key, there is no gap before them
+        let upper = entries.iter().map(|&(key, _)| key).find(|&key| key > lower);
+        
+        let key = order_key_between(lower, upper, &self.node_id);
+        let position = serde_json::json!({ "column": column, "key": key });
+        self.upsert_value(task_id, COLUMN_POSITION_FIELD.to_string(), position);
+        Ok(key)
+    }
+}
+
+/// Exported workspace: board name -> document state
+#[derive(Serialize)]
+struct WorkspaceState<'a> {
+    node_id: &'a str,
+    boards: BTreeMap<&'a str, DocumentState<'a>>,
+}
+
+#[derive(Deserialize)]
+struct ImportedWorkspace {
+    boards: BTreeMap<String, serde_json::Value>,
+}
+
+impl ImportedWorkspace {
+    fn parse(json: &str) -> Result<BTreeMap<String, ImportedState>, String> {
+        let workspace: ImportedWorkspace = serde_json::from_str(json).map_err(|e| format!("Parse error: {}", e))?;
+        workspace
+            .boards
+            .into_iter()
+            .map(|(name, board)| {
+                let state = ImportedState::from_value(board).map_err(|e| format!("Parse error: board {}: {}", name, e))?;
+                Ok((name, state))
+            })
+            .collect()
+    }
+}
+
+/// Named boards, each its own document with its own tasks, sharing one
+/// node id and Lamport counter so timestamps are ordered across boards.
+/// Boards export, import and merge together.
+#[wasm_bindgen]
+pub struct CrdtWorkspace {
+    node_id: String,
+    boards: BTreeMap<String, CrdtDocument>,
+}
+
+#[wasm_bindgen]
+impl CrdtWorkspace {
+    #[wasm_bindgen(constructor)]
+    pub fn new(node_id: String) -> Self {
+        Self { node_id, boards: BTreeMap::new() }
+    }
+    
+    /// Add an empty board; false if the name is taken
+    pub fn create_board(&mut self, name: String) -> bool {
+        if self.boards.contains_key(&name) {
+            return false;
+        }
+        self.boards.insert(name, CrdtDocument::new(self.node_id.clone()));
+        true
+    }
+    
+    pub fn board_names(&self) -> Vec<String> {
+        self.boards.keys().cloned().collect()
+    }
+    
+    /// `get_tasks` of a board
+    pub fn get_board(&self, name: &str) -> Result<JsValue, JsValue> {
+        self.board(name).map(|board| board.get_tasks(None)).ok_or_else(|| board_not_found(name))
+    }
+    
+    /// `upsert_field` on a board
+    pub fn upsert_field(&mut self, board: &str, task_id: &str, field: String, value: JsValue) -> Result<(), JsValue> {
+        self.board_mut(board).ok_or_else(|| board_not_found(board))?.upsert_field(task_id, field, value)
+    }
+    
+    /// `upsert_task` on a board
+    pub fn upsert_task(&mut self, board: &str, task_id: &str, fields_js: JsValue) -> Result<(), JsValue> {
+        self.board_mut(board).ok_or_else(|| board_not_found(board))?.upsert_task(task_id, fields_js)
+    }
+    
+    /// `delete_task` on a board
+    pub fn delete_task(&mut self, board: &str, task_id: &str) -> Result<(), JsValue> {
+        self.board_mut(board).ok_or_else(|| board_not_found(board))?.delete_task(task_id);
+        Ok(())
+    }
+    
+    /// `tick` every board's hybrid clock
+    pub fn tick(&mut self, now_ms: f64) {
+        for board in self.boards.values_mut() {
+            board.tick(now_ms);
+        }
+    }
+    
+    /// Every board as one JSON string: `{node_id, boards: {name: export}}`
+    pub fn export_all(&self) -> String {
+        let state = WorkspaceState {
+            node_id: &self.node_id,
+            boards: self.boards.iter().map(|(name, board)| (name.as_str(), board.state())).collect(),
+        };
+        serde_json::to_string(&state).unwrap_or_default()
+    }
+    
+    /// Replace every board with those of an `export_all`. Nothing changes
+    /// if any board fails to load.
+    pub fn import_all(&mut self, json: &str) -> Result<(), JsValue> {
+        self.import_boards(json).map_err(|e| JsValue::from_str(&format!("Import error: {}", e)))
+    }
+    
+    /// Merge an `export_all` board by board, creating boards missing
+    /// here. Nothing changes if any board fails to parse or exceeds the
+    /// merge limits.
+    pub fn merge_all(&mut self, other_json: &str) -> Result<(), JsValue> {
+        self.merge_boards(other_json).map_err(|e| JsValue::from_str(&e))?;
+        for board in self.boards.values_mut() {
+            board.notify_changes();
+        }
+        Ok(())
+    }
+}
+
+impl CrdtWorkspace {
+    pub fn board(&self, name: &str) -> Option<&CrdtDocument> {
+        self.boards.get(name)
+    }
+    
+    /// A board, its clock first moved past every other board's so its
+    /// next write is ordered after theirs
+    pub fn board_mut(&mut self, name: &str) -> Option<&mut CrdtDocument> {
+        let counter = self.boards.values().map(|board| board.counter).max().unwrap_or(0);
+        let wall_ms = self.boards.values().map(|board| board.wall_ms).max().unwrap_or(0);
+        let board = self.boards.get_mut(name)?;
+        board.counter = counter;
+        board.wall_ms = wall_ms;
+        Some(board)
+    }
+    
+    fn import_boards(&mut self, json: &str) -> Result<(), String> {
+        let mut boards = BTreeMap::new();
+        for (name, state) in ImportedWorkspace::parse(json)? {
+            let mut board = CrdtDocument::new(self.node_id.clone());
+            board.load_checked(state).map_err(|e| format!("board {}: {}", name, e))?;
+            boards.insert(name, board);
+        }
+        self.boards = boards;
+        Ok(())
+    }
+    
+    fn merge_boards(&mut self, other_json: &str) -> Result<Vec<(String, MergeReport)>, String> {
+        let incoming = ImportedWorkspace::parse(other_json)?;
+        for (name, state) in &incoming {
+            let (limits, counter, ticked_ms) = self
+                .boards
+                .get(name)
+                .map_or((MergeLimits::default(), 0, 0), |board| (board.limits.clone(), board.counter, board.ticked_ms));
+            limits.check_state(state, counter, ticked_ms).map_err(|e| format!("board {}: {}", name, e))?;
+        }
+        
+        let mut reports = Vec::new();
+        for (name, state) in incoming {
+            let board = self.boards.entry(name.clone()).or_insert_with(|| CrdtDocument::new(self.node_id.clone()));
+            let report = board.merge_imported(state).map_err(|e| format!("board {}: {}", name, e))?;
+            reports.push((name, report));
+        }
+        Ok(reports)
+    }
+}
+
+fn board_not_found(name: &str) -> JsValue {
+    JsValue::from_str(&format!("Board not found: {}", name))
+}
+
+/// Length of a value encoded as JSON (0 if it cannot be encoded)
+fn encoded_len<T: Serialize + ?Sized>(value: &T) -> usize {
+    serde_json::to_vec(value).map_or(0, |bytes| bytes.len())
+}
+
+const SYNC_CODE_SALT: &[u8] = b"khun-phaen-sync-code";
+
+/// 64-bit FNV-1a over the concatenated parts, with a final avalanche step
+/// so all output bits depend on the input. Unlike `DefaultHasher` this is
+/// stable across Rust versions and platforms.
+fn fnv1a_64(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in parts {
+        for &byte in *part {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash
+}
+
+/// Convert a hash to a 6-character code without ambiguous characters
+fn encode_sync_code(hash: u64) -> String {
+    const CHARS: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+    let mut result = String::new();
+    let mut num = hash;
+    
+    for _ in 0..6 {
+        result.push(CHARS[(num % CHARS.len() as u64) as usize] as char);
+        num /= CHARS.len() as u64;
+    }
+    
+    result
+}
+
+/// Field holding a task's fractional position in the manual ordering
+const ORDER_FIELD: &str = "order";
+
+/// Field holding a task's kanban column and its position key within it,
+/// `{column, key}`
+const COLUMN_POSITION_FIELD: &str = "column_position";
+
+/// Digits of fractional order keys, in ascending ASCII order
+const ORDER_DIGITS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+fn order_digit(c: u8) -> usize {
+    ORDER_DIGITS.iter().position(|&d| d == c).unwrap_or(0)
+}
+
+/// Midpoint between two fractional keys, `lower < result < upper`.
+/// `lower` may be empty (start of list) and `upper` None (end of list).
+/// Keys never end in the zero digit, so there is always room between them.
+fn order_midpoint(lower: &str, upper: Option<&str>) -> String {
+    let lower = lower.as_bytes();
+    
+    if let Some(upper) = upper.map(str::as_bytes) {
+        // Skip the common prefix, treating missing lower digits as zero
+        let mut n = 0;
+        while n < upper.len() && lower.get(n).copied().unwrap_or(ORDER_DIGITS[0]) == upper[n] {
+            n += 1;
+        }
+        if n > 0 {
+            let prefix = String::from_utf8_lossy(&upper[..n]).into_owned();
+            let rest_lower = std::str::from_utf8(lower.get(n..).unwrap_or(&[])).unwrap_or("");
+            let rest_upper = std::str::from_utf8(&upper[n..]).unwrap_or("");
+            return prefix + &order_midpoint(rest_lower, Some(rest_upper));
+        }
+    }
+    
+    let digit_lower = lower.first().map(|&c| order_digit(c)).unwrap_or(0);
+    let digit_upper = upper.and_then(|u| u.as_bytes().first()).map(|&c| order_digit(c)).unwrap_or(ORDER_DIGITS.len());
+    
+    if digit_upper - digit_lower > 1 {
+        return (ORDER_DIGITS[(digit_lower + digit_upper) / 2] as char).to_string();
+    }
+    
+    match upper {
+        // `upper` has more digits, so its first digit alone sorts between
+        Some(u) if u.len() > 1 => u[..1].to_string(),
+        _ => {
+            let rest_lower = std::str::from_utf8(lower.get(1..).unwrap_or(&[])).unwrap_or("");
+            (ORDER_DIGITS[digit_lower] as char).to_string() + &order_midpoint(rest_lower, None)
+        }
+    }
+}
+
+/// Order key between `lower` and `upper`, suffixed with digits derived from
+/// the node id so two replicas moving tasks into the same gap concurrently
+/// don't produce identical keys
+fn order_key_between(lower: &str, upper: Option<&str>, node_id: &str) -> String {
+    let mut key = order_midpoint(lower, upper);
+    // A suffix can only be appended safely when the key isn't a prefix of `upper`
+    while let Some(upper) = upper {
+        if !upper.starts_with(key.as_str()) {
+            break;
+        }
+        key = order_midpoint(&key, Some(upper));
+    }
+    
+    let hash = node_id.bytes().fold(0u32, |h, b| h.wrapping_mul(31).wrapping_add(b as u32));
+    let base = ORDER_DIGITS.len() as u32 - 1;
+    // Skip the zero digit so the key keeps its no-trailing-zero invariant
+    key.push(ORDER_DIGITS[1 + (hash % base) as usize] as char);
+    key.push(ORDER_DIGITS[1 + (hash / base % base) as usize] as char);
+    key
+}
+
+#[cfg(feature = "binary")]
+mod binary {
+    //! `KB`, the export version, then the document state as MessagePack
+    //! with named fields. Task values are arbitrary JSON, so the encoding
+    //! has to be self-describing like JSON itself.
+    use super::{ImportedState, STATE_VERSION};
+    
+    const MAGIC: [u8; 2] = [b'K', b'B'];
+    
+    pub fn encode<T: serde::Serialize>(state: &T) -> Vec<u8> {
+        let mut out = Vec::from(MAGIC);
+        out.push(STATE_VERSION as u8);
+        // Like `export`, an unencodable state gives an empty export
+        match rmp_serde::encode::write_named(&mut out, state) {
+            Ok(()) => out,
+            Err(_) => Vec::new(),
+        }
+    }
+    
+    pub fn decode(bytes: &[u8]) -> Result<ImportedState, String> {
+        let body = match bytes {
+            [b'K', b'B', version, body @ ..] if *version as u64 == STATE_VERSION => body,
+            [b'K', b'B', version, ..] => return Err(format!("unsupported export version {}", version)),
+            _ => return Err("not a binary export (bad magic bytes)".to_string()),
+        };
+        rmp_serde::from_slice(body).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(feature = "compression")]
+mod compression {
+    //! The `wasm-compress` chunked format: `KC`, the format version, the
+    //! chunk count and each chunk's size (u32 LE), then the chunks. A
+    //! chunk is `KP`, the format version, a CRC32 of its uncompressed
+    //! bytes and a size-prepended LZ4 block. Base64 on top.
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use lz4_flex::{compress_prepend_size, decompress_size_prepended};
+    
+    const FORMAT_VERSION: u8 = 1;
+    const FRAME_MAGIC: [u8; 3] = [b'K', b'P', FORMAT_VERSION];
+    const CHUNKED_MAGIC: [u8; 3] = [b'K', b'C', FORMAT_VERSION];
+    
+    /// Uncompressed bytes per chunk; corruption is located to one chunk
+    pub const CHUNK_SIZE: usize = 64 * 1024;
+    
+    pub fn compress(data: &[u8]) -> String {
+        let chunks: Vec<Vec<u8>> = data.chunks(CHUNK_SIZE).map(frame).collect();
+        let mut out = Vec::with_capacity(CHUNKED_MAGIC.len() + 4 * (chunks.len() + 1) + chunks.iter().map(Vec::len).sum::<usize>());
+        out.extend_from_slice(&CHUNKED_MAGIC);
+        out.extend_from_slice(&(chunks.len() as u32).to_le_bytes());
+        for chunk in &chunks {
+            out.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+        }
+        for chunk in &chunks {
+            out.extend_from_slice(chunk);
+        }
+        STANDARD.encode(&out)
+    }
+    
+    fn frame(data: &[u8]) -> Vec<u8> {
+        let mut framed = FRAME_MAGIC.to_vec();
+        framed.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+        framed.extend(compress_prepend_size(data));
+        framed
+    }
+    
+    /// Decompress and verify a payload from `compress`. Payloads from
+    /// before checksums were a bare size-prepended LZ4 block.
+    pub fn decompress(data: &str) -> Result<String, String> {
+        // Whitespace (line breaks from storage layers) is ignored like in
+        // `wasm-compress`; anything else malformed is rejected
+        let cleaned: Vec<u8> = data.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+        let payload = STANDARD.decode(cleaned).map_err(|e| format!("Base64 decode error: {}", e))?;
+        let bytes = if payload.starts_with(&CHUNKED_MAGIC) {
+            // A bare block can start with the magic bytes by chance
+            decompress_chunked(&payload).or_else(|err| decompress_block(&payload).map_err(|_| err))?
+        } else {
+            decompress_block(&payload).map_err(|e| format!("Decompression error: {}", e))?
+        };
+        String::from_utf8(bytes).map_err(|e| format!("UTF-8 decode error: {}", e))
+    }
+    
+    /// Offsets in errors are into the base64-decoded payload. The header
+    /// is untrusted, so offsets are checked rather than left to overflow.
+    fn decompress_chunked(payload: &[u8]) -> Result<Vec<u8>, String> {
+        let truncated = || format!("Corrupted export: truncated at byte {}", payload.len());
+        let read_u32 = |pos: usize| -> Result<usize, String> {
+            let bytes = payload.get(pos..pos + 4).ok_or_else(truncated)?;
+            Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+        };
+        let count = read_u32(CHUNKED_MAGIC.len())?;
+        let sizes_at = CHUNKED_MAGIC.len() + 4;
+        // Each chunk needs at least its 4-byte size in the header
+        if count > (payload.len() - sizes_at) / 4 {
+            return Err(format!("Corrupted export: chunk count {} does not fit", count));
+        }
+        let mut pos = sizes_at + 4 * count;
+        
+        let mut out = Vec::new();
+        for i in 0..count {
+            let size = read_u32(sizes_at + 4 * i)?;
+            let chunk = pos
+                .checked_add(size)
+                .and_then(|end| payload.get(pos..end))
+                .ok_or_else(|| format!("Corrupted export: chunk {} at byte {} is truncated", i, pos))?;
+            let decompressed = unframe(chunk).map_err(|e| format!("Corrupted export: chunk {} at byte {}: {}", i, pos, e))?;
+            out.extend(decompressed);
+            pos += size;
+        }
+        if pos != payload.len() {
+            return Err(format!("Corrupted export: trailing data at byte {}", pos));
+        }
+        Ok(out)
+    }
+    
+    fn unframe(chunk: &[u8]) -> Result<Vec<u8>, String> {
+        let [b'K', b'P', FORMAT_VERSION, rest @ ..] = chunk else {
+            return Err("bad chunk header".to_string());
+        };
+        let (checksum, block) = rest.split_first_chunk::<4>().ok_or("bad chunk header")?;
+        let decompressed = decompress_block(block).map_err(|e| format!("decompression error: {}", e))?;
+        if crc32fast::hash(&decompressed) != u32::from_le_bytes(*checksum) {
+            return Err("checksum mismatch".to_string());
+        }
+        Ok(decompressed)
+    }
+    
+    /// LZ4 can't expand data by more than this factor
+    const MAX_LZ4_RATIO: usize = 255;
+    
+    /// Decompress a size-prepended LZ4 block, checking the declared size
+    /// first: lz4_flex allocates it up front, and a chunked header read as
+    /// a bare block claims gigabytes once there are 128 chunks
+    pub(crate) fn decompress_block(data: &[u8]) -> Result<Vec<u8>, String> {
+        let (size, block) = data.split_first_chunk::<4>().ok_or("truncated size")?;
+        let size = u32::from_le_bytes(*size) as usize;
+        if size > block.len().saturating_mul(MAX_LZ4_RATIO) {
+            return Err(format!("declared size {} is more than {} compressed bytes can hold", size, block.len()));
+        }
+        decompress_size_prepended(data).map_err(|e| e.to_string())
+    }
+}
+
+/// Utility: Generate unique node ID
+/// Takes timestamp from JS (Date.now()) to avoid SystemTime issues in WASM
+///
+/// Deprecated: the id is a hash of the timestamp truncated to 32 bits, so
+/// tabs opened in the same millisecond collide. Use `generate_node_id_v2`.
+/// Kept producing the same ids as before for existing callers.
+#[wasm_bindgen]
+pub fn generate_node_id(timestamp_ms: f64) -> String {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    
+    // What the old `u32` parameter received, e.g. for `Date.now() & 0xFFFFFFFF`
+    let timestamp_ms = timestamp_ms as i64 as u32;
+    let mut hasher = DefaultHasher::new();
+    timestamp_ms.hash(&mut hasher);
+    format!("node_{:x}", hasher.finish())
+}
+
+/// Utility: Generate a node ID from 128 random bits
+#[wasm_bindgen]
+pub fn generate_node_id_v2() -> String {
+    format!("node_{}", random_hex::<16>())
+}
+
+/// `N` random bytes as lowercase hex
+fn random_hex<const N: usize>() -> String {
+    let mut bytes = [0u8; N];
+    fill_random(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Utility: Generate a random task ID (UUID v4)
+#[wasm_bindgen]
+pub fn generate_task_id() -> String {
+    let mut bytes = [0u8; 16];
+    fill_random(&mut bytes);
+    bytes[6] = bytes[6] & 0x0f | 0x40;
+    bytes[8] = bytes[8] & 0x3f | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+#[cfg(target_arch = "wasm32")]
+fn fill_random(buf: &mut [u8]) {
+    get_random_values(buf);
+}
+
+// Native builds have no `crypto`; `RandomState` is seeded randomly per process
+#[cfg(not(target_arch = "wasm32"))]
+fn fill_random(buf: &mut [u8]) {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    
+    for chunk in buf.chunks_mut(8) {
+        let bytes = RandomState::new().build_hasher().finish().to_le_bytes();
+        chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// Export parsed back into a value so HashMap ordering doesn't matter
+    fn state(doc: &CrdtDocument) -> serde_json::Value {
+        serde_json::from_str(&doc.export()).unwrap()
+    }
+    
+    #[test]
+    fn test_apply_operations_is_idempotent() {
+        let mut source = CrdtDocument::new("node_a".to_string());
+        source.upsert_value("1", "title".to_string(), "Write docs".to_string());
+        source.upsert_value("1", "status".to_string(), "todo".to_string());
+        source.delete_task("1");
+        source.upsert_value("2", "title".to_string(), "Ship it".to_string());
+        let ops_json = serde_json::to_string(&source.operations).unwrap();
+        
+        let mut target = CrdtDocument::new("node_b".to_string());
+        target.apply_ops_json(&ops_json).unwrap();
+        let first = target.export();
+        let pending = target.operations.len();
+        
+        target.apply_ops_json(&ops_json).unwrap();
+        assert_eq!(first, target.export());
+        assert_eq!(pending, target.operations.len());
+        assert!(source.operations.iter().all(|op| target.has_seen(&op.op_id())));
+        assert!(!target.has_seen("node_a:5"));
+        assert!(target.tasks["1"].deleted);
+    }
+    
+    #[test]
+    fn test_order_keys_sort_between_neighbours() {
+        assert_eq!(order_midpoint("", None), "V");
+        for (lower, upper) in [("", Some("1")), ("V", Some("V1")), ("Vz", Some("W")), ("a", None), ("01", Some("02"))] {
+            let key = order_key_between(lower, upper, "node_a");
+            assert!(key.as_str() > lower, "{} > {}", key, lower);
+            if let Some(upper) = upper {
+                assert!(key.as_str() < upper, "{} < {}", key, upper);
+            }
+        }
+        assert_ne!(order_key_between("A", Some("B"), "node_a"), order_key_between("A", Some("B"), "node_b"));
+    }
+    
+    #[test]
+    fn test_move_task_survives_merge() {
+        let mut doc = CrdtDocument::new("node_a".to_string());
+        for id in 1..=3 {
+            doc.upsert_value(&id.to_string(), "title".to_string(), format!("Task {}", id));
+        }
+        doc.move_task_between("1", None, None).unwrap();
+        doc.move_task_between("2", Some("1"), None).unwrap();
+        doc.move_task_between("3", Some("2"), None).unwrap();
+        doc.move_task_between("3", Some("1"), Some("2")).unwrap();
+        
+        let order = |d: &CrdtDocument| d.sorted_tasks().iter().map(|t| t.id.clone()).collect::<Vec<_>>();
+        assert_eq!(order(&doc), ["1", "3", "2"]);
+        
+        let mut peer = CrdtDocument::new("node_b".to_string());
+        peer.merge(&doc.export()).unwrap();
+        assert_eq!(order(&peer), ["1", "3", "2"]);
+        assert!(doc.move_task_between("1", Some("2"), Some("3")).is_err());
+    }
+    
+    #[test]
+    fn test_track_merge_ops_reemits_merged_changes() {
+        let mut remote = CrdtDocument::new("node_a".to_string());
+        remote.upsert_value("1", "title".to_string(), "Review PR".to_string());
+        remote.upsert_value("1", "status".to_string(), "doing".to_string());
+        remote.upsert_value("2", "title".to_string(), "Old task".to_string());
+        let mut local_base = CrdtDocument::new("node_b".to_string());
+        local_base.merge(&remote.export()).unwrap();
+        remote.delete_task("2");
+        let export = remote.export();
+        
+        let mut silent = CrdtDocument::new("node_b".to_string());
+        silent.merge(&local_base.export()).unwrap();
+        silent.merge(&export).unwrap();
+        assert!(silent.operations.is_empty());
+        
+        let mut tracking = CrdtDocument::new("node_b".to_string());
+        tracking.set_track_merge_ops(true);
+        tracking.merge(&local_base.export()).unwrap();
+        assert_eq!(tracking.operations.len(), 3);
+        tracking.merge(&export).unwrap();
+        assert_eq!(tracking.operations.len(), 4);
+        assert!(matches!(tracking.operations[3], Operation::Delete { ref task_id, .. } if task_id == "2"));
+        assert_eq!(state(&silent), state(&tracking));
+    }
+    
+    #[test]
+    fn test_sync_codes_rarely_collide() {
+        let codes: HashSet<String> = (0..10_000)
+            .map(|i| CrdtDocument::new(format!("node_{:x}", i)).get_sync_code())
+            .collect();
+        assert!(codes.len() >= 9_998, "{} distinct codes", codes.len());
+        
+        let doc = CrdtDocument::new("node_a".to_string());
+        assert_eq!(doc.get_sync_code(), CrdtDocument::new("node_a".to_string()).get_sync_code());
+    }
+    
+    #[test]
+    fn test_content_sync_code_tracks_content() {
+        let mut a = CrdtDocument::new("node_a".to_string());
+        a.upsert_value("1", "title".to_string(), "Plan".to_string());
+        a.upsert_value("2", "title".to_string(), "Build".to_string());
+        let mut b = CrdtDocument::new("node_b".to_string());
+        b.merge(&a.export()).unwrap();
+        assert_eq!(a.get_content_sync_code(), b.get_content_sync_code());
+        
+        let before = a.get_content_sync_code();
+        a.upsert_value("1", "status".to_string(), "done".to_string());
+        assert_ne!(before, a.get_content_sync_code());
+    }
+    
+    #[test]
+    fn test_merge_report_counts_changes() {
+        let mut local = CrdtDocument::new("node_a".to_string());
+        local.upsert_value("1", "title".to_string(), "Design".to_string());
+        local.upsert_value("1", "status".to_string(), "todo".to_string());
+        let mut remote = CrdtDocument::new("node_b".to_string());
+        remote.merge(&local.export()).unwrap();
+        
+        remote.counter = local.counter;
+        remote.upsert_value("1", "status".to_string(), "done".to_string());
+        remote.upsert_value("2", "title".to_string(), "Test".to_string());
+        
+        let report = local.merge_state(&remote.export()).unwrap();
+        assert_eq!(report, MergeReport { added: 1, updated: 1, deleted: 0, conflicts: 0, ..report.clone() });
+        
+        let again = local.merge_state(&remote.export()).unwrap();
+        assert_eq!(again, MergeReport::default());
+    }
+    
+    #[test]
+    fn test_concurrent_counter_increments_add_up() {
+        let mut a = CrdtDocument::new("node_a".to_string());
+        a.upsert_value("1", "title".to_string(), "Log hours".to_string());
+        let mut b = CrdtDocument::new("node_b".to_string());
+        b.merge(&a.export()).unwrap();
+        
+        a.increment_counter("1", "hours".to_string(), 5);
+        b.increment_counter("1", "hours".to_string(), 5);
+        b.increment_counter("1", "hours".to_string(), -2);
+        b.increment_counter("1", "hours".to_string(), 2);
+        
+        let a_export = a.export();
+        a.merge(&b.export()).unwrap();
+        b.merge(&a_export).unwrap();
+        assert_eq!(a.get_counter("1", "hours"), 10.0);
+        assert_eq!(b.get_counter("1", "hours"), 10.0);
+        
+        // Merging again is idempotent
+        a.merge(&b.export()).unwrap();
+        assert_eq!(a.get_counter("1", "hours"), 10.0);
+        
+        let view = serde_json::to_value(TaskView::new(&a.tasks["1"])).unwrap();
+        assert_eq!(view["counter_values"]["hours"], 10);
+        assert!(view["fields"].get("hours").is_none());
+        
+        let mut c = CrdtDocument::new("node_c".to_string());
+        c.apply_ops_json(&serde_json::to_string(&b.operations).unwrap()).unwrap();
+        assert_eq!(c.get_counter("1", "hours"), 5.0);
+    }
+    
+    #[test]
+    fn test_counter_received_by_merge_and_ops_counts_once() {
+        let mut a = CrdtDocument::new("node_a".to_string());
+        a.increment_counter("1", "hours".to_string(), 5);
+        a.increment_counter("1", "hours".to_string(), -1);
+        let ops = serde_json::to_string(&a.operations).unwrap();
+        
+        let mut b = CrdtDocument::new("node_b".to_string());
+        b.merge_state(&a.export()).unwrap();
+        b.apply_ops_json(&ops).unwrap();
+        assert_eq!(b.get_counter("1", "hours"), 4.0);
+        
+        let mut c = CrdtDocument::new("node_c".to_string());
+        c.apply_ops_json(&ops).unwrap();
+        c.merge_state(&a.export()).unwrap();
+        assert_eq!(c.get_counter("1", "hours"), 4.0);
+        
+        // Ops from before running totals are still added
+        let mut legacy: serde_json::Value = serde_json::from_str(&ops).unwrap();
+        for op in legacy.as_array_mut().unwrap() {
+            op["Increment"].as_object_mut().unwrap().remove("total");
+        }
+        let mut d = CrdtDocument::new("node_d".to_string());
+        d.apply_ops_json(&legacy.to_string()).unwrap();
+        assert_eq!(d.get_counter("1", "hours"), 4.0);
+    }
+    
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compressed_export_round_trips() {
+        let mut doc = CrdtDocument::new("node_a".to_string());
+        for id in 0..50 {
+            doc.upsert_value(&id.to_string(), "title".to_string(), format!("งานที่ {} - repeated title text", id));
+            doc.upsert_value(&id.to_string(), "status".to_string(), "todo".to_string());
+        }
+        let compressed = doc.export_compressed();
+        assert!(compressed.len() < doc.export().len());
+        
+        let mut restored = CrdtDocument::new("node_b".to_string());
+        restored.import_compressed(&compressed).unwrap();
+        assert_eq!(state(&doc)["tasks"], state(&restored)["tasks"]);
+    }
+    
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_corrupted_compressed_export_is_rejected() {
+        let mut doc = CrdtDocument::new("node_a".to_string());
+        for id in 0..1000 {
+            doc.upsert_value(&id.to_string(), "title".to_string(), format!("Task {} with a reasonably long title", id));
+        }
+        assert!(doc.export().len() > 2 * compression::CHUNK_SIZE);
+        let compressed = doc.export_compressed();
+        assert_eq!(compression::decompress(&compressed).unwrap(), doc.export());
+        
+        // Plain exports are detected and imported as they are
+        let mut plain = CrdtDocument::new("node_b".to_string());
+        plain.import_compressed(&doc.export()).unwrap();
+        assert_eq!(state(&doc)["tasks"], state(&plain)["tasks"]);
+        
+        // Flip a byte inside the last chunk
+        let mut corrupted = compressed.into_bytes();
+        let at = corrupted.len() - 100;
+        corrupted[at] = if corrupted[at] == b'A' { b'B' } else { b'A' };
+        let err = compression::decompress(std::str::from_utf8(&corrupted).unwrap()).unwrap_err();
+        let last_chunk = doc.export().len().div_ceil(compression::CHUNK_SIZE) - 1;
+        assert!(err.starts_with(&format!("Corrupted export: chunk {} at byte ", last_chunk)), "{}", err);
+        
+        let err = compression::decompress("KC!!").unwrap_err();
+        assert!(err.starts_with("Base64 decode error") && err.contains("offset 2"), "{}", err);
+        
+        let mut crafted = b"KC\x01".to_vec();
+        crafted.extend_from_slice(&u32::MAX.to_le_bytes());
+        crafted.extend_from_slice(&[0; 8]);
+        let err = compression::decompress(&base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &crafted)).unwrap_err();
+        assert!(err.contains("does not fit"), "{}", err);
+        
+        // 128 chunks: "KC\x01\x80" read as a bare block's size is ~2 GB
+        let mut payload = base64::Engine::decode(&base64::engine::general_purpose::STANDARD, compression::compress(&vec![b'x'; 128 * compression::CHUNK_SIZE])).unwrap();
+        let first_chunk = 3 + 4 * 129;
+        payload[first_chunk + 3] ^= 0xff;
+        assert!(compression::decompress_block(&payload).unwrap_err().contains("declared size"));
+        let err = compression::decompress(&base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &payload)).unwrap_err();
+        assert_eq!(err, format!("Corrupted export: chunk 0 at byte {}: checksum mismatch", first_chunk));
+    }
+    
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compressed_export_reads_like_wasm_compress() {
+        let mut doc = CrdtDocument::new("node_a".to_string());
+        for id in 0..500 {
+            doc.upsert_value(&id.to_string(), "title".to_string(), format!("Task {}", id));
+        }
+        let export = doc.export();
+        assert_eq!(wasm_compress::decompress_chunked(&doc.export_compressed()).unwrap(), export);
+        let compressed = wasm_compress::compress_chunked(&export, compression::CHUNK_SIZE).unwrap();
+        assert_eq!(compression::decompress(&compressed).unwrap(), export);
+    }
+
+    #[test]
+    fn test_compact_operations_keeps_latest_update() {
+        let mut doc = CrdtDocument::new("node-a".to_string());
+        doc.upsert_value("1", "status".to_string(), "todo".to_string());
+        for i in 0..10 {
+            doc.upsert_value("1", "notes".to_string(), format!("draft {}", i));
+        }
+        assert_eq!(doc.operations.len(), 11);
+
+        assert_eq!(doc.compact_operations(), 9);
+        assert_eq!(doc.operations.len(), 2);
+        match &doc.operations[1] {
+            Operation::Update { field, value, timestamp, .. } => {
+                assert_eq!(field, "notes");
+                assert_eq!(value, "draft 9");
+                assert_eq!(timestamp.counter, 11);
+            }
+            op => panic!("unexpected op {:?}", op),
+        }
+
+        let mut replica = CrdtDocument::new("node-b".to_string());
+        replica.apply_ops_json(&serde_json::to_string(&doc.operations).unwrap()).unwrap();
+        assert_eq!(state(&replica)["tasks"]["1"]["fields"], state(&doc)["tasks"]["1"]["fields"]);
+    }
+
+    #[test]
+    fn test_max_operations_compacts_automatically() {
+        let mut doc = CrdtDocument::new("node-a".to_string());
+        doc.set_max_operations(3);
+        for i in 0..10 {
+            doc.upsert_value("1", "notes".to_string(), format!("draft {}", i));
+        }
+        assert!(doc.operations.len() <= 3);
+    }
+
+    #[test]
+    fn test_concurrent_edits_are_reported() {
+        let mut a = CrdtDocument::new("node-a".to_string());
+        let mut b = CrdtDocument::new("node-b".to_string());
+        a.upsert_value("1", "title".to_string(), "Write spec".to_string());
+        b.merge_state(&a.export()).unwrap();
+        
+        // Sequential edit: b has seen a's title, no conflict for a
+        b.upsert_value("1", "title".to_string(), "Write spec v2".to_string());
+        a.merge_state(&b.export()).unwrap();
+        assert!(a.conflicts.is_empty());
+        
+        // Concurrent edits on both replicas
+        let (a_state, b_state) = {
+            a.upsert_value("1", "status".to_string(), "doing".to_string());
+            b.upsert_value("1", "status".to_string(), "blocked".to_string());
+            (a.export(), b.export())
+        };
+        a.merge_state(&b_state).unwrap();
+        b.merge_state(&a_state).unwrap();
+        
+        assert_eq!(a.conflicts, b.conflicts);
+        assert_eq!(a.conflicts.len(), 1);
+        assert_eq!(a.conflicts[0].field, "status");
+        let kept = &a.conflicts[0].kept;
+        assert_eq!(&a.tasks["1"].fields["status"].value, kept);
+        assert_eq!(&b.tasks["1"].fields["status"].value, kept);
+        assert_eq!(state(&a)["tasks"], state(&b)["tasks"]);
+    }
+    
+    #[test]
+    fn test_concurrent_operations_are_reported() {
+        let mut a = CrdtDocument::new("node-a".to_string());
+        let mut b = CrdtDocument::new("node-b".to_string());
+        a.upsert_value("1", "notes".to_string(), "from a".to_string());
+        b.upsert_value("1", "notes".to_string(), "from b".to_string());
+        
+        a.apply_ops_json(&serde_json::to_string(&b.operations).unwrap()).unwrap();
+        assert_eq!(a.conflicts.len(), 1);
+        assert_eq!(a.clock["node-b"], 1);
+        
+        // Unversioned exports (bare task maps) go through import_legacy
+        let mut c = CrdtDocument::new("node-c".to_string());
+        let err = c.merge_state(&serde_json::to_string(&a.tasks).unwrap()).unwrap_err();
+        assert!(err.contains("import_legacy"), "{}", err);
+        c.merge_state(&a.export()).unwrap();
+        assert_eq!(c.tasks.len(), 1);
+        assert!(c.conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_counter_advances_past_remote_timestamps() {
+        let mut a = CrdtDocument::new("node-a".to_string());
+        let mut b = CrdtDocument::new("node-b".to_string());
+        for i in 0..100 {
+            a.upsert_value("1", "title".to_string(), format!("Draft {}", i));
+        }
+        b.merge_state(&a.export()).unwrap();
+        b.upsert_value("1", "title".to_string(), "Final".to_string());
+        a.merge_state(&b.export()).unwrap();
+        assert_eq!(a.tasks["1"].fields["title"].value, "Final");
+        
+        // Same through operations
+        let mut c = CrdtDocument::new("node-c".to_string());
+        c.apply_ops_json(&serde_json::to_string(&a.operations).unwrap()).unwrap();
+        c.upsert_value("1", "title".to_string(), "From c".to_string());
+        a.apply_ops_json(&serde_json::to_string(&c.operations).unwrap()).unwrap();
+        assert_eq!(a.tasks["1"].fields["title"].value, "From c");
+    }
+
+    #[test]
+    fn test_operation_deltas_converge_like_merge() {
+        let mut a = CrdtDocument::new("node-a".to_string());
+        let mut b = CrdtDocument::new("node-b".to_string());
+        let mut a_sent = LamportTimestamp::new(0, "");
+        let mut b_sent = LamportTimestamp::new(0, "");
+        let mut sync = |a: &mut CrdtDocument, b: &mut CrdtDocument| {
+            let a_ops = serde_json::to_string(&a.operations_since(a_sent.counter, Some(&a_sent.node_id))).unwrap();
+            let b_ops = serde_json::to_string(&b.operations_since(b_sent.counter, Some(&b_sent.node_id))).unwrap();
+            a_sent = LamportTimestamp::new(a.counter, &a.node_id);
+            b_sent = LamportTimestamp::new(b.counter, &b.node_id);
+            b.apply_ops_json(&a_ops).unwrap();
+            a.apply_ops_json(&b_ops).unwrap();
+            // Re-sending a delta is harmless
+            b.apply_ops_json(&a_ops).unwrap();
+            a_ops.len() + b_ops.len()
+        };
+        
+        a.upsert_value("1", "title".to_string(), "Plan release".to_string());
+        b.upsert_value("2", "title".to_string(), "Fix crash".to_string());
+        sync(&mut a, &mut b);
+        a.upsert_value("2", "status".to_string(), "done".to_string());
+        b.upsert_value("1", "status".to_string(), "doing".to_string());
+        b.delete_task("2");
+        let second = sync(&mut a, &mut b);
+        assert!(second < serde_json::to_string(&a.operations).unwrap().len() + serde_json::to_string(&b.operations).unwrap().len());
+        
+        let mut merged = CrdtDocument::new("node-m".to_string());
+        merged.merge_state(&a.export()).unwrap();
+        merged.merge_state(&b.export()).unwrap();
+        for doc in [&a, &b] {
+            for (id, task) in &merged.tasks {
+                assert_eq!(state(doc)["tasks"][id.to_string()]["fields"], serde_json::to_value(&task.fields).unwrap());
+                assert_eq!(doc.tasks[id].deleted, task.deleted);
+            }
+        }
+    }
+
+    #[test]
+    fn test_seen_ops_stay_bounded() {
+        let mut seen = SeenNode::default();
+        for counter in [1, 2, 4, 5] {
+            assert!(seen.insert(counter));
+        }
+        assert!(!seen.insert(4));
+        assert_eq!(seen.contiguous, 2);
+        assert!(seen.insert(3));
+        assert_eq!(seen.contiguous, 5);
+        assert!(seen.overflow.is_empty());
+        
+        // Counters with permanent gaps (Lamport jumps) don't grow forever
+        for counter in (10..10_000).step_by(2) {
+            seen.insert(counter);
+        }
+        assert!(seen.overflow.len() <= MAX_SEEN_OVERFLOW);
+        assert!(seen.contains(9_998));
+    }
+    
+    #[test]
+    fn test_tombstones_compact_once_acknowledged() {
+        let mut a = CrdtDocument::new("node_a".to_string());
+        let mut b = CrdtDocument::new("node_b".to_string());
+        a.upsert_value("1", "title".to_string(), "Old task".to_string());
+        a.upsert_value("2", "title".to_string(), "Keep me".to_string());
+        b.merge_state(&a.export()).unwrap();
+        a.merge_state(&b.export()).unwrap();
+        
+        a.delete_task("1");
+        // node_b has not seen the delete yet
+        assert_eq!(a.purge_tombstones(u64::MAX), CompactionReport { purged: 0, pending: 1 });
+        // Too recent for the cutoff
+        b.merge_state(&a.export()).unwrap();
+        a.merge_state(&b.export()).unwrap();
+        assert_eq!(a.purge_tombstones(1), CompactionReport::default());
+        
+        assert_eq!(a.purge_tombstones(u64::MAX), CompactionReport { purged: 1, pending: 0 });
+        assert!(!a.tasks.contains_key("1"));
+        b.purge_tombstones(u64::MAX);
+        assert!(!b.tasks.contains_key("1"));
+        
+        // Compacted peers stay compacted after exchanging state again
+        a.merge_state(&b.export()).unwrap();
+        b.merge_state(&a.export()).unwrap();
+        assert!(!a.tasks.contains_key("1") && !b.tasks.contains_key("1"));
+        assert_eq!(state(&a)["purged"], state(&b)["purged"]);
+        assert_eq!(a.tasks["2"].fields["title"].value, "Keep me");
+    }
+    
+    #[test]
+    fn test_compaction_does_not_resurrect_for_stale_peers() {
+        let mut a = CrdtDocument::new("node_a".to_string());
+        a.upsert_value("1", "title".to_string(), "Old task".to_string());
+        // node_c copies the task, then goes offline before the delete
+        let mut c = CrdtDocument::new("node_c".to_string());
+        c.apply_ops_json(&serde_json::to_string(&a.operations).unwrap()).unwrap();
+        let stale_ops = serde_json::to_string(&a.operations).unwrap();
+        let stale_export = c.export();
+        
+        a.delete_task("1");
+        // node_c never synced with node_a, so it isn't a known peer
+        assert_eq!(a.purge_tombstones(u64::MAX).purged, 1);
+        
+        // The stale peer's copy and ops don't bring the task back
+        a.merge_state(&stale_export).unwrap();
+        a.apply_ops_json(&stale_ops).unwrap();
+        assert!(!a.tasks.contains_key("1"));
+        
+        // The stale peer drops its copy on receiving the delete marker
+        let report = c.merge_state(&a.export()).unwrap();
+        assert_eq!(report.deleted, 1);
+        assert!(!c.tasks.contains_key("1"));
+    }
+    
+    #[test]
+    fn test_typed_values_round_trip() {
+        let values = [
+            ("title", serde_json::json!("งานใหม่")),
+            ("hours", serde_json::json!(3)),
+            ("progress", serde_json::json!(0.5)),
+            ("is_archived", serde_json::json!(true)),
+            ("tags", serde_json::json!(["ui", "bug"])),
+            ("assignee", serde_json::json!({"id": 7, "name": "Somchai"})),
+        ];
+        let mut a = CrdtDocument::new("node_a".to_string());
+        for (field, value) in &values {
+            a.upsert_value("1", field.to_string(), value.clone());
+        }
+        
+        let mut imported = CrdtDocument::new("node_b".to_string());
+        imported.import_state(&a.export()).unwrap();
+        let mut merged = CrdtDocument::new("node_c".to_string());
+        merged.merge_state(&a.export()).unwrap();
+        let mut applied = CrdtDocument::new("node_d".to_string());
+        applied.apply_ops_json(&serde_json::to_string(&a.operations).unwrap()).unwrap();
+        
+        for doc in [&imported, &merged, &applied] {
+            for (field, value) in &values {
+                assert_eq!(&doc.tasks["1"].fields[*field].value, value);
+            }
+        }
+    }
+    
+    #[test]
+    fn test_string_value_exports_still_load() {
+        let old = r#"{"1":{"id":1,"fields":{"title":{"value":"Old task","timestamp":{"counter":1,"node_id":"node_a"}},"is_archived":{"value":"1","timestamp":{"counter":2,"node_id":"node_a"}}},"deleted":false,"created_at":{"counter":1,"node_id":"node_a"},"updated_at":{"counter":2,"node_id":"node_a"}}}"#;
+        let mut doc = CrdtDocument::new("node_b".to_string());
+        doc.load(ImportedState::parse_legacy(old).unwrap());
+        assert_eq!(doc.tasks["node_a:1"].fields["title"].value, "Old task");
+        assert_eq!(doc.tasks["node_a:1"].fields["is_archived"].value, "1");
+        
+        let ops = r#"[{"Update":{"task_id":"node_a:1","field":"status","value":"done","timestamp":{"counter":3,"node_id":"node_a"}}}]"#;
+        doc.apply_ops_json(ops).unwrap();
+        assert_eq!(doc.tasks["node_a:1"].fields["status"].value, "done");
+    }
+    
+    #[test]
+    fn test_legacy_task_ids_stay_distinct_after_migration() {
+        // Both devices created their own "task 1" before string ids
+        let legacy = |node: &str, title: &str| {
+            let mut doc = CrdtDocument::new(node.to_string());
+            doc.upsert_value("1", "title".to_string(), title.to_string());
+            let mut state = state(&doc);
+            state.as_object_mut().unwrap().remove("version");
+            state["tasks"]["1"]["id"] = 1.into();
+            state.to_string()
+        };
+        let (phone_export, laptop_export) = (legacy("phone", "Buy milk"), legacy("laptop", "Fix bug"));
+        assert!(ImportedState::parse(&phone_export).is_err());
+        
+        let mut phone = CrdtDocument::new("phone".to_string());
+        phone.load(ImportedState::parse_legacy(&phone_export).unwrap());
+        let mut laptop = CrdtDocument::new("laptop".to_string());
+        laptop.load(ImportedState::parse_legacy(&laptop_export).unwrap());
+        
+        let (phone_state, laptop_state) = (phone.export(), laptop.export());
+        phone.merge_state(&laptop_state).unwrap();
+        laptop.merge_state(&phone_state).unwrap();
+        assert_eq!(state(&phone)["tasks"], state(&laptop)["tasks"]);
+        assert_eq!(phone.tasks["phone:1"].fields["title"].value, "Buy milk");
+        assert_eq!(phone.tasks["laptop:1"].fields["title"].value, "Fix bug");
+        
+        // The same synced task migrates to the same id on every device
+        let mut tablet = CrdtDocument::new("tablet".to_string());
+        tablet.load(ImportedState::parse_legacy(&phone_export).unwrap());
+        assert_eq!(tablet.tasks["phone:1"].fields["title"].value, "Buy milk");
+    }
+    
+    #[test]
+    fn test_migrated_task_is_edited_and_deleted_in_place() {
+        let mut legacy_doc = CrdtDocument::new("phone".to_string());
+        legacy_doc.upsert_value("5", "title".to_string(), "Buy milk");
+        let mut legacy = state(&legacy_doc);
+        legacy.as_object_mut().unwrap().remove("version");
+        legacy["tasks"]["5"]["id"] = 5.into();
+        
+        let mut doc = CrdtDocument::new("laptop".to_string());
+        doc.load(ImportedState::parse_legacy(&legacy.to_string()).unwrap());
+        assert_eq!(doc.resolve_task_id("5"), "phone:5");
+        assert_eq!(doc.resolve_task_id("6"), "6");
+        
+        // The app still knows the task by its SQLite id
+        let id = doc.resolve_task_id("5");
+        doc.upsert_value(&id, "title".to_string(), "Buy oat milk");
+        assert_eq!(doc.tasks.len(), 1);
+        assert_eq!(doc.tasks["phone:5"].fields["title"].value, "Buy oat milk");
+        doc.delete_task(&doc.resolve_task_id("5"));
+        assert_eq!(doc.tasks.len(), 1);
+        assert!(doc.tasks["phone:5"].deleted);
+        
+        // With the same number from two creators, this node's task wins
+        doc.upsert_value("laptop:5", "title".to_string(), "Fix bug");
+        assert_eq!(doc.resolve_task_id("5"), "laptop:5");
+    }
+    
+    #[test]
+    fn test_concurrent_tag_adds_both_survive() {
+        let mut phone = CrdtDocument::new("phone".to_string());
+        let mut laptop = CrdtDocument::new("laptop".to_string());
+        phone.add_tag("1", "urgent".to_string());
+        laptop.add_tag("1", "frontend".to_string());
+        
+        let phone_ops = serde_json::to_string(&phone.operations).unwrap();
+        let (phone_state, laptop_state) = (phone.export(), laptop.export());
+        phone.merge_state(&laptop_state).unwrap();
+        laptop.merge_state(&phone_state).unwrap();
+        assert_eq!(phone.get_tags("1"), ["frontend", "urgent"]);
+        assert_eq!(laptop.get_tags("1"), ["frontend", "urgent"]);
+        
+        let mut relay = CrdtDocument::new("relay".to_string());
+        relay.apply_ops_json(&phone_ops).unwrap();
+        relay.apply_ops_json(&serde_json::to_string(&laptop.operations).unwrap()).unwrap();
+        assert_eq!(relay.get_tags("1"), ["frontend", "urgent"]);
+    }
+    
+    #[test]
+    fn test_concurrent_tag_add_and_remove() {
+        let mut a = CrdtDocument::new("node_a".to_string());
+        let mut b = CrdtDocument::new("node_b".to_string());
+        a.add_tag("1", "urgent".to_string());
+        a.add_tag("1", "bug".to_string());
+        b.merge_state(&a.export()).unwrap();
+        
+        // a removes both tags while b re-adds one it already had
+        a.remove_tag("1", "urgent".to_string());
+        a.remove_tag("1", "bug".to_string());
+        b.add_tag("1", "urgent".to_string());
+        
+        let (a_state, b_state) = (a.export(), b.export());
+        a.merge_state(&b_state).unwrap();
+        b.merge_state(&a_state).unwrap();
+        
+        // The remove didn't see b's new add, so "urgent" stays; "bug" goes
+        assert_eq!(a.get_tags("1"), ["urgent"]);
+        assert_eq!(b.get_tags("1"), ["urgent"]);
+        assert_eq!(state(&a)["tasks"]["1"]["tags"], state(&b)["tasks"]["1"]["tags"]);
+        
+        // Replaying the op logs in either order gives the same set
+        let (a_ops, b_ops) = (
+            serde_json::to_string(&a.operations).unwrap(),
+            serde_json::to_string(&b.operations).unwrap(),
+        );
+        let mut ab = CrdtDocument::new("node_c".to_string());
+        ab.apply_ops_json(&a_ops).unwrap();
+        ab.apply_ops_json(&b_ops).unwrap();
+        let mut ba = CrdtDocument::new("node_d".to_string());
+        ba.apply_ops_json(&b_ops).unwrap();
+        ba.apply_ops_json(&a_ops).unwrap();
+        assert_eq!(ab.get_tags("1"), ["urgent"]);
+        assert_eq!(ba.get_tags("1"), ["urgent"]);
+    }
+    
+    #[test]
+    fn test_time_spent_counter_survives_export() {
+        let mut phone = CrdtDocument::new("phone".to_string());
+        let mut laptop = CrdtDocument::new("laptop".to_string());
+        phone.increment_field("1", "minutes".to_string(), 30);
+        laptop.increment_field("1", "minutes".to_string(), 30);
+        
+        let phone_state = phone.export();
+        phone.merge_state(&laptop.export()).unwrap();
+        laptop.merge_state(&phone_state).unwrap();
+        assert_eq!(phone.get_counter("1", "minutes"), 60.0);
+        assert_eq!(laptop.get_counter("1", "minutes"), 60.0);
+        
+        // Per-node components round-trip, so later merges still add up
+        let mut restored = CrdtDocument::new("phone".to_string());
+        restored.import_state(&phone.export()).unwrap();
+        assert_eq!(restored.tasks["1"].counters["minutes"], phone.tasks["1"].counters["minutes"]);
+        restored.increment_field("1", "minutes".to_string(), 15);
+        laptop.merge_state(&restored.export()).unwrap();
+        assert_eq!(laptop.get_counter("1", "minutes"), 75.0);
+    }
+    
+    #[test]
+    fn test_column_order_converges() {
+        let mut a = CrdtDocument::new("node_a".to_string());
+        for id in 1..=4 {
+            a.upsert_value(&id.to_string(), "title".to_string(), format!("Card {}", id));
+        }
+        a.move_into_column("1", "todo", None).unwrap();
+        a.move_into_column("2", "todo", Some("1")).unwrap();
+        a.move_into_column("3", "todo", Some("2")).unwrap();
+        let mut b = CrdtDocument::new("node_b".to_string());
+        b.merge_state(&a.export()).unwrap();
+        assert_eq!(b.get_column_order("todo"), ["1", "2", "3"]);
+        
+        // Interleaved moves: different tasks on each side, plus the same
+        // task moved on both, where the later move wins
+        a.move_into_column("3", "todo", None).unwrap();
+        b.move_into_column("4", "todo", Some("1")).unwrap();
+        b.move_into_column("2", "doing", None).unwrap();
+        a.move_into_column("2", "todo", Some("1")).unwrap();
+        b.move_into_column("1", "doing", Some("2")).unwrap();
+        assert!(a.move_into_column("1", "done", Some("4")).is_err());
+        
+        let (a_state, b_state) = (a.export(), b.export());
+        let mut ab = CrdtDocument::new("node_c".to_string());
+        ab.merge_state(&a_state).unwrap();
+        ab.merge_state(&b_state).unwrap();
+        let mut ba = CrdtDocument::new("node_d".to_string());
+        ba.merge_state(&b_state).unwrap();
+        ba.merge_state(&a_state).unwrap();
+        a.merge_state(&b_state).unwrap();
+        b.merge_state(&a_state).unwrap();
+        
+        for doc in [&a, &b, &ab, &ba] {
+            assert_eq!(doc.get_column_order("todo"), ["3", "4"]);
+            assert_eq!(doc.get_column_order("doing"), ["2", "1"]);
+        }
+    }
+    
+    #[test]
+    fn test_undo_and_redo_local_edits() {
+        let mut doc = CrdtDocument::new("node_a".to_string());
+        doc.upsert_value("1", "title".to_string(), "Draft");
+        doc.upsert_value("1", "status".to_string(), "todo");
+        doc.upsert_value("1", "status".to_string(), "done");
+        
+        assert!(doc.undo());
+        assert_eq!(doc.tasks["1"].fields["status"].value, "todo");
+        assert!(doc.redo());
+        assert_eq!(doc.tasks["1"].fields["status"].value, "done");
+        assert!(!doc.can_redo());
+        
+        doc.delete_task("1");
+        assert!(doc.undo());
+        assert!(!doc.tasks["1"].deleted);
+        
+        // Undo back to before the task existed
+        while doc.undo() {}
+        assert!(doc.tasks["1"].deleted);
+        assert!(!doc.can_undo());
+        
+        // Undo writes are ordinary ops, so peers follow along
+        let mut peer = CrdtDocument::new("node_b".to_string());
+        peer.apply_ops_json(&serde_json::to_string(&doc.operations).unwrap()).unwrap();
+        assert!(peer.tasks["1"].deleted);
+        assert!(!peer.can_undo());
+        assert!(doc.redo());
+        peer.merge_state(&doc.export()).unwrap();
+        assert!(!peer.tasks["1"].deleted);
+        
+        doc.set_max_undo(2);
+        for i in 0..5 {
+            doc.upsert_value("1", "notes".to_string(), format!("note {}", i));
+        }
+        assert!(doc.undo() && doc.undo());
+        assert!(!doc.undo());
+        assert_eq!(doc.tasks["1"].fields["notes"].value, "note 2");
+    }
+    
+    #[test]
+    fn test_redo_does_not_clobber_remote_edit() {
+        let mut a = CrdtDocument::new("node_a".to_string());
+        let mut b = CrdtDocument::new("node_b".to_string());
+        a.upsert_value("1", "title".to_string(), "Report");
+        a.upsert_value("1", "status".to_string(), "todo");
+        a.upsert_value("1", "status".to_string(), "doing");
+        a.undo();
+        
+        b.merge_state(&a.export()).unwrap();
+        b.upsert_value("1", "status".to_string(), "review");
+        a.merge_state(&b.export()).unwrap();
+        
+        assert!(a.can_redo());
+        assert!(!a.redo());
+        assert_eq!(a.tasks["1"].fields["status"].value, "review");
+        // Undo skips the edit the remote change replaced too
+        assert!(a.undo());
+        assert_eq!(a.tasks["1"].fields["status"].value, "review");
+        assert!(a.tasks["1"].deleted);
+    }
+    
+    #[test]
+    fn test_merge_reports_changed_tasks() {
+        let mut remote = CrdtDocument::new("node_a".to_string());
+        remote.upsert_value("1", "title".to_string(), "Keep");
+        remote.upsert_value("2", "title".to_string(), "Edit me");
+        remote.upsert_value("3", "title".to_string(), "Remove me");
+        let mut local = CrdtDocument::new("node_b".to_string());
+        local.merge_state(&remote.export()).unwrap();
+        
+        remote.upsert_value("4", "title".to_string(), "New");
+        remote.upsert_value("2", "status".to_string(), "done");
+        remote.delete_task("3");
+        
+        let report = local.merge_value(state(&remote)).unwrap();
+        assert_eq!(report.changes, MergeChanges {
+            created: vec!["4".to_string()],
+            updated: vec![TaskChange { task_id: "2".to_string(), fields: vec!["status".to_string()] }],
+            deleted: vec!["3".to_string()],
+        });
+        assert_eq!((report.added, report.updated, report.deleted), (1, 1, 1));
+        
+        let again = local.merge_value(state(&remote)).unwrap();
+        assert_eq!(again.changes, MergeChanges::default());
+    }
+    
+    #[test]
+    fn test_restore_task_syncs() {
+        let mut a = CrdtDocument::new("node_a".to_string());
+        let mut b = CrdtDocument::new("node_b".to_string());
+        a.upsert_value("1", "title".to_string(), "Oops");
+        b.merge_state(&a.export()).unwrap();
+        a.delete_task("1");
+        b.merge_state(&a.export()).unwrap();
+        assert!(b.tasks["1"].deleted);
+        
+        b.restore_task("1");
+        assert!(!b.tasks["1"].deleted);
+        a.merge_state(&b.export()).unwrap();
+        assert!(!a.tasks["1"].deleted);
+        
+        let mut c = CrdtDocument::new("node_c".to_string());
+        c.apply_ops_json(&serde_json::to_string(&a.operations).unwrap()).unwrap();
+        assert!(c.tasks["1"].deleted);
+        c.apply_ops_json(&serde_json::to_string(&b.operations).unwrap()).unwrap();
+        assert!(!c.tasks["1"].deleted);
+    }
+    
+    #[test]
+    fn test_purged_task_stays_gone() {
+        let mut a = CrdtDocument::new("node_a".to_string());
+        a.upsert_value("1", "title".to_string(), "Spam");
+        a.upsert_value("2", "title".to_string(), "Keep");
+        let mut b = CrdtDocument::new("node_b".to_string());
+        b.merge_state(&a.export()).unwrap();
+        let stale = b.export();
+        
+        a.purge_task("1");
+        assert!(!a.tasks.contains_key("1"));
+        a.merge_state(&stale).unwrap();
+        assert!(!a.tasks.contains_key("1"));
+        assert!(a.tasks.contains_key("2"));
+        
+        // The un-purged peer drops its copy too, via state or ops
+        let mut c = CrdtDocument::new("node_c".to_string());
+        c.merge_state(&stale).unwrap();
+        c.apply_ops_json(&serde_json::to_string(&a.operations).unwrap()).unwrap();
+        assert!(!c.tasks.contains_key("1"));
+        b.merge_state(&a.export()).unwrap();
+        assert!(!b.tasks.contains_key("1"));
+    }
+    
+    #[test]
+    fn test_purge_concurrent_with_edit_converges() {
+        let mut a = CrdtDocument::new("node_a".to_string());
+        a.upsert_value("1", "title".to_string(), "Draft");
+        let mut b = CrdtDocument::new("node_b".to_string());
+        b.merge_state(&a.export()).unwrap();
+        
+        // B's edit is later than A's purge by Lamport counter
+        a.purge_task("1");
+        b.upsert_value("1", "title".to_string(), "Edited");
+        b.upsert_value("1", "status".to_string(), "done");
+        assert!(wins(&b.tasks["1"].updated_at, &a.purged["1"]));
+        
+        let (a_state, b_state) = (a.export(), b.export());
+        a.merge_state(&b_state).unwrap();
+        b.merge_state(&a_state).unwrap();
+        assert!(!a.tasks.contains_key("1"));
+        assert!(!b.tasks.contains_key("1"));
+        assert_eq!(state(&a)["purged"], state(&b)["purged"]);
+        
+        // Same through ops, in either order
+        let mut c = CrdtDocument::new("node_c".to_string());
+        c.merge_state(&a_state).unwrap();
+        let mut d = CrdtDocument::new("node_d".to_string());
+        d.merge_state(&b_state).unwrap();
+        let (a_ops, b_ops) = (serde_json::to_string(&a.operations).unwrap(), serde_json::to_string(&b.operations).unwrap());
+        c.apply_ops_json(&b_ops).unwrap();
+        d.apply_ops_json(&a_ops).unwrap();
+        assert!(!c.tasks.contains_key("1"));
+        assert!(!d.tasks.contains_key("1"));
+    }
+    
+    #[test]
+    fn test_bulk_upsert_matches_single_upserts() {
+        let mut import = BTreeMap::new();
+        for id in 1..=1000u32 {
+            let fields = serde_json::json!({
+                "title": format!("Imported {}", id),
+                "status": "todo",
+                "priority": id % 5,
+                "labels": ["import"],
+            });
+            import.insert(id.to_string(), fields.as_object().unwrap().clone());
+        }
+        
+        let mut bulk = CrdtDocument::new("node_a".to_string());
+        for (id, fields) in import.clone() {
+            bulk.upsert_fields(&id, fields);
+        }
+        let mut single = CrdtDocument::new("node_a".to_string());
+        for (id, fields) in import {
+            for (field, value) in fields {
+                single.upsert_value(&id, field, value);
+            }
+        }
+        
+        assert_eq!(bulk.tasks.len(), 1000);
+        assert_eq!(bulk.operations.len(), 1000);
+        assert_eq!(single.operations.len(), 4000);
+        assert_eq!(bulk.tasks["737"].fields["title"].value, "Imported 737");
+        assert_eq!(bulk.tasks["737"].fields["priority"].value, 2);
+        let values = |doc: &CrdtDocument| -> BTreeMap<String, BTreeMap<String, serde_json::Value>> {
+            doc.tasks
+                .iter()
+                .map(|(id, task)| (id.clone(), task.fields.iter().map(|(f, v)| (f.clone(), v.value.clone())).collect()))
+                .collect()
+        };
+        assert_eq!(values(&bulk), values(&single));
+        
+        let mut replica = CrdtDocument::new("node_b".to_string());
+        replica.apply_ops(bulk.operations.clone());
+        assert_eq!(state(&replica)["tasks"], state(&bulk)["tasks"]);
+    }
+    
+    #[test]
+    fn test_upsert_fields_uses_one_timestamp() {
+        let mut doc = CrdtDocument::new("node-a".to_string());
+        doc.upsert_value("task-1", "title".to_string(), "Plan");
+        let before = doc.counter;
+        
+        let fields = serde_json::json!({ "title": "Plan v2", "status": "doing", "priority": 3 });
+        doc.upsert_fields("task-1", fields.as_object().unwrap().clone());
+        assert_eq!(doc.counter, before + 1);
+        assert_eq!(doc.operations.len(), 2);
+        let task = &doc.tasks["task-1"];
+        assert_eq!(task.fields["title"].value, "Plan v2");
+        assert_eq!(task.fields["status"].value, "doing");
+        assert_eq!(task.fields["priority"].value, 3);
+        
+        // Undo still steps back one field at a time
+        assert!(doc.undo());
+        assert_eq!(doc.tasks["task-1"].fields["title"].value, "Plan");
+        assert_eq!(doc.tasks["task-1"].fields["status"].value, "doing");
+        
+        // The batch is compacted away once every field is overwritten
+        doc.upsert_value("task-1", "status".to_string(), "done");
+        assert_eq!(doc.compact_operations(), 0);
+        doc.upsert_value("task-1", "priority".to_string(), 1);
+        assert_eq!(doc.compact_operations(), 1);
+        assert!(!doc.operations.iter().any(|op| matches!(op, Operation::UpdateFields { .. })));
+    }
+    
+    #[test]
+    fn test_change_log_reports_only_real_changes() {
+        fn listen(doc: &mut CrdtDocument) {
+            doc.changes = ChangeLog(Some(Vec::new()));
+        }
+        fn fields(changes: &[Change]) -> Vec<(&str, &str, &serde_json::Value, ChangeOrigin)> {
+            changes.iter().map(|c| (c.task_id.as_str(), c.field.as_str(), &c.value, c.origin)).collect()
+        }
+        
+        let mut a = CrdtDocument::new("node_a".to_string());
+        listen(&mut a);
+        a.upsert_value("1", "title".to_string(), "Draft");
+        a.upsert_value("1", "title".to_string(), "Draft");
+        a.write_deleted("1", true);
+        a.write_deleted("1", true);
+        let changes = a.changes.take();
+        assert_eq!(fields(&changes), vec![
+            ("1", "title", &serde_json::json!("Draft"), ChangeOrigin::Local),
+            ("1", "deleted", &serde_json::json!(true), ChangeOrigin::Local),
+        ]);
+        a.write_deleted("1", false);
+        let stale = a.export();
+        
+        let mut b = CrdtDocument::new("node_b".to_string());
+        b.merge_state(&stale).unwrap();
+        b.upsert_value("1", "title".to_string(), "Final");
+        b.increment_counter("1", "minutes".to_string(), 15);
+        
+        // Newer remote values are reported once; older ones lose silently
+        a.changes.take();
+        a.merge_state(&b.export()).unwrap();
+        a.merge_state(&b.export()).unwrap();
+        a.merge_state(&stale).unwrap();
+        let changes = a.changes.take();
+        assert_eq!(fields(&changes), vec![
+            ("1", "title", &serde_json::json!("Final"), ChangeOrigin::Remote),
+            ("1", "minutes", &serde_json::json!(15), ChangeOrigin::Remote),
+        ]);
+        
+        let mut c = CrdtDocument::new("node_c".to_string());
+        listen(&mut c);
+        c.apply_ops(b.operations.clone());
+        c.apply_ops(b.operations.clone());
+        let stale_op = Operation::Update {
+            op_id: "node_a:1".to_string(),
+            task_id: "1".to_string(),
+            field: "title".to_string(),
+            value: "Draft".into(),
+            timestamp: LamportTimestamp::new(1, "node_a"),
+            clock: VectorClock::new(),
+            author: None,
+        };
+        c.apply_ops(vec![stale_op]);
+        let changes = c.changes.take();
+        assert_eq!(fields(&changes), vec![
+            ("1", "title", &serde_json::json!("Final"), ChangeOrigin::Remote),
+            ("1", "minutes", &serde_json::json!(15), ChangeOrigin::Remote),
+        ]);
+        
+        // Nothing is collected without a listener
+        let mut quiet = CrdtDocument::new("node_d".to_string());
+        quiet.upsert_value("1", "title".to_string(), "Draft");
+        assert!(quiet.changes.take().is_empty());
+    }
+    
+    #[test]
+    fn test_field_strategies_converge() {
+        use serde_json::json;
+        
+        // Phone and laptop write `field` without seeing each other; the
+        // phone's write has the newer timestamp (node ids break the tie)
+        let replicas = |field: &str, strategy: &str, phone_value: &serde_json::Value, laptop_value: &serde_json::Value| {
+            let mut phone = CrdtDocument::new("phone".to_string());
+            let mut laptop = CrdtDocument::new("laptop".to_string());
+            for (doc, value) in [(&mut phone, phone_value), (&mut laptop, laptop_value)] {
+                doc.set_strategy(field.to_string(), strategy).unwrap();
+                doc.upsert_value("1", field.to_string(), value.clone());
+            }
+            (phone, laptop)
+        };
+        // Merge by state and by operations, each in both orders
+        let merged = |field: &str, strategy: &str, phone_value: serde_json::Value, laptop_value: serde_json::Value| {
+            let (mut phone, mut laptop) = replicas(field, strategy, &phone_value, &laptop_value);
+            let (phone_state, laptop_state) = (phone.export(), laptop.export());
+            phone.merge_state(&laptop_state).unwrap();
+            laptop.merge_state(&phone_state).unwrap();
+            
+            let (mut phone_ops, mut laptop_ops) = replicas(field, strategy, &phone_value, &laptop_value);
+            let (from_phone, from_laptop) = (phone_ops.operations.clone(), laptop_ops.operations.clone());
+            phone_ops.apply_ops(from_laptop);
+            laptop_ops.apply_ops(from_phone);
+            
+            let value = phone.tasks["1"].fields[field].clone();
+            for other in [&laptop, &phone_ops, &laptop_ops] {
+                assert_eq!(other.tasks["1"].fields[field], value, "{} diverged", strategy);
+            }
+            (phone, laptop, value)
+        };
+        
+        let (_, _, title) = merged("title", "lww", json!("Phone title"), json!("Laptop title"));
+        assert_eq!(title.value, "Phone title");
+        
+        let (mut phone, mut laptop, priority) = merged("priority", "max_numeric", json!(1), json!(3));
+        assert_eq!(priority.value, 3);
+        // A later edit has seen the max, so it may lower the value
+        phone.upsert_value("1", "priority".to_string(), 0);
+        laptop.merge_state(&phone.export()).unwrap();
+        assert_eq!(laptop.tasks["1"].fields["priority"].value, 0);
+        
+        let (_, _, notes) = merged("notes", "concat_unique", json!("Call vendor\nphone note"), json!("Call vendor\nlaptop note"));
+        assert_eq!(notes.value, "Call vendor\nlaptop note\nphone note");
+        let (_, _, labels) = merged("labels", "concat_unique", json!(["a", "b"]), json!(["b", "c"]));
+        assert_eq!(labels.value, json!(["b", "c", "a"]));
+        
+        let (mut phone, mut laptop, status) = merged("status", "manual", json!("done"), json!("blocked"));
+        assert_eq!(status.value, "done");
+        assert_eq!(status.conflicting.len(), 1);
+        assert_eq!(status.conflicting[0].value, "blocked");
+        assert!(laptop.resolve_value("1", "status".to_string(), json!("blocked")));
+        assert!(!laptop.resolve_value("1", "status".to_string(), json!("done")));
+        phone.merge_state(&laptop.export()).unwrap();
+        assert_eq!(phone.tasks["1"].fields["status"], laptop.tasks["1"].fields["status"]);
+        assert_eq!(phone.tasks["1"].fields["status"].value, "blocked");
+        assert!(phone.tasks["1"].fields["status"].conflicting.is_empty());
+        
+        assert!(phone.set_strategy("status".to_string(), "average").is_err());
+    }
+    
+    #[test]
+    fn test_peer_behind_checkpoint_needs_snapshot() {
+        let mut a = CrdtDocument::new("node-a".to_string());
+        let mut b = CrdtDocument::new("node-b".to_string());
+        a.upsert_value("1", "title".to_string(), "Plan release".to_string());
+        b.apply_ops_json(&serde_json::to_string(&a.operations).unwrap()).unwrap();
+        let b_seen = LamportTimestamp::new(a.counter, &a.node_id);
+        
+        // B goes offline; A keeps editing and checkpoints in between
+        a.upsert_value("1", "status".to_string(), "doing".to_string());
+        a.upsert_value("2", "title".to_string(), "Fix crash".to_string());
+        a.fold_operations();
+        let checkpoint = a.checkpoint.clone().unwrap();
+        assert_eq!(checkpoint.timestamp, LamportTimestamp::new(a.counter, &a.node_id));
+        assert_eq!(checkpoint.clock["node-a"], a.counter);
+        assert!(a.operations.is_empty());
+        a.upsert_value("2", "status".to_string(), "done".to_string());
+        
+        // Folding an empty log leaves the checkpoint alone
+        let mut c = CrdtDocument::new("node-c".to_string());
+        c.fold_operations();
+        assert!(c.checkpoint.is_none());
+        
+        assert!(matches!(a.delta_since(b_seen.counter, Some(&b_seen.node_id)), Delta::SnapshotRequired { .. }));
+        assert!(matches!(a.delta_since(b_seen.counter, None), Delta::SnapshotRequired { .. }));
+        b.merge_state(&a.export()).unwrap();
+        assert_eq!(state(&b)["tasks"], state(&a)["tasks"]);
+        
+        // A peer that was at the checkpoint only needs the newer ops
+        let at = &checkpoint.timestamp;
+        match a.delta_since(at.counter, Some(&at.node_id)) {
+            Delta::Operations { operations } => {
+                assert_eq!(operations.len(), 1);
+                assert!(operations[0].timestamp() > at);
+            }
+            Delta::SnapshotRequired { .. } => panic!("peer at the checkpoint should get operations"),
+        }
+        let export: serde_json::Value = serde_json::from_str(&a.export_since_checkpoint()).unwrap();
+        assert_eq!(export["checkpoint"]["clock"]["node-a"], checkpoint.clock["node-a"]);
+        assert_eq!(export["operations"].as_array().unwrap().len(), 1);
+    }
+    
+    #[test]
+    fn test_filtered_tasks() {
+        let mut doc = CrdtDocument::new("node-a".to_string());
+        for i in 0..5 {
+            let status = if i % 2 == 0 { "doing" } else { "todo" };
+            doc.upsert_fields(&format!("task-{}", i), serde_json::json!({ "status": status, "priority": i }).as_object().unwrap().clone());
+        }
+        let filter = |json: serde_json::Value| serde_json::from_value::<TaskFilter>(json).unwrap();
+        let ids = |tasks: Vec<&CrdtTask>| tasks.into_iter().map(|task| task.id.clone()).collect::<Vec<_>>();
+        
+        assert_eq!(ids(doc.filtered_tasks(&filter(serde_json::json!({ "status": "doing" })))), ["task-0", "task-2", "task-4"]);
+        assert_eq!(ids(doc.filtered_tasks(&filter(serde_json::json!({ "status": "doing", "priority": 2 })))), ["task-2"]);
+        assert!(doc.filtered_tasks(&filter(serde_json::json!({ "status": "done" }))).is_empty());
+        
+        // Pages are taken after filtering, in get_tasks order
+        assert_eq!(ids(doc.filtered_tasks(&filter(serde_json::json!({ "offset": 1, "limit": 2 })))), ["task-1", "task-2"]);
+        assert_eq!(ids(doc.filtered_tasks(&filter(serde_json::json!({ "status": "doing", "offset": 2, "limit": 2 })))), ["task-4"]);
+        assert_eq!(doc.get_task_ids(), ["task-0", "task-1", "task-2", "task-3", "task-4"]);
+        
+        let seen = doc.counter;
+        doc.upsert_value("task-3", "status".to_string(), "done".to_string());
+        doc.upsert_value("task-5", "title".to_string(), "New".to_string());
+        doc.delete_task("task-0");
+        assert_eq!(ids(doc.filtered_tasks(&filter(serde_json::json!({ "updated_after": seen })))), ["task-3", "task-5"]);
+        assert_eq!(ids(doc.filtered_tasks(&filter(serde_json::json!({ "updated_after": seen, "status": "done" })))), ["task-3"]);
+    }
+    
+    #[test]
+    fn test_field_history_orders_writes_across_nodes() {
+        let mut a = CrdtDocument::new("node-a".to_string());
+        let mut b = CrdtDocument::new("node-b".to_string());
+        a.upsert_value("1", "status".to_string(), "todo".to_string());
+        b.apply_ops(a.operations.clone());
+        let (a_sent, b_sent) = (a.operations.len(), b.operations.len());
+        
+        // Concurrent edits: b's has the newer timestamp and wins
+        a.upsert_value("1", "status".to_string(), "doing".to_string());
+        b.upsert_value("1", "status".to_string(), "blocked".to_string());
+        b.upsert_value("1", "status".to_string(), "done".to_string());
+        let from_a = a.operations[a_sent..].to_vec();
+        let from_b = b.operations[b_sent..].to_vec();
+        a.apply_ops(from_b);
+        b.apply_ops(from_a);
+        
+        let timeline = |doc: &CrdtDocument| {
+            doc.field_history("1", "status")
+                .iter()
+                .map(|entry| (entry.value.as_str().unwrap().to_string(), entry.superseded))
+                .collect::<Vec<_>>()
+        };
+        let expected = [("todo", false), ("doing", true), ("blocked", false), ("done", false)]
+            .map(|(value, superseded)| (value.to_string(), superseded));
+        assert_eq!(timeline(&a), expected);
+        assert_eq!(timeline(&b), expected);
+        assert_eq!(a.field_history("1", "status"), b.field_history("1", "status"));
+        
+        // A merge of the same writes adds nothing new
+        let mut c = CrdtDocument::new("node-c".to_string());
+        c.merge_state(&a.export()).unwrap();
+        c.merge_state(&b.export()).unwrap();
+        assert_eq!(timeline(&c), [("done".to_string(), false)]);
+        
+        // History only travels in exports that ask for it
+        assert!(state(&a).get("history").is_none());
+        a.set_include_history(true);
+        let mut restored = CrdtDocument::new("node-a".to_string());
+        restored.load(ImportedState::parse(&a.export()).unwrap());
+        assert_eq!(timeline(&restored), expected);
+        c.merge_state(&a.export()).unwrap();
+        assert_eq!(timeline(&c), expected);
+    }
+    
+    #[test]
+    fn test_field_history_is_bounded() {
+        let mut doc = CrdtDocument::new("node-a".to_string());
+        doc.set_history_limit(3);
+        for i in 0..5 {
+            doc.upsert_value("1", "title".to_string(), format!("Draft {}", i));
+        }
+        let values = |doc: &CrdtDocument| {
+            doc.field_history("1", "title").iter().map(|entry| entry.value.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(values(&doc), ["Draft 2", "Draft 3", "Draft 4"]);
+        
+        doc.set_history_limit(1);
+        assert_eq!(values(&doc), ["Draft 4"]);
+        doc.set_history_limit(0);
+        assert!(doc.field_history("1", "title").is_empty());
+        doc.upsert_value("1", "title".to_string(), "Final".to_string());
+        assert!(doc.field_history("1", "title").is_empty());
+    }
+    
+    #[test]
+    fn test_oversized_payloads_are_rejected_whole() {
+        use serde_json::json;
+        
+        let mut doc = CrdtDocument::new("node-a".to_string());
+        doc.upsert_value("1", "title".to_string(), "Existing".to_string());
+        let before = doc.export();
+        let counter = doc.counter;
+        let task = |fields: serde_json::Value, counter: u64| {
+            let fields: serde_json::Map<String, serde_json::Value> = fields
+                .as_object()
+                .unwrap()
+                .iter()
+                .map(|(field, value)| (field.clone(), json!({ "value": value, "timestamp": { "counter": counter, "node_id": "evil" } })))
+                .collect();
+            json!({
+                "id": "x", "fields": fields, "deleted": false,
+                "created_at": { "counter": counter, "node_id": "evil" },
+                "updated_at": { "counter": counter, "node_id": "evil" }
+            })
+        };
+        let state = |tasks: Vec<(String, serde_json::Value)>| {
+            json!({ "version": STATE_VERSION, "node_id": "evil", "tasks": tasks.into_iter().collect::<serde_json::Map<_, _>>() }).to_string()
+        };
+        let rejected = |doc: &mut CrdtDocument, payload: &str, limit: &str| {
+            let err = doc.merge_state(payload).unwrap_err();
+            assert!(err.contains(limit), "{}", err);
+            let err = doc.load_checked(ImportedState::parse(payload).unwrap()).unwrap_err();
+            assert_eq!(err.limit, limit);
+        };
+        
+        doc.limits = MergeLimits { max_tasks: 3, max_fields_per_task: 4, ..MergeLimits::default() };
+        let many_tasks = (0..4).map(|i| (format!("t{}", i), task(json!({ "title": "ok" }), 2))).collect();
+        rejected(&mut doc, &state(many_tasks), "max_tasks");
+        let many_fields: serde_json::Map<_, _> = (0..5).map(|i| (format!("f{}", i), json!(i))).collect();
+        rejected(&mut doc, &state(vec![("x".to_string(), task(many_fields.into(), 2))]), "max_fields_per_task");
+        
+        doc.limits = MergeLimits::default();
+        let huge = "x".repeat(doc.limits.max_value_bytes + 1);
+        rejected(&mut doc, &state(vec![("x".to_string(), task(json!({ "notes": huge }), 2))]), "max_value_bytes");
+        rejected(&mut doc, &state(vec![("x".to_string(), task(json!({ "title": "late" }), u64::MAX))]), "max_counter_jump");
+        
+        // Operations are checked as a batch before any is applied
+        let ops = |value: serde_json::Value, counter: u64| {
+            json!([
+                { "Update": { "task_id": "1", "field": "status", "value": "ok", "timestamp": { "counter": counter, "node_id": "evil" } } },
+                { "Update": { "task_id": "1", "field": "title", "value": value, "timestamp": { "counter": counter, "node_id": "evil" } } }
+            ])
+        };
+        let err = doc.limits.check_operations(&serde_json::from_value::<Vec<Operation>>(ops(json!("Hijacked"), u64::MAX)).unwrap(), doc.counter, 0).unwrap_err();
+        assert_eq!((err.limit, err.actual), ("max_counter_jump", u64::MAX - doc.counter));
+        assert!(doc.limits.check_operations(&serde_json::from_value::<Vec<Operation>>(ops(json!(huge), 5)).unwrap(), doc.counter, 0).is_err());
+        assert_eq!(doc.export(), before);
+        assert_eq!(doc.counter, counter);
+        
+        // Ordinary merges are within the default limits
+        let mut peer = CrdtDocument::new("node-b".to_string());
+        for i in 0..50 {
+            peer.upsert_value(&format!("task-{}", i), "title".to_string(), "A".repeat(1000));
+        }
+        assert!(peer.limits.check_operations(&peer.operations, doc.counter, 0).is_ok());
+        doc.merge_state(&peer.export()).unwrap();
+        assert_eq!(doc.tasks.len(), 51);
+    }
+    
+    /// Every ordering of `items`
+    fn permutations<T: Clone>(items: &[T]) -> Vec<Vec<T>> {
+        if items.len() <= 1 {
+            return vec![items.to_vec()];
+        }
+        let mut all = Vec::new();
+        for i in 0..items.len() {
+            let mut rest = items.to_vec();
+            let first = rest.remove(i);
+            for mut tail in permutations(&rest) {
+                tail.insert(0, first.clone());
+                all.push(tail);
+            }
+        }
+        all
+    }
+    
+    #[test]
+    fn test_equal_counters_break_ties_by_node_id() {
+        let a = LamportTimestamp::new(5, "node-a");
+        let b = LamportTimestamp::new(5, "node-b");
+        assert!(wins(&b, &a));
+        assert!(!wins(&a, &b));
+        assert!(!wins(&a, &a.clone()));
+        assert!(wins(&LamportTimestamp::new(6, "node-a"), &b));
+    }
+    
+    #[test]
+    fn test_any_delivery_order_converges() {
+        // Converged view: task id -> (deleted, field values)
+        let view = |doc: &CrdtDocument| {
+            doc.tasks
+                .iter()
+                .map(|(id, task)| {
+                    let fields: BTreeMap<String, serde_json::Value> =
+                        task.fields.iter().map(|(field, value)| (field.clone(), value.value.clone())).collect();
+                    (id.clone(), (task.deleted, fields))
+                })
+                .collect::<BTreeMap<_, _>>()
+        };
+        
+        let mut a = CrdtDocument::new("node-a".to_string());
+        let mut b = CrdtDocument::new("node-b".to_string());
+        let mut c = CrdtDocument::new("node-c".to_string());
+        a.upsert_value("1", "title".to_string(), "From a".to_string());
+        b.apply_ops(a.operations.clone());
+        c.apply_ops(a.operations.clone());
+        // Same counter on every node: a deletes while b and c edit
+        a.delete_task("1");
+        b.upsert_value("1", "status".to_string(), "doing".to_string());
+        c.upsert_value("1", "title".to_string(), "From c".to_string());
+        b.upsert_value("2", "title".to_string(), "From b".to_string());
+        c.upsert_value("2", "title".to_string(), "Also c".to_string());
+        c.delete_task("2");
+        assert_eq!(a.counter, b.counter - 1);
+        
+        let ops: Vec<Operation> = [&a, &b, &c].iter().flat_map(|doc| doc.operations.clone()).collect();
+        let mut expected = None;
+        for order in permutations(&ops) {
+            let mut doc = CrdtDocument::new("node-x".to_string());
+            for op in order {
+                doc.apply_ops(vec![op]);
+            }
+            let state = view(&doc);
+            assert_eq!(&state, expected.get_or_insert_with(|| state.clone()));
+        }
+        let expected = expected.unwrap();
+        assert!(expected["1"].0, "a concurrent edit must not undo the delete");
+        assert_eq!(expected["1"].1["title"], "From c");
+        // c's later delete of its own write wins over b's title
+        assert!(expected["2"].0);
+        
+        // Exchanging whole states, in any order, agrees with the ops
+        let exports: Vec<String> = [&a, &b, &c].iter().map(|doc| doc.export()).collect();
+        for order in permutations(&exports) {
+            let mut doc = CrdtDocument::new("node-x".to_string());
+            for export in order {
+                doc.merge_state(&export).unwrap();
+            }
+            assert_eq!(view(&doc), expected);
+        }
+        for doc in [&mut a, &mut b, &mut c] {
+            doc.apply_ops(ops.clone());
+            assert_eq!(view(doc), expected);
+        }
+    }
+    
+    #[test]
+    fn test_fields_keep_their_author_through_merge() {
+        let mut alice = CrdtDocument::new("node-a".to_string());
+        let mut bob = CrdtDocument::new("node-b".to_string());
+        alice.set_author(Some("Alice".to_string()));
+        bob.set_author(Some("Bob".to_string()));
+        alice.upsert_value("1", "title".to_string(), "Plan release".to_string());
+        bob.merge_state(&alice.export()).unwrap();
+        bob.upsert_value("1", "status".to_string(), "doing".to_string());
+        let mut by_ops = CrdtDocument::new("node-c".to_string());
+        by_ops.apply_ops(alice.operations.iter().chain(&bob.operations).cloned().collect());
+        alice.merge_state(&bob.export()).unwrap();
+        
+        for doc in [&alice, &bob, &by_ops] {
+            let view = serde_json::to_value(TaskView::new(&doc.tasks["1"])).unwrap();
+            assert_eq!(view["fields"]["title"]["last_modified_by"], "Alice");
+            assert_eq!(view["fields"]["status"]["last_modified_by"], "Bob");
+            assert_eq!(view["last_modified_by"], "Bob");
+        }
+        
+        // The author moves with the winning value
+        alice.upsert_value("1", "status".to_string(), "done".to_string());
+        bob.merge_state(&alice.export()).unwrap();
+        assert_eq!(bob.tasks["1"].fields["status"].author.as_deref(), Some("Alice"));
+        assert_eq!(bob.field_history("1", "status").iter().map(|entry| entry.author.as_deref()).collect::<Vec<_>>(), [Some("Bob"), Some("Alice")]);
+        assert_eq!(serde_json::to_value(TaskView::new(&bob.tasks["1"])).unwrap()["last_modified_by"], "Alice");
+    }
+    
+    #[test]
+    fn test_stats_rank_the_largest_task_first() {
+        let mut doc = CrdtDocument::new("node-a".to_string());
+        for i in 0..5 {
+            doc.upsert_value(&format!("task-{}", i), "title".to_string(), format!("Task {}", i));
+        }
+        doc.upsert_value("task-3", "notes".to_string(), "log line\n".repeat(20_000));
+        doc.delete_task("task-4");
+        
+        let stats = doc.document_stats(3);
+        assert_eq!(stats.active_tasks, 4);
+        assert_eq!(stats.tombstones, 1);
+        assert_eq!(stats.largest_tasks.len(), 3);
+        assert_eq!(stats.largest_tasks[0].task_id, "task-3");
+        assert!(stats.largest_tasks[0].bytes > 180_000);
+        assert!(stats.largest_tasks[0].bytes > stats.largest_tasks[1].bytes * 100);
+        assert_eq!(doc.task_size("task-3"), Some(stats.largest_tasks[0].bytes));
+        assert_eq!(doc.task_size("missing"), None);
+        
+        let task_bytes: usize = doc.tasks.keys().filter_map(|id| doc.task_size(id)).sum();
+        assert_eq!(stats.task_bytes, task_bytes);
+        assert!(stats.operation_bytes > 180_000, "the fat write is still in the log");
+        assert_eq!(stats.total_bytes, stats.task_bytes + stats.operation_bytes);
+    }
+    
+    #[test]
+    fn test_diff_predicts_what_merge_does() {
+        let mut a = CrdtDocument::new("node-a".to_string());
+        for id in ["t1", "t2", "t3", "t4"] {
+            a.upsert_value(id, "title".to_string(), format!("{} from a", id));
+        }
+        a.upsert_value("t1", "status".to_string(), "todo".to_string());
+        a.increment_counter("t1", "points".to_string(), 1);
+        a.add_tag("t1", "x".to_string());
+        a.delete_task("t3");
+        let mut b = CrdtDocument::new("node-b".to_string());
+        b.merge_state(&a.export()).unwrap();
+        
+        b.restore_task("t3");
+        b.upsert_value("t1", "title".to_string(), "t1 from b".to_string());
+        b.upsert_value("t1", "status".to_string(), "blocked".to_string());
+        b.increment_counter("t1", "points".to_string(), 2);
+        b.add_tag("t1", "y".to_string());
+        b.delete_task("t2");
+        b.upsert_value("t5", "title".to_string(), "new in b".to_string());
+        a.delete_task("t4");
+        // a's status write is the newer one
+        a.counter += 20;
+        a.upsert_value("t1", "status".to_string(), "doing".to_string());
+        
+        let incoming = b.export();
+        let before = a.export();
+        let preview = a.merge_preview(&incoming).unwrap();
+        assert_eq!(a.export(), before, "diff must not change the document");
+        assert_eq!(preview.created, ["t5"]);
+        assert_eq!(preview.restored, ["t3"]);
+        assert_eq!(preview.deleted, ["t2"]);
+        assert_eq!(preview.updated.len(), 1);
+        let t1 = &preview.updated[0];
+        let winners: Vec<(&str, DiffWinner)> = t1.fields.iter().map(|f| (f.field.as_str(), f.winner)).collect();
+        assert_eq!(winners, [
+            ("points", DiffWinner::Incoming),
+            ("status", DiffWinner::Local),
+            ("tags", DiffWinner::Incoming),
+            ("title", DiffWinner::Incoming),
+        ]);
+        assert_eq!(t1.fields[1].local, Some("doing".into()));
+        assert_eq!(t1.fields[1].incoming, Some("blocked".into()));
+        
+        let report = a.merge_state(&incoming).unwrap();
+        let mut created = [preview.created.clone(), preview.restored.clone()].concat();
+        created.sort_unstable();
+        assert_eq!(report.changes.created, created);
+        assert_eq!(report.changes.deleted, preview.deleted);
+        let predicted: Vec<TaskChange> = preview
+            .updated
+            .iter()
+            .map(|task| TaskChange {
+                task_id: task.task_id.clone(),
+                fields: task.fields.iter().filter(|f| f.winner != DiffWinner::Local).map(|f| f.field.clone()).collect(),
+            })
+            .collect();
+        assert_eq!(report.changes.updated, predicted);
+        for field in &t1.fields {
+            assert_eq!(a.tasks["t1"].field_value(&field.field), field.result, "{}", field.field);
+        }
+    }
+    
+    #[test]
+    fn test_wall_time_beats_a_counter_that_ran_ahead() {
+        let mut busy = CrdtDocument::new("node-a".to_string());
+        let mut quiet = CrdtDocument::new("node-b".to_string());
+        busy.tick(1_000_000.0);
+        for i in 0..100 {
+            busy.upsert_value("task-1", "title".to_string(), format!("Draft {}", i));
+        }
+        quiet.tick(1_060_000.0);
+        quiet.upsert_value("task-1", "title".to_string(), "Final".to_string());
+        assert!(busy.counter > quiet.counter);
+        
+        busy.merge_state(&quiet.export()).unwrap();
+        quiet.merge_state(&busy.export()).unwrap();
+        for doc in [&busy, &quiet] {
+            assert_eq!(doc.tasks["task-1"].fields["title"].value, "Final");
+        }
+        
+        // The clock never goes back, so the busy replica's next edit still wins
+        busy.tick(5.0);
+        busy.upsert_value("task-1", "title".to_string(), "After".to_string());
+        assert_eq!(busy.tasks["task-1"].fields["title"].timestamp.wall_ms, 1_060_000);
+        quiet.merge_state(&busy.export()).unwrap();
+        assert_eq!(quiet.tasks["task-1"].fields["title"].value, "After");
+    }
+    
+    #[test]
+    fn test_wall_time_from_the_future_is_rejected() {
+        let now = 1_700_000_000_000.0;
+        let mut doc = CrdtDocument::new("node-a".to_string());
+        doc.tick(now);
+        doc.upsert_value("task-1", "title".to_string(), "Mine".to_string());
+        let before = doc.export();
+        
+        // A peer whose clock is two days ahead
+        let mut skewed = CrdtDocument::new("node-b".to_string());
+        skewed.tick(now + 2.0 * 24.0 * 60.0 * 60.0 * 1000.0);
+        skewed.upsert_value("task-1", "title".to_string(), "Theirs".to_string());
+        let err = doc.merge_state(&skewed.export()).unwrap_err();
+        assert!(err.contains("max_wall_skew_ms"), "{}", err);
+        let err = doc.apply_ops_json(&serde_json::to_string(&skewed.operations).unwrap()).unwrap_err();
+        assert!(err.contains("max_wall_skew_ms"), "{}", err);
+        assert_eq!(doc.export(), before);
+        assert_eq!(doc.wall_ms, now as u64);
+        
+        // A crafted timestamp can't pin the clock either
+        let mut state = state(&skewed);
+        state["tasks"]["task-1"]["fields"]["title"]["timestamp"]["wall_ms"] = u64::MAX.into();
+        assert!(doc.merge_state(&state.to_string()).unwrap_err().contains("max_wall_skew_ms"));
+        
+        // Ordinary drift is fine, and the limit can be lifted
+        let mut drifting = CrdtDocument::new("node-c".to_string());
+        drifting.tick(now + 60_000.0);
+        drifting.upsert_value("task-2", "title".to_string(), "Close enough".to_string());
+        doc.merge_state(&drifting.export()).unwrap();
+        doc.limits = MergeLimits { max_wall_skew_ms: 0, ..MergeLimits::default() };
+        doc.merge_state(&skewed.export()).unwrap();
+        assert_eq!(doc.tasks["task-1"].fields["title"].value, "Theirs");
+    }
+    
+    #[test]
+    fn test_timestamps_without_wall_time_still_load() {
+        let old: LamportTimestamp = serde_json::from_str(r#"{"counter": 7, "node_id": "node-a"}"#).unwrap();
+        assert_eq!(old, LamportTimestamp::new(7, "node-a"));
+        assert_eq!(serde_json::to_string(&old).unwrap(), r#"{"counter":7,"node_id":"node-a"}"#);
+        
+        // An untimed write loses to any timed one, whatever its counter
+        let mut legacy = CrdtDocument::new("node-a".to_string());
+        for _ in 0..10 {
+            legacy.upsert_value("task-1", "status".to_string(), "old".to_string());
+        }
+        let mut current = CrdtDocument::new("node-b".to_string());
+        current.tick(1_700_000_000_000.0);
+        current.upsert_value("task-1", "status".to_string(), "new".to_string());
+        
+        legacy.merge_state(&current.export()).unwrap();
+        assert_eq!(legacy.tasks["task-1"].fields["status"].value, "new");
+        current.merge_state(&legacy.export()).unwrap();
+        assert_eq!(current.tasks["task-1"].fields["status"].value, "new");
+        
+        // Once it has seen a timed write, the legacy replica writes after it
+        legacy.upsert_value("task-1", "status".to_string(), "newer".to_string());
+        current.merge_state(&legacy.export()).unwrap();
+        assert_eq!(current.tasks["task-1"].fields["status"].value, "newer");
+    }
+    
+    #[test]
+    fn test_workspace_round_trips_boards() {
+        let mut workspace = CrdtWorkspace::new("node-a".to_string());
+        assert!(workspace.create_board("design".to_string()));
+        assert!(workspace.create_board("dev".to_string()));
+        assert!(!workspace.create_board("dev".to_string()));
+        workspace.board_mut("design").unwrap().upsert_value("task-1", "title".to_string(), "Mockups".to_string());
+        workspace.board_mut("dev").unwrap().upsert_value("task-1", "title".to_string(), "API".to_string());
+        workspace.board_mut("design").unwrap().upsert_value("task-2", "title".to_string(), "Logo".to_string());
+        
+        // One counter across boards
+        let timestamp = |workspace: &CrdtWorkspace, board: &str, task: &str| {
+            workspace.board(board).unwrap().tasks[task].fields["title"].timestamp.counter
+        };
+        assert!(timestamp(&workspace, "dev", "task-1") > timestamp(&workspace, "design", "task-1"));
+        assert!(timestamp(&workspace, "design", "task-2") > timestamp(&workspace, "dev", "task-1"));
+        
+        let mut copy = CrdtWorkspace::new("node-a".to_string());
+        copy.import_boards(&workspace.export_all()).unwrap();
+        assert_eq!(copy.board_names(), ["design", "dev"]);
+        for name in ["design", "dev"] {
+            let export = |workspace: &CrdtWorkspace| serde_json::from_str::<serde_json::Value>(&workspace.board(name).unwrap().export()).unwrap();
+            assert_eq!(export(&copy), export(&workspace));
+        }
+        assert!(copy.import_boards(r#"{"boards": {"ok": {"version": 2, "tasks": {}}, "bad": {"tasks": 1}}}"#).is_err());
+        assert_eq!(copy.board_names(), ["design", "dev"]);
+    }
+    
+    #[test]
+    fn test_workspace_merge_keeps_boards_apart() {
+        let mut a = CrdtWorkspace::new("node-a".to_string());
+        a.create_board("design".to_string());
+        a.create_board("dev".to_string());
+        a.board_mut("design").unwrap().upsert_value("task-1", "title".to_string(), "Mockups".to_string());
+        a.board_mut("dev").unwrap().upsert_value("task-2", "title".to_string(), "API".to_string());
+        let mut b = CrdtWorkspace::new("node-b".to_string());
+        b.create_board("dev".to_string());
+        b.create_board("ops".to_string());
+        b.board_mut("dev").unwrap().upsert_value("task-3", "title".to_string(), "Tests".to_string());
+        b.board_mut("ops").unwrap().upsert_value("task-1", "title".to_string(), "Deploy".to_string());
+        
+        let reports = a.merge_boards(&b.export_all()).unwrap();
+        assert_eq!(reports.iter().map(|(name, report)| (name.as_str(), report.added)).collect::<Vec<_>>(), [("dev", 1), ("ops", 1)]);
+        assert_eq!(a.board_names(), ["design", "dev", "ops"]);
+        let ids = |workspace: &CrdtWorkspace, board: &str| {
+            workspace.board(board).unwrap().sorted_tasks().into_iter().map(|task| task.id.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(&a, "design"), ["task-1"]);
+        assert_eq!(ids(&a, "dev"), ["task-2", "task-3"]);
+        assert_eq!(ids(&a, "ops"), ["task-1"]);
+        // Same task id, different boards: neither overwrote the other
+        assert_eq!(a.board("design").unwrap().tasks["task-1"].fields["title"].value, "Mockups");
+        assert_eq!(a.board("ops").unwrap().tasks["task-1"].fields["title"].value, "Deploy");
+        
+        // The merged-in board joins the shared counter
+        a.board_mut("ops").unwrap().upsert_value("task-4", "title".to_string(), "Monitor".to_string());
+        let ops = a.board("ops").unwrap();
+        assert!(ops.counter > a.board("design").unwrap().counter);
+        assert!(ops.tasks["task-4"].fields["title"].timestamp.counter > ops.tasks["task-1"].fields["title"].timestamp.counter);
+    }
+    
+    #[test]
+    fn test_apply_operations_reports_only_tasks_that_changed() {
+        let mut a = CrdtDocument::new("node-a".to_string());
+        let mut b = CrdtDocument::new("node-b".to_string());
+        a.upsert_value("task-1", "title".to_string(), "Old".to_string());
+        a.upsert_value("task-2", "title".to_string(), "Keep".to_string());
+        a.upsert_value("task-3", "title".to_string(), "Doomed".to_string());
+        b.apply_ops(a.operations.clone());
+        let stale_title = a.operations[0].clone();
+        a.clear_operations();
+        
+        // b edits task-1 later, so a's next write to it is outdated on b
+        a.upsert_value("task-1", "title".to_string(), "Edited on a".to_string());
+        let stale: Vec<Operation> = a.operations.drain(..).collect();
+        b.counter += 10;
+        b.upsert_value("task-1", "title".to_string(), "Edited on b".to_string());
+        
+        a.upsert_value("task-2", "status".to_string(), "doing".to_string());
+        a.delete_task("task-3");
+        a.upsert_value("task-4", "title".to_string(), "New".to_string());
+        let fresh: Vec<Operation> = a.operations.clone();
+        
+        let batch: Vec<Operation> = std::iter::once(stale_title).chain(stale).chain(fresh).collect();
+        let report = b.apply_ops(batch);
+        assert_eq!(report, ApplyReport {
+            created: vec!["task-4".to_string()],
+            updated: vec!["task-2".to_string()],
+            deleted: vec!["task-3".to_string()],
+            applied: 3,
+            skipped: 2,
+        });
+        assert_eq!(b.tasks["task-1"].fields["title"].value, "Edited on b");
+    }
+    
+    #[test]
+    fn test_schema_rejects_or_moves_unknown_fields() {
+        let mut doc = CrdtDocument::new("node-a".to_string());
+        doc.schema = Some(serde_json::from_value(serde_json::json!({
+            "fields": { "title": "string", "assignee": "string", "priority": "number" }
+        })).unwrap());
+        let fields = |json: serde_json::Value| json.as_object().unwrap().clone();
+        
+        assert!(doc.schema_fields(fields(serde_json::json!({ "title": "Plan", "priority": 2 }))).is_ok());
+        assert_eq!(
+            doc.schema_fields(fields(serde_json::json!({ "title": "Plan", "assginee": "somchai" }))),
+            Err("Schema error: unknown field 'assginee'".to_string())
+        );
+        assert_eq!(
+            doc.schema_field("priority".to_string(), &"high".into()),
+            Err("Schema error: field 'priority' must be a number".to_string())
+        );
+        assert_eq!(doc.schema_field("priority".to_string(), &serde_json::Value::Null), Ok("priority".to_string()));
+        
+        doc.schema.as_mut().unwrap().strict = false;
+        let coerced = doc.schema_fields(fields(serde_json::json!({ "title": "Plan", "assginee": "somchai" }))).unwrap();
+        assert_eq!(coerced.keys().collect::<Vec<_>>(), ["extras.assginee", "title"]);
+        doc.upsert_fields("task-1", coerced);
+        assert!(doc.schema_violations().is_empty());
+        
+        // Fields written before the schema are reported
+        doc.tasks.get_mut("task-1").unwrap().fields.insert(
+            "assginee".to_string(),
+            CrdtValue::new("somchai".into(), LamportTimestamp::new(1, "node-a")),
+        );
+        assert_eq!(doc.schema_violations(), [SchemaViolation {
+            task_id: "task-1".to_string(),
+            field: "assginee".to_string(),
+            message: "unknown field 'assginee'".to_string(),
+        }]);
+    }
+    
+    #[test]
+    fn test_merge_quarantines_fields_outside_the_schema() {
+        let mut peer = CrdtDocument::new("node-b".to_string());
+        peer.upsert_fields("task-1", serde_json::json!({ "title": "Plan", "assginee": "somchai", "priority": "high" }).as_object().unwrap().clone());
+        let mut doc = CrdtDocument::new("node-a".to_string());
+        doc.schema = Some(serde_json::from_value(serde_json::json!({
+            "fields": { "title": "string", "priority": "number" }
+        })).unwrap());
+        
+        doc.merge_state(&peer.export()).unwrap();
+        let mut fields: Vec<(&str, &serde_json::Value)> =
+            doc.tasks["task-1"].fields.iter().map(|(field, value)| (field.as_str(), &value.value)).collect();
+        fields.sort_by_key(|(field, _)| *field);
+        assert_eq!(fields, [
+            ("extras.assginee", &serde_json::json!("somchai")),
+            ("extras.priority", &serde_json::json!("high")),
+            ("title", &serde_json::json!("Plan")),
+        ]);
+        assert!(doc.schema_violations().is_empty());
+        
+        // Operations from the peer are quarantined the same way
+        peer.upsert_value("task-1", "colour".to_string(), "red".to_string());
+        doc.apply_ops(peer.operations.clone());
+        assert_eq!(doc.tasks["task-1"].fields["extras.colour"].value, "red");
+        assert!(!doc.tasks["task-1"].fields.contains_key("colour"));
+    }
+    
+    #[cfg(feature = "binary")]
+    #[test]
+    fn test_binary_export_merges_like_json() {
+        let mut doc = CrdtDocument::new("node-a".to_string());
+        doc.tick(1_700_000_000_000.0);
+        doc.upsert_fields("task-1", serde_json::json!({
+            "title": "ตรวจงาน",
+            "estimate": 2.5,
+            "labels": ["ops", "urgent"],
+            "meta": { "done": false, "parent": null },
+        }).as_object().unwrap().clone());
+        doc.upsert_value("task-2", "title".to_string(), "Remove me");
+        doc.delete_task("task-2");
+        
+        let bytes = doc.export_binary();
+        assert_eq!(&bytes[..3], b"KB\x02");
+        assert!(bytes.len() < doc.export().len());
+        
+        let mut from_json = CrdtDocument::new("node-b".to_string());
+        from_json.merge_state(&doc.export()).unwrap();
+        let mut from_binary = CrdtDocument::new("node-b".to_string());
+        from_binary.merge_binary_state(&bytes).unwrap();
+        assert_eq!(state(&from_binary), state(&from_json));
+        assert_eq!(state(&from_binary)["tasks"], state(&doc)["tasks"]);
+    }
+    
+    #[cfg(feature = "binary")]
+    #[test]
+    fn test_binary_import_rejects_bad_header() {
+        let mut doc = CrdtDocument::new("node-a".to_string());
+        doc.upsert_value("task-1", "title".to_string(), "Plan");
+        let before = doc.export();
+        
+        let mut bytes = doc.export_binary();
+        bytes[0] = b'X';
+        assert_eq!(doc.merge_binary_state(&bytes).unwrap_err(), "Parse error: not a binary export (bad magic bytes)");
+        bytes[0] = b'K';
+        bytes[2] = 9;
+        assert_eq!(doc.merge_binary_state(&bytes).unwrap_err(), "Parse error: unsupported export version 9");
+        assert!(doc.merge_binary_state(b"KB").is_err());
+        assert_eq!(doc.export(), before);
+    }
+    
+    #[test]
+    fn test_ephemeral_fields_expire_and_stay_local() {
+        let mut doc = CrdtDocument::new("node-a".to_string());
+        doc.upsert_value("task-1", "title".to_string(), "Plan");
+        let operations = doc.operations.len();
+        
+        doc.prune_ephemeral(10);
+        doc.set_ephemeral("task-1", "editing_by".to_string(), "Alice".into(), 5);
+        assert_eq!(doc.operations.len(), operations);
+        let view = serde_json::to_value(doc.task_view("task-1").unwrap()).unwrap();
+        assert_eq!(view["ephemeral"]["editing_by"], "Alice");
+        assert!(view["fields"].get("editing_by").is_none());
+        assert!(!doc.export().contains("editing_by"));
+        
+        let mut peer = CrdtDocument::new("node-b".to_string());
+        peer.merge_state(&doc.export()).unwrap();
+        assert!(peer.ephemeral.is_empty());
+        
+        assert_eq!(doc.prune_ephemeral(14), 0);
+        assert_eq!(serde_json::to_value(doc.task_view("task-1").unwrap()).unwrap()["ephemeral"]["editing_by"], "Alice");
+        assert_eq!(doc.prune_ephemeral(15), 1);
+        let view = serde_json::to_value(doc.task_view("task-1").unwrap()).unwrap();
+        assert!(view.get("ephemeral").is_none());
+        assert!(doc.ephemeral.is_empty());
+    }
+    
+    #[test]
+    fn test_collision_policy_decides_what_happens_to_a_reused_id() {
+        let make = |node: &str, title: &str| {
+            let mut doc = CrdtDocument::new(node.to_string());
+            doc.upsert_fields("7", serde_json::json!({ "title": title, "status": "todo" }).as_object().unwrap().clone());
+            doc
+        };
+        let phone = make("phone", "Buy milk").export();
+        
+        // merge: one task, the newer write of each field wins
+        let mut laptop = make("laptop", "Fix printer");
+        let report = laptop.merge_state(&phone).unwrap();
+        assert!(report.remapped.is_empty());
+        assert_eq!(laptop.tasks.len(), 1);
+        
+        // error: nothing changes
+        let mut laptop = make("laptop", "Fix printer");
+        laptop.collision_policy = CollisionPolicy::Error;
+        let before = laptop.export();
+        assert_eq!(
+            laptop.merge_state(&phone).unwrap_err(),
+            "Task id collision: '7' was created separately on laptop and phone"
+        );
+        assert_eq!(laptop.export(), before);
+        
+        // fork: the incoming task gets its own id, again on a re-merge
+        let mut laptop = make("laptop", "Fix printer");
+        laptop.collision_policy = CollisionPolicy::Fork;
+        let report = laptop.merge_state(&phone).unwrap();
+        assert_eq!(report.remapped, [TaskRemap { from: "7".to_string(), to: "7@phone".to_string() }]);
+        assert_eq!(laptop.tasks["7"].fields["title"].value, "Fix printer");
+        assert_eq!(laptop.tasks["7@phone"].fields["title"].value, "Buy milk");
+        assert_eq!(laptop.tasks["7@phone"].id, "7@phone");
+        laptop.merge_state(&phone).unwrap();
+        assert_eq!(laptop.tasks.len(), 2);
+        
+        // The same task edited on two nodes is not a collision
+        let mut tablet = CrdtDocument::new("tablet".to_string());
+        tablet.merge_state(&phone).unwrap();
+        tablet.upsert_value("7", "title".to_string(), "Buy milk and eggs");
+        let mut phone = make("phone", "Buy milk");
+        phone.collision_policy = CollisionPolicy::Error;
+        assert!(phone.merge_state(&tablet.export()).unwrap().remapped.is_empty());
+    }
+    
+    #[test]
+    fn test_import_merges_unless_replacing() {
+        let mut doc = CrdtDocument::new("laptop".to_string());
+        doc.upsert_value("task-1", "title".to_string(), "Write report");
+        doc.upsert_value("task-2", "title".to_string(), "Call bank");
+        let backup = doc.export();
+        doc.upsert_value("task-1", "title".to_string(), "Write final report");
+        doc.upsert_value("task-3", "title".to_string(), "Made offline");
+        let counter = doc.counter;
+        
+        let summary = doc.import_state(&backup).unwrap();
+        assert_eq!(summary, ImportSummary { added: 0, preserved: 3, overwritten: 0 });
+        assert_eq!(doc.tasks["task-1"].fields["title"].value, "Write final report");
+        assert!(doc.tasks.contains_key("task-3"));
+        assert_eq!(doc.counter, counter);
+        
+        let summary = doc.import_replace_state(&backup).unwrap();
+        assert_eq!(summary, ImportSummary { added: 0, preserved: 1, overwritten: 2 });
+        assert_eq!(doc.tasks["task-1"].fields["title"].value, "Write report");
+        assert!(!doc.tasks.contains_key("task-3"));
+        assert!(doc.operations.is_empty());
+        // New writes still move past everything seen before
+        doc.upsert_value("task-1", "title".to_string(), "Rewrite report");
+        assert_eq!(doc.tasks["task-1"].fields["title"].timestamp.counter, counter + 1);
+    }
+    
+    #[test]
+    fn test_sync_targets_each_get_every_operation() {
+        let mut doc = CrdtDocument::new("node-a".to_string());
+        let counters = |ops: Vec<&Operation>| ops.iter().map(|op| op.timestamp().counter).collect::<Vec<_>>();
+        for i in 1..=4 {
+            doc.upsert_value("task-1", format!("field-{}", i), i);
+        }
+        assert_eq!(counters(doc.operations_for("peer".to_string())), [1, 2, 3, 4]);
+        assert_eq!(counters(doc.operations_for("room".to_string())), [1, 2, 3, 4]);
+        
+        // The room keeps up, the peer lags: only what both have goes
+        assert_eq!(doc.ack_operations("room".to_string(), 4), 0);
+        assert_eq!(doc.ack_operations("peer".to_string(), 2), 2);
+        assert_eq!(doc.operations.len(), 2);
+        doc.upsert_value("task-1", "field-5".to_string(), 5);
+        assert_eq!(counters(doc.operations_for("room".to_string())), [5]);
+        assert_eq!(counters(doc.operations_for("peer".to_string())), [3, 4, 5]);
+        
+        assert_eq!(doc.ack_operations("peer".to_string(), 5), 2);
+        assert_eq!(counters(doc.operations_for("room".to_string())), [5]);
+        assert!(doc.remove_sync_target("room"));
+        assert_eq!(doc.ack_operations("peer".to_string(), 5), 1);
+        assert!(doc.operations.is_empty());
+        assert_eq!(doc.checkpoint.as_ref().unwrap().timestamp.counter, 5);
+    }
+    
+    #[test]
+    fn test_concurrent_archive_and_edit_converge() {
+        let mut a = CrdtDocument::new("node_a".to_string());
+        let mut b = CrdtDocument::new("node_b".to_string());
+        a.upsert_value("1", "title".to_string(), "Ship v1");
+        a.upsert_value("2", "title".to_string(), "Ship v2");
+        b.merge_state(&a.export()).unwrap();
+        
+        // a archives while b, unaware, edits the same task
+        a.archive_task("1");
+        b.upsert_value("1", "status".to_string(), "done");
+        let ids = |d: &CrdtDocument| d.sorted_tasks().iter().map(|t| t.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&a), ["2"]);
+        assert_eq!(a.ordered_tasks(true).len(), 2);
+        
+        let from_a = a.export();
+        a.merge_state(&b.export()).unwrap();
+        b.merge_state(&from_a).unwrap();
+        for doc in [&a, &b] {
+            assert!(doc.tasks["1"].archived && !doc.tasks["1"].deleted);
+            assert_eq!(doc.tasks["1"].fields["status"].value, "done");
+            assert_eq!(ids(doc), ["2"]);
+            assert_eq!(doc.archived_tasks().len(), 1);
+        }
+        assert_eq!(state(&a)["tasks"], state(&b)["tasks"]);
+        
+        // Ops in either order land on the same state
+        let mut c = CrdtDocument::new("node_c".to_string());
+        c.apply_ops_json(&serde_json::to_string(&b.operations).unwrap()).unwrap();
+        c.apply_ops_json(&serde_json::to_string(&a.operations).unwrap()).unwrap();
+        assert!(c.tasks["1"].archived);
+        assert_eq!(c.tasks["1"].fields["status"].value, "done");
+        
+        // Archived tasks aren't tombstones, and unarchive wins like restore
+        a.merge_state(&b.export()).unwrap();
+        b.merge_state(&a.export()).unwrap();
+        assert_eq!(a.purge_tombstones(u64::MAX), CompactionReport::default());
+        assert!(a.tasks.contains_key("1"));
+        b.unarchive_task("1");
+        a.merge_state(&b.export()).unwrap();
+        assert_eq!(ids(&a), ["1", "2"]);
+        assert!(b.undo());
+        assert!(b.tasks["1"].archived);
+    }
+    
+    #[test]
+    fn test_merge_skips_tasks_with_nothing_new() {
+        let mut a = CrdtDocument::new("node_a".to_string());
+        let mut b = CrdtDocument::new("node_b".to_string());
+        for id in ["1", "2", "3"] {
+            a.upsert_value(id, "title".to_string(), format!("Task {}", id));
+        }
+        a.increment_counter("1", "time_spent".to_string(), 5);
+        b.merge_state(&a.export()).unwrap();
+        
+        let identical = a.export();
+        assert_eq!(b.merge_state(&identical).unwrap(), MergeReport::default());
+        assert_eq!(b.merge_state(&b.export()).unwrap(), MergeReport::default());
+        
+        a.upsert_value("2", "status".to_string(), "done");
+        let newer = |local: &CrdtDocument, remote: &CrdtDocument| {
+            let mut ids: Vec<&String> = remote.tasks.iter().filter(|(id, task)| !local.tasks[*id].covers(task)).map(|(id, _)| id).collect();
+            ids.sort();
+            ids.into_iter().cloned().collect::<Vec<_>>()
+        };
+        assert_eq!(newer(&b, &a), ["2"]);
+        let report = b.merge_state(&a.export()).unwrap();
+        assert_eq!((report.updated, report.changes.updated.len()), (1, 1));
+        
+        // Older but concurrent: b's write to another field is not newer
+        // than a's latest, yet still has to be merged
+        a.upsert_value("3", "notes".to_string(), "later");
+        b.counter = 0;
+        b.upsert_value("3", "status".to_string(), "doing");
+        assert!(wins(&a.tasks["3"].updated_at, &b.tasks["3"].updated_at));
+        assert_eq!(newer(&a, &b), ["3"]);
+        a.merge_state(&b.export()).unwrap();
+        assert_eq!(a.tasks["3"].fields["status"].value, "doing");
+    }
+    
+    #[test]
+    fn test_node_ids_are_unique() {
+        let ids: HashSet<String> = (0..10_000).map(|_| generate_node_id_v2()).collect();
+        assert_eq!(ids.len(), 10_000);
+        assert!(ids.iter().all(|id| id.len() == "node_".len() + 32));
+        
+        // The old export still maps a timestamp to the same id
+        let now = 1_700_000_000_000.0;
+        assert_eq!(generate_node_id(now), generate_node_id(now));
+        assert_eq!(generate_node_id(now), generate_node_id((1_700_000_000_000u64 & 0xFFFF_FFFF) as f64));
+        assert_eq!(generate_node_id(-1.0), generate_node_id(u32::MAX as f64));
+        assert_ne!(generate_node_id_v2(), generate_node_id_v2());
+    }
+    
+    #[test]
+    fn test_relayed_multi_field_upsert_keeps_every_field() {
+        let mut a = CrdtDocument::new("node_a".to_string());
+        let fields = serde_json::json!({ "title": "Ship v2", "status": "doing", "notes": "after QA" });
+        let fields = fields.as_object().unwrap();
+        a.upsert_fields("1", fields.clone());
+        
+        let mut relay = CrdtDocument::new("relay".to_string());
+        relay.set_track_merge_ops(true);
+        relay.merge_state(&a.export()).unwrap();
+        assert_eq!(relay.operations.len(), 3);
+        
+        let mut c = CrdtDocument::new("node_c".to_string());
+        let report = c.apply_ops_json(&serde_json::to_string(&relay.operations).unwrap()).unwrap();
+        assert_eq!(report.applied, 3);
+        for (field, value) in fields {
+            assert_eq!(&c.tasks["1"].fields[field].value, value);
+        }
+        
+        // Re-sent, or after the original op, they are skipped
+        assert_eq!(c.apply_ops_json(&serde_json::to_string(&relay.operations).unwrap()).unwrap().applied, 0);
+        let mut d = CrdtDocument::new("node_d".to_string());
+        d.apply_ops_json(&serde_json::to_string(&a.operations).unwrap()).unwrap();
+        assert_eq!(d.apply_ops_json(&serde_json::to_string(&relay.operations).unwrap()).unwrap().applied, 0);
+        c.prune_seen_operations(u64::MAX);
+        assert!(c.seen_ops.other.is_empty());
+        assert!(c.has_seen("node_a:1:title"));
+    }
+    
+    #[test]
+    fn test_purge_and_replace_report_changes() {
+        let mut a = CrdtDocument::new("node_a".to_string());
+        a.upsert_value("1", "title".to_string(), "Draft");
+        a.upsert_value("2", "title".to_string(), "Spare");
+        let mut backup = CrdtDocument::new("node_b".to_string());
+        backup.upsert_value("1", "title".to_string(), "Draft");
+        backup.upsert_value("3", "title".to_string(), "Restored");
+        
+        a.changes = ChangeLog(Some(Vec::new()));
+        a.purge("2");
+        a.purge("2");
+        let changes = a.changes.take();
+        assert_eq!(changes.len(), 1);
+        assert_eq!((changes[0].task_id.as_str(), changes[0].field.as_str(), changes[0].origin), ("2", "deleted", ChangeOrigin::Local));
+        
+        // "1" has the same title, written elsewhere: not a change
+        a.upsert_value("4", "title".to_string(), "Gone");
+        a.changes.take();
+        a.import_replace_state(&backup.export()).unwrap();
+        let changes: Vec<(String, String, serde_json::Value)> =
+            a.changes.take().into_iter().map(|c| (c.task_id, c.field, c.value)).collect();
+        assert_eq!(changes, [
+            ("3".to_string(), "title".to_string(), serde_json::json!("Restored")),
+            ("4".to_string(), "deleted".to_string(), serde_json::json!(true)),
+        ]);
+    }
+    
+    thread_local! {
+        /// Batches `notify_changes` would pass to the callback, one per call
+        pub(super) static NOTIFIED: std::cell::RefCell<Vec<Vec<Change>>> = const { std::cell::RefCell::new(Vec::new()) };
+    }
+    
+    /// `(task_id, field)` of each batch notified since the last call
+    fn notified() -> Vec<Vec<(String, String)>> {
+        NOTIFIED.with(|notified| notified.take())
+            .into_iter()
+            .map(|batch| batch.into_iter().map(|change| (change.task_id, change.field)).collect())
+            .collect()
+    }
+    
+    #[test]
+    fn test_each_call_notifies_once() {
+        let mut peer = CrdtDocument::new("node_b".to_string());
+        peer.upsert_value("1", "title".to_string(), "Draft");
+        peer.upsert_value("2", "title".to_string(), "Spare");
+        let mut doc = CrdtDocument::new("node_a".to_string());
+        // What `set_on_change` does, without a JS function
+        doc.changes = ChangeLog(Some(Vec::new()));
+        notified();
+        
+        doc.merge(&peer.export()).unwrap();
+        let pair = |task_id: &str, field: &str| (task_id.to_string(), field.to_string());
+        assert_eq!(notified(), [vec![pair("1", "title"), pair("2", "title")]]);
+        
+        doc.purge_task("2");
+        assert_eq!(notified(), [vec![pair("2", "deleted")]]);
+        
+        let backup = peer.export();
+        doc.upsert_value("3", "title".to_string(), "Local only");
+        doc.changes.take();
+        doc.replace_state(&backup).unwrap();
+        assert_eq!(notified(), [vec![pair("2", "title"), pair("3", "deleted")]]);
+    }
 }
@@ -0,0 +1,76 @@
+//! `set_on_change` with a real JS callback: `wasm-pack test --node`
+#![cfg(target_arch = "wasm32")]
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use wasm_bindgen_test::*;
+use wasm_crdt::CrdtDocument;
+
+type Calls = Rc<RefCell<Vec<(String, String, String)>>>;
+
+/// Document whose callback records `(task_id, field, origin)` per change
+fn recording(node_id: &str) -> (CrdtDocument, Calls) {
+    let calls: Calls = Rc::default();
+    let log = calls.clone();
+    let callback = Closure::<dyn FnMut(JsValue)>::new(move |change: JsValue| {
+        let get = |key: &str| {
+            js_sys::Reflect::get(&change, &key.into()).ok().and_then(|value| value.as_string()).unwrap_or_default()
+        };
+        log.borrow_mut().push((get("task_id"), get("field"), get("origin")));
+    });
+    let mut doc = CrdtDocument::new(node_id.to_string());
+    doc.set_on_change(callback.into_js_value().unchecked_into());
+    (doc, calls)
+}
+
+/// Run the queued callbacks and return what they recorded since last time
+async fn drain(calls: &Calls) -> Vec<(String, String, String)> {
+    JsFuture::from(js_sys::Promise::resolve(&JsValue::NULL)).await.unwrap();
+    calls.borrow_mut().drain(..).collect()
+}
+
+fn call(task_id: &str, field: &str, origin: &str) -> (String, String, String) {
+    (task_id.to_string(), field.to_string(), origin.to_string())
+}
+
+#[wasm_bindgen_test]
+async fn callbacks_fire_only_for_real_changes() {
+    let (mut doc, calls) = recording("node_a");
+
+    doc.upsert_field("1", "title".to_string(), "Draft".into()).unwrap();
+    doc.upsert_field("1", "title".to_string(), "Draft".into()).unwrap();
+    assert_eq!(drain(&calls).await, [call("1", "title", "local")]);
+
+    // A newer remote write is reported; merging it again, or an older
+    // state, is not
+    let stale = doc.export();
+    let mut peer = CrdtDocument::new("node_b".to_string());
+    peer.merge(&doc.export()).unwrap();
+    peer.upsert_field("1", "title".to_string(), "Final".into()).unwrap();
+    doc.merge(&peer.export()).unwrap();
+    doc.merge(&peer.export()).unwrap();
+    doc.merge(&stale).unwrap();
+    assert_eq!(drain(&calls).await, [call("1", "title", "remote")]);
+
+    doc.delete_task("1");
+    doc.delete_task("1");
+    assert_eq!(drain(&calls).await, [call("1", "deleted", "local")]);
+
+    doc.upsert_field("2", "title".to_string(), "Spare".into()).unwrap();
+    drain(&calls).await;
+    doc.purge_task("2");
+    assert_eq!(drain(&calls).await, [call("2", "deleted", "local")]);
+
+    let mut backup = CrdtDocument::new("node_c".to_string());
+    backup.upsert_field("3", "title".to_string(), "Restored".into()).unwrap();
+    doc.import_replace(&backup.export()).unwrap();
+    assert_eq!(drain(&calls).await, [call("3", "title", "remote")]);
+
+    doc.clear_on_change();
+    doc.upsert_field("3", "title".to_string(), "Quiet".into()).unwrap();
+    assert!(drain(&calls).await.is_empty());
+}
@@ -1,32 +1,616 @@
 use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
+#[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(js_namespace = console)]
     fn log(s: &str);
 }
 
+// Native builds (tests) have no JS console to log to
+#[cfg(not(target_arch = "wasm32"))]
+fn log(_s: &str) {}
+
+const DEFAULT_CACHE_CAPACITY: usize = 32;
+
+const DEFAULT_MAX_FIELD_CHARS: usize = 10_000;
+const DEFAULT_FUZZY_MAX_CHARS: usize = 1_000;
+
+static EMPTY_POSTINGS: Vec<usize> = Vec::new();
+
+/// Fields compared by `find_similar`
+const SIMILARITY_FIELDS: FieldMask = SearchField::Title.bit() | SearchField::Notes.bit();
+
+/// Bigram inside a single word; bigrams spanning a space depend on word
+/// order, so they're left out of similarity comparisons
+fn is_word_bigram(ngram: &str) -> bool {
+    ngram.chars().count() == 2 && !ngram.chars().any(char::is_whitespace)
+}
+
+/// Whether `s` starts with an ISO-8601 calendar date (`YYYY-MM-DD`).
+/// Dates in that form order the same as strings, which is all the date
+/// range search needs.
+fn is_iso_date(s: &str) -> bool {
+    let bytes = s.as_bytes();
+    bytes.len() >= 10
+        && bytes[..10].iter().enumerate().all(|(i, b)| match i {
+            4 | 7 => *b == b'-',
+            _ => b.is_ascii_digit(),
+        })
+        && (bytes.len() == 10 || bytes[10] == b'T' || bytes[10] == b' ')
+}
+
+fn string_or_number<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Id {
+        Text(String),
+        Integer(i64),
+        Float(f64),
+    }
+    Ok(match Id::deserialize(deserializer)? {
+        Id::Text(id) => id,
+        Id::Integer(id) => id.to_string(),
+        Id::Float(id) => id.to_string(),
+    })
+}
+
 macro_rules! console_log {
     ($($t:tt)*) => (log(&format_args!($($t)*).to_string()))
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct SearchDocument {
-    pub id: u32,
+    /// Numeric ids (SQLite rows) are read as their decimal string
+    #[serde(deserialize_with = "string_or_number")]
+    pub id: String,
     pub title: String,
     pub project: String,
     pub category: String,
     pub notes: String,
     pub status: String,
     pub assignee: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// ISO-8601 date or date-time the task is due
+    #[serde(default)]
+    pub due_date: Option<String>,
+}
+
+/// Document fields that can be searched individually
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SearchField {
+    Title,
+    Project,
+    Category,
+    Notes,
+    Assignee,
+    Tags,
+}
+
+const SEARCH_FIELDS: [SearchField; 6] = [
+    SearchField::Title,
+    SearchField::Project,
+    SearchField::Category,
+    SearchField::Notes,
+    SearchField::Assignee,
+    SearchField::Tags,
+];
+
+/// Bit set of `SearchField`s
+type FieldMask = u8;
+
+const ALL_FIELDS: FieldMask = 0b11_1111;
+
+impl SearchField {
+    fn name(self) -> &'static str {
+        match self {
+            SearchField::Title => "title",
+            SearchField::Project => "project",
+            SearchField::Category => "category",
+            SearchField::Notes => "notes",
+            SearchField::Assignee => "assignee",
+            SearchField::Tags => "tags",
+        }
+    }
+
+    const fn bit(self) -> FieldMask {
+        1 << (self as u8)
+    }
+
+    fn text(self, doc: &SearchDocument) -> String {
+        match self {
+            SearchField::Title => doc.title.clone(),
+            SearchField::Project => doc.project.clone(),
+            SearchField::Category => doc.category.clone(),
+            SearchField::Notes => doc.notes.clone(),
+            SearchField::Assignee => doc.assignee.clone(),
+            SearchField::Tags => doc.tags.join(" "),
+        }
+    }
+
+    /// Parse a list of field names into a mask; `None` means all fields
+    fn parse_mask(fields: Option<&[String]>) -> Result<FieldMask, String> {
+        let Some(fields) = fields else {
+            return Ok(ALL_FIELDS);
+        };
+        let mut mask = 0;
+        for name in fields {
+            match SEARCH_FIELDS.iter().find(|f| f.name() == name.to_lowercase()) {
+                Some(field) => mask |= field.bit(),
+                None => {
+                    let valid: Vec<&str> = SEARCH_FIELDS.iter().map(|f| f.name()).collect();
+                    return Err(format!("Unknown search field '{}'; valid fields: {}", name, valid.join(", ")));
+                }
+            }
+        }
+        Ok(mask)
+    }
+}
+
+/// Index entry: a document containing the n-gram (its position in
+/// `documents`), and in which fields
+#[derive(Clone, Copy, Debug)]
+struct Posting {
+    pos: usize,
+    fields: FieldMask,
+}
+
+/// How the `tags` filter combines multiple tags
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum TagMode {
+    #[default]
+    All,
+    Any,
+}
+
+/// Filter object accepted by `search_filtered`
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(default)]
+pub struct SearchFilters {
+    pub tags: Vec<String>,
+    pub tag_mode: TagMode,
+    pub project: Option<String>,
+    pub category: Option<String>,
+    pub status: Option<String>,
+    pub assignee: Option<String>,
+}
+
+impl SearchFilters {
+    /// Parse a JS filter object; `null`/`undefined` means no filtering
+    fn from_js(filters_js: JsValue) -> Result<Self, JsValue> {
+        if filters_js.is_undefined() || filters_js.is_null() {
+            return Ok(SearchFilters::default());
+        }
+        serde_wasm_bindgen::from_value(filters_js)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse filters: {}", e)))
+    }
+
+    fn matches(&self, doc: &SearchDocument) -> bool {
+        let field_ok = |wanted: &Option<String>, value: &str| {
+            wanted.as_ref().is_none_or(|w| w.eq_ignore_ascii_case(value))
+        };
+        if !(field_ok(&self.project, &doc.project)
+            && field_ok(&self.category, &doc.category)
+            && field_ok(&self.status, &doc.status)
+            && field_ok(&self.assignee, &doc.assignee))
+        {
+            return false;
+        }
+        
+        if self.tags.is_empty() {
+            return true;
+        }
+        let has_tag = |tag: &String| doc.tags.iter().any(|t| t.eq_ignore_ascii_case(tag));
+        match self.tag_mode {
+            TagMode::All => self.tags.iter().all(has_tag),
+            TagMode::Any => self.tags.iter().any(has_tag),
+        }
+    }
+
+    /// Stable representation used as part of the query cache key
+    fn cache_key(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// A query split into free text and `tag:` terms
+struct ParsedQuery {
+    text: String,
+    tags: Vec<String>,
+}
+
+impl ParsedQuery {
+    fn parse(query_lower: &str) -> Self {
+        let mut words = Vec::new();
+        let mut tags = Vec::new();
+        for word in query_lower.split_whitespace() {
+            match word.strip_prefix("tag:") {
+                Some(tag) if !tag.is_empty() => tags.push(tag.to_string()),
+                _ => words.push(word),
+            }
+        }
+        ParsedQuery { text: words.join(" "), tags }
+    }
+}
+
+/// Outcome of `index_documents`
+#[derive(Serialize, Debug)]
+pub struct IndexReport {
+    pub indexed: usize,
+    pub errors: Vec<IndexError>,
+}
+
+/// A document that could not be indexed, by position in the input array
+#[derive(Serialize, Debug)]
+pub struct IndexError {
+    pub index: usize,
+    pub message: String,
+}
+
+/// A document similar to a `find_similar` candidate
+#[derive(Serialize, Debug)]
+pub struct SimilarDocument {
+    pub id: String,
+    pub similarity: f32,
+}
+
+/// Engine settings. The field limits keep huge fields (e.g. pasted logs)
+/// from blowing up the index.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SearchConfig {
+    /// Characters of each field indexed; the rest is not n-grammed
+    pub max_field_chars: usize,
+    /// Longer targets skip the word-by-word fuzzy comparison
+    pub fuzzy_max_chars: usize,
+    /// Only match documents containing every query word, literally or
+    /// with a small typo (AND instead of OR semantics)
+    pub require_all_terms: bool,
+    /// Edit distance used for typo tolerance
+    pub fuzzy_algorithm: FuzzyAlgorithm,
+}
+
+/// How typos are counted. Damerau-Levenshtein (optimal string alignment)
+/// counts swapping two adjacent characters ("teh" -> "the") as one edit
+/// instead of two.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum FuzzyAlgorithm {
+    #[default]
+    Levenshtein,
+    Damerau,
+}
+
+impl FuzzyAlgorithm {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "levenshtein" => Ok(FuzzyAlgorithm::Levenshtein),
+            "damerau" => Ok(FuzzyAlgorithm::Damerau),
+            _ => Err(format!("Unknown fuzzy algorithm '{}'; use levenshtein or damerau", name)),
+        }
+    }
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            max_field_chars: DEFAULT_MAX_FIELD_CHARS,
+            fuzzy_max_chars: DEFAULT_FUZZY_MAX_CHARS,
+            require_all_terms: false,
+            fuzzy_algorithm: FuzzyAlgorithm::default(),
+        }
+    }
+}
+
+/// Major version of the snapshot format. Bump it for changes older
+/// readers can't handle; additive fields should use `#[serde(default)]`.
+const SNAPSHOT_VERSION: u32 = 2;
+
+/// Persisted engine state: documents plus config, the index is rebuilt
+#[derive(Serialize, Deserialize)]
+struct Snapshot<'a> {
+    version: u32,
+    #[serde(default)]
+    config: Cow<'a, SearchConfig>,
+    documents: Cow<'a, [SearchDocument]>,
+}
+
+#[derive(Deserialize)]
+struct SnapshotHeader {
+    version: u32,
+}
+
+#[derive(Serialize)]
+struct IndexStats {
+    document_count: usize,
+    ngram_count: usize,
+    total_postings: usize,
+    /// Documents per n-gram on average
+    avg_postings_per_ngram: f64,
+    /// Documents behind the most common n-gram; a common one is a
+    /// stopword candidate
+    largest_posting_list: usize,
+    truncated: Vec<String>,
+}
+
+/// At most `max` characters of `text`, and whether anything was cut
+fn truncate_chars(text: &str, max: usize) -> (&str, bool) {
+    match text.char_indices().nth(max) {
+        Some((end, _)) => (&text[..end], true),
+        None => (text, false),
+    }
+}
+
+/// A search result with a short excerpt around its match
+#[derive(Serialize)]
+struct SearchSnippet<'a> {
+    document: &'a SearchDocument,
+    /// Field the snippet was taken from
+    field: &'static str,
+    snippet: String,
+}
+
+/// Marks text cut from either end of a snippet
+const ELLIPSIS: char = '…';
+
+/// Char position and length of the first occurrence of one of `terms`
+/// (lowercase) in `text`, trying the terms in order. Positions count
+/// chars, so Thai text is never split inside a character.
+fn find_term(text: &[char], terms: &[Vec<char>]) -> Option<(usize, usize)> {
+    let lower: Vec<char> = text.iter().map(|c| c.to_lowercase().next().unwrap_or(*c)).collect();
+    terms.iter().filter(|term| !term.is_empty()).find_map(|term| {
+        lower.windows(term.len()).position(|window| window == term.as_slice()).map(|start| (start, term.len()))
+    })
+}
+
+/// `text` around a match, `context` chars on each side, with ellipses
+/// where it was cut
+fn excerpt(text: &[char], start: usize, len: usize, context: usize) -> String {
+    let from = start.saturating_sub(context);
+    let to = (start + len + context).min(text.len());
+    let mut snippet = String::new();
+    if from > 0 {
+        snippet.push(ELLIPSIS);
+    }
+    snippet.extend(&text[from..to]);
+    if to < text.len() {
+        snippet.push(ELLIPSIS);
+    }
+    snippet
+}
+
+/// One bonus added to a document's score, e.g. `title_prefix`
+#[derive(Serialize, Debug, PartialEq)]
+struct ScoreComponent {
+    reason: &'static str,
+    value: f32,
+}
+
+impl ScoreComponent {
+    fn new(reason: &'static str, value: f32) -> Self {
+        ScoreComponent { reason, value }
+    }
+}
+
+/// Score breakdown returned by `explain`
+#[derive(Serialize, Debug)]
+struct ScoreExplanation {
+    id: String,
+    /// Whether `search` returns the document
+    matched: bool,
+    /// Query n-gram hits, one point each
+    ngram_matches: f32,
+    bonuses: Vec<ScoreComponent>,
+    score: f32,
+}
+
+/// N-gram score plus bonuses, summed in order
+fn total_score(ngram_matches: f32, bonuses: &[ScoreComponent]) -> f32 {
+    bonuses.iter().fold(ngram_matches, |score, bonus| score + bonus.value)
+}
+
+/// Fields search results can be grouped by
+const GROUP_FIELDS: [&str; 4] = ["project", "category", "status", "assignee"];
+
+/// Search results sharing one value of the grouping field
+#[derive(Serialize)]
+struct ResultGroup<'a> {
+    key: String,
+    total: usize,
+    documents: Vec<&'a SearchDocument>,
+}
+
+/// Match counts per value of each grouping field
+#[derive(Serialize, Default, Debug)]
+struct Facets<'a> {
+    project: BTreeMap<&'a str, usize>,
+    category: BTreeMap<&'a str, usize>,
+    status: BTreeMap<&'a str, usize>,
+    assignee: BTreeMap<&'a str, usize>,
+}
+
+/// Top results plus facet counts over every match
+#[derive(Serialize)]
+struct FacetedResults<'a> {
+    results: Vec<&'a SearchDocument>,
+    facets: Facets<'a>,
+}
+
+/// Entry of the term dictionary used for suggestions
+struct Term {
+    text: String,        // original casing of the first occurrence
+    field: &'static str, // "word" or "tag"
+    frequency: usize,
+}
+
+/// Punctuation trimmed from both ends of words, including Thai marks
+/// (paiyannoi, angkhankhu, khomut) and typographic quotes. Thai vowel
+/// and tone marks are not punctuation and must stay attached.
+fn is_edge_punctuation(c: char) -> bool {
+    matches!(
+        c,
+        ',' | '.' | ';' | ':' | '!' | '?' | '(' | ')' | '[' | ']' | '{' | '}' | '"' | '\''
+            | 'ฯ' | '๚' | '๛' | '“' | '”' | '‘' | '’' | '…'
+    )
+}
+
+/// Split text into search tokens: each whitespace-separated word with
+/// surrounding punctuation trimmed, plus the parts of compound words
+/// (`fixLoginAPI`, `update_user_profile`, `auth-service`, `v1.2`, `src/lib`)
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    for word in text.split_whitespace() {
+        let word = word.trim_matches(is_edge_punctuation);
+        if word.is_empty() {
+            continue;
+        }
+        tokens.push(word.to_string());
+        let parts = split_compound(word);
+        if parts.len() > 1 {
+            tokens.extend(parts);
+        }
+    }
+    tokens
+}
+
+/// Split a word on `_ - . /` and on case transitions. An uppercase run
+/// followed by a lowercase letter keeps its last capital for the next part,
+/// so `APIServer` becomes `API` + `Server`.
+fn split_compound(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    
+    for (i, &c) in chars.iter().enumerate() {
+        if matches!(c, '_' | '-' | '.' | '/') {
+            if !current.is_empty() {
+                parts.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+        if !current.is_empty() && c.is_uppercase() {
+            let prev = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if prev.is_lowercase() || (prev.is_uppercase() && next_is_lower) {
+                parts.push(std::mem::take(&mut current));
+            }
+        }
+        current.push(c);
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+/// A suggestion together with the field it came from
+#[derive(Serialize, Clone)]
+pub struct Suggestion {
+    pub text: String,
+    pub field: String,
+}
+
+/// Spelling corrections for a query; `corrected` is None when every
+/// word is already a known term
+#[derive(Serialize, Debug)]
+struct QueryCorrection {
+    corrected: Option<String>,
+    corrections: Vec<WordCorrection>,
+}
+
+#[derive(Serialize, Debug)]
+struct WordCorrection {
+    original: String,
+    suggestion: String,
+    distance: usize,
+}
+
+/// Largest edit distance `correct` accepts
+const MAX_CORRECTION_DISTANCE: usize = 2;
+
+/// Small LRU cache of query -> ranked document positions.
+/// Entries are positions into `SearchEngine::documents`, so the cache must
+/// be invalidated whenever the document list changes.
+struct QueryCache {
+    capacity: usize,
+    entries: Vec<(String, Vec<usize>)>, // most recently used last
+    hits: u64,
+    misses: u64,
+}
+
+impl QueryCache {
+    fn new(capacity: usize) -> Self {
+        QueryCache {
+            capacity,
+            entries: Vec::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<Vec<usize>> {
+        match self.entries.iter().position(|(k, _)| k == key) {
+            Some(pos) => {
+                let entry = self.entries.remove(pos);
+                let ranked = entry.1.clone();
+                self.entries.push(entry);
+                self.hits += 1;
+                Some(ranked)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn put(&mut self, key: String, ranked: Vec<usize>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.entries.retain(|(k, _)| *k != key);
+        if self.entries.len() >= self.capacity {
+            self.entries.remove(0);
+        }
+        self.entries.push((key, ranked));
+    }
+
+    fn invalidate(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[derive(Serialize)]
+struct CacheStats {
+    hits: u64,
+    misses: u64,
+    size: usize,
+    capacity: usize,
 }
 
 #[wasm_bindgen]
 pub struct SearchEngine {
     documents: Vec<SearchDocument>,
-    ngram_index: HashMap<String, Vec<u32>>, // ngram -> document ids
+    ngram_index: HashMap<String, Vec<Posting>>, // ngram -> documents and fields
+    tag_index: HashMap<String, Vec<usize>>, // lowercased tag -> document positions
+    similarity_sizes: Vec<usize>, // distinct title+notes bigrams, by document position
+    terms: HashMap<(String, &'static str), Term>, // (lowercased term, field) -> term
+    note_words: HashSet<String>, // lowercased words of notes: known to `correct`, never suggested
+    truncated: Vec<String>, // ids of documents with a field over max_field_chars
+    config: SearchConfig,
+    cache: RefCell<QueryCache>,
+}
+
+impl Default for SearchEngine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[wasm_bindgen]
@@ -39,46 +623,191 @@ impl SearchEngine {
         SearchEngine {
             documents: Vec::new(),
             ngram_index: HashMap::new(),
+            tag_index: HashMap::new(),
+            similarity_sizes: Vec::new(),
+            terms: HashMap::new(),
+            note_words: HashSet::new(),
+            truncated: Vec::new(),
+            config: SearchConfig::default(),
+            cache: RefCell::new(QueryCache::new(DEFAULT_CACHE_CAPACITY)),
         }
     }
 
-    /// Add or update documents in the search index
-    pub fn index_documents(&mut self, documents_js: JsValue) -> Result<(), JsValue> {
-        let documents: Vec<SearchDocument> = serde_wasm_bindgen::from_value(documents_js)
+    /// Add or update documents in the search index.
+    /// Malformed entries are skipped and reported as
+    /// `{indexed, errors: [{index, message}]}`; with `strict` set, any
+    /// malformed entry fails the whole batch and leaves the index untouched.
+    pub fn index_documents(&mut self, documents_js: JsValue, strict: Option<bool>) -> Result<JsValue, JsValue> {
+        let values: Vec<serde_json::Value> = serde_wasm_bindgen::from_value(documents_js)
             .map_err(|e| JsValue::from_str(&format!("Failed to parse documents: {}", e)))?;
         
-        self.documents = documents;
-        self.build_index();
+        let report = self
+            .index_values(values, strict.unwrap_or(false))
+            .map_err(|e| JsValue::from_str(&e))?;
+        
+        console_log!("Indexed {} documents ({} skipped)", report.indexed, report.errors.len());
+        serde_wasm_bindgen::to_value(&report)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+
+    /// Add a document, or replace the indexed document with the same id
+    pub fn update_document(&mut self, document_js: JsValue) -> Result<(), JsValue> {
+        let document: SearchDocument = serde_wasm_bindgen::from_value(document_js)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse document: {}", e)))?;
         
-        console_log!("Indexed {} documents", self.documents.len());
+        self.upsert_document(document);
+        Ok(())
+    }
+
+    /// Remove a document from the index. Returns false if the id was unknown.
+    pub fn remove_document(&mut self, id: &str) -> bool {
+        let before = self.documents.len();
+        self.documents.retain(|doc| doc.id != id);
+        if self.documents.len() == before {
+            return false;
+        }
+        self.build_index();
+        true
+    }
+
+    /// Set index limits from `{max_field_chars, fuzzy_max_chars,
+    /// require_all_terms, fuzzy_algorithm}`; missing keys take their
+    /// defaults. Rebuilds the index.
+    pub fn set_config(&mut self, config_js: JsValue) -> Result<(), JsValue> {
+        let config: SearchConfig = serde_wasm_bindgen::from_value(config_js)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse config: {}", e)))?;
+        self.apply_config(config);
         Ok(())
     }
 
+    /// Count typos with `levenshtein` (default) or `damerau`, which
+    /// treats swapped adjacent characters as one edit
+    pub fn set_fuzzy_algorithm(&mut self, name: String) -> Result<(), JsValue> {
+        self.config.fuzzy_algorithm = FuzzyAlgorithm::parse(&name).map_err(|e| JsValue::from_str(&e))?;
+        self.cache.borrow_mut().invalidate();
+        Ok(())
+    }
+
+    /// Get the current index limits
+    pub fn get_config(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.config).unwrap_or(JsValue::NULL)
+    }
+
+    /// Serialize documents and config into a versioned JSON snapshot
+    pub fn snapshot(&self) -> String {
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            config: Cow::Borrowed(&self.config),
+            documents: Cow::Borrowed(&self.documents),
+        };
+        serde_json::to_string(&snapshot).unwrap_or_default()
+    }
+
+    /// Replace the engine state with a snapshot from `snapshot`. Snapshots
+    /// from an unknown format version are rejected.
+    pub fn restore(&mut self, snapshot: &str) -> Result<(), JsValue> {
+        self.restore_snapshot(snapshot).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Get index size `{document_count, ngram_count, total_postings,
+    /// avg_postings_per_ngram, largest_posting_list}` and the ids of
+    /// documents truncated at index time as `truncated`
+    pub fn stats(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.collect_stats()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Same as `stats`
+    pub fn index_stats(&self) -> JsValue {
+        self.stats()
+    }
+
+    /// Set the query cache capacity (0 disables caching)
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        let mut cache = self.cache.borrow_mut();
+        cache.capacity = capacity;
+        let excess = cache.entries.len().saturating_sub(capacity);
+        cache.entries.drain(..excess);
+    }
+
+    /// Get query cache hit/miss counters
+    pub fn cache_stats(&self) -> JsValue {
+        let cache = self.cache.borrow();
+        let stats = CacheStats {
+            hits: cache.hits,
+            misses: cache.misses,
+            size: cache.entries.len(),
+            capacity: cache.capacity,
+        };
+        serde_wasm_bindgen::to_value(&stats).unwrap_or(JsValue::NULL)
+    }
+
     fn build_index(&mut self) {
         self.ngram_index.clear();
+        self.tag_index.clear();
+        self.similarity_sizes.clear();
+        self.terms.clear();
+        self.note_words.clear();
+        self.truncated.clear();
+        self.cache.borrow_mut().invalidate();
         
-        for doc in &self.documents {
-            let searchable_text = format!(
-                "{} {} {} {} {}",
-                doc.title,
-                doc.project,
-                doc.category,
-                doc.notes,
-                doc.assignee
-            ).to_lowercase();
+        for (pos, doc) in self.documents.iter().enumerate() {
+            // Term dictionary: words (with compound parts) and whole tags
+            let words = tokenize(&doc.title)
+                .into_iter()
+                .chain(tokenize(&doc.project))
+                .chain(tokenize(&doc.category))
+                .map(|word| (word, "word"))
+                .chain(doc.tags.iter().map(|tag| (tag.clone(), "tag")));
+            for (text, field) in words {
+                self.terms
+                    .entry((text.to_lowercase(), field))
+                    .or_insert_with(|| Term { text, field, frequency: 0 })
+                    .frequency += 1;
+            }
+            self.note_words.extend(tokenize(&doc.notes).iter().map(|word| word.to_lowercase()));
+            
+            // Whole-token tag index for exact `tag:` matching
+            for tag in &doc.tags {
+                self.tag_index
+                    .entry(tag.to_lowercase())
+                    .or_default()
+                    .push(pos);
+            }
+            
+            // Build n-gram index per field, remembering which fields
+            // produced each n-gram so searches can be limited to fields
+            let mut doc_ngrams: HashMap<String, FieldMask> = HashMap::new();
+            let mut truncated = false;
+            for field in SEARCH_FIELDS {
+                let text = field.text(doc).to_lowercase();
+                let (text, cut) = truncate_chars(&text, self.config.max_field_chars);
+                truncated |= cut;
+                if text.is_empty() {
+                    continue;
+                }
+                for ngram in self.generate_ngrams(text, 2) {
+                    *doc_ngrams.entry(ngram).or_insert(0) |= field.bit();
+                }
+            }
+            let similarity_size = doc_ngrams
+                .iter()
+                .filter(|(ngram, fields)| *fields & SIMILARITY_FIELDS != 0 && is_word_bigram(ngram))
+                .count();
+            self.similarity_sizes.push(similarity_size);
+            if truncated {
+                self.truncated.push(doc.id.clone());
+            }
             
-            // Build n-gram index (2-grams and 3-grams)
-            let ngrams = self.generate_ngrams(&searchable_text, 2);
-            for ngram in ngrams {
+            for (ngram, fields) in doc_ngrams {
                 self.ngram_index
                     .entry(ngram)
-                    .or_insert_with(Vec::new)
-                    .push(doc.id);
+                    .or_default()
+                    .push(Posting { pos, fields });
             }
         }
         
         // Remove duplicates from index
-        for ids in self.ngram_index.values_mut() {
+        for ids in self.tag_index.values_mut() {
             ids.sort_unstable();
             ids.dedup();
         }
@@ -105,97 +834,84 @@ impl SearchEngine {
         ngrams
     }
 
-    /// Search with fuzzy matching
-    pub fn search(&self, query: String, limit: usize) -> Result<JsValue, JsValue> {
+    /// Search with fuzzy matching. `fields` optionally limits matching to
+    /// some of: title, project, category, notes, assignee, tags.
+    pub fn search(&self, query: String, limit: usize, fields: Option<Vec<String>>) -> Result<JsValue, JsValue> {
         if query.trim().is_empty() {
             return serde_wasm_bindgen::to_value(&self.documents)
                 .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)));
         }
 
-        let query_lower = query.to_lowercase();
-        let mut doc_scores: HashMap<u32, f32> = HashMap::new();
+        let mask = SearchField::parse_mask(fields.as_deref()).map_err(|e| JsValue::from_str(&e))?;
+        let top_results = self.search_documents(&query, &SearchFilters::default(), mask, limit);
+        
+        serde_wasm_bindgen::to_value(&top_results)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
 
-        // Score based on n-gram matching
-        let query_ngrams = self.generate_ngrams(&query_lower, 2);
-        for ngram in query_ngrams {
-            if let Some(doc_ids) = self.ngram_index.get(&ngram) {
-                for &id in doc_ids {
-                    *doc_scores.entry(id).or_insert(0.0) += 1.0;
-                }
-            }
-        }
+    /// Search restricted by a filter object:
+    /// `{tags, tag_mode: "all" | "any", project, category, status, assignee}`
+    pub fn search_filtered(&self, query: String, filters_js: JsValue, limit: usize, fields: Option<Vec<String>>) -> Result<JsValue, JsValue> {
+        let mask = SearchField::parse_mask(fields.as_deref()).map_err(|e| JsValue::from_str(&e))?;
+        let filters = SearchFilters::from_js(filters_js)?;
+        
+        let top_results = self.search_documents(&query, &filters, mask, limit);
+        
+        serde_wasm_bindgen::to_value(&top_results)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
 
-        // Calculate final scores with various bonuses
-        let mut results: Vec<(f32, &SearchDocument)> = Vec::new();
+    /// Search and group the matches by `group_by` (project, category,
+    /// status or assignee). Each group holds its top `per_group_limit`
+    /// documents and total match count; groups are ordered by their best
+    /// match.
+    pub fn search_grouped(&self, query: String, group_by: String, per_group_limit: usize) -> Result<JsValue, JsValue> {
+        let groups = self.group_results(&query, &group_by, per_group_limit).map_err(|e| JsValue::from_str(&e))?;
         
-        for doc in &self.documents {
-            let base_score = *doc_scores.get(&doc.id).unwrap_or(&0.0);
-            
-            if base_score == 0.0 {
-                continue;
-            }
-            
-            let mut final_score = base_score;
-            
-            // Exact match bonuses
-            let title_lower = doc.title.to_lowercase();
-            let project_lower = doc.project.to_lowercase();
-            let category_lower = doc.category.to_lowercase();
-            let notes_lower = doc.notes.to_lowercase();
-            let assignee_lower = doc.assignee.to_lowercase();
-            
-            // Title exact match (highest priority)
-            if title_lower == query_lower {
-                final_score += 100.0;
-            } else if title_lower.starts_with(&query_lower) {
-                final_score += 50.0;
-            } else if title_lower.contains(&query_lower) {
-                final_score += 30.0;
-            }
-            
-            // Word boundary match in title
-            for word in title_lower.split_whitespace() {
-                if word == query_lower {
-                    final_score += 20.0;
-                } else if word.starts_with(&query_lower) {
-                    final_score += 10.0;
-                }
-            }
-            
-            // Other field matches
-            if project_lower.contains(&query_lower) {
-                final_score += 15.0;
-            }
-            if category_lower.contains(&query_lower) {
-                final_score += 12.0;
-            }
-            if assignee_lower.contains(&query_lower) {
-                final_score += 18.0;
-            }
-            if notes_lower.contains(&query_lower) {
-                final_score += 8.0;
-            }
-            
-            // Fuzzy match for typo tolerance
-            let fuzzy_score = self.fuzzy_score(&query_lower, &title_lower);
-            final_score += fuzzy_score * 10.0;
-            
-            if final_score > 0.0 {
-                results.push((final_score, doc));
-            }
-        }
+        serde_wasm_bindgen::to_value(&groups)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+
+    /// Search and return `{results, facets: {project, category, status,
+    /// assignee}}`, where each facet maps a value to how many documents
+    /// match the query with it. Facets count every match, not just the
+    /// `limit` results returned.
+    pub fn search_facets(&self, query: String, limit: usize) -> Result<JsValue, JsValue> {
+        let faceted = self.faceted_results(&query, limit);
         
-        // Sort by score (descending)
-        results.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        serde_wasm_bindgen::to_value(&faceted)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+
+    /// Search and return `{document, field, snippet}` per result: up to
+    /// `context_chars` characters either side of the first match in
+    /// `notes`, or in the first other field that contains it. Without an
+    /// exact match the snippet is the start of `notes`.
+    pub fn search_snippets(&self, query: String, limit: usize, context_chars: usize) -> Result<JsValue, JsValue> {
+        let snippets = self.snippets(&query, limit, context_chars);
         
-        // Take top results
-        let top_results: Vec<&SearchDocument> = results
-            .into_iter()
-            .take(limit)
-            .map(|(_, doc)| doc)
-            .collect();
+        serde_wasm_bindgen::to_value(&snippets)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+
+    /// Why `doc_id` scores what it does for `query` in `search`:
+    /// `{id, matched, ngram_matches, bonuses: [{reason, value}], score}`.
+    /// `score` is `ngram_matches` plus every bonus; `matched` tells
+    /// whether `search` returns the document at all.
+    pub fn explain(&self, query: String, doc_id: String) -> Result<JsValue, JsValue> {
+        let explanation = self.explain_document(&query, &doc_id).map_err(|e| JsValue::from_str(&e))?;
         
-        serde_wasm_bindgen::to_value(&top_results)
+        serde_wasm_bindgen::to_value(&explanation)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+
+    /// Documents due between `from` and `to` (ISO-8601, both inclusive),
+    /// earliest first, whatever their text. A date-only `to` covers the
+    /// whole day. Documents without a due date are left out.
+    pub fn search_by_date_range(&self, from: String, to: String, limit: usize) -> Result<JsValue, JsValue> {
+        let docs = self.due_between(&from, &to, limit).map_err(|e| JsValue::from_str(&e))?;
+        
+        serde_wasm_bindgen::to_value(&docs)
             .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
     }
 
@@ -230,6 +946,11 @@ impl SearchEngine {
         
         let containment_ratio = matches as f32 / query_chars.len() as f32;
         
+        // Word-by-word Levenshtein is too slow for very long targets
+        if target_chars.len() > self.config.fuzzy_max_chars {
+            return containment_ratio / 2.0;
+        }
+        
         // Calculate Levenshtein distance for words
         let query_words: Vec<&str> = query.split_whitespace().collect();
         let target_words: Vec<&str> = target.split_whitespace().collect();
@@ -238,7 +959,7 @@ impl SearchEngine {
         
         for q_word in &query_words {
             for t_word in &target_words {
-                let dist = self.levenshtein_distance(q_word, t_word);
+                let dist = self.edit_distance(q_word, t_word);
                 let max_len = q_word.len().max(t_word.len()) as f32;
                 if max_len > 0.0 {
                     let similarity = 1.0 - (dist as f32 / max_len);
@@ -250,6 +971,14 @@ impl SearchEngine {
         (containment_ratio + best_word_score) / 2.0
     }
 
+    /// Distance between two words under the configured `fuzzy_algorithm`
+    fn edit_distance(&self, s1: &str, s2: &str) -> usize {
+        match self.config.fuzzy_algorithm {
+            FuzzyAlgorithm::Levenshtein => self.levenshtein_distance(s1, s2),
+            FuzzyAlgorithm::Damerau => self.damerau_levenshtein(s1, s2),
+        }
+    }
+
     fn levenshtein_distance(&self, s1: &str, s2: &str) -> usize {
         let s1_chars: Vec<char> = s1.chars().collect();
         let s2_chars: Vec<char> = s2.chars().collect();
@@ -262,11 +991,11 @@ impl SearchEngine {
         
         let mut matrix = vec![vec![0; len2 + 1]; len1 + 1];
         
-        for i in 0..=len1 {
-            matrix[i][0] = i;
+        for (i, row) in matrix.iter_mut().enumerate() {
+            row[0] = i;
         }
-        for j in 0..=len2 {
-            matrix[0][j] = j;
+        for (j, cell) in matrix[0].iter_mut().enumerate() {
+            *cell = j;
         }
         
         for i in 1..=len1 {
@@ -281,81 +1010,1148 @@ impl SearchEngine {
         matrix[len1][len2]
     }
 
+    /// Levenshtein plus transposition of adjacent characters as a single
+    /// edit (optimal string alignment: no substring is edited twice)
+    fn damerau_levenshtein(&self, s1: &str, s2: &str) -> usize {
+        let s1_chars: Vec<char> = s1.chars().collect();
+        let s2_chars: Vec<char> = s2.chars().collect();
+        
+        let len1 = s1_chars.len();
+        let len2 = s2_chars.len();
+        
+        if len1 == 0 { return len2; }
+        if len2 == 0 { return len1; }
+        
+        let mut matrix = vec![vec![0; len2 + 1]; len1 + 1];
+        
+        for (i, row) in matrix.iter_mut().enumerate() {
+            row[0] = i;
+        }
+        for (j, cell) in matrix[0].iter_mut().enumerate() {
+            *cell = j;
+        }
+        
+        for i in 1..=len1 {
+            for j in 1..=len2 {
+                let cost = if s1_chars[i - 1] == s2_chars[j - 1] { 0 } else { 1 };
+                matrix[i][j] = (matrix[i - 1][j] + 1)
+                    .min(matrix[i][j - 1] + 1)
+                    .min(matrix[i - 1][j - 1] + cost);
+                if i > 1 && j > 1 && s1_chars[i - 1] == s2_chars[j - 2] && s1_chars[i - 2] == s2_chars[j - 1] {
+                    matrix[i][j] = matrix[i][j].min(matrix[i - 2][j - 2] + 1);
+                }
+            }
+        }
+        
+        matrix[len1][len2]
+    }
+
     /// Quick search - simpler but faster
     pub fn quick_search(&self, query: String) -> Result<JsValue, JsValue> {
-        self.search(query, 50)
+        self.search(query, 50, None)
+    }
+
+    /// Export the indexed documents as a JSON array, optionally restricted
+    /// by the same filter object `search_filtered` accepts. The output can
+    /// be passed straight back to `index_documents`.
+    pub fn export_documents(&self, filters_js: Option<JsValue>) -> Result<String, JsValue> {
+        let filters = match filters_js {
+            Some(filters_js) => SearchFilters::from_js(filters_js)?,
+            None => SearchFilters::default(),
+        };
+        Ok(self.export_filtered(&filters))
+    }
+
+    /// Find indexed documents similar to a candidate document (for example
+    /// a task about to be created), by Jaccard similarity of title + notes
+    /// bigrams. Returns `[{id, similarity}]`, most similar first.
+    pub fn find_similar(&self, doc_js: JsValue, threshold: f32, limit: usize) -> Result<JsValue, JsValue> {
+        let doc: SearchDocument = serde_wasm_bindgen::from_value(doc_js)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse document: {}", e)))?;
+        
+        let similar = self.similar_documents(&doc, threshold, limit);
+        serde_wasm_bindgen::to_value(&similar)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
     }
 
     /// Get suggestions based on partial input
     pub fn suggest(&self, partial: String, limit: usize) -> Result<JsValue, JsValue> {
-        if partial.len() < 2 {
-            return serde_wasm_bindgen::to_value(&Vec::<String>::new())
-                .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)));
+        let top_suggestions: Vec<String> = self
+            .suggestions(&partial, limit)
+            .into_iter()
+            .map(|s| s.text)
+            .collect();
+        
+        serde_wasm_bindgen::to_value(&top_suggestions)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+
+    /// Get suggestions as `{text, field}` objects so the UI can tell tags
+    /// apart from words
+    pub fn suggest_with_source(&self, partial: String, limit: usize) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&self.suggestions(&partial, limit))
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+
+    /// "Did you mean": replace query words that aren't corpus terms with
+    /// the closest term within edit distance 2, preferring frequent terms.
+    /// Returns `{corrected, corrections: [{original, suggestion, distance}]}`.
+    pub fn correct(&self, query: String) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.correction(&query)).unwrap_or(JsValue::NULL)
+    }
+
+    /// Clear the index
+    pub fn clear(&mut self) {
+        self.documents.clear();
+        self.ngram_index.clear();
+        self.tag_index.clear();
+        self.similarity_sizes.clear();
+        self.terms.clear();
+        self.note_words.clear();
+        self.truncated.clear();
+        self.cache.borrow_mut().invalidate();
+    }
+
+    /// Get document count
+    pub fn count(&self) -> usize {
+        self.documents.len()
+    }
+}
+
+impl SearchEngine {
+    /// Parse documents one by one and index the valid ones
+    fn index_values(&mut self, values: Vec<serde_json::Value>, strict: bool) -> Result<IndexReport, String> {
+        let mut documents = Vec::with_capacity(values.len());
+        let mut errors = Vec::new();
+        
+        for (index, value) in values.into_iter().enumerate() {
+            match serde_json::from_value::<SearchDocument>(value) {
+                Ok(document) => documents.push(document),
+                Err(e) => errors.push(IndexError { index, message: e.to_string() }),
+            }
+        }
+        
+        if strict && !errors.is_empty() {
+            let details: Vec<String> = errors
+                .iter()
+                .map(|e| format!("document {}: {}", e.index, e.message))
+                .collect();
+            return Err(format!("Failed to parse documents: {}", details.join("; ")));
         }
+        
+        let indexed = documents.len();
+        self.load_documents(documents);
+        Ok(IndexReport { indexed, errors })
+    }
 
-        let partial_lower = partial.to_lowercase();
-        let mut suggestions: Vec<(f32, String)> = Vec::new();
-        let mut seen = std::collections::HashSet::new();
+    fn apply_config(&mut self, config: SearchConfig) {
+        self.config = config;
+        self.build_index();
+    }
+
+    fn restore_snapshot(&mut self, snapshot: &str) -> Result<(), String> {
+        let header: SnapshotHeader = serde_json::from_str(snapshot)
+            .map_err(|e| format!("Invalid search snapshot: {}", e))?;
+        if header.version != SNAPSHOT_VERSION {
+            return Err(format!(
+                "Unsupported search snapshot version {} (expected {})",
+                header.version, SNAPSHOT_VERSION
+            ));
+        }
+        let snapshot: Snapshot = serde_json::from_str(snapshot)
+            .map_err(|e| format!("Invalid search snapshot: {}", e))?;
         
-        // Collect all unique words from documents
-        for doc in &self.documents {
-            let words: Vec<&str> = doc.title.split_whitespace()
-                .chain(doc.project.split_whitespace())
-                .chain(doc.category.split_whitespace())
+        self.config = snapshot.config.into_owned();
+        self.load_documents(snapshot.documents.into_owned());
+        Ok(())
+    }
+
+    fn collect_stats(&self) -> IndexStats {
+        let ngrams = self.ngram_index.len();
+        let postings = self.ngram_index.values().map(Vec::len).sum();
+        IndexStats {
+            document_count: self.documents.len(),
+            ngram_count: ngrams,
+            total_postings: postings,
+            avg_postings_per_ngram: if ngrams == 0 { 0.0 } else { postings as f64 / ngrams as f64 },
+            largest_posting_list: self.ngram_index.values().map(Vec::len).max().unwrap_or(0),
+            truncated: self.truncated.clone(),
+        }
+    }
+
+    /// Replace the indexed documents
+    fn load_documents(&mut self, documents: Vec<SearchDocument>) {
+        self.documents = documents;
+        self.build_index();
+    }
+
+    fn upsert_document(&mut self, document: SearchDocument) {
+        match self.documents.iter_mut().find(|doc| doc.id == document.id) {
+            Some(existing) => *existing = document,
+            None => self.documents.push(document),
+        }
+        self.build_index();
+    }
+
+    /// Top `limit` documents for a query, served from the query cache
+    /// when possible
+    fn search_documents(&self, query: &str, filters: &SearchFilters, fields: FieldMask, limit: usize) -> Vec<&SearchDocument> {
+        let query_lower = query.to_lowercase();
+        let cache_key = format!("{}\u{1f}{}\u{1f}{}", query_lower, filters.cache_key(), fields);
+        let cached = self.cache.borrow_mut().get(&cache_key);
+        let ranked = match cached {
+            Some(ranked) => ranked,
+            None => {
+                let ranked = self.rank(&query_lower, filters, fields);
+                self.cache.borrow_mut().put(cache_key, ranked.clone());
+                ranked
+            }
+        };
+        
+        ranked
+            .into_iter()
+            .take(limit)
+            .map(|pos| &self.documents[pos])
+            .collect()
+    }
+
+    /// Score every document against a lowercased query and return the
+    /// positions of matching documents, best first. `tag:` terms and
+    /// filters restrict the candidates; the remaining text is scored
+    /// against the fields in `fields` only.
+    fn rank(&self, query_lower: &str, filters: &SearchFilters, fields: FieldMask) -> Vec<usize> {
+        self.score_documents(query_lower, filters, fields).into_iter().map(|(_, pos)| pos).collect()
+    }
+
+    /// `rank` with the scores. Tag and filter only queries score 0.
+    fn score_documents(&self, query_lower: &str, filters: &SearchFilters, fields: FieldMask) -> Vec<(f32, usize)> {
+        let parsed = ParsedQuery::parse(query_lower);
+        let tagged: Vec<&Vec<usize>> = parsed
+            .tags
+            .iter()
+            .map(|tag| self.tag_index.get(tag).unwrap_or(&EMPTY_POSTINGS))
+            .collect();
+        let is_candidate = |pos: usize, doc: &SearchDocument| {
+            tagged.iter().all(|positions| positions.binary_search(&pos).is_ok()) && filters.matches(doc)
+        };
+        
+        // Only tags / filters: keep document order
+        if parsed.text.is_empty() {
+            return self
+                .documents
+                .iter()
+                .enumerate()
+                .filter(|&(pos, doc)| is_candidate(pos, doc))
+                .map(|(pos, _)| (0.0, pos))
                 .collect();
-            
-            for word in words {
-                let word_lower = word.to_lowercase();
-                if seen.contains(&word_lower) {
-                    continue;
+        }
+        let query_lower = parsed.text.as_str();
+        
+        let mut doc_scores: HashMap<usize, f32> = HashMap::new();
+        // Documents sharing at least one bigram with the query; single
+        // characters add to the score but are too weak to select a document
+        let mut matched: std::collections::HashSet<usize> = std::collections::HashSet::new();
+        let short_query = query_lower.chars().count() < 2;
+
+        // Score based on n-gram matching
+        let query_ngrams = self.generate_ngrams(query_lower, 2);
+        for ngram in query_ngrams {
+            let selects = short_query || ngram.chars().count() >= 2;
+            if let Some(postings) = self.ngram_index.get(&ngram) {
+                for posting in postings.iter().filter(|p| p.fields & fields != 0) {
+                    *doc_scores.entry(posting.pos).or_insert(0.0) += 1.0;
+                    if selects {
+                        matched.insert(posting.pos);
+                    }
                 }
-                seen.insert(word_lower.clone());
-                
-                let mut score = 0.0f32;
-                
-                // Prefix match
-                if word_lower.starts_with(&partial_lower) {
-                    score += 2.0;
+            }
+        }
+
+        let query_words: Vec<&str> = query_lower.split_whitespace().collect();
+
+        // Calculate final scores with various bonuses
+        let mut results: Vec<(f32, usize)> = Vec::new();
+        
+        for (pos, doc) in self.documents.iter().enumerate() {
+            let base_score = *doc_scores.get(&pos).unwrap_or(&0.0);
+            
+            if base_score == 0.0 || !matched.contains(&pos) || !is_candidate(pos, doc) {
+                continue;
+            }
+            if self.config.require_all_terms && !self.contains_all_words(doc, &query_words, fields) {
+                continue;
+            }
+            
+            let bonuses = self.bonuses(doc, query_lower, fields);
+            let final_score = total_score(base_score, &bonuses);
+            
+            if final_score > 0.0 {
+                results.push((final_score, pos));
+            }
+        }
+        
+        // Sort by score (descending)
+        results.sort_by(|a, b| b.0.total_cmp(&a.0));
+        
+        results
+    }
+
+    fn explain_document(&self, query: &str, doc_id: &str) -> Result<ScoreExplanation, String> {
+        let pos = self
+            .documents
+            .iter()
+            .position(|doc| doc.id == doc_id)
+            .ok_or_else(|| format!("Document not found: {}", doc_id))?;
+        let query_lower = query.to_lowercase();
+        let ranked = self.score_documents(&query_lower, &SearchFilters::default(), ALL_FIELDS);
+        let matched = ranked.iter().any(|&(_, ranked_pos)| ranked_pos == pos);
+        
+        let parsed = ParsedQuery::parse(&query_lower);
+        if parsed.text.is_empty() {
+            return Ok(ScoreExplanation { id: doc_id.to_string(), matched, ngram_matches: 0.0, bonuses: Vec::new(), score: 0.0 });
+        }
+        let ngram_matches = self
+            .generate_ngrams(&parsed.text, 2)
+            .iter()
+            .filter_map(|ngram| self.ngram_index.get(ngram))
+            .map(|postings| postings.iter().filter(|p| p.pos == pos && p.fields & ALL_FIELDS != 0).count() as f32)
+            .sum();
+        let bonuses = self.bonuses(&self.documents[pos], &parsed.text, ALL_FIELDS);
+        let score = total_score(ngram_matches, &bonuses);
+        Ok(ScoreExplanation { id: doc_id.to_string(), matched, ngram_matches, bonuses, score })
+    }
+
+    /// Bonuses on top of the n-gram score of a document matching a
+    /// lowercased query, in the order `rank` adds them
+    fn bonuses(&self, doc: &SearchDocument, query_lower: &str, fields: FieldMask) -> Vec<ScoreComponent> {
+        let mut bonuses = Vec::new();
+        
+        // Exact match bonuses
+        let title_lower = doc.title.to_lowercase();
+        let project_lower = doc.project.to_lowercase();
+        let category_lower = doc.category.to_lowercase();
+        let notes_lower = doc.notes.to_lowercase();
+        let assignee_lower = doc.assignee.to_lowercase();
+        
+        let searched = |field: SearchField| fields & field.bit() != 0;
+        
+        if searched(SearchField::Title) {
+            // Title exact match (highest priority)
+            if title_lower == query_lower {
+                bonuses.push(ScoreComponent::new("title_exact", 100.0));
+            } else if title_lower.starts_with(query_lower) {
+                bonuses.push(ScoreComponent::new("title_prefix", 50.0));
+            } else if title_lower.contains(query_lower) {
+                bonuses.push(ScoreComponent::new("title_contains", 30.0));
+            }
+            
+            // Word boundary match in title, including compound word parts
+            for word in tokenize(&doc.title) {
+                let word = word.to_lowercase();
+                if word == query_lower {
+                    bonuses.push(ScoreComponent::new("title_word_exact", 20.0));
+                } else if word.starts_with(query_lower) {
+                    bonuses.push(ScoreComponent::new("title_word_prefix", 10.0));
                 }
-                
-                // Contains match
-                if word_lower.contains(&partial_lower) {
-                    score += 1.0;
+            }
+        }
+        
+        // Other field matches
+        if searched(SearchField::Project) && project_lower.contains(query_lower) {
+            bonuses.push(ScoreComponent::new("project_contains", 15.0));
+        }
+        if searched(SearchField::Category) && category_lower.contains(query_lower) {
+            bonuses.push(ScoreComponent::new("category_contains", 12.0));
+        }
+        if searched(SearchField::Assignee) && assignee_lower.contains(query_lower) {
+            bonuses.push(ScoreComponent::new("assignee_contains", 18.0));
+        }
+        if searched(SearchField::Notes) && notes_lower.contains(query_lower) {
+            bonuses.push(ScoreComponent::new("notes_contains", 8.0));
+        }
+        
+        // Tags are whole tokens, so an exact tag beats partial text
+        if searched(SearchField::Tags) {
+            for tag in &doc.tags {
+                let tag_lower = tag.to_lowercase();
+                if tag_lower == query_lower {
+                    bonuses.push(ScoreComponent::new("tag_exact", 25.0));
+                } else if tag_lower.starts_with(query_lower) {
+                    bonuses.push(ScoreComponent::new("tag_prefix", 10.0));
                 }
-                
-                // Fuzzy match
-                let fuzzy = self.fuzzy_score(&partial_lower, &word_lower);
-                if fuzzy > 0.7 {
-                    score += fuzzy;
+            }
+        }
+        
+        // Fuzzy match for typo tolerance
+        if searched(SearchField::Title) {
+            let fuzzy_score = self.fuzzy_score(query_lower, &title_lower);
+            if fuzzy_score > 0.0 {
+                bonuses.push(ScoreComponent::new("title_fuzzy", fuzzy_score * 10.0));
+            }
+        }
+        
+        bonuses
+    }
+
+    fn snippets(&self, query: &str, limit: usize, context: usize) -> Vec<SearchSnippet<'_>> {
+        // The whole query first, then its words
+        let parsed = ParsedQuery::parse(&query.to_lowercase());
+        let terms: Vec<Vec<char>> = std::iter::once(parsed.text.as_str())
+            .chain(parsed.text.split_whitespace())
+            .map(|term| term.chars().collect())
+            .collect();
+        let fields = std::iter::once(SearchField::Notes).chain(SEARCH_FIELDS.into_iter().filter(|&f| f != SearchField::Notes));
+        
+        self.search_documents(query, &SearchFilters::default(), ALL_FIELDS, limit)
+            .into_iter()
+            .map(|document| {
+                let matched = fields.clone().find_map(|field| {
+                    let text: Vec<char> = field.text(document).chars().collect();
+                    let (start, len) = find_term(&text, &terms)?;
+                    Some((field, excerpt(&text, start, len, context)))
+                });
+                let (field, snippet) = matched.unwrap_or_else(|| {
+                    let notes: Vec<char> = document.notes.chars().collect();
+                    (SearchField::Notes, excerpt(&notes, 0, 0, 2 * context))
+                });
+                SearchSnippet { document, field: field.name(), snippet }
+            })
+            .collect()
+    }
+
+    fn due_between(&self, from: &str, to: &str, limit: usize) -> Result<Vec<&SearchDocument>, String> {
+        for bound in [from, to] {
+            if !is_iso_date(bound) {
+                return Err(format!("Not an ISO-8601 date: {}", bound));
+            }
+        }
+        
+        let mut due: Vec<(&str, &SearchDocument)> = self
+            .documents
+            .iter()
+            .filter_map(|doc| Some((doc.due_date.as_deref().filter(|date| is_iso_date(date))?, doc)))
+            .filter(|(date, _)| *date >= from && date.get(..to.len()).unwrap_or(date) <= to)
+            .collect();
+        due.sort_by_key(|(date, _)| *date);
+        
+        Ok(due.into_iter().take(limit).map(|(_, doc)| doc).collect())
+    }
+
+    fn group_results(&self, query: &str, group_by: &str, per_group_limit: usize) -> Result<Vec<ResultGroup<'_>>, String> {
+        let group_by = group_by.to_lowercase();
+        if !GROUP_FIELDS.contains(&group_by.as_str()) {
+            return Err(format!("Unknown group field '{}'; valid fields: {}", group_by, GROUP_FIELDS.join(", ")));
+        }
+        let key_of = |doc: &SearchDocument| match group_by.as_str() {
+            "project" => doc.project.clone(),
+            "category" => doc.category.clone(),
+            "status" => doc.status.clone(),
+            _ => doc.assignee.clone(),
+        };
+        
+        // Results come best first, so groups are created in order of
+        // their best match
+        let mut groups: Vec<ResultGroup> = Vec::new();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for doc in self.search_documents(query, &SearchFilters::default(), ALL_FIELDS, usize::MAX) {
+            let key = key_of(doc);
+            let pos = *positions.entry(key.clone()).or_insert_with(|| {
+                groups.push(ResultGroup { key, total: 0, documents: Vec::new() });
+                groups.len() - 1
+            });
+            let group = &mut groups[pos];
+            group.total += 1;
+            if group.documents.len() < per_group_limit {
+                group.documents.push(doc);
+            }
+        }
+        Ok(groups)
+    }
+
+    fn faceted_results(&self, query: &str, limit: usize) -> FacetedResults<'_> {
+        let mut results = self.search_documents(query, &SearchFilters::default(), ALL_FIELDS, usize::MAX);
+        let mut facets = Facets::default();
+        for doc in &results {
+            *facets.project.entry(&doc.project).or_insert(0) += 1;
+            *facets.category.entry(&doc.category).or_insert(0) += 1;
+            *facets.status.entry(&doc.status).or_insert(0) += 1;
+            *facets.assignee.entry(&doc.assignee).or_insert(0) += 1;
+        }
+        results.truncate(limit);
+        FacetedResults { results, facets }
+    }
+
+    /// Whether every word occurs in the searched fields, either as a
+    /// substring or as a token within a small edit distance. Fields longer
+    /// than `fuzzy_max_chars` only get the substring check.
+    fn contains_all_words(&self, doc: &SearchDocument, words: &[&str], fields: FieldMask) -> bool {
+        let texts: Vec<String> = SEARCH_FIELDS
+            .iter()
+            .filter(|field| fields & field.bit() != 0)
+            .map(|field| field.text(doc).to_lowercase())
+            .collect();
+        
+        words.iter().all(|word| {
+            let max_distance = if word.chars().count() <= 5 { 1 } else { 2 };
+            texts.iter().any(|text| {
+                text.contains(word)
+                    || (text.chars().count() <= self.config.fuzzy_max_chars
+                        && tokenize(text)
+                            .iter()
+                            .any(|token| self.edit_distance(word, token) <= max_distance))
+            })
+        })
+    }
+
+    fn export_filtered(&self, filters: &SearchFilters) -> String {
+        let documents: Vec<&SearchDocument> = self
+            .documents
+            .iter()
+            .filter(|doc| filters.matches(doc))
+            .collect();
+        serde_json::to_string(&documents).unwrap_or_else(|_| "[]".to_string())
+    }
+
+    /// Indexed documents whose title + notes bigram sets overlap the
+    /// candidate's with Jaccard similarity of at least `threshold`.
+    /// Intersections are counted through the n-gram postings, so only
+    /// documents sharing a bigram are ever looked at.
+    fn similar_documents(&self, doc: &SearchDocument, threshold: f32, limit: usize) -> Vec<SimilarDocument> {
+        let title = doc.title.to_lowercase();
+        let notes = doc.notes.to_lowercase();
+        let (title, _) = truncate_chars(&title, self.config.max_field_chars);
+        let (notes, _) = truncate_chars(&notes, self.config.max_field_chars);
+        let candidate: std::collections::HashSet<String> = self
+            .generate_ngrams(title, 2)
+            .into_iter()
+            .chain(self.generate_ngrams(notes, 2))
+            .filter(|ngram| is_word_bigram(ngram))
+            .collect();
+        if candidate.is_empty() {
+            return Vec::new();
+        }
+        
+        let mut intersections: HashMap<usize, usize> = HashMap::new();
+        for ngram in &candidate {
+            if let Some(postings) = self.ngram_index.get(ngram) {
+                for posting in postings.iter().filter(|p| p.fields & SIMILARITY_FIELDS != 0) {
+                    *intersections.entry(posting.pos).or_insert(0) += 1;
                 }
-                
-                if score > 0.0 {
-                    suggestions.push((score, word.to_string()));
+            }
+        }
+        
+        let mut similar: Vec<SimilarDocument> = intersections
+            .into_iter()
+            .filter(|&(pos, _)| self.documents[pos].id != doc.id)
+            .filter_map(|(pos, shared)| {
+                let size = self.similarity_sizes[pos];
+                let similarity = shared as f32 / (candidate.len() + size - shared) as f32;
+                (similarity >= threshold).then(|| SimilarDocument { id: self.documents[pos].id.clone(), similarity })
+            })
+            .collect();
+        
+        similar.sort_by(|a, b| b.similarity.total_cmp(&a.similarity).then(a.id.cmp(&b.id)));
+        similar.truncate(limit);
+        similar
+    }
+
+    fn correction(&self, query: &str) -> QueryCorrection {
+        let mut corrections = Vec::new();
+        let mut words = Vec::new();
+        
+        for word in query.split_whitespace() {
+            // Look up "login," as "login", keeping the punctuation around it
+            let core = word.trim_matches(is_edge_punctuation);
+            let word_lower = core.to_lowercase();
+            let known = word.to_lowercase().starts_with("tag:")
+                || word_lower.chars().count() < 3
+                || self.note_words.contains(&word_lower)
+                || ["word", "tag"].iter().any(|field| self.terms.contains_key(&(word_lower.clone(), *field)));
+            let closest = if known { None } else { self.closest_term(&word_lower) };
+            
+            match closest {
+                Some((suggestion, distance)) => {
+                    let start = word.find(core).unwrap_or(0);
+                    words.push(format!("{}{}{}", &word[..start], suggestion, &word[start + core.len()..]));
+                    corrections.push(WordCorrection { original: core.to_string(), suggestion, distance });
                 }
+                None => words.push(word.to_string()),
+            }
+        }
+        
+        QueryCorrection {
+            corrected: (!corrections.is_empty()).then(|| words.join(" ")),
+            corrections,
+        }
+    }
+
+    /// Closest dictionary term within `MAX_CORRECTION_DISTANCE`: smallest
+    /// distance, then highest frequency, then alphabetical
+    fn closest_term(&self, word: &str) -> Option<(String, usize)> {
+        let word_len = word.chars().count();
+        let mut best: Option<(usize, usize, &str)> = None;
+        
+        for ((term, _), entry) in &self.terms {
+            if term.chars().count().abs_diff(word_len) > MAX_CORRECTION_DISTANCE {
+                continue;
+            }
+            let distance = self.edit_distance(word, term);
+            if distance > MAX_CORRECTION_DISTANCE {
+                continue;
+            }
+            let better = best.is_none_or(|(best_distance, best_frequency, best_term)| {
+                distance.cmp(&best_distance)
+                    .then(best_frequency.cmp(&entry.frequency))
+                    .then(term.as_str().cmp(best_term))
+                    .is_lt()
+            });
+            if better {
+                best = Some((distance, entry.frequency, term));
+            }
+        }
+        
+        best.map(|(distance, _, term)| (term.to_string(), distance))
+    }
+
+    /// Ranked word and tag suggestions for a partial input
+    fn suggestions(&self, partial: &str, limit: usize) -> Vec<Suggestion> {
+        if partial.len() < 2 {
+            return Vec::new();
+        }
+
+        // `to_lowercase` is Unicode-defined, not locale-dependent
+        let partial_lower = partial.trim_matches(is_edge_punctuation).to_lowercase();
+        let mut suggestions: Vec<(f32, &Term)> = Vec::new();
+        
+        for ((term_lower, _), term) in &self.terms {
+            let mut score = 0.0f32;
+            
+            // Prefix match
+            if term_lower.starts_with(&partial_lower) {
+                score += 2.0;
+            }
+            
+            // Contains match
+            if term_lower.contains(&partial_lower) {
+                score += 1.0;
+            }
+            
+            // Fuzzy match
+            let fuzzy = self.fuzzy_score(&partial_lower, term_lower);
+            if fuzzy > 0.7 {
+                score += fuzzy;
+            }
+            
+            if score > 0.0 {
+                suggestions.push((score, term));
             }
         }
         
-        suggestions.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
-        suggestions.dedup_by(|a, b| a.1.to_lowercase() == b.1.to_lowercase());
+        // Best score first, then more frequent terms, then alphabetical
+        suggestions.sort_by(|a, b| {
+            b.0.total_cmp(&a.0)
+                .then(b.1.frequency.cmp(&a.1.frequency))
+                .then_with(|| a.1.text.cmp(&b.1.text))
+        });
         
-        let top_suggestions: Vec<String> = suggestions
+        suggestions
             .into_iter()
             .take(limit)
-            .map(|(_, word)| word)
-            .collect();
-        
-        serde_wasm_bindgen::to_value(&top_suggestions)
-            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+            .map(|(_, term)| Suggestion {
+                text: term.text.clone(),
+                field: term.field.to_string(),
+            })
+            .collect()
     }
 
-    /// Clear the index
-    pub fn clear(&mut self) {
-        self.documents.clear();
-        self.ngram_index.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(id: &str, title: &str, notes: &str) -> SearchDocument {
+        SearchDocument {
+            id: id.to_string(),
+            title: title.to_string(),
+            project: "Khun Phaen".to_string(),
+            category: "dev".to_string(),
+            notes: notes.to_string(),
+            status: "todo".to_string(),
+            assignee: "somchai".to_string(),
+            tags: Vec::new(),
+            due_date: None,
+        }
     }
 
-    /// Get document count
-    pub fn count(&self) -> usize {
-        self.documents.len()
+    fn tagged(id: &str, title: &str, notes: &str, tags: &[&str]) -> SearchDocument {
+        SearchDocument {
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..doc(id, title, notes)
+        }
+    }
+
+    fn ids(docs: &[&SearchDocument]) -> Vec<String> {
+        docs.iter().map(|d| d.id.clone()).collect()
+    }
+
+    #[test]
+    fn test_repeated_query_served_from_cache() {
+        let mut engine = SearchEngine::new();
+        engine.load_documents(vec![doc("1", "Fix login bug", ""), doc("2", "Write report", "")]);
+
+        let first = ids(&engine.search_documents("login", &SearchFilters::default(), ALL_FIELDS, 10));
+        let second = ids(&engine.search_documents("login", &SearchFilters::default(), ALL_FIELDS, 10));
+        assert_eq!(first, second);
+
+        let cache = engine.cache.borrow();
+        assert_eq!((cache.hits, cache.misses), (1, 1));
+    }
+
+    #[test]
+    fn test_update_document_invalidates_cache() {
+        let mut engine = SearchEngine::new();
+        engine.load_documents(vec![doc("1", "Fix login bug", ""), doc("2", "Write report", "")]);
+        assert_eq!(ids(&engine.search_documents("report", &SearchFilters::default(), ALL_FIELDS, 10))[0], "2");
+
+        engine.upsert_document(doc("2", "Plan sprint", ""));
+        engine.upsert_document(doc("3", "Monthly report", ""));
+        assert_eq!(ids(&engine.search_documents("report", &SearchFilters::default(), ALL_FIELDS, 10))[0], "3");
+        assert_eq!(engine.cache.borrow().misses, 2);
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let mut cache = QueryCache::new(2);
+        cache.put("a".to_string(), vec![0]);
+        cache.put("b".to_string(), vec![1]);
+        assert!(cache.get("a").is_some());
+        cache.put("c".to_string(), vec![2]);
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("c").is_some());
+    }
+
+    #[test]
+    fn test_exact_tag_outranks_partial_text() {
+        let mut engine = SearchEngine::new();
+        engine.load_documents(vec![
+            tagged("1", "Fix footer", "this is urgently needed", &[]),
+            tagged("2", "Fix header", "", &["urgent", "frontend"]),
+        ]);
+        let none = SearchFilters::default();
+
+        assert_eq!(ids(&engine.search_documents("urgent", &none, ALL_FIELDS, 10)), vec!["2", "1"]);
+        assert_eq!(ids(&engine.search_documents("tag:urgent", &none, ALL_FIELDS, 10)), vec!["2"]);
+        assert_eq!(ids(&engine.search_documents("tag:urgent fix", &none, ALL_FIELDS, 10)), vec!["2"]);
+        assert!(engine.search_documents("tag:urg", &none, ALL_FIELDS, 10).is_empty());
+    }
+
+    #[test]
+    fn test_tags_filter_all_and_any() {
+        let mut engine = SearchEngine::new();
+        engine.load_documents(vec![
+            tagged("1", "Fix login", "", &["urgent"]),
+            tagged("2", "Fix logout", "", &["urgent", "backend"]),
+            tagged("3", "Fix signup", "", &["backend"]),
+        ]);
+        let mut filters = SearchFilters {
+            tags: vec!["urgent".to_string(), "backend".to_string()],
+            ..SearchFilters::default()
+        };
+
+        assert_eq!(ids(&engine.search_documents("fix", &filters, ALL_FIELDS, 10)), vec!["2"]);
+        filters.tag_mode = TagMode::Any;
+        let mut any = ids(&engine.search_documents("fix", &filters, ALL_FIELDS, 10));
+        any.sort();
+        assert_eq!(any, vec!["1", "2", "3"]);
+    }
+
+    #[test]
+    fn test_suggestions_include_tags() {
+        let mut engine = SearchEngine::new();
+        engine.load_documents(vec![tagged("1", "Deploy", "", &["deployment"])]);
+
+        let suggestions = engine.suggestions("deplo", 5);
+        assert!(suggestions.iter().any(|s| s.text == "deployment" && s.field == "tag"));
+        assert!(suggestions.iter().any(|s| s.text == "Deploy" && s.field == "word"));
+    }
+
+    #[test]
+    fn test_malformed_documents_are_skipped() {
+        let values = vec![
+            serde_json::to_value(doc("1", "Fix login bug", "")).unwrap(),
+            serde_json::json!({"id": 2, "title": 42}),
+            serde_json::to_value(doc("3", "Write report", "")).unwrap(),
+        ];
+
+        let mut engine = SearchEngine::new();
+        let report = engine.index_values(values.clone(), false).unwrap();
+        assert_eq!(report.indexed, 2);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].index, 1);
+        assert_eq!(ids(&engine.search_documents("report", &SearchFilters::default(), ALL_FIELDS, 10))[0], "3");
+
+        let mut strict = SearchEngine::new();
+        strict.load_documents(vec![doc("9", "Existing", "")]);
+        let err = strict.index_values(values, true).unwrap_err();
+        assert!(err.contains("document 1"));
+        assert_eq!(strict.count(), 1);
+    }
+
+    #[test]
+    fn test_search_limited_to_fields() {
+        let mut engine = SearchEngine::new();
+        engine.load_documents(vec![doc("1", "Fix header", "database migration notes")]);
+        let title = SearchField::parse_mask(Some(&["title".to_string()])).unwrap();
+        let notes = SearchField::parse_mask(Some(&["Notes".to_string()])).unwrap();
+        let none = SearchFilters::default();
+
+        assert!(engine.search_documents("migration", &none, title, 10).is_empty());
+        assert_eq!(ids(&engine.search_documents("migration", &none, notes, 10)), vec!["1"]);
+        assert_eq!(ids(&engine.search_documents("migration", &none, ALL_FIELDS, 10)), vec!["1"]);
+
+        let err = SearchField::parse_mask(Some(&["titel".to_string()])).unwrap_err();
+        assert!(err.contains("titel") && err.contains("title, project, category, notes, assignee, tags"));
+    }
+
+    #[test]
+    fn test_find_similar_detects_reworded_duplicate() {
+        let mut engine = SearchEngine::new();
+        engine.load_documents(vec![doc("1", "Fix the login bug", ""), doc("2", "Write quarterly report", "")]);
+
+        let similar = engine.similar_documents(&doc("99", "login bug fix", ""), 0.5, 10);
+        assert_eq!(similar.len(), 1);
+        assert_eq!(similar[0].id, "1");
+        assert!(similar[0].similarity > 0.7);
+
+        let unrelated = engine.similar_documents(&doc("99", "Order new chairs", ""), 0.5, 10);
+        assert!(unrelated.is_empty());
+    }
+
+    #[test]
+    fn test_export_documents_round_trips() {
+        let documents = vec![
+            tagged("1", "Fix login", "ทดสอบ", &["urgent"]),
+            tagged("2", "Write report", "", &[]),
+            SearchDocument { status: "done".to_string(), ..tagged("3", "Ship v1", "notes", &["release"]) },
+        ];
+        let mut engine = SearchEngine::new();
+        engine.load_documents(documents.clone());
+
+        let full = engine.export_filtered(&SearchFilters::default());
+        let mut restored = SearchEngine::new();
+        let report = restored.index_values(serde_json::from_str(&full).unwrap(), true).unwrap();
+        assert_eq!(report.indexed, 3);
+        assert_eq!(restored.documents, documents);
+
+        let done = SearchFilters { status: Some("done".to_string()), ..SearchFilters::default() };
+        let filtered: Vec<SearchDocument> = serde_json::from_str(&engine.export_filtered(&done)).unwrap();
+        assert_eq!(filtered, vec![documents[2].clone()]);
+    }
+
+    #[test]
+    fn test_tokenize_splits_compound_words() {
+        assert_eq!(tokenize("fixLoginAPI"), vec!["fixLoginAPI", "fix", "Login", "API"]);
+        assert_eq!(tokenize("APIServer v1.2"), vec!["APIServer", "API", "Server", "v1.2", "v1", "2"]);
+        assert_eq!(tokenize("update_user_profile src/lib"), vec!["update_user_profile", "update", "user", "profile", "src/lib", "src", "lib"]);
+        assert_eq!(tokenize("งาน ใหม่"), vec!["งาน", "ใหม่"]);
+    }
+
+    #[test]
+    fn test_compound_parts_get_word_bonus_and_suggestions() {
+        let mut engine = SearchEngine::new();
+        engine.load_documents(vec![doc("1", "cataloging", ""), doc("2", "fixLoginAPI", ""), doc("3", "update_user_profile", "")]);
+
+        assert_eq!(ids(&engine.search_documents("login", &SearchFilters::default(), ALL_FIELDS, 10))[..2], ["2", "1"]);
+        let suggestions = engine.suggestions("profi", 5);
+        assert_eq!(suggestions[0].text, "profile");
+    }
+
+    #[test]
+    fn test_search_grouped_by_project() {
+        let mut engine = SearchEngine::new();
+        let mut docs = vec![
+            doc("1", "Login page", ""),
+            doc("2", "Login", ""),
+            doc("3", "Fix login redirect", ""),
+            doc("4", "Dark mode", ""),
+        ];
+        docs[0].project = "Web".to_string();
+        docs[1].project = "Mobile".to_string();
+        docs[2].project = "Web".to_string();
+        docs[3].project = "Web".to_string();
+        engine.load_documents(docs);
+
+        let groups = engine.group_results("login", "project", 1).unwrap();
+        let keys: Vec<&str> = groups.iter().map(|g| g.key.as_str()).collect();
+        assert_eq!(keys, ["Mobile", "Web"]);
+        assert_eq!(groups[0].total, 1);
+        assert_eq!(groups[1].total, 2);
+        assert_eq!(ids(&groups[1].documents), ["1"]);
+        assert!(groups.iter().all(|g| g.documents.iter().all(|d| d.project == g.key)));
+
+        assert!(engine.group_results("login", "title", 1).is_err());
+    }
+
+    #[test]
+    fn test_huge_notes_are_truncated() {
+        let mut engine = SearchEngine::new();
+        let log_line = "2024-01-01T00:00:00Z ERROR worker crashed with code 137 ";
+        let notes = log_line.repeat(1_000_000 / log_line.len());
+        engine.load_documents(vec![doc("1", "Investigate worker crash", &notes), doc("2", "Write docs", "")]);
+
+        let stats = engine.collect_stats();
+        assert_eq!(stats.truncated, ["1"]);
+        assert!(stats.total_postings < 2 * DEFAULT_MAX_FIELD_CHARS);
+        assert_eq!(engine.search_documents("investigate", &SearchFilters::default(), ALL_FIELDS, 10)[0].id, "1");
+
+        engine.apply_config(SearchConfig { max_field_chars: 2_000_000, ..SearchConfig::default() });
+        assert!(engine.collect_stats().truncated.is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_round_trips_documents_and_config() {
+        let mut engine = SearchEngine::new();
+        engine.apply_config(SearchConfig { max_field_chars: 8, fuzzy_max_chars: 50, require_all_terms: true, ..SearchConfig::default() });
+        engine.load_documents(vec![doc("1", "Login page", "long notes beyond the cap"), tagged("2", "Dark mode", "", &["ui"])]);
+        let snapshot = engine.snapshot();
+
+        let mut restored = SearchEngine::new();
+        restored.restore_snapshot(&snapshot).unwrap();
+        assert_eq!(restored.documents, engine.documents);
+        assert_eq!(restored.config.max_field_chars, 8);
+        assert_eq!(restored.config.fuzzy_max_chars, 50);
+        assert!(restored.config.require_all_terms);
+        assert_eq!(restored.collect_stats().truncated, ["1", "2"]);
+        assert_eq!(ids(&restored.search_documents("tag:ui", &SearchFilters::default(), ALL_FIELDS, 10)), ["2"]);
+        assert_eq!(restored.snapshot(), snapshot);
+    }
+
+    #[test]
+    fn test_restore_rejects_unknown_version() {
+        let mut engine = SearchEngine::new();
+        engine.load_documents(vec![doc("1", "Login page", "")]);
+        let snapshot = engine.snapshot().replace("\"version\":2", "\"version\":3");
+
+        let err = engine.restore_snapshot(&snapshot).unwrap_err();
+        assert!(err.contains("version 3"), "{}", err);
+        assert_eq!(engine.count(), 1);
+        assert!(engine.restore_snapshot("not json").is_err());
+    }
+
+    #[test]
+    fn test_correct_misspelled_words() {
+        let mut engine = SearchEngine::new();
+        let mut docs = vec![doc("1", "Fix login page", ""), doc("2", "Project setup", ""), doc("3", "Protect admin routes", "")];
+        docs[0].project = "Project".to_string();
+        engine.load_documents(docs);
+
+        let correction = engine.correction("projct");
+        assert_eq!(correction.corrected.as_deref(), Some("project"));
+        assert_eq!(correction.corrections[0].distance, 1);
+
+        let correction = engine.correction("fix lgoin page");
+        assert_eq!(correction.corrected.as_deref(), Some("fix login page"));
+        assert_eq!(correction.corrections.len(), 1);
+        assert_eq!(correction.corrections[0].original, "lgoin");
+
+        let correction = engine.correction("Login Page");
+        assert!(correction.corrected.is_none());
+        assert!(correction.corrections.is_empty());
+
+        let correction = engine.correction("(projct), login!");
+        assert_eq!(correction.corrected.as_deref(), Some("(project), login!"));
+        assert_eq!(correction.corrections[0].original, "projct");
+    }
+
+    #[test]
+    fn test_words_in_notes_are_not_corrected() {
+        let mut engine = SearchEngine::new();
+        engine.load_documents(vec![doc("1", "Fix logic bug", "Users see the login screen twice")]);
+
+        let correction = engine.correction("login screen");
+        assert!(correction.corrected.is_none(), "{:?}", correction.corrected);
+        assert_eq!(engine.correction("logik").corrected.as_deref(), Some("logic"));
+        assert!(engine.suggestions("scr", 5).is_empty());
+    }
+
+    #[test]
+    fn test_require_all_terms() {
+        let mut engine = SearchEngine::new();
+        engine.load_documents(vec![
+            doc("1", "Login page redesign", ""),
+            doc("2", "Login bug on Safari", ""),
+            doc("3", "Logn timeout", "crash bug"),
+        ]);
+        let search = |engine: &SearchEngine| {
+            let mut found = ids(&engine.search_documents("login bug", &SearchFilters::default(), ALL_FIELDS, 10));
+            found.sort_unstable();
+            found
+        };
+        assert_eq!(search(&engine), ["1", "2", "3"]);
+
+        engine.apply_config(SearchConfig { require_all_terms: true, ..SearchConfig::default() });
+        assert_eq!(search(&engine), ["2", "3"]);
+        assert_eq!(engine.search_documents("login bug", &SearchFilters::default(), ALL_FIELDS, 10)[0].id, "2");
+    }
+
+    #[test]
+    fn test_suggestions_ignore_surrounding_punctuation() {
+        let mut engine = SearchEngine::new();
+        engine.load_documents(vec![
+            doc("1", "Deploy, then verify", ""),
+            doc("2", "Fix deploy (staging)", ""),
+            doc("3", "“deploy” ทดสอบ, (ทดสอบ) ทดสอบฯ", ""),
+        ]);
+
+        let suggestions = engine.suggestions("deplo", 10);
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].text, "Deploy");
+
+        let suggestions = engine.suggestions("ทดส", 10);
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].text, "ทดสอบ");
+
+        // Tone marks at the end of a word are kept
+        assert_eq!(tokenize("ที่,"), ["ที่"]);
+    }
+
+    #[test]
+    fn test_snippet_is_trimmed_around_the_match() {
+        let mut engine = SearchEngine::new();
+        let notes = format!("{}ติดตั้ง deploy script บนเซิร์ฟเวอร์{}", "ก่อนหน้า ".repeat(40), " หลังจากนั้น".repeat(40));
+        engine.load_documents(vec![doc("1", "Release", &notes), doc("2", "Deploy docs", "short")]);
+
+        let snippets = engine.snippets("deploy", 10, 20);
+        let first = snippets.iter().find(|s| s.document.id == "1").unwrap();
+        assert_eq!(first.field, "notes");
+        assert!(first.snippet.contains("deploy"));
+        assert!(first.snippet.starts_with(ELLIPSIS) && first.snippet.ends_with(ELLIPSIS));
+        assert_eq!(first.snippet.chars().count(), 20 + "deploy".len() + 20 + 2);
+        assert!(notes.contains(first.snippet.trim_matches(ELLIPSIS)));
+
+        // Notes without the term: the title holds the match
+        let second = snippets.iter().find(|s| s.document.id == "2").unwrap();
+        assert_eq!((second.field, second.snippet.as_str()), ("title", "Deploy docs"));
+    }
+
+    #[test]
+    fn test_index_stats_count_postings() {
+        let mut engine = SearchEngine::new();
+        let title_only = |id: &str, title: &str| SearchDocument {
+            project: String::new(),
+            category: String::new(),
+            assignee: String::new(),
+            ..doc(id, title, "")
+        };
+        assert_eq!(engine.collect_stats().avg_postings_per_ngram, 0.0);
+
+        // "ab": ab, a, b; "abc": ab, bc, a, b, c
+        engine.load_documents(vec![title_only("1", "ab"), title_only("2", "ABC")]);
+        let stats = engine.collect_stats();
+        assert_eq!((stats.document_count, stats.ngram_count, stats.total_postings), (2, 5, 8));
+        assert_eq!(stats.avg_postings_per_ngram, 1.6);
+        assert_eq!(stats.largest_posting_list, 2);
+    }
+
+    #[test]
+    fn test_explain_components_add_up_to_the_score() {
+        let mut engine = SearchEngine::new();
+        engine.load_documents(vec![
+            tagged("1", "Deploy script", "run deploy on staging", &["deploy"]),
+            doc("2", "Fix login", "unrelated"),
+        ]);
+
+        let explanation = engine.explain_document("Deploy", "1").unwrap();
+        assert!(explanation.matched);
+        let reasons: Vec<&str> = explanation.bonuses.iter().map(|b| b.reason).collect();
+        assert_eq!(reasons, ["title_prefix", "title_word_exact", "notes_contains", "tag_exact", "title_fuzzy"]);
+        let sum = explanation.ngram_matches + explanation.bonuses.iter().map(|b| b.value).sum::<f32>();
+        assert!((sum - explanation.score).abs() < 1e-4);
+
+        let scores = engine.score_documents("deploy", &SearchFilters::default(), ALL_FIELDS);
+        assert_eq!(scores[0], (explanation.score, 0));
+
+        assert!(!engine.explain_document("script", "2").unwrap().matched);
+        assert_eq!(engine.explain_document("deploy", "9").unwrap_err(), "Document not found: 9");
+    }
+
+    #[test]
+    fn test_damerau_counts_a_transposition_once() {
+        let mut engine = SearchEngine::new();
+        assert_eq!(engine.edit_distance("teh", "the"), 2);
+        engine.config.fuzzy_algorithm = FuzzyAlgorithm::parse("damerau").unwrap();
+        assert_eq!(engine.edit_distance("teh", "the"), 1);
+        assert_eq!(engine.edit_distance("kitten", "sitting"), 3);
+        assert!(FuzzyAlgorithm::parse("soundex").is_err());
+
+        // Corrections pick it up too: "lgoin" is one swap from "login"
+        engine.load_documents(vec![doc("1", "Fix login page", "")]);
+        assert_eq!(engine.correction("lgoin").corrections[0].distance, 1);
+    }
+
+    #[test]
+    fn test_date_range_is_inclusive_and_skips_undated() {
+        let mut engine = SearchEngine::new();
+        let due = |id: &str, date: Option<&str>| SearchDocument {
+            due_date: date.map(str::to_string),
+            ..doc(id, "Task", "")
+        };
+        engine.load_documents(vec![
+            due("1", Some("2024-03-31T18:00:00Z")),
+            due("2", None),
+            due("3", Some("2024-03-01")),
+            due("4", Some("2024-02-29")),
+            due("5", Some("2024-04-01")),
+            due("6", Some("next week")),
+        ]);
+
+        let in_march = engine.due_between("2024-03-01", "2024-03-31", 10).unwrap();
+        assert_eq!(ids(&in_march), ["3", "1"]);
+        assert_eq!(ids(&engine.due_between("2024-01-01", "2024-12-31", 2).unwrap()), ["4", "3"]);
+        assert_eq!(ids(&engine.due_between("2024-03-01", "2024-03-31T12:00", 10).unwrap()), ["3"]);
+        assert_eq!(engine.due_between("march", "2024-03-31", 10).unwrap_err(), "Not an ISO-8601 date: march");
+
+        // Older payloads without the field still deserialize
+        let old: SearchDocument = serde_json::from_value(serde_json::json!({
+            "id": "7", "title": "t", "project": "", "category": "", "notes": "", "status": "", "assignee": ""
+        }))
+        .unwrap();
+        assert_eq!(old.due_date, None);
+    }
+
+    #[test]
+    fn test_facets_count_every_match() {
+        let mut engine = SearchEngine::new();
+        let in_category = |id: &str, title: &str, category: &str| SearchDocument {
+            category: category.to_string(),
+            ..doc(id, title, "")
+        };
+        engine.load_documents(vec![
+            in_category("1", "Login page", "Design"),
+            in_category("2", "Login button", "Design"),
+            in_category("3", "Login tests", "QA"),
+            SearchDocument { status: "done".to_string(), ..in_category("4", "Login copy", "Design") },
+            in_category("5", "Pay rent", "QA"),
+        ]);
+
+        let faceted = engine.faceted_results("login", 2);
+        assert_eq!(faceted.results.len(), 2);
+        assert_eq!(faceted.facets.category, BTreeMap::from([("Design", 3), ("QA", 1)]));
+        assert_eq!(faceted.facets.status, BTreeMap::from([("done", 1), ("todo", 3)]));
+        assert_eq!(faceted.facets.project, BTreeMap::from([("Khun Phaen", 4)]));
+    }
+
+    #[test]
+    fn test_numeric_ids_are_indexed() {
+        let mut engine = SearchEngine::new();
+        let value = |id: serde_json::Value| serde_json::json!({
+            "id": id, "title": "Fix login", "project": "", "category": "", "notes": "", "status": "", "assignee": ""
+        });
+        let report = engine.index_values(vec![value(7.into()), value("abc".into()), value(true.into())], false).unwrap();
+        assert_eq!(report.indexed, 2);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(ids(&engine.search_documents("login", &SearchFilters::default(), ALL_FIELDS, 10)), ["7", "abc"]);
     }
 }